@@ -0,0 +1,132 @@
+//! Throughput of the ports moving bulk data, e.g., to stress-test parsers.
+//!
+//! Run with `cargo bench`.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serialport::{ClearBuffer, SerialPort};
+use virtual_serialport::VirtualPort;
+
+// Sizes of the chunks written and read at once
+const CHUNK_SIZES: [usize; 3] = [64, 4096, 65536];
+
+// Data moved per iteration
+const TRANSFER_SIZE: usize = 1 << 20;
+
+// Writes and reads back `TRANSFER_SIZE` bytes in chunks of `data.len()`
+fn transfer(writer: &mut VirtualPort, reader: &mut VirtualPort, data: &[u8], buf: &mut [u8]) {
+    for _ in 0..TRANSFER_SIZE / data.len() {
+        writer.write_all(data).unwrap();
+        reader.read_exact(buf).unwrap();
+    }
+}
+
+fn loopback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loopback");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for chunk_size in CHUNK_SIZES {
+        let mut port = VirtualPort::loopback(115_200, chunk_size).unwrap();
+        let mut reader = port.clone();
+        let data = vec![0x55; chunk_size];
+        let mut buf = vec![0; chunk_size];
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &data, |b, data| {
+            b.iter(|| transfer(&mut port, &mut reader, data, &mut buf))
+        });
+    }
+    group.finish();
+}
+
+fn pair(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pair");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for chunk_size in CHUNK_SIZES {
+        let (mut port1, mut port2) = VirtualPort::pair(115_200, chunk_size).unwrap();
+        let data = vec![0x55; chunk_size];
+        let mut buf = vec![0; chunk_size];
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &data, |b, data| {
+            b.iter(|| transfer(&mut port1, &mut port2, data, &mut buf))
+        });
+    }
+    group.finish();
+}
+
+// A writer and a reader on their own threads, as in soak tests, contending
+// for the channel
+fn threads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("threads");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for chunk_size in CHUNK_SIZES {
+        let data = vec![0x55; chunk_size];
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &data, |b, data| {
+            b.iter(|| {
+                let (mut port1, mut port2) = VirtualPort::pair(115_200, chunk_size).unwrap();
+                port1.set_blocking_writes(true);
+                let reader = thread::spawn(move || {
+                    let mut buf = vec![0; chunk_size];
+                    for _ in 0..TRANSFER_SIZE / chunk_size {
+                        port2.read_exact(&mut buf).unwrap();
+                    }
+                });
+                for _ in 0..TRANSFER_SIZE / chunk_size {
+                    port1.write_all(data).unwrap();
+                }
+                reader.join().unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+// The same transfers through a bare mutex-guarded queue, the least a locked
+// channel costs
+fn baseline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mutex_queue");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    for chunk_size in CHUNK_SIZES {
+        let queue = Arc::new(Mutex::new(VecDeque::with_capacity(chunk_size)));
+        let data = vec![0x55; chunk_size];
+        let mut buf = vec![0; chunk_size];
+        group.bench_with_input(BenchmarkId::from_parameter(chunk_size), &data, |b, data| {
+            b.iter(|| {
+                for _ in 0..TRANSFER_SIZE / chunk_size {
+                    queue.lock().unwrap().extend(data.iter().copied());
+                    let mut queue = queue.lock().unwrap();
+                    let (front, back) = queue.as_slices();
+                    buf[..front.len()].copy_from_slice(front);
+                    buf[front.len()..].copy_from_slice(back);
+                    queue.clear();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// With faults, the data goes through the receiver byte by byte
+fn faults(c: &mut Criterion) {
+    let mut group = c.benchmark_group("faults");
+    group.throughput(Throughput::Bytes(TRANSFER_SIZE as u64));
+    let chunk_size = 4096;
+    let (mut port1, mut port2) = VirtualPort::pair(115_200, 2 * chunk_size).unwrap();
+    port2.set_noise_seed(0);
+    port2.set_byte_duplication(0.0001);
+    let data = vec![0x55; chunk_size];
+    group.bench_function("duplication", |b| {
+        b.iter(|| {
+            for _ in 0..TRANSFER_SIZE / chunk_size {
+                port1.write_all(&data).unwrap();
+                port2.clear(ClearBuffer::Input).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, loopback, pair, threads, baseline, faults);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+fn main() {
+    // Declares the custom cfgs (`--cfg loom` and `--cfg nightly`), so the
+    // `unexpected_cfgs` lint accepts them. Older cargo ignores these lines.
+    println!("cargo:rustc-check-cfg=cfg(loom)");
+    println!("cargo:rustc-check-cfg=cfg(nightly)");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,270 @@
+//! `proptest` strategies for the configuration of the simulator.
+//!
+//! The [`Arbitrary`] implementations generate valid port settings, link
+//! conditions, fault schedules and traffic patterns (as [`Recording`]s to
+//! play back with a [`Replayer`](crate::Replayer)), within ranges which keep
+//! a test fast: delays stay below a second and probabilities below one half.
+
+use std::time::Duration;
+
+use proptest::{
+    arbitrary::Arbitrary,
+    collection::vec,
+    option,
+    prelude::{any, Just},
+    prop_oneof,
+    strategy::{BoxedStrategy, Strategy},
+};
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+
+use crate::{
+    ByteLoss, ControlLine, FaultSchedule, GilbertElliott, Jitter, LinkConditions, Record,
+    RecordedEvent, Recording, Settings,
+};
+
+// Baud rates of the generated settings
+const BAUD_RATES: [u32; 8] = [1200, 2400, 4800, 9600, 19_200, 38_400, 57_600, 115_200];
+
+// Largest probability of the generated faults
+const MAX_PROBABILITY: f64 = 0.5;
+
+fn probability() -> impl Strategy<Value = f64> {
+    0.0..=MAX_PROBABILITY
+}
+
+fn millis(max: u64) -> impl Strategy<Value = Duration> {
+    (0..=max).prop_map(Duration::from_millis)
+}
+
+impl Arbitrary for Settings {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let data_bits = prop_oneof![
+            Just(DataBits::Five),
+            Just(DataBits::Six),
+            Just(DataBits::Seven),
+            Just(DataBits::Eight),
+        ];
+        let parity = prop_oneof![Just(Parity::None), Just(Parity::Odd), Just(Parity::Even)];
+        let stop_bits = prop_oneof![Just(StopBits::One), Just(StopBits::Two)];
+        let flow_control = prop_oneof![
+            Just(FlowControl::None),
+            Just(FlowControl::Software),
+            Just(FlowControl::Hardware),
+        ];
+        let timeout = prop_oneof![Just(Duration::MAX), millis(1000)];
+        (
+            proptest::sample::select(&BAUD_RATES[..]),
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control,
+            timeout,
+        )
+            .prop_map(
+                |(baud_rate, data_bits, parity, stop_bits, flow_control, timeout)| Settings {
+                    baud_rate,
+                    data_bits,
+                    parity,
+                    stop_bits,
+                    flow_control,
+                    timeout,
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for ByteLoss {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(ByteLoss::None),
+            probability().prop_map(ByteLoss::Random),
+            (2..=100u64).prop_map(ByteLoss::EveryNth),
+            vec(any::<bool>(), 1..=16).prop_map(ByteLoss::Pattern),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for Jitter {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(Jitter::None),
+            probability().prop_map(Jitter::Uniform),
+            probability().prop_map(Jitter::Normal),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for GilbertElliott {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (probability(), probability(), probability(), probability())
+            .prop_map(
+                |(good_to_bad, bad_to_good, good_error_rate, bad_error_rate)| GilbertElliott {
+                    good_to_bad,
+                    bad_to_good,
+                    good_error_rate,
+                    bad_error_rate,
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for LinkConditions {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            any::<bool>(),
+            millis(100),
+            any::<Jitter>(),
+            any::<Jitter>(),
+            any::<bool>(),
+            any::<ByteLoss>(),
+            probability(),
+            probability(),
+            option::of(any::<GilbertElliott>()),
+        )
+            .prop_map(
+                |(
+                    simulate_delay,
+                    latency,
+                    timing_jitter,
+                    latency_jitter,
+                    noise_on_config_mismatch,
+                    byte_loss,
+                    byte_duplication,
+                    byte_insertion,
+                    burst_errors,
+                )| LinkConditions {
+                    simulate_delay,
+                    latency,
+                    timing_jitter,
+                    latency_jitter,
+                    noise_on_config_mismatch,
+                    byte_loss,
+                    byte_duplication,
+                    byte_insertion,
+                    burst_errors,
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for FaultSchedule {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        // Each fault is triggered at an offset or after a time, and corrupts
+        // or drops a few bytes or takes the link down
+        let fault = (any::<bool>(), 0..1024u64, millis(500), 0..3u8, 1..=8u64);
+        vec(fault, 0..=8)
+            .prop_map(|faults| {
+                faults.into_iter().fold(
+                    FaultSchedule::new(),
+                    |schedule, (at_offset, offset, time, action, count)| match (at_offset, action) {
+                        (true, 0) => schedule.corrupt_at(offset, count),
+                        (true, 1) => schedule.drop_at(offset, count),
+                        (true, _) => schedule.disconnect_at(offset),
+                        (false, 0) => schedule.corrupt_after(time, count),
+                        (false, 1) => schedule.drop_after(time, count),
+                        (false, _) => schedule.disconnect_after(time),
+                    },
+                )
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Recording {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    /// Generates a traffic pattern starting with the line settings: chunks
+    /// of data sent and received, changes of the input lines and of the
+    /// settings, separated by gaps of up to 100 ms.
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let line = prop_oneof![
+            Just(ControlLine::Cts),
+            Just(ControlLine::Dsr),
+            Just(ControlLine::Cd),
+            Just(ControlLine::Ri),
+        ];
+        let event = prop_oneof![
+            vec(any::<u8>(), 1..=64).prop_map(RecordedEvent::Sent),
+            vec(any::<u8>(), 1..=64).prop_map(RecordedEvent::Received),
+            (line, any::<bool>()).prop_map(|(line, level)| RecordedEvent::Line(line, level)),
+            any::<Settings>().prop_map(RecordedEvent::Settings),
+        ];
+        (any::<Settings>(), vec((millis(100), event), 0..=16))
+            .prop_map(|(settings, events)| {
+                let mut offset = Duration::ZERO;
+                let start = Record {
+                    offset,
+                    event: RecordedEvent::Settings(settings),
+                };
+                let records = std::iter::once(start)
+                    .chain(events.into_iter().map(|(gap, event)| {
+                        offset += gap;
+                        Record { offset, event }
+                    }))
+                    .collect();
+                Recording::new(records)
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::VirtualPort;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_arbitrary_configuration(
+            settings in any::<Settings>(),
+            conditions in any::<LinkConditions>(),
+            schedule in any::<FaultSchedule>(),
+        ) {
+            // The generated configuration is accepted by a port
+            let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+            port1.apply_settings(&settings);
+            port1.set_incoming_conditions(conditions.clone());
+            port1.set_fault_schedule(schedule);
+            prop_assert_eq!(port1.settings(), settings);
+            prop_assert_eq!(port2.outgoing_conditions(), conditions);
+        }
+
+        #[test]
+        fn test_arbitrary_recording(recording in any::<Recording>()) {
+            // The traffic pattern is ordered and survives saving
+            let records = recording.records();
+            prop_assert!(records.windows(2).all(|pair| pair[0].offset <= pair[1].offset));
+            let mut text = Vec::new();
+            recording.write_to(&mut text).unwrap();
+            prop_assert_eq!(Recording::read_from(&text[..]).unwrap(), recording);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Assertions on the traffic of the ports, for tests.
+
+use std::time::Duration;
+
+use crate::{
+    hexdump::{diff, hexdump},
+    TrafficDirection, VirtualPort,
+};
+
+impl VirtualPort {
+    /// Asserts that the peer received exactly `expected` from the port,
+    /// taking the data out of the receive buffer of the peer as a read
+    /// would. The data still in flight is waited for, up to the timeout of
+    /// the port.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data differs, showing a diff of the hexdumps, or if it
+    /// can't be taken (e.g., the link is down or a byte has a line error).
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// use virtual_serialport::VirtualPort;
+    ///
+    /// let (mut port1, _port2) = VirtualPort::pair(9600, 1024).unwrap();
+    /// port1.write_all(b"AT\r").unwrap();
+    /// port1.assert_transmitted(b"AT\r");
+    /// ```
+    #[track_caller]
+    pub fn assert_transmitted(&self, expected: &[u8]) {
+        let transmitted = match self.pipe.take_transmitted() {
+            Ok(transmitted) => transmitted,
+            Err(err) => panic!("failed to take the transmitted data: {}", err),
+        };
+        if transmitted != expected {
+            panic!(
+                "transmitted data differs from the expected one \
+                 (- expected: {} bytes, + transmitted: {} bytes)\n{}",
+                expected.len(),
+                transmitted.len(),
+                diff(expected, &transmitted)
+            );
+        }
+    }
+
+    /// Asserts that the port neither sends nor receives any data for
+    /// `duration`, waiting for it to pass.
+    ///
+    /// # Panics
+    ///
+    /// Panics as soon as data moves, showing a hexdump of it.
+    #[track_caller]
+    pub fn assert_idle(&self, duration: Duration) {
+        let traffic = self.monitor();
+        if let Ok(event) = traffic.recv_timeout(duration) {
+            let direction = match event.direction {
+                TrafficDirection::Sent => "sent",
+                TrafficDirection::Received => "received",
+            };
+            panic!(
+                "port wasn't idle: it {} {} bytes\n{}",
+                direction,
+                event.data.len(),
+                hexdump(&event.data).join("\n")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, thread};
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_assert_transmitted() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_simulate_delay(true);
+        port2.set_simulate_delay(true);
+        port1.write_all(b"hello").unwrap();
+        port1.assert_transmitted(b"hello");
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+        port2.assert_idle(Duration::from_millis(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "\
+        - 0000  68 65 6c 6c 6f                                   |hello|\n\
+        + 0000  68 65 6c 6c 6f 2c 20 77 6f 72 6c 64              |hello, world|")]
+    fn test_assert_transmitted_differs() {
+        let (mut port1, _port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.write_all(b"hello, world").unwrap();
+        port1.assert_transmitted(b"hello");
+    }
+
+    #[test]
+    #[should_panic(expected = "port wasn't idle: it received 2 bytes")]
+    fn test_assert_idle() {
+        let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            port1.write_all(b"ok").unwrap();
+        });
+        port2.assert_idle(Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,493 @@
+//! Asynchronous access to virtual ports (requires the `async` or `futures`
+//! feature).
+//!
+//! [`AsyncVirtualPort`] wraps a [`VirtualPort`] and implements the
+//! `tokio::io::AsyncRead`/`AsyncWrite` traits (`async` feature) and the
+//! runtime-agnostic `futures::io::AsyncRead`/`AsyncWrite` traits (`futures`
+//! feature). Pending reads and writes are woken up by the peer (or, with the
+//! transmission delay simulation, as the data arrives) instead of blocking a
+//! thread, so the port works with any runtime.
+//!
+//! Unlike blocking reads, an async read completes as soon as any data is
+//! available and does not use the port timeout (wrap the operation into the
+//! timeout facility of the runtime instead). The same applies to the
+//! [`readable`](AsyncVirtualPort::readable) and
+//! [`writable`](AsyncVirtualPort::writable) readiness futures.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use serialport::Result;
+
+use crate::VirtualPort;
+
+/// `AsyncVirtualPort` is an asynchronous wrapper around [`VirtualPort`].
+///
+/// The wrapped port remains fully configurable through [`get_ref`] and
+/// [`get_mut`], so all `SerialPort` settings can still be changed.
+///
+/// [`get_ref`]: AsyncVirtualPort::get_ref
+/// [`get_mut`]: AsyncVirtualPort::get_mut
+pub struct AsyncVirtualPort {
+    port: VirtualPort,
+}
+
+// A future completing once `poll` is ready (`std::future::poll_fn` requires a
+// newer compiler than the crate MSRV)
+pub(crate) struct PollFn<F>(pub(crate) F);
+
+impl<T, F> Future for PollFn<F>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T> + Unpin,
+{
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        (self.0)(cx)
+    }
+}
+
+impl AsyncVirtualPort {
+    /// Wraps a virtual port for asynchronous use.
+    pub fn new(port: VirtualPort) -> Self {
+        Self { port }
+    }
+
+    /// Opens a single loopback asynchronous port with the specified baud rate.
+    pub fn loopback(baud_rate: u32, buffer_capacity: usize) -> Result<Self> {
+        VirtualPort::loopback(baud_rate, buffer_capacity).map(Self::new)
+    }
+
+    /// Opens a pair of connected asynchronous ports with the specified baud rate.
+    pub fn pair(baud_rate: u32, buffer_capacity: usize) -> Result<(Self, Self)> {
+        let (port1, port2) = VirtualPort::pair(baud_rate, buffer_capacity)?;
+        Ok((Self::new(port1), Self::new(port2)))
+    }
+
+    /// Returns a reference to the wrapped port.
+    pub fn get_ref(&self) -> &VirtualPort {
+        &self.port
+    }
+
+    /// Returns a mutable reference to the wrapped port.
+    pub fn get_mut(&mut self) -> &mut VirtualPort {
+        &mut self.port
+    }
+
+    /// Unwraps the port.
+    pub fn into_inner(self) -> VirtualPort {
+        self.port
+    }
+
+    /// Waits until data is available for reading.
+    pub async fn readable(&self) -> io::Result<()> {
+        PollFn(|cx: &mut Context<'_>| self.port.pipe.poll_readable(cx).map(|_| Ok(()))).await
+    }
+
+    /// Waits until the receiving buffer has free space for writing.
+    pub async fn writable(&self) -> io::Result<()> {
+        PollFn(|cx: &mut Context<'_>| self.port.pipe.poll_writable(cx).map(Ok)).await
+    }
+}
+
+impl From<VirtualPort> for AsyncVirtualPort {
+    fn from(port: VirtualPort) -> Self {
+        Self::new(port)
+    }
+}
+
+impl AsyncVirtualPort {
+    // Reads the available data into `buf`, registering the task for wakeup if
+    // there is none
+    pub(crate) fn poll_read_into(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            let available = match self.port.pipe.poll_readable(cx) {
+                Poll::Ready(available) => available,
+                Poll::Pending => return Poll::Pending,
+            };
+            let len = available.min(buf.len());
+
+            // The data may be gone by now (the input buffer was cleared or a
+            // cloned port read it), which must not be reported as EOF. With
+            // nothing available, the peer is closed.
+            let bytes_read = match self.port.pipe.try_read(&mut buf[..len]) {
+                Ok(0) if len > 0 => continue,
+                Ok(bytes_read) => bytes_read,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+
+            if let Some(error) = self.port.receive_noise() {
+                if let Err(err) = self.port.damage(&mut buf[..bytes_read], error) {
+                    return Poll::Ready(Err(err));
+                }
+            }
+
+            return Poll::Ready(Ok(bytes_read));
+        }
+    }
+
+    // Completes once the data written has been transmitted, as a blocking
+    // flush does, registering the task for wakeup meanwhile
+    pub(crate) fn poll_flush_port(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.port.simulate_tx_delay() {
+            return Poll::Ready(Ok(()));
+        }
+        self.port.pipe.poll_transmitted(cx).map(Ok)
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncRead for AsyncVirtualPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.poll_read_into(cx, buf.initialize_unfilled())
+            .map_ok(|bytes_read| buf.advance(bytes_read))
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncWrite for AsyncVirtualPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.port.pipe.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_port(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_port(cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_io::AsyncRead for AsyncVirtualPort {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_read_into(cx, buf)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_io::AsyncWrite for AsyncVirtualPort {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.port.pipe.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_port(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush_port(cx)
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::TokioClock;
+
+    #[tokio::test]
+    async fn test_async_loopback() {
+        let mut port = AsyncVirtualPort::loopback(9600, 1024).unwrap();
+        let write_data = b"hello";
+        let mut read_data = [0u8; 5];
+
+        port.write_all(write_data).await.unwrap();
+        port.read_exact(&mut read_data).await.unwrap();
+        assert_eq!(&read_data, write_data);
+    }
+
+    #[tokio::test]
+    async fn test_async_pair_wakes_reader() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(9600, 1024).unwrap();
+
+        let reader = tokio::spawn(async move {
+            let mut read_data = [0u8; 5];
+            port2.read_exact(&mut read_data).await.unwrap();
+            read_data
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        port1.write_all(b"hello").await.unwrap();
+
+        assert_eq!(&reader.await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_async_write_waits_for_space() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(9600, 4).unwrap();
+
+        let writer = tokio::spawn(async move {
+            port1.write_all(b"abcdefgh").await.unwrap();
+        });
+
+        let mut read_data = [0u8; 8];
+        port2.read_exact(&mut read_data).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(&read_data, b"abcdefgh");
+    }
+
+    #[tokio::test]
+    async fn test_async_readiness() {
+        let (mut port1, port2) = AsyncVirtualPort::pair(9600, 2).unwrap();
+
+        let reader = tokio::spawn(async move {
+            port2.readable().await.unwrap();
+            port2
+        });
+
+        port1.writable().await.unwrap();
+        port1.write_all(b"ab").await.unwrap();
+        let mut port2 = reader.await.unwrap();
+
+        let writer = tokio::spawn(async move {
+            port1.writable().await.unwrap();
+        });
+
+        let mut read_data = [0u8; 2];
+        port2.read_exact(&mut read_data).await.unwrap();
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_read_after_input_cleared() {
+        let mut port = AsyncVirtualPort::loopback(300, 1024).unwrap();
+        port.get_mut().set_simulate_delay(true);
+        let mut clone = port.get_ref().clone();
+
+        port.write_all(b"lost").await.unwrap();
+        let reader = tokio::spawn(async move {
+            let mut read_data = [0u8; 4];
+            port.read_exact(&mut read_data).await.unwrap();
+            read_data
+        });
+
+        // Drop the data while the reader waits for it to arrive
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        serialport::SerialPort::clear(&clone, serialport::ClearBuffer::Input).unwrap();
+        std::io::Write::write_all(&mut clone, b"kept").unwrap();
+
+        assert_eq!(&reader.await.unwrap(), b"kept");
+    }
+
+    #[tokio::test]
+    async fn test_async_delay_simulation() {
+        let mut port = AsyncVirtualPort::loopback(50, 1024).unwrap();
+        port.get_mut().set_simulate_delay(true);
+
+        port.write_all(b"hello").await.unwrap();
+
+        let mut read_data = [0u8; 5];
+        let start = Instant::now();
+        port.read_exact(&mut read_data).await.unwrap();
+
+        assert_eq!(&read_data, b"hello");
+        assert!(start.elapsed().as_millis() > 700);
+    }
+
+    #[tokio::test]
+    async fn test_async_flush_waits_for_transmission() {
+        let (mut port1, _port2) = AsyncVirtualPort::pair(1200, 1024).unwrap();
+        port1.get_mut().set_simulate_tx_delay(true);
+
+        // 60 bytes take half a second at 1200 baud
+        let start = Instant::now();
+        port1.write_all(&[0x55; 60]).await.unwrap();
+        assert!(serialport::SerialPort::bytes_to_write(port1.get_ref()).unwrap() > 0);
+        port1.flush().await.unwrap();
+        assert_eq!(
+            serialport::SerialPort::bytes_to_write(port1.get_ref()).unwrap(),
+            0
+        );
+        assert!(start.elapsed().as_millis() > 400);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_polls_share_a_timer() {
+        let mut port = AsyncVirtualPort::loopback(50, 1024).unwrap();
+        port.get_mut().set_clock(Arc::new(TokioClock));
+        port.get_mut().set_simulate_delay(true);
+        port.write_all(b"hello").await.unwrap();
+
+        // A read polled again and again while the data is in flight waits
+        // with a single timer of the runtime
+        let mut read_data = [0u8; 5];
+        PollFn(|cx: &mut Context<'_>| {
+            for _ in 0..100 {
+                assert!(port.poll_read_into(cx, &mut read_data).is_pending());
+            }
+            Poll::Ready(())
+        })
+        .await;
+        let metrics = tokio::runtime::Handle::current().metrics();
+        assert_eq!(metrics.num_alive_tasks(), 1);
+
+        port.read_exact(&mut read_data).await.unwrap();
+        assert_eq!(&read_data, b"hello");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_async_paused_time() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(50, 1024).unwrap();
+        port1.get_mut().set_clock(Arc::new(TokioClock));
+        port2.get_mut().set_simulate_delay(true);
+        port2.get_mut().set_link_latency(Duration::from_secs(10));
+
+        // About 11 seconds pass in the virtual time of tokio only
+        let start = Instant::now();
+        let virtual_start = tokio::time::Instant::now();
+        port1.write_all(b"hello").await.unwrap();
+        let mut read_data = [0u8; 5];
+        port2.read_exact(&mut read_data).await.unwrap();
+
+        assert_eq!(&read_data, b"hello");
+        assert!(virtual_start.elapsed() > Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[cfg(all(test, feature = "futures"))]
+mod futures_tests {
+    use std::time::Instant;
+
+    use futures::{
+        executor::block_on,
+        io::{AsyncReadExt, AsyncWriteExt},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_futures_pair() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(9600, 1024).unwrap();
+
+        block_on(async {
+            let mut read_data = [0u8; 5];
+            port1.write_all(b"hello").await.unwrap();
+            port2.read_exact(&mut read_data).await.unwrap();
+            assert_eq!(&read_data, b"hello");
+        });
+    }
+
+    #[test]
+    fn test_futures_reader_is_woken() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(9600, 1024).unwrap();
+
+        let reader = std::thread::spawn(move || {
+            block_on(async {
+                let mut read_data = [0u8; 5];
+                port2.read_exact(&mut read_data).await.unwrap();
+                read_data
+            })
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        block_on(port1.write_all(b"hello")).unwrap();
+
+        assert_eq!(&reader.join().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_futures_delay_simulation() {
+        let mut port = AsyncVirtualPort::loopback(50, 1024).unwrap();
+        port.get_mut().set_simulate_delay(true);
+
+        block_on(async {
+            port.write_all(b"hello").await.unwrap();
+
+            let mut read_data = [0u8; 5];
+            let start = Instant::now();
+            port.read_exact(&mut read_data).await.unwrap();
+
+            assert_eq!(&read_data, b"hello");
+            assert!(start.elapsed().as_millis() > 700);
+        });
+    }
+}
+
+#[cfg(all(test, feature = "async-std"))]
+mod async_std_tests {
+    use std::time::{Duration, Instant};
+
+    use async_std::{
+        io::{self, ReadExt, WriteExt},
+        task,
+    };
+
+    use super::*;
+
+    #[async_std::test]
+    async fn test_async_std_pair() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(9600, 1024).unwrap();
+
+        let reader = task::spawn(async move {
+            let mut read_data = [0u8; 5];
+            port2.read_exact(&mut read_data).await.unwrap();
+            read_data
+        });
+
+        task::sleep(Duration::from_millis(20)).await;
+        port1.write_all(b"hello").await.unwrap();
+
+        assert_eq!(&reader.await, b"hello");
+    }
+
+    #[async_std::test]
+    async fn test_async_std_timeout() {
+        let mut port = AsyncVirtualPort::loopback(9600, 1024).unwrap();
+        let mut read_data = [0u8; 5];
+
+        let result = io::timeout(Duration::from_millis(50), port.read(&mut read_data)).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[async_std::test]
+    async fn test_async_std_delay_simulation() {
+        let mut port = AsyncVirtualPort::loopback(50, 1024).unwrap();
+        port.get_mut().set_simulate_delay(true);
+
+        port.write_all(b"hello").await.unwrap();
+
+        let mut read_data = [0u8; 5];
+        let start = Instant::now();
+        port.read_exact(&mut read_data).await.unwrap();
+
+        assert_eq!(&read_data, b"hello");
+        assert!(start.elapsed().as_millis() > 700);
+    }
+}
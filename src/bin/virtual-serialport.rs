@@ -0,0 +1,262 @@
+//! Command line companion of the `virtual-serialport` crate.
+//!
+//! Exposes virtual ports through pseudo-terminals, so programs that open
+//! serial ports by path can be connected to each other, to network clients,
+//! or to a replayed capture.
+
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    net::TcpListener,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use virtual_serialport::{Pty, Settings, VirtualPort};
+
+const USAGE: &str = "\
+Usage: virtual-serialport <COMMAND> [OPTIONS]
+
+Commands:
+  pair              Create two linked pseudo-terminals
+  tcp <ADDR>        Create a pseudo-terminal connected to raw TCP clients of ADDR
+  rfc2217 <ADDR>    Create a pseudo-terminal connected to RFC 2217 clients of ADDR
+  replay <FILE>     Create a pseudo-terminal receiving the contents of FILE
+
+Options:
+  --baud <RATE>     Line settings of the simulated link, as a baud rate or a
+                    shorthand like 115200,8N1 [default: 9600]
+  --loop            Replay the file repeatedly
+  -h, --help        Print this help
+
+The command runs until it is interrupted.";
+
+// How often the liveness of the links is checked
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Size of the buffers of the simulated link
+const BUFFER_CAPACITY: usize = 4096;
+
+struct Options {
+    command: String,
+    argument: Option<String>,
+    settings: Settings,
+    repeat: bool,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match (options.command.as_str(), options.argument.as_deref()) {
+        ("pair", None) => pair(&options),
+        ("tcp", Some(address)) => tcp(&options, address),
+        ("rfc2217", Some(address)) => rfc2217(&options, address),
+        ("replay", Some(path)) => replay(&options, path),
+        _ => unreachable!("the arguments are checked by parse_args"),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        command: String::new(),
+        argument: None,
+        settings: Settings::new(9600),
+        repeat: false,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--baud" => {
+                let value = args.next().ok_or("--baud requires a value")?;
+                options.settings = value.parse().map_err(|err| format!("{}", err))?;
+            }
+            "--loop" => options.repeat = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ if options.command.is_empty() => options.command = arg,
+            _ if options.argument.is_none() => options.argument = Some(arg),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    match (options.command.as_str(), options.argument.is_some()) {
+        ("", _) => Err("no command given".into()),
+        ("pair", false) | ("tcp" | "rfc2217" | "replay", true) => Ok(options),
+        ("pair", true) => Err(format!("{} takes no argument", options.command)),
+        ("tcp" | "rfc2217" | "replay", false) => {
+            Err(format!("{} requires an argument", options.command))
+        }
+        (command, _) => Err(format!("unknown command: {}", command)),
+    }
+}
+
+// Opens a pair of ports with the line settings of the options
+fn open_pair(options: &Options) -> io::Result<(VirtualPort, VirtualPort)> {
+    let (mut port1, mut port2) = VirtualPort::pair(options.settings.baud_rate, BUFFER_CAPACITY)?;
+    port1.apply_settings(&options.settings);
+    port2.apply_settings(&options.settings);
+    Ok((port1, port2))
+}
+
+fn open_pty(port: VirtualPort) -> io::Result<Pty> {
+    let pty = port.open_pty()?;
+    println!("{}", pty.path().display());
+    Ok(pty)
+}
+
+// Waits until one of the pseudo-terminals stops working
+fn wait(ptys: &[&Pty]) -> io::Result<()> {
+    while ptys.iter().all(|pty| pty.is_running()) {
+        thread::sleep(CHECK_INTERVAL);
+    }
+    Err(io::Error::new(io::ErrorKind::Other, "link stopped"))
+}
+
+fn pair(options: &Options) -> io::Result<()> {
+    let (port1, port2) = open_pair(options)?;
+    let pty1 = open_pty(port1)?;
+    let pty2 = open_pty(port2)?;
+    wait(&[&pty1, &pty2])
+}
+
+fn tcp(options: &Options, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (port, remote) = open_pair(options)?;
+    let pty = open_pty(port)?;
+
+    // Clients are served one at a time, until they disconnect
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let connected = Arc::new(AtomicBool::new(true));
+        let reader = Disconnect {
+            inner: stream.try_clone()?,
+            connected: connected.clone(),
+        };
+        let bridge = remote.clone().bridge_to_io(reader, stream)?;
+
+        while connected.load(Ordering::Relaxed) && bridge.is_running() && pty.is_running() {
+            thread::sleep(CHECK_INTERVAL);
+        }
+        if !pty.is_running() {
+            break;
+        }
+    }
+    wait(&[&pty])
+}
+
+fn rfc2217(options: &Options, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (port, remote) = open_pair(options)?;
+    let pty = open_pty(port)?;
+    let server = remote.serve_rfc2217(listener)?;
+
+    while server.is_running() && pty.is_running() {
+        thread::sleep(CHECK_INTERVAL);
+    }
+    server.stop()?;
+    wait(&[&pty])
+}
+
+fn replay(options: &Options, path: &str) -> io::Result<()> {
+    let data = fs::read(path)?;
+    let (port, mut feed) = open_pair(options)?;
+    let pty = open_pty(port)?;
+
+    // The data drains from the feed at the pace of the line settings
+    feed.set_simulate_tx_delay(true);
+
+    loop {
+        let mut remaining = &data[..];
+        while !remaining.is_empty() {
+            match feed.write(remaining) {
+                Ok(len) => remaining = &remaining[len..],
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    feed.wait_writable(Some(CHECK_INTERVAL)).ok();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        if !options.repeat {
+            break;
+        }
+    }
+    wait(&[&pty])
+}
+
+// Reader clearing the `connected` flag once the client disconnects
+struct Disconnect<R> {
+    inner: R,
+    connected: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for Disconnect<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        if !matches!(result, Ok(len) if len > 0) {
+            self.connected.store(false, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialport::{DataBits, Parity};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse(&["replay", "capture.bin", "--baud", "115200,7E2", "--loop"]).unwrap();
+        assert_eq!(options.command, "replay");
+        assert_eq!(options.argument.as_deref(), Some("capture.bin"));
+        assert_eq!(options.settings.baud_rate, 115_200);
+        assert_eq!(options.settings.data_bits, DataBits::Seven);
+        assert_eq!(options.settings.parity, Parity::Even);
+        assert!(options.repeat);
+
+        let options = parse(&["pair"]).unwrap();
+        assert_eq!(options.settings, Settings::new(9600));
+        assert!(!options.repeat);
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        let error = |args: &[&str]| parse(args).err().unwrap();
+        assert_eq!(error(&["pair", "--bogus"]), "unknown option: --bogus");
+        assert_eq!(error(&["pair", "--baud"]), "--baud requires a value");
+        assert!(parse(&["pair", "--baud", "fast"]).is_err());
+        assert_eq!(error(&[]), "no command given");
+        assert_eq!(error(&["pair", "extra"]), "pair takes no argument");
+        assert_eq!(error(&["tcp"]), "tcp requires an argument");
+        assert_eq!(
+            error(&["replay", "a.bin", "b.bin"]),
+            "unexpected argument: b.bin"
+        );
+        assert_eq!(error(&["bogus"]), "unknown command: bogus");
+    }
+}
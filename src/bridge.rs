@@ -0,0 +1,389 @@
+//! Bridging a virtual port to another serial port.
+//!
+//! A [`Bridge`] pumps data between a [`VirtualPort`] and another (typically
+//! physical) port in background threads until it is stopped or dropped. This
+//! places the simulator into a live hardware link: the peer of the virtual
+//! port talks to the real device, and the data crossing the bridge can be
+//! captured or altered with a tap (see [`VirtualPort::bridge_to_with`]).
+
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serialport::{Result, SerialPort};
+
+use crate::VirtualPort;
+
+mod rfc2217;
+mod stdio;
+mod udp;
+
+#[cfg(feature = "websocket")]
+mod websocket;
+
+#[cfg(all(feature = "named-pipe", windows))]
+mod named_pipe;
+
+#[cfg(unix)]
+mod remote;
+
+#[cfg(all(feature = "pty", unix))]
+mod pty;
+
+#[cfg(all(feature = "pty", unix))]
+pub use pty::Pty;
+
+// How long a pump blocks before checking whether the bridge is stopped
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Size of the intermediate buffer of a pump
+pub(crate) const CHUNK_SIZE: usize = 256;
+
+/// Direction of the data crossing a bridge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the peer of the virtual port to the bridged port.
+    Outgoing,
+
+    /// From the bridged port to the peer of the virtual port.
+    Incoming,
+}
+
+/// A running bridge between a virtual port and another serial port.
+///
+/// If either direction fails, the whole bridge stops, e.g., with `BrokenPipe`
+/// once the peer of the virtual port is closed. Dropping the bridge
+/// stops it as well, but [`stop`](Bridge::stop) also reports the error that
+/// terminated it, if any.
+pub struct Bridge {
+    running: Arc<AtomicBool>,
+    pumps: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl Bridge {
+    pub(crate) fn new() -> Self {
+        Self {
+            running: Arc::new(AtomicBool::new(true)),
+            pumps: Vec::new(),
+        }
+    }
+
+    // Spawns a thread calling `step` until the bridge is stopped or the step
+    // fails (which stops the other pumps too). A step must not block longer
+    // than `POLL_INTERVAL`.
+    pub(crate) fn spawn<F>(&mut self, mut step: F)
+    where
+        F: FnMut(&AtomicBool) -> io::Result<()> + Send + 'static,
+    {
+        let running = self.running.clone();
+        self.pumps.push(thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                if let Err(err) = step(&running) {
+                    running.store(false, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+            Ok(())
+        }));
+    }
+
+    /// Returns whether the bridge is still forwarding data.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Stops the bridge and waits for the pumps to finish, returning the
+    /// error that terminated the bridge, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    /// Waits for the pumps to finish on their own (once a step fails or
+    /// stops the bridge), returning the error that terminated the bridge, if
+    /// any.
+    pub(crate) fn join(mut self) -> io::Result<()> {
+        self.join_pumps()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.running.store(false, Ordering::Relaxed);
+        self.join_pumps()
+    }
+
+    fn join_pumps(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for pump in self.pumps.drain(..) {
+            let pump_result = pump.join().unwrap_or_else(|_| {
+                Err(io::Error::new(io::ErrorKind::Other, "bridge pump panicked"))
+            });
+            if result.is_ok() {
+                result = pump_result;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+impl VirtualPort {
+    /// Connects the port to another (typically physical) serial port.
+    ///
+    /// Everything the peer of this virtual port writes is forwarded to
+    /// `port`, and everything received from `port` is delivered to the peer.
+    /// The timeout of `port` is changed to a short polling interval.
+    pub fn bridge_to(self, port: Box<dyn SerialPort>) -> Result<Bridge> {
+        self.bridge_to_with(port, |_, _| {})
+    }
+
+    /// Connects the port to another serial port like
+    /// [`bridge_to`](VirtualPort::bridge_to), passing every chunk of data
+    /// crossing the bridge through `tap` first.
+    ///
+    /// The tap may record the data (capture) or modify, extend or clear the
+    /// chunk (fault injection) before it is forwarded.
+    pub fn bridge_to_with<F>(self, mut port: Box<dyn SerialPort>, tap: F) -> Result<Bridge>
+    where
+        F: FnMut(Direction, &mut Vec<u8>) + Send + 'static,
+    {
+        port.set_timeout(POLL_INTERVAL)?;
+        let mut port_writer = port.try_clone()?;
+        let mut port_reader = port;
+
+        let tap = Arc::new(Mutex::new(tap));
+        let mut bridge = Bridge::new();
+
+        // Peer of the virtual port -> bridged port
+        let mut virtual_reader = self.clone();
+        let outgoing_tap = tap.clone();
+        bridge.spawn(move |running| {
+            let mut buf = [0u8; CHUNK_SIZE];
+            let len = receive_ready(&mut virtual_reader, &mut buf)?;
+            if len == 0 {
+                return Ok(());
+            }
+
+            let mut chunk = buf[..len].to_vec();
+            (outgoing_tap.lock().unwrap())(Direction::Outgoing, &mut chunk);
+
+            // The bridged port may refuse data while its output buffer is full
+            let mut data = &chunk[..];
+            while !data.is_empty() && running.load(Ordering::Relaxed) {
+                match port_writer.write(data) {
+                    Ok(len) => data = &data[len..],
+                    Err(err) if is_retryable(&err) => thread::sleep(POLL_INTERVAL),
+                    Err(err) => return Err(err),
+                }
+            }
+            port_writer.flush()
+        });
+
+        // Bridged port -> peer of the virtual port
+        let mut virtual_writer = self;
+        bridge.spawn(move |running| {
+            // Read what has arrived, or wait for a single byte, as the read
+            // only returns once the whole buffer is filled
+            let available = port_reader.bytes_to_read()? as usize;
+            let mut buf = [0u8; CHUNK_SIZE];
+            let buf = &mut buf[..available.clamp(1, CHUNK_SIZE)];
+
+            let len = match port_reader.read(buf) {
+                Ok(len) => len,
+                Err(err) if is_retryable(&err) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            if len == 0 {
+                return Ok(());
+            }
+
+            let mut chunk = buf[..len].to_vec();
+            (tap.lock().unwrap())(Direction::Incoming, &mut chunk);
+            send_all(&mut virtual_writer, &chunk, running)
+        });
+
+        Ok(bridge)
+    }
+}
+
+// Reads the data available on the virtual port, waiting for it at most
+// `POLL_INTERVAL` (zero is returned if nothing arrives). Fails with
+// `BrokenPipe` once the peer is closed, ending the pump.
+pub(crate) fn receive_ready(port: &mut VirtualPort, buf: &mut [u8]) -> io::Result<usize> {
+    match port.wait_readable(Some(POLL_INTERVAL)) {
+        Ok(()) => {}
+        Err(err) if is_retryable(&err) => return Ok(0),
+        Err(err) => return Err(err),
+    }
+
+    match port.try_read(buf) {
+        Err(err) if is_retryable(&err) => Ok(0),
+        // The peer is closed, so there is nothing to wait for
+        Ok(0) if !buf.is_empty() => Err(hangup()),
+        result => result,
+    }
+}
+
+// Error ending a pump once the peer of the virtual port is closed and its
+// data has been read
+pub(crate) fn hangup() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "peer port is closed")
+}
+
+// Writes the whole buffer into the virtual port, waiting for free space while
+// the bridge is running
+pub(crate) fn send_all(
+    port: &mut VirtualPort,
+    mut buf: &[u8],
+    running: &AtomicBool,
+) -> io::Result<()> {
+    while !buf.is_empty() && running.load(Ordering::Relaxed) {
+        match port.write(buf) {
+            Ok(len) => buf = &buf[len..],
+            Err(err) if is_retryable(&err) => match port.wait_writable(Some(POLL_INTERVAL)) {
+                Err(err) if !is_retryable(&err) => return Err(err),
+                _ => {}
+            },
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn is_retryable(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn test_bridge_to_port() {
+        // The physical link is simulated by another pair of virtual ports
+        let (mut app, proxy) = VirtualPort::pair(9600, 1024).unwrap();
+        let (device_side, mut device) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(TIMEOUT).unwrap();
+        device.set_timeout(TIMEOUT).unwrap();
+
+        let bridge = proxy.bridge_to(device_side.into_boxed()).unwrap();
+        assert!(bridge.is_running());
+
+        let mut buf = [0u8; 5];
+        app.write_all(b"hello").unwrap();
+        device.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        device.write_all(b"world").unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+
+        bridge.stop().unwrap();
+    }
+
+    #[test]
+    fn test_bridge_join() {
+        // The bridge ends once a pump fails, stopping the other ones
+        let mut bridge = Bridge::new();
+        let mut steps = 0;
+        bridge.spawn(move |_| {
+            steps += 1;
+            if steps == 3 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            Ok(())
+        });
+        bridge.spawn(|_| {
+            thread::sleep(Duration::from_millis(1));
+            Ok(())
+        });
+        let err = bridge.join().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_bridge_ends_on_hangup() {
+        let (app, proxy) = VirtualPort::pair(9600, 1024).unwrap();
+        let (device_side, _device) = VirtualPort::pair(9600, 1024).unwrap();
+        let bridge = proxy.bridge_to(device_side.into_boxed()).unwrap();
+
+        drop(app);
+        let err = bridge.join().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_bridge_forwards_large_transfers() {
+        let (mut app, proxy) = VirtualPort::pair(9600, 64).unwrap();
+        let (device_side, mut device) = VirtualPort::pair(9600, 64).unwrap();
+
+        let _bridge = proxy.bridge_to(device_side.into_boxed()).unwrap();
+
+        // More data than both buffers can hold at once
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buf = [0u8; 64];
+            while received.len() < 1000 {
+                device.wait_readable(Some(TIMEOUT)).unwrap();
+                let len = device.try_read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..len]);
+            }
+            received
+        });
+
+        let mut sent = &data[..];
+        while !sent.is_empty() {
+            match app.write(sent) {
+                Ok(len) => sent = &sent[len..],
+                Err(_) => app.wait_writable(Some(TIMEOUT)).unwrap(),
+            }
+        }
+
+        assert_eq!(reader.join().unwrap(), data);
+    }
+
+    #[test]
+    fn test_bridge_tap() {
+        let (mut app, proxy) = VirtualPort::pair(9600, 1024).unwrap();
+        let (device_side, mut device) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(TIMEOUT).unwrap();
+        device.set_timeout(TIMEOUT).unwrap();
+
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let log = captured.clone();
+        let _bridge = proxy
+            .bridge_to_with(device_side.into_boxed(), move |direction, data| {
+                log.lock().unwrap().push((direction, data.clone()));
+                if direction == Direction::Incoming {
+                    data.make_ascii_uppercase();
+                }
+            })
+            .unwrap();
+
+        let mut buf = [0u8; 2];
+        app.write_all(b"hi").unwrap();
+        device.read_exact(&mut buf).unwrap();
+        device.write_all(b"ok").unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"OK");
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured[0], (Direction::Outgoing, b"hi".to_vec()));
+        assert_eq!(captured.last().unwrap().0, Direction::Incoming);
+    }
+}
@@ -0,0 +1,240 @@
+//! Named pipe backing for virtual ports (Windows only).
+
+use std::{
+    io, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use serialport::Result;
+use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, GetLastError, ERROR_BROKEN_PIPE, ERROR_NO_DATA, ERROR_PIPE_CONNECTED,
+        ERROR_PIPE_LISTENING, HANDLE, INVALID_HANDLE_VALUE,
+    },
+    Storage::FileSystem::{ReadFile, WriteFile, PIPE_ACCESS_DUPLEX},
+    System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_NOWAIT, PIPE_READMODE_BYTE,
+        PIPE_TYPE_BYTE,
+    },
+};
+
+use super::{hangup, is_retryable, send_all, Bridge, CHUNK_SIZE, POLL_INTERVAL};
+use crate::VirtualPort;
+
+// How long a connected pipe waits for data before polling the client again
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+impl VirtualPort {
+    /// Exposes the port as the named pipe `\\.\pipe\vserial-<name>`.
+    ///
+    /// A client opening the pipe (e.g., with `CreateFile`) exchanges data
+    /// with the peer of this port. Clients are served one at a time, and the
+    /// pipe exists until the returned bridge is stopped or dropped.
+    pub fn serve_named_pipe(self, name: &str) -> Result<Bridge> {
+        let pipe = NamedPipe::create(name)?;
+        let mut port = self;
+        let mut connected = false;
+
+        let mut bridge = Bridge::new();
+        bridge.spawn(move |running| {
+            if !connected {
+                connected = pipe.connect()?;
+                if !connected {
+                    thread::sleep(POLL_INTERVAL);
+                }
+                return Ok(());
+            }
+
+            let mut buf = [0u8; CHUNK_SIZE];
+            let mut idle = true;
+
+            // Client -> port
+            match pipe.read(&mut buf) {
+                Ok(0) => {}
+                Ok(len) => {
+                    idle = false;
+                    send_all(&mut port, &buf[..len], running)?;
+                }
+                Err(err) if err.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => {
+                    connected = false;
+                    return pipe.disconnect();
+                }
+                Err(err) => return Err(err),
+            }
+
+            // Port -> client
+            match port.try_read(&mut buf) {
+                // The peer is closed, so the server has nothing left to do
+                Ok(0) => return Err(hangup()),
+                Ok(len) => {
+                    idle = false;
+                    if !pipe.write_all(&buf[..len], running)? {
+                        connected = false;
+                        return pipe.disconnect();
+                    }
+                }
+                Err(err) if is_retryable(&err) => {}
+                Err(err) => return Err(err),
+            }
+
+            if idle {
+                match port.wait_readable(Some(IDLE_WAIT)) {
+                    Err(err) if !is_retryable(&err) => return Err(err),
+                    _ => {}
+                }
+            }
+            Ok(())
+        });
+
+        Ok(bridge)
+    }
+}
+
+// Server end of a named pipe in non-blocking mode
+struct NamedPipe(HANDLE);
+
+impl NamedPipe {
+    fn create(name: &str) -> io::Result<Self> {
+        let path: Vec<u16> = format!(r"\\.\pipe\vserial-{}", name)
+            .encode_utf16()
+            .chain(Some(0))
+            .collect();
+
+        let buffer_size = CHUNK_SIZE as u32;
+        let handle = unsafe {
+            CreateNamedPipeW(
+                path.as_ptr(),
+                PIPE_ACCESS_DUPLEX,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT,
+                1,
+                buffer_size,
+                buffer_size,
+                0,
+                ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+
+    // Checks for a client without waiting, returning whether one is connected
+    fn connect(&self) -> io::Result<bool> {
+        if unsafe { ConnectNamedPipe(self.0, ptr::null_mut()) } != 0 {
+            return Ok(true);
+        }
+
+        match unsafe { GetLastError() } {
+            ERROR_PIPE_CONNECTED => Ok(true),
+            ERROR_PIPE_LISTENING => Ok(false),
+            // A client connected and closed the pipe before it was served
+            ERROR_NO_DATA => self.disconnect().map(|_| false),
+            code => Err(io::Error::from_raw_os_error(code as i32)),
+        }
+    }
+
+    fn disconnect(&self) -> io::Result<()> {
+        if unsafe { DisconnectNamedPipe(self.0) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Reads the data sent by the client, returning zero if there is none
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = 0;
+        let ok = unsafe {
+            ReadFile(
+                self.0,
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                &mut len,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return match unsafe { GetLastError() } {
+                ERROR_NO_DATA => Ok(0),
+                code => Err(io::Error::from_raw_os_error(code as i32)),
+            };
+        }
+        Ok(len as usize)
+    }
+
+    // Writes the whole buffer while the bridge is running, returning `false`
+    // if the client has disconnected
+    fn write_all(&self, mut buf: &[u8], running: &AtomicBool) -> io::Result<bool> {
+        while !buf.is_empty() && running.load(Ordering::Relaxed) {
+            let mut len = 0;
+            let ok = unsafe {
+                WriteFile(
+                    self.0,
+                    buf.as_ptr(),
+                    buf.len() as u32,
+                    &mut len,
+                    ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                return match unsafe { GetLastError() } {
+                    ERROR_BROKEN_PIPE | ERROR_NO_DATA => Ok(false),
+                    code => Err(io::Error::from_raw_os_error(code as i32)),
+                };
+            }
+
+            // A non-blocking pipe accepts nothing while its buffer is full
+            if len == 0 {
+                thread::sleep(IDLE_WAIT);
+            }
+            buf = &buf[len as usize..];
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for NamedPipe {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Write},
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_named_pipe_round_trip() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+
+        let bridge = port.serve_named_pipe("test-round-trip").unwrap();
+        let mut client = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(r"\\.\pipe\vserial-test-round-trip")
+            .unwrap();
+
+        let mut buf = [0u8; 5];
+        client.write_all(b"hello").unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        app.write_all(b"world").unwrap();
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+
+        bridge.stop().unwrap();
+    }
+}
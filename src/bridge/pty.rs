@@ -0,0 +1,102 @@
+//! Pseudo-terminal backing for virtual ports (Unix only).
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serialport::{Result, SerialPort, TTYPort};
+
+use super::Bridge;
+use crate::VirtualPort;
+
+/// A pseudo-terminal connected to a virtual port.
+///
+/// Programs opening the terminal at [`path`](Pty::path) (e.g., `minicom` or
+/// a pyserial script) talk to the peer of the virtual port. The terminal is
+/// removed once the `Pty` is stopped or dropped.
+pub struct Pty {
+    bridge: Bridge,
+    path: PathBuf,
+
+    // Keeps the terminal open between the connections of other programs, so
+    // the master side never reports a hang-up
+    _slave: TTYPort,
+}
+
+impl Pty {
+    /// Returns the path of the terminal device (e.g., `/dev/pts/3`).
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns whether data is still pumped between the terminal and the
+    /// virtual port.
+    pub fn is_running(&self) -> bool {
+        self.bridge.is_running()
+    }
+
+    /// Stops pumping data and closes the terminal, returning the error that
+    /// terminated the pumps, if any.
+    pub fn stop(self) -> io::Result<()> {
+        self.bridge.stop()
+    }
+}
+
+impl VirtualPort {
+    /// Creates a pseudo-terminal pumped to and from the port, so that
+    /// external processes can connect to the simulation through it.
+    ///
+    /// The terminal is in raw mode; its line settings are not reflected on
+    /// the virtual port.
+    pub fn open_pty(self) -> Result<Pty> {
+        let (master, slave) = TTYPort::pair()?;
+        let path = slave
+            .name()
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "pty has no name"))?;
+
+        Ok(Pty {
+            bridge: self.bridge_to(Box::new(master))?,
+            path,
+            _slave: slave,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_pty_round_trip() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+
+        let pty = port.open_pty().unwrap();
+        assert!(pty.path().exists());
+
+        let mut terminal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(pty.path())
+            .unwrap();
+
+        let mut buf = [0u8; 5];
+        terminal.write_all(b"hello").unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        app.write_all(b"world").unwrap();
+        terminal.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+
+        pty.stop().unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+//! Virtual port pairs spanning two processes (Unix only).
+//!
+//! Each process holds an ordinary [`VirtualPort`], whose data and control
+//! lines are carried to the other process over a Unix domain socket. The
+//! socket transports frames of a type byte, a big-endian 16-bit length and
+//! the payload: data frames carry the bytes written by a port, and control
+//! frames carry its RTS and DTR lines, which drive CTS and DSR/CD on the
+//! other side just like in a local pair.
+
+use std::{
+    io::{self, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::Arc,
+};
+
+use serialport::{Result, SerialPort};
+
+use super::{is_retryable, receive_ready, send_all, Bridge, CHUNK_SIZE, POLL_INTERVAL};
+use crate::VirtualPort;
+
+const DATA_FRAME: u8 = 0;
+const CONTROL_FRAME: u8 = 1;
+
+// Bits of the payload of a control frame
+const RTS: u8 = 0x01;
+const DTR: u8 = 0x02;
+
+impl VirtualPort {
+    /// Accepts the other end of a cross-process pair on `listener`.
+    ///
+    /// Blocks until a process connects with
+    /// [`connect_remote`](VirtualPort::connect_remote), and returns the
+    /// local end of the pair. The link is closed once the returned port and
+    /// all its clones are dropped.
+    pub fn accept_remote(
+        listener: &UnixListener,
+        baud_rate: u32,
+        buffer_capacity: usize,
+    ) -> Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::remote(stream, baud_rate, buffer_capacity)
+    }
+
+    /// Connects to the other end of a cross-process pair, which is accepted
+    /// by another process with [`accept_remote`](VirtualPort::accept_remote)
+    /// on a listener bound to `path`.
+    pub fn connect_remote<P>(path: P, baud_rate: u32, buffer_capacity: usize) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let stream = UnixStream::connect(path)?;
+        Self::remote(stream, baud_rate, buffer_capacity)
+    }
+
+    // Creates a local pair whose second port is linked to the other process
+    fn remote(stream: UnixStream, baud_rate: u32, buffer_capacity: usize) -> Result<Self> {
+        let (mut port, proxy) = Self::pair(baud_rate, buffer_capacity)?;
+
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = stream;
+        let mut bridge = Bridge::new();
+
+        // Local port -> other process
+        let mut proxy_reader = proxy.clone();
+        let mut lines = None;
+        bridge.spawn(move |_| {
+            let mut buf = [0u8; CHUNK_SIZE];
+            let len = receive_ready(&mut proxy_reader, &mut buf)?;
+            if len > 0 {
+                write_frame(&mut writer, DATA_FRAME, &buf[..len])?;
+            }
+
+            // The lines driven by the local port are seen by the proxy
+            let mut state = 0;
+            if *proxy_reader.cts.lock().unwrap() {
+                state |= RTS;
+            }
+            if *proxy_reader.dsr_cd.lock().unwrap() {
+                state |= DTR;
+            }
+            if lines != Some(state) {
+                write_frame(&mut writer, CONTROL_FRAME, &[state])?;
+                lines = Some(state);
+            }
+            Ok(())
+        });
+
+        // Other process -> local port
+        let mut proxy_writer = proxy;
+        let mut pending = Vec::new();
+        bridge.spawn(move |running| {
+            let mut buf = [0u8; CHUNK_SIZE];
+            match reader.read(&mut buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "remote port disconnected",
+                    ))
+                }
+                Ok(len) => pending.extend_from_slice(&buf[..len]),
+                Err(err) if is_retryable(&err) => return Ok(()),
+                Err(err) => return Err(err),
+            }
+
+            while let Some((kind, payload_len)) = frame_header(&pending) {
+                let payload = pending[3..3 + payload_len].to_vec();
+                pending.drain(..3 + payload_len);
+
+                match (kind, payload.first()) {
+                    (DATA_FRAME, _) => send_all(&mut proxy_writer, &payload, running)?,
+                    (CONTROL_FRAME, Some(&state)) => {
+                        proxy_writer.write_request_to_send(state & RTS != 0)?;
+                        proxy_writer.write_data_terminal_ready(state & DTR != 0)?;
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        });
+
+        port.link = Some(Arc::new(bridge));
+        Ok(port)
+    }
+}
+
+fn write_frame(stream: &mut UnixStream, kind: u8, payload: &[u8]) -> io::Result<()> {
+    // Chunks never exceed the 16-bit length
+    let len = payload.len() as u16;
+    let mut frame = vec![kind];
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+// Returns the type and payload length of the first frame in `data` if the
+// frame is complete
+fn frame_header(data: &[u8]) -> Option<(u8, usize)> {
+    match *data {
+        [kind, high, low, ..] => {
+            let len = u16::from_be_bytes([high, low]) as usize;
+            (data.len() >= 3 + len).then(|| (kind, len))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn test_cross_process_pair() {
+        let path = std::env::temp_dir().join(format!("vserial-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // The other process is simulated by a thread
+        let simulator = thread::spawn(move || {
+            let mut device = VirtualPort::accept_remote(&listener, 9600, 1024).unwrap();
+            device.set_timeout(TIMEOUT).unwrap();
+
+            let mut buf = [0u8; 4];
+            device.read_exact(&mut buf).unwrap();
+            device.write_all(b"pong").unwrap();
+
+            // Wait until the application drops RTS
+            let deadline = Instant::now() + TIMEOUT;
+            while device.read_clear_to_send().unwrap() {
+                assert!(Instant::now() < deadline);
+                thread::sleep(POLL_INTERVAL);
+            }
+            buf
+        });
+
+        let mut app = VirtualPort::connect_remote(&path, 9600, 1024).unwrap();
+        app.set_timeout(TIMEOUT).unwrap();
+
+        let mut buf = [0u8; 4];
+        app.write_all(b"ping").unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+
+        app.write_request_to_send(false).unwrap();
+        assert_eq!(&simulator.join().unwrap(), b"ping");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,644 @@
+//! RFC 2217 (Telnet COM port control) server.
+//!
+//! The server exposes a virtual port over TCP, so remote tools speaking RFC
+//! 2217 (e.g., pyserial's `rfc2217://` URLs, esptool or ser2net clients) can
+//! use it as if it were a network-attached serial port. Line settings and
+//! control lines requested by the client are applied to the virtual port,
+//! and changes of the modem lines seen by the port are reported back to the
+//! client. One client is served at a time.
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::AtomicBool,
+    thread,
+    time::Duration,
+};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, Result, SerialPort, StopBits};
+
+use super::{hangup, is_retryable, send_all, Bridge, CHUNK_SIZE, POLL_INTERVAL};
+use crate::VirtualPort;
+
+// Telnet commands and options
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// COM port option commands sent by the client (responses add 100)
+const SIGNATURE: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const FLOWCONTROL_SUSPEND: u8 = 8;
+const FLOWCONTROL_RESUME: u8 = 9;
+const SET_LINESTATE_MASK: u8 = 10;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+// Modem state bits
+const MODEM_CD: u8 = 0x80;
+const MODEM_RI: u8 = 0x40;
+const MODEM_DSR: u8 = 0x20;
+const MODEM_CTS: u8 = 0x10;
+
+// How long an idle session waits for data before checking the modem lines
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+impl VirtualPort {
+    /// Serves the port over TCP using the RFC 2217 protocol.
+    ///
+    /// Clients connecting to `listener` exchange data with the peer of this
+    /// port and control its line settings and modem lines through the
+    /// Telnet COM port control option. Clients are served one at a time, and
+    /// the server runs until the returned bridge is stopped or dropped.
+    pub fn serve_rfc2217(self, listener: TcpListener) -> Result<Bridge> {
+        listener.set_nonblocking(true)?;
+
+        let mut session: Option<Session> = None;
+        let mut bridge = Bridge::new();
+        bridge.spawn(move |running| {
+            if let Some(active) = session.as_mut() {
+                if !active.step(running)? {
+                    session = None;
+                }
+                return Ok(());
+            }
+
+            match listener.accept() {
+                Ok((stream, _)) => session = Some(Session::new(stream, self.clone())?),
+                Err(err) if is_retryable(&err) => thread::sleep(POLL_INTERVAL),
+                Err(err) => return Err(err),
+            }
+            Ok(())
+        });
+
+        Ok(bridge)
+    }
+}
+
+// Position of the parser within the Telnet stream
+#[derive(Clone, Copy)]
+enum TelnetState {
+    Data,
+    Iac,
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+// A connected RFC 2217 client
+struct Session {
+    stream: TcpStream,
+    port: VirtualPort,
+
+    state: TelnetState,
+    subnegotiation: Vec<u8>,
+
+    // Data sent to the client is suspended by the client
+    suspended: bool,
+
+    // Break state requested by the client
+    break_state: bool,
+
+    // Modem lines reported to the client
+    modem_state: u8,
+    modem_state_mask: u8,
+}
+
+impl Session {
+    fn new(stream: TcpStream, mut port: VirtualPort) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+
+        let modem_state = read_modem_state(&mut port)?;
+        Ok(Self {
+            stream,
+            port,
+            state: TelnetState::Data,
+            subnegotiation: Vec::new(),
+            suspended: false,
+            break_state: false,
+            modem_state,
+            modem_state_mask: 0xff,
+        })
+    }
+
+    // Serves the client for a while, returning `false` once it disconnects
+    fn step(&mut self, running: &AtomicBool) -> io::Result<bool> {
+        let mut buf = [0u8; CHUNK_SIZE];
+        let mut idle = true;
+
+        // Client -> port
+        match self.stream.read(&mut buf) {
+            Ok(0) => return Ok(false),
+            Ok(len) => {
+                idle = false;
+                let payload = self.receive(&buf[..len])?;
+                send_all(&mut self.port, &payload, running)?;
+            }
+            Err(err) if is_retryable(&err) => {}
+            Err(err) if err.kind() == io::ErrorKind::ConnectionReset => return Ok(false),
+            Err(err) => return Err(err),
+        }
+
+        // Port -> client
+        if !self.suspended {
+            match self.port.try_read(&mut buf) {
+                // The peer is closed, so the server has nothing left to do
+                Ok(0) => return Err(hangup()),
+                Ok(len) => {
+                    idle = false;
+                    let mut escaped = Vec::with_capacity(len);
+                    for &byte in &buf[..len] {
+                        escaped.push(byte);
+                        if byte == IAC {
+                            escaped.push(IAC);
+                        }
+                    }
+                    self.send(&escaped)?;
+                }
+                Err(err) if is_retryable(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.notify_modem_state()?;
+
+        if idle {
+            match self.port.wait_readable(Some(IDLE_WAIT)) {
+                Err(err) if !is_retryable(&err) => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    // Feeds bytes received from the client through the Telnet parser,
+    // returning the data to be written into the port
+    fn receive(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(data.len());
+
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (TelnetState::Data, IAC) => TelnetState::Iac,
+                (TelnetState::Data, _) => {
+                    payload.push(byte);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, IAC) => {
+                    payload.push(IAC);
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, DO | DONT | WILL | WONT) => TelnetState::Negotiation(byte),
+                (TelnetState::Iac, SB) => {
+                    self.subnegotiation.clear();
+                    TelnetState::Subnegotiation
+                }
+                (TelnetState::Iac, _) => TelnetState::Data,
+                (TelnetState::Negotiation(command), option) => {
+                    self.negotiate(command, option)?;
+                    TelnetState::Data
+                }
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationIac,
+                (TelnetState::Subnegotiation, _) => {
+                    self.subnegotiation.push(byte);
+                    TelnetState::Subnegotiation
+                }
+                (TelnetState::SubnegotiationIac, SE) => {
+                    let subnegotiation = std::mem::take(&mut self.subnegotiation);
+                    self.subnegotiate(&subnegotiation)?;
+                    TelnetState::Data
+                }
+                (TelnetState::SubnegotiationIac, _) => {
+                    self.subnegotiation.push(byte);
+                    TelnetState::Subnegotiation
+                }
+            };
+        }
+
+        Ok(payload)
+    }
+
+    // Accepts the options required by RFC 2217 and refuses all others
+    fn negotiate(&mut self, command: u8, option: u8) -> io::Result<()> {
+        let supported = matches!(option, BINARY | SGA | COM_PORT_OPTION);
+        let reply = match (command, supported) {
+            (DO, true) => WILL,
+            (DO, false) => WONT,
+            (WILL, true) => DO,
+            (WILL, false) => DONT,
+            (DONT, _) => WONT,
+            _ => DONT,
+        };
+        self.send(&[IAC, reply, option])
+    }
+
+    fn subnegotiate(&mut self, data: &[u8]) -> io::Result<()> {
+        let (command, value) = match data {
+            [COM_PORT_OPTION, command, value @ ..] => (*command, value),
+            _ => return Ok(()),
+        };
+
+        match command {
+            SIGNATURE => {
+                let signature = concat!("virtual-serialport ", env!("CARGO_PKG_VERSION"));
+                self.respond(command, signature.as_bytes())
+            }
+            SET_BAUDRATE => {
+                if let [a, b, c, d] = *value {
+                    let baud_rate = u32::from_be_bytes([a, b, c, d]);
+                    if baud_rate != 0 {
+                        self.port.set_baud_rate(baud_rate)?;
+                    }
+                }
+                let baud_rate = self.port.baud_rate()?;
+                self.respond(command, &baud_rate.to_be_bytes())
+            }
+            SET_DATASIZE => {
+                let data_bits = match value.first() {
+                    Some(5) => Some(DataBits::Five),
+                    Some(6) => Some(DataBits::Six),
+                    Some(7) => Some(DataBits::Seven),
+                    Some(8) => Some(DataBits::Eight),
+                    _ => None,
+                };
+                if let Some(data_bits) = data_bits {
+                    self.port.set_data_bits(data_bits)?;
+                }
+                let data_bits: u8 = self.port.data_bits()?.into();
+                self.respond(command, &[data_bits])
+            }
+            SET_PARITY => {
+                // Mark and space parity are not supported by `serialport`
+                let parity = match value.first() {
+                    Some(1) => Some(Parity::None),
+                    Some(2) => Some(Parity::Odd),
+                    Some(3) => Some(Parity::Even),
+                    _ => None,
+                };
+                if let Some(parity) = parity {
+                    self.port.set_parity(parity)?;
+                }
+                let parity = match self.port.parity()? {
+                    Parity::None => 1,
+                    Parity::Odd => 2,
+                    Parity::Even => 3,
+                };
+                self.respond(command, &[parity])
+            }
+            SET_STOPSIZE => {
+                // 1.5 stop bits are not supported by `serialport`
+                let stop_bits = match value.first() {
+                    Some(1) => Some(StopBits::One),
+                    Some(2) => Some(StopBits::Two),
+                    _ => None,
+                };
+                if let Some(stop_bits) = stop_bits {
+                    self.port.set_stop_bits(stop_bits)?;
+                }
+                let stop_bits = match self.port.stop_bits()? {
+                    StopBits::One => 1,
+                    StopBits::Two => 2,
+                };
+                self.respond(command, &[stop_bits])
+            }
+            SET_CONTROL => {
+                let control = value.first().copied().unwrap_or(0);
+                let state = self.control(control)?;
+                self.respond(command, &[state])
+            }
+            FLOWCONTROL_SUSPEND => {
+                self.suspended = true;
+                Ok(())
+            }
+            FLOWCONTROL_RESUME => {
+                self.suspended = false;
+                Ok(())
+            }
+            SET_LINESTATE_MASK => self.respond(command, value),
+            SET_MODEMSTATE_MASK => {
+                self.modem_state_mask = value.first().copied().unwrap_or(0xff);
+                self.respond(command, &[self.modem_state_mask])
+            }
+            PURGE_DATA => {
+                let buffer = match value.first() {
+                    Some(1) => Some(ClearBuffer::Input),
+                    Some(2) => Some(ClearBuffer::Output),
+                    Some(3) => Some(ClearBuffer::All),
+                    _ => None,
+                };
+                if let Some(buffer) = buffer {
+                    self.port.clear(buffer)?;
+                }
+                self.respond(command, value)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Applies a SET-CONTROL request, returning the resulting state
+    fn control(&mut self, control: u8) -> io::Result<u8> {
+        match control {
+            1 => self.port.set_flow_control(FlowControl::None)?,
+            2 => self.port.set_flow_control(FlowControl::Software)?,
+            3 => self.port.set_flow_control(FlowControl::Hardware)?,
+            5 => {
+                self.port.set_break()?;
+                self.break_state = true;
+            }
+            6 => {
+                self.port.clear_break()?;
+                self.break_state = false;
+            }
+            8 | 9 => self.port.write_data_terminal_ready(control == 8)?,
+            11 | 12 => self.port.write_request_to_send(control == 11)?,
+            _ => {}
+        }
+
+        Ok(match control {
+            0..=3 => match self.port.flow_control()? {
+                FlowControl::None => 1,
+                FlowControl::Software => 2,
+                FlowControl::Hardware => 3,
+            },
+            4..=6 => {
+                if self.break_state {
+                    5
+                } else {
+                    6
+                }
+            }
+            7..=9 => {
+                if *self.port.dtr.lock().unwrap() {
+                    8
+                } else {
+                    9
+                }
+            }
+            10..=12 => {
+                if *self.port.rts.lock().unwrap() {
+                    11
+                } else {
+                    12
+                }
+            }
+            // Inbound flow control is not simulated separately
+            _ => 14,
+        })
+    }
+
+    // Reports changes of the modem lines to the client
+    fn notify_modem_state(&mut self) -> io::Result<()> {
+        let state = read_modem_state(&mut self.port)?;
+        let changed = state ^ self.modem_state;
+        if changed == 0 {
+            return Ok(());
+        }
+        self.modem_state = state;
+
+        // Delta bits occupy the lower nibble
+        let deltas = (changed & (MODEM_CD | MODEM_DSR | MODEM_CTS)) >> 4;
+        let trailing_ri = if changed & MODEM_RI != 0 && state & MODEM_RI == 0 {
+            0x04
+        } else {
+            0
+        };
+        let notification = (state | deltas | trailing_ri) & self.modem_state_mask;
+        if notification != 0 {
+            self.respond(NOTIFY_MODEMSTATE, &[notification])?;
+        }
+        Ok(())
+    }
+
+    // Sends a COM port option subnegotiation to the client
+    fn respond(&mut self, command: u8, value: &[u8]) -> io::Result<()> {
+        let mut message = vec![IAC, SB, COM_PORT_OPTION, command + SERVER_OFFSET];
+        for &byte in value {
+            message.push(byte);
+            if byte == IAC {
+                message.push(IAC);
+            }
+        }
+        message.extend_from_slice(&[IAC, SE]);
+        self.send(&message)
+    }
+
+    fn send(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            match self.stream.write(data) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => data = &data[len..],
+                Err(err) if is_retryable(&err) => thread::sleep(IDLE_WAIT),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_modem_state(port: &mut VirtualPort) -> io::Result<u8> {
+    let mut state = 0;
+    if port.read_carrier_detect()? {
+        state |= MODEM_CD;
+    }
+    if port.read_ring_indicator()? {
+        state |= MODEM_RI;
+    }
+    if port.read_data_set_ready()? {
+        state |= MODEM_DSR;
+    }
+    if port.read_clear_to_send()? {
+        state |= MODEM_CTS;
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    // Reads from the client socket until `expected` has been received
+    fn expect(stream: &mut TcpStream, expected: &[u8]) {
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !received.ends_with(expected) {
+            assert!(Instant::now() < deadline, "received {:?}", received);
+            let mut buf = [0u8; 64];
+            match stream.read(&mut buf) {
+                Ok(len) => received.extend_from_slice(&buf[..len]),
+                Err(err) if is_retryable(&err) => {}
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    fn connect() -> (TcpStream, VirtualPort, VirtualPort, Bridge) {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+        let server_side = port.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = port.serve_rfc2217(listener).unwrap();
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        (stream, app, server_side, server)
+    }
+
+    #[test]
+    fn test_rfc2217_data_with_escaping() {
+        let (mut stream, mut app, _, server) = connect();
+
+        stream.write_all(&[b'a', IAC, IAC, b'b']).unwrap();
+        let mut buf = [0u8; 3];
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [b'a', IAC, b'b']);
+
+        app.write_all(&[IAC, b'c']).unwrap();
+        expect(&mut stream, &[IAC, IAC, b'c']);
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn test_rfc2217_ends_on_hangup() {
+        let (_stream, app, _, server) = connect();
+
+        drop(app);
+        let err = server.join().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_rfc2217_line_settings() {
+        let (mut stream, _app, server_side, _server) = connect();
+
+        stream.write_all(&[IAC, WILL, COM_PORT_OPTION]).unwrap();
+        expect(&mut stream, &[IAC, DO, COM_PORT_OPTION]);
+
+        let mut request = vec![IAC, SB, COM_PORT_OPTION, SET_BAUDRATE];
+        request.extend_from_slice(&115_200u32.to_be_bytes());
+        request.extend_from_slice(&[IAC, SE]);
+        stream.write_all(&request).unwrap();
+
+        let mut response = vec![IAC, SB, COM_PORT_OPTION, SET_BAUDRATE + SERVER_OFFSET];
+        response.extend_from_slice(&115_200u32.to_be_bytes());
+        response.extend_from_slice(&[IAC, SE]);
+        expect(&mut stream, &response);
+        assert_eq!(server_side.baud_rate().unwrap(), 115_200);
+
+        stream
+            .write_all(&[IAC, SB, COM_PORT_OPTION, SET_PARITY, 3, IAC, SE])
+            .unwrap();
+        expect(
+            &mut stream,
+            &[
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                SET_PARITY + SERVER_OFFSET,
+                3,
+                IAC,
+                SE,
+            ],
+        );
+        assert_eq!(server_side.parity().unwrap(), Parity::Even);
+    }
+
+    #[test]
+    fn test_rfc2217_break() {
+        let (mut stream, app, _, _server) = connect();
+
+        // The break requested by the client reaches the application
+        stream
+            .write_all(&[IAC, SB, COM_PORT_OPTION, SET_CONTROL, 5, IAC, SE])
+            .unwrap();
+        expect(
+            &mut stream,
+            &[
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                SET_CONTROL + SERVER_OFFSET,
+                5,
+                IAC,
+                SE,
+            ],
+        );
+        assert!(app.break_received());
+
+        stream
+            .write_all(&[IAC, SB, COM_PORT_OPTION, SET_CONTROL, 6, IAC, SE])
+            .unwrap();
+        expect(
+            &mut stream,
+            &[
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                SET_CONTROL + SERVER_OFFSET,
+                6,
+                IAC,
+                SE,
+            ],
+        );
+        assert!(!app.break_received());
+    }
+
+    #[test]
+    fn test_rfc2217_modem_lines() {
+        let (mut stream, mut app, _, _server) = connect();
+
+        // The client drops DTR, which the application sees as DSR
+        stream
+            .write_all(&[IAC, SB, COM_PORT_OPTION, SET_CONTROL, 9, IAC, SE])
+            .unwrap();
+        expect(
+            &mut stream,
+            &[
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                SET_CONTROL + SERVER_OFFSET,
+                9,
+                IAC,
+                SE,
+            ],
+        );
+        assert!(!app.read_data_set_ready().unwrap());
+
+        // The application drops RTS, which is reported as a CTS change
+        app.write_request_to_send(false).unwrap();
+        let state = MODEM_CD | MODEM_DSR | 0x01;
+        expect(
+            &mut stream,
+            &[
+                IAC,
+                SB,
+                COM_PORT_OPTION,
+                NOTIFY_MODEMSTATE + SERVER_OFFSET,
+                state,
+                IAC,
+                SE,
+            ],
+        );
+    }
+}
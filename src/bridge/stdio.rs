@@ -0,0 +1,133 @@
+//! Bridging a virtual port to byte streams such as stdin and stdout.
+
+use std::{
+    io::{self, Read, Write},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+};
+
+use serialport::Result;
+
+use super::{receive_ready, send_all, Bridge, CHUNK_SIZE, POLL_INTERVAL};
+use crate::VirtualPort;
+
+impl VirtualPort {
+    /// Connects the port to the standard input and output of the process.
+    ///
+    /// This makes it easy to drive the peer of the port from expect-style
+    /// harnesses or shell pipelines. See
+    /// [`bridge_to_io`](VirtualPort::bridge_to_io) for details.
+    pub fn bridge_to_stdio(self) -> Result<Bridge> {
+        self.bridge_to_io(io::stdin(), io::stdout())
+    }
+
+    /// Connects the port to arbitrary byte streams: everything read from
+    /// `reader` is delivered to the peer of this port, and everything the
+    /// peer writes is written to `writer`.
+    ///
+    /// As a blocking read cannot be interrupted, `reader` is read in a
+    /// detached thread, which finishes after its next read once the bridge is
+    /// stopped. Reaching the end of `reader` does not stop the bridge.
+    pub fn bridge_to_io<R, W>(self, mut reader: R, mut writer: W) -> Result<Bridge>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let mut bridge = Bridge::new();
+
+        // Peer of the virtual port -> writer
+        let mut virtual_reader = self.clone();
+        bridge.spawn(move |_| {
+            let mut buf = [0u8; CHUNK_SIZE];
+            let len = receive_ready(&mut virtual_reader, &mut buf)?;
+            if len == 0 {
+                return Ok(());
+            }
+            writer.write_all(&buf[..len])?;
+            writer.flush()
+        });
+
+        // Reader -> peer of the virtual port, through a channel
+        let (sender, receiver) = mpsc::channel::<io::Result<Vec<u8>>>();
+        thread::spawn(move || loop {
+            let mut buf = [0u8; CHUNK_SIZE];
+            let result = match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(len) => Ok(buf[..len].to_vec()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+            let failed = result.is_err();
+            if sender.send(result).is_err() || failed {
+                return;
+            }
+        });
+
+        let mut virtual_writer = self;
+        bridge.spawn(move |running| match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(chunk) => send_all(&mut virtual_writer, &chunk?, running),
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            // The reader has reached its end
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(POLL_INTERVAL);
+                Ok(())
+            }
+        });
+
+        Ok(bridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    // A writer shared with the test
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_bridge_to_io() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+
+        let output = SharedWriter::default();
+        let bridge = port
+            .bridge_to_io(io::Cursor::new(b"input".to_vec()), output.clone())
+            .unwrap();
+
+        let mut buf = [0u8; 5];
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"input");
+
+        app.write_all(b"output").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while output.0.lock().unwrap().len() < 6 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(&output.0.lock().unwrap()[..], b"output");
+
+        // The end of the input does not stop the bridge
+        assert!(bridge.is_running());
+        bridge.stop().unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+//! UDP datagram bridge.
+
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use serialport::Result;
+
+use super::{is_retryable, receive_ready, send_all, Bridge, POLL_INTERVAL};
+use crate::VirtualPort;
+
+// Largest payload of a UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+impl VirtualPort {
+    /// Connects the port to a remote UDP endpoint.
+    ///
+    /// Every datagram received from `remote` is delivered to the peer of this
+    /// port as a whole, and the data written by the peer is sent to `remote`
+    /// as datagrams: each datagram carries the data that has arrived since
+    /// the previous one, so a single write normally becomes a single
+    /// datagram. Datagrams from other sources are ignored.
+    pub fn bridge_to_udp<A>(self, socket: UdpSocket, remote: A) -> Result<Bridge>
+    where
+        A: ToSocketAddrs,
+    {
+        socket.connect(remote)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let sender = socket.try_clone()?;
+        let receiver = socket;
+
+        let mut bridge = Bridge::new();
+
+        // Peer of the virtual port -> remote endpoint
+        let mut virtual_reader = self.clone();
+        bridge.spawn(move |_| {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            let len = receive_ready(&mut virtual_reader, &mut buf)?;
+            if len > 0 {
+                sender.send(&buf[..len])?;
+            }
+            Ok(())
+        });
+
+        // Remote endpoint -> peer of the virtual port
+        let mut virtual_writer = self;
+        bridge.spawn(move |running| {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            match receiver.recv(&mut buf) {
+                Ok(len) => send_all(&mut virtual_writer, &buf[..len], running),
+                Err(err) if is_retryable(&err) => Ok(()),
+                // Reported on some platforms when an earlier datagram was
+                // not delivered because nothing listens on the remote port
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+                Err(err) => Err(err),
+            }
+        });
+
+        Ok(bridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_udp_datagrams() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+
+        let gateway = UdpSocket::bind("127.0.0.1:0").unwrap();
+        gateway
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let bridge = port
+            .bridge_to_udp(socket, gateway.local_addr().unwrap())
+            .unwrap();
+
+        // Every write becomes a separate datagram
+        let mut buf = [0u8; 64];
+        app.write_all(b"frame").unwrap();
+        assert_eq!(gateway.recv(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"frame");
+
+        // Datagrams are delivered to the port as a byte stream
+        gateway.send_to(b"one", address).unwrap();
+        gateway.send_to(b"two", address).unwrap();
+        let mut received = [0u8; 6];
+        app.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"onetwo");
+
+        bridge.stop().unwrap();
+    }
+}
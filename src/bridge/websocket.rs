@@ -0,0 +1,175 @@
+//! WebSocket bridge.
+//!
+//! The server lets a browser-based dashboard (or any other WebSocket client)
+//! act as the remote device of a virtual port, or monitor the data written
+//! by its peer. One client is served at a time.
+
+use std::{
+    io,
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use serialport::Result;
+use tungstenite::{Error, Message, WebSocket};
+
+use super::{hangup, is_retryable, send_all, Bridge, CHUNK_SIZE, POLL_INTERVAL};
+use crate::VirtualPort;
+
+// How long the handshake with a new client may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How long an idle session waits for data before polling the client again
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+impl VirtualPort {
+    /// Serves the port to WebSocket clients connecting to `listener`.
+    ///
+    /// The data written by the peer of this port is sent to the client in
+    /// binary messages, and the payload of every binary or text message
+    /// received from the client is delivered to the peer. The server runs
+    /// until the returned bridge is stopped or dropped.
+    pub fn serve_websocket(self, listener: TcpListener) -> Result<Bridge> {
+        listener.set_nonblocking(true)?;
+
+        let mut port = self;
+        let mut session: Option<WebSocket<TcpStream>> = None;
+
+        let mut bridge = Bridge::new();
+        bridge.spawn(move |running| {
+            let socket = match session.as_mut() {
+                Some(socket) => socket,
+                None => {
+                    match listener.accept() {
+                        Ok((stream, _)) => session = handshake(stream)?,
+                        Err(err) if is_retryable(&err) => thread::sleep(POLL_INTERVAL),
+                        Err(err) => return Err(err),
+                    }
+                    return Ok(());
+                }
+            };
+
+            let mut idle = true;
+
+            // Client -> port
+            match socket.read() {
+                Ok(message @ (Message::Binary(_) | Message::Text(_))) => {
+                    idle = false;
+                    send_all(&mut port, &message.into_data(), running)?;
+                }
+                // Pings are answered and closing is completed by `tungstenite`
+                Ok(_) => idle = false,
+                Err(Error::Io(err)) if is_retryable(&err) => {}
+                // The client has left or violated the protocol
+                Err(_) => {
+                    session = None;
+                    return Ok(());
+                }
+            }
+
+            // Port -> client
+            let mut buf = [0u8; CHUNK_SIZE];
+            let sent = match port.try_read(&mut buf) {
+                // The peer is closed, so the server has nothing left to do
+                Ok(0) => return Err(hangup()),
+                Ok(len) => {
+                    idle = false;
+                    socket.send(Message::binary(buf[..len].to_vec()))
+                }
+                Err(err) if is_retryable(&err) => socket.flush(),
+                Err(err) => return Err(err),
+            };
+            match sent {
+                // Messages that do not fit into the socket are sent later
+                Ok(()) => {}
+                Err(Error::Io(err)) if is_retryable(&err) => {}
+                Err(_) => {
+                    session = None;
+                    return Ok(());
+                }
+            }
+
+            if idle {
+                match port.wait_readable(Some(IDLE_WAIT)) {
+                    Err(err) if !is_retryable(&err) => return Err(err),
+                    _ => {}
+                }
+            }
+            Ok(())
+        });
+
+        Ok(bridge)
+    }
+}
+
+// Completes the opening handshake with a new client, returning `None` if the
+// client fails to complete it in time
+fn handshake(stream: TcpStream) -> io::Result<Option<WebSocket<TcpStream>>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+
+    let socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(_) => return Ok(None),
+    };
+    socket.get_ref().set_nonblocking(true)?;
+    Ok(Some(socket))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_websocket_round_trip() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let bridge = port.serve_websocket(listener).unwrap();
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let (mut client, _) = tungstenite::client("ws://localhost/", stream).unwrap();
+
+        let mut buf = [0u8; 5];
+        client.send(Message::binary(b"hello".to_vec())).unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        client.send(Message::text("text!")).unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"text!");
+
+        app.write_all(b"world").unwrap();
+        let message = client.read().unwrap();
+        assert_eq!(message, Message::binary(b"world".to_vec()));
+
+        client.close(None).unwrap();
+        bridge.stop().unwrap();
+    }
+
+    #[test]
+    fn test_websocket_ends_on_hangup() {
+        let (app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let bridge = port.serve_websocket(listener).unwrap();
+
+        let stream = TcpStream::connect(address).unwrap();
+        let (_client, _) = tungstenite::client("ws://localhost/", stream).unwrap();
+
+        drop(app);
+        let err = bridge.join().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,493 @@
+//! Multidrop buses shared by several virtual ports.
+//!
+//! A [`VirtualBus`] models a shared line such as RS-485: the data sent by
+//! any attached port is received by all the others. On a half-duplex bus,
+//! transmissions which overlap in time collide and garble each other, as
+//! they do on the wire. Ports join and leave the bus at any time, as devices
+//! plugged into a running network do.
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serialport::Result;
+
+use crate::{
+    pipe::{BusMember, ByteTime, Pipe},
+    VirtualPort,
+};
+
+/// Collision of two transmissions on a half-duplex bus, delivered to the
+/// receivers returned by [`VirtualBus::subscribe_collisions`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusCollision {
+    /// Number of bytes of the new transmission which overlap the ongoing
+    /// one, garbled on both.
+    pub bytes: usize,
+
+    /// When the colliding transmission started.
+    pub timestamp: Instant,
+}
+
+/// Change of the ports attached to a bus, delivered to the receivers
+/// returned by [`VirtualBus::subscribe_events`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusEvent {
+    /// A port was attached as the given participant.
+    Attached(usize),
+
+    /// The given participant was detached, explicitly or as every handle of
+    /// its port was dropped.
+    Detached(usize),
+}
+
+/// Directions in which a port attached to a bus may use it, set with
+/// [`VirtualBus::set_direction`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BusDirection {
+    /// The port transmits and receives (the default).
+    Both,
+
+    /// The port only transmits, as a broadcast beacon does: its reads fail
+    /// with `PermissionDenied`, and it receives nothing.
+    TransmitOnly,
+
+    /// The port only receives, as a listen-only sniffer does: its writes fail
+    /// with `PermissionDenied`.
+    ReceiveOnly,
+}
+
+// State of the line shared by the ports of a bus
+pub(crate) struct BusLine {
+    // Attached endpoints by participant ID, and the ID of the next one
+    members: Vec<(usize, BusMember)>,
+    next_participant: usize,
+
+    half_duplex: bool,
+
+    // Time to transmit a byte on the bus
+    byte_time: ByteTime,
+
+    // Participant which transmitted last, and when its transmission ends
+    sender: Option<usize>,
+    busy_until: Instant,
+
+    collisions: u64,
+    subscribers: Vec<Sender<BusCollision>>,
+    event_subscribers: Vec<Sender<BusEvent>>,
+}
+
+impl BusLine {
+    fn new(baud_rate: u32) -> Self {
+        Self {
+            members: Vec::new(),
+            next_participant: 0,
+            half_duplex: false,
+            // 8 data bits, a start bit and a stop bit
+            byte_time: ByteTime::new(10, baud_rate),
+            sender: None,
+            busy_until: Instant::now(),
+            collisions: 0,
+            subscribers: Vec::new(),
+            event_subscribers: Vec::new(),
+        }
+    }
+
+    // Links a new endpoint to the attached ones, returning its participant ID
+    fn attach(&mut self, member: BusMember) -> usize {
+        let participant = self.next_participant;
+        self.next_participant += 1;
+        for (_, other) in &self.members {
+            member.link(other);
+        }
+        self.members.push((participant, member));
+        self.publish(BusEvent::Attached(participant));
+        participant
+    }
+
+    // Unlinks an endpoint from the others, returning whether it was attached
+    fn detach(&mut self, participant: usize) -> bool {
+        let index = match self.members.iter().position(|(id, _)| *id == participant) {
+            Some(index) => index,
+            None => return false,
+        };
+        let (_, member) = self.members.remove(index);
+        for (_, other) in &self.members {
+            member.unlink(other);
+        }
+        if self.sender == Some(participant) {
+            self.sender = None;
+        }
+        self.publish(BusEvent::Detached(participant));
+        true
+    }
+
+    fn is_attached(&self, participant: usize) -> bool {
+        self.members.iter().any(|(id, _)| *id == participant)
+    }
+
+    fn publish(&mut self, event: BusEvent) {
+        self.event_subscribers
+            .retain(|sender| sender.send(event).is_ok());
+    }
+
+    /// Accounts for `len` bytes sent by `participant` at `now`, returning
+    /// how many of them overlap the transmission of another participant
+    /// (none unless the bus is half-duplex).
+    pub(crate) fn transmit(&mut self, participant: usize, len: usize, now: Instant) -> usize {
+        // A detached port doesn't drive the line anymore
+        if !self.is_attached(participant) {
+            return 0;
+        }
+
+        let duration = self.byte_time.duration(len);
+        let overlap = self.busy_until.saturating_duration_since(now);
+        let collided = match self.sender {
+            Some(sender)
+                if sender != participant && self.half_duplex && overlap > Duration::ZERO =>
+            {
+                let byte = self.byte_time.duration(1).max(Duration::from_nanos(1));
+                len.min(ceil_div(overlap, byte))
+            }
+            _ => 0,
+        };
+
+        // Data queued behind the previous transmission of the same sender
+        // goes out after it
+        let start = match self.sender {
+            Some(sender) if sender == participant => self.busy_until.max(now),
+            _ => now,
+        };
+        self.busy_until = self.busy_until.max(start + duration);
+        self.sender = Some(participant);
+
+        if collided > 0 {
+            self.collisions += 1;
+            let collision = BusCollision {
+                bytes: collided,
+                timestamp: now,
+            };
+            self.subscribers
+                .retain(|sender| sender.send(collision).is_ok());
+        }
+        collided
+    }
+}
+
+/// Attachment of an endpoint to a bus, detaching it once dropped.
+pub(crate) struct Attachment {
+    line: Arc<Mutex<BusLine>>,
+    participant: usize,
+}
+
+impl Attachment {
+    /// Accounts for `len` bytes sent through the bus at `now` (see
+    /// [`BusLine::transmit`]).
+    pub(crate) fn transmit(&self, len: usize, now: Instant) -> usize {
+        self.line
+            .lock()
+            .unwrap()
+            .transmit(self.participant, len, now)
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        if let Ok(mut line) = self.line.lock() {
+            line.detach(self.participant);
+        }
+    }
+}
+
+// Number of whole `unit`s needed to cover `duration`
+fn ceil_div(duration: Duration, unit: Duration) -> usize {
+    let (duration, unit) = (duration.as_nanos(), unit.as_nanos());
+    usize::try_from((duration + unit - 1) / unit).unwrap_or(usize::MAX)
+}
+
+/// A shared bus connecting any number of virtual ports, such as an RS-485
+/// multidrop network.
+///
+/// The data written by a port attached with [`add_port`](VirtualBus::add_port)
+/// is received by all the other ports, but not by itself. The bus never holds
+/// a writer back: the data which doesn't fit in the buffer of a receiver is
+/// lost for it. A port stays attached until it is
+/// [`detach`](VirtualBus::detach)ed or every handle of it is dropped.
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use virtual_serialport::VirtualBus;
+///
+/// let bus = VirtualBus::new(9600, 1024);
+/// let mut master = bus.add_port().unwrap();
+/// let mut slave1 = bus.add_port().unwrap();
+/// let mut slave2 = bus.add_port().unwrap();
+///
+/// master.write_all(b"poll").unwrap();
+/// let mut read_data = [0u8; 4];
+/// slave1.read_exact(&mut read_data).unwrap();
+/// slave2.read_exact(&mut read_data).unwrap();
+/// assert_eq!(&read_data, b"poll");
+/// ```
+pub struct VirtualBus {
+    baud_rate: u32,
+    buffer_capacity: usize,
+    line: Arc<Mutex<BusLine>>,
+}
+
+impl VirtualBus {
+    /// Creates a full-duplex bus without ports, whose ports get the given
+    /// baud rate and receive buffer capacity.
+    pub fn new(baud_rate: u32, buffer_capacity: usize) -> Self {
+        Self {
+            baud_rate,
+            buffer_capacity,
+            line: Arc::new(Mutex::new(BusLine::new(baud_rate))),
+        }
+    }
+
+    /// Attaches a new port to the bus, as the next participant. Participants
+    /// are numbered from 0 in the order they are attached.
+    pub fn add_port(&self) -> Result<VirtualPort> {
+        let mut line = self.line.lock().unwrap();
+        let participant = line.next_participant;
+        let attachment = Attachment {
+            line: self.line.clone(),
+            participant,
+        };
+        let pipe = Pipe::bus(self.buffer_capacity, attachment);
+        line.attach(pipe.bus_member());
+        drop(line);
+        VirtualPort::with_own_lines(self.baud_rate, pipe)
+    }
+
+    /// Detaches a port from the bus, as when a device is unplugged, returning
+    /// whether it was attached. The port keeps working on its own: its data
+    /// goes nowhere and it receives nothing.
+    pub fn detach(&self, port: &VirtualPort) -> bool {
+        match self.participant(port) {
+            Some(participant) => self.line.lock().unwrap().detach(participant),
+            None => false,
+        }
+    }
+
+    /// Returns the participant ID of a port attached to the bus.
+    pub fn participant(&self, port: &VirtualPort) -> Option<usize> {
+        let attachment = port.pipe.bus_attachment()?;
+        if !Arc::ptr_eq(&attachment.line, &self.line) {
+            return None;
+        }
+        Some(attachment.participant)
+            .filter(|&participant| self.line.lock().unwrap().is_attached(participant))
+    }
+
+    /// Returns the IDs of the attached participants.
+    pub fn participants(&self) -> Vec<usize> {
+        let line = self.line.lock().unwrap();
+        line.members.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Subscribes to the ports joining and leaving the bus.
+    pub fn subscribe_events(&self) -> Receiver<BusEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.line.lock().unwrap().event_subscribers.push(sender);
+        receiver
+    }
+
+    /// Returns the directions in which a port attached to the bus may use it.
+    pub fn direction(&self, port: &VirtualPort) -> Option<BusDirection> {
+        self.participant(port)?;
+        Some(match port.pipe.directions() {
+            (true, false) => BusDirection::TransmitOnly,
+            (false, true) => BusDirection::ReceiveOnly,
+            _ => BusDirection::Both,
+        })
+    }
+
+    /// Restricts a port attached to the bus to the given directions,
+    /// returning whether it is attached.
+    pub fn set_direction(&self, port: &VirtualPort, direction: BusDirection) -> bool {
+        if self.participant(port).is_none() {
+            return false;
+        }
+        let (transmit, receive) = match direction {
+            BusDirection::Both => (true, true),
+            BusDirection::TransmitOnly => (true, false),
+            BusDirection::ReceiveOnly => (false, true),
+        };
+        port.pipe.set_directions(transmit, receive);
+        true
+    }
+
+    /// Returns whether the ports share a single line.
+    pub fn half_duplex(&self) -> bool {
+        self.line.lock().unwrap().half_duplex
+    }
+
+    /// Sets whether the ports share a single line, as on a two-wire RS-485
+    /// bus. A transmission starting while another port is still transmitting
+    /// (at the baud rate of the bus) then collides with it: the overlapping
+    /// bytes of both are garbled for every receiver, as framing errors if it
+    /// reports line errors, and the collision is counted.
+    pub fn set_half_duplex(&self, enabled: bool) {
+        self.line.lock().unwrap().half_duplex = enabled;
+    }
+
+    /// Returns the number of collisions since the bus was created.
+    pub fn collisions(&self) -> u64 {
+        self.line.lock().unwrap().collisions
+    }
+
+    /// Subscribes to the collisions on the bus, so arbitration and retry
+    /// logic can react to them.
+    pub fn subscribe_collisions(&self) -> Receiver<BusCollision> {
+        let (sender, receiver) = mpsc::channel();
+        self.line.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        sync::Arc,
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+    use crate::{LineError, ManualClock};
+
+    #[test]
+    fn test_bus() {
+        let bus = VirtualBus::new(9600, 1024);
+        let mut port1 = bus.add_port().unwrap();
+        let mut port2 = bus.add_port().unwrap();
+        let mut port3 = bus.add_port().unwrap();
+
+        port1.write_all(b"ab").unwrap();
+        port2.write_all(b"cd").unwrap();
+        assert_eq!(port1.bytes_to_read().unwrap(), 2);
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+        let mut read_data = [0u8; 4];
+        port3.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+
+        // A full-duplex bus doesn't collide
+        assert_eq!(bus.collisions(), 0);
+    }
+
+    #[test]
+    fn test_bus_collision() {
+        let bus = VirtualBus::new(9600, 1024);
+        bus.set_half_duplex(true);
+        let collisions = bus.subscribe_collisions();
+
+        // The time doesn't move, so the transmissions overlap entirely
+        let clock = Arc::new(ManualClock::new());
+        let mut port1 = bus.add_port().unwrap();
+        let mut port2 = bus.add_port().unwrap();
+        let mut port3 = bus.add_port().unwrap();
+        port1.set_clock(clock.clone());
+        port2.set_clock(clock.clone());
+        port3.set_report_line_errors(true);
+
+        port1.write_all(b"abcd").unwrap();
+        port1.write_all(b"ef").unwrap();
+        assert_eq!(bus.collisions(), 0);
+        port2.write_all(b"xy").unwrap();
+        assert_eq!(bus.collisions(), 1);
+        assert_eq!(collisions.try_recv().unwrap().bytes, 2);
+
+        // The receivers get the start of the first transmission intact
+        let mut read_data = [0u8; 8];
+        assert_eq!(port3.read(&mut read_data).unwrap(), 4);
+        assert_eq!(&read_data[..4], b"abcd");
+        let err = port3.read(&mut read_data).unwrap_err();
+        assert_eq!(LineError::from_io(&err), Some(LineError::Framing));
+
+        // Once the line is free again, the transmissions go through
+        clock.advance(Duration::from_millis(10));
+        port2.write_all(b"z").unwrap();
+        assert_eq!(bus.collisions(), 1);
+        assert_eq!(port1.bytes_to_read().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_bus_detach() {
+        let bus = VirtualBus::new(9600, 1024);
+        let events = bus.subscribe_events();
+        let mut port1 = bus.add_port().unwrap();
+        let mut port2 = bus.add_port().unwrap();
+        let port3 = bus.add_port().unwrap();
+        assert_eq!(bus.participant(&port2), Some(1));
+        assert_eq!(bus.participants(), [0, 1, 2]);
+
+        assert!(bus.detach(&port2));
+        assert!(!bus.detach(&port2));
+        assert_eq!(bus.participant(&port2), None);
+        drop(port3);
+        assert_eq!(bus.participants(), [0]);
+
+        // The detached port neither sends nor receives anything
+        port1.write_all(b"a").unwrap();
+        port2.write_all(b"b").unwrap();
+        assert_eq!(port1.bytes_to_read().unwrap(), 0);
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+
+        let mut port4 = bus.add_port().unwrap();
+        port4.write_all(b"c").unwrap();
+        assert_eq!(port1.bytes_to_read().unwrap(), 1);
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            events,
+            [
+                BusEvent::Attached(0),
+                BusEvent::Attached(1),
+                BusEvent::Attached(2),
+                BusEvent::Detached(1),
+                BusEvent::Detached(2),
+                BusEvent::Attached(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bus_direction() {
+        let bus = VirtualBus::new(9600, 1024);
+        let mut beacon = bus.add_port().unwrap();
+        let mut sniffer = bus.add_port().unwrap();
+        let mut node = bus.add_port().unwrap();
+        assert!(bus.set_direction(&beacon, BusDirection::TransmitOnly));
+        assert!(bus.set_direction(&sniffer, BusDirection::ReceiveOnly));
+        assert_eq!(bus.direction(&sniffer), Some(BusDirection::ReceiveOnly));
+        assert_eq!(bus.direction(&node), Some(BusDirection::Both));
+
+        beacon.write_all(b"a").unwrap();
+        node.write_all(b"b").unwrap();
+        assert_eq!(
+            sniffer.write(b"c").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        let mut read_data = [0u8; 2];
+        sniffer.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ab");
+        assert_eq!(
+            beacon.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        // The restriction is lifted once both directions are allowed again
+        bus.set_direction(&beacon, BusDirection::Both);
+        node.write_all(b"d").unwrap();
+        beacon.read_exact(&mut read_data[..1]).unwrap();
+        assert_eq!(read_data[0], b'd');
+    }
+}
@@ -0,0 +1,315 @@
+//! Captures of the traffic of a port to pcapng files.
+//!
+//! The format is described in
+//! <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html>. Each
+//! chunk of data written to or received by the port becomes an Enhanced
+//! Packet Block, whose direction flag tells the two apart, so the capture can
+//! be opened in Wireshark and decoded with the dissector of the serial
+//! protocol (e.g., assigned to the user link type).
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{mpsc::Sender, Arc, Mutex, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{TrafficDirection, TrafficEvent, VirtualPort};
+
+/// Link type `LINKTYPE_USER0`, reserved for private use, which the captures
+/// use unless told otherwise.
+pub const LINKTYPE_USER0: u16 = 147;
+
+// Block types
+const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+
+// Options
+const END_OF_OPTIONS: u16 = 0;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+// Direction bits of the flags of a packet
+const INBOUND: u32 = 0b01;
+const OUTBOUND: u32 = 0b10;
+
+// Writer of a capture, shared by the buffers it records
+struct Sink {
+    writer: Box<dyn Write + Send>,
+
+    // Time of the clock of the port and the wall clock time when the capture
+    // started, mapping the timestamps of the data to the wall clock
+    start: Instant,
+    wall_start: SystemTime,
+
+    // First error writing the capture, reported by `Capture::finish`
+    error: Option<io::Error>,
+}
+
+/// Destination of the data recorded by a [`CaptureTap`].
+pub(crate) trait CaptureSink: Send {
+    /// Records the data sent (`outbound`) or received by the captured port
+    /// at `timestamp`.
+    fn record(&mut self, data: &[u8], outbound: bool, timestamp: Instant);
+}
+
+impl CaptureSink for Sink {
+    // Writes an Enhanced Packet Block
+    fn record(&mut self, data: &[u8], outbound: bool, timestamp: Instant) {
+        if self.error.is_some() || data.is_empty() {
+            return;
+        }
+
+        let time = self.wall_start + timestamp.saturating_duration_since(self.start);
+        let nanos = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+        let nanos = u64::try_from(nanos.as_nanos()).unwrap_or(u64::MAX);
+        let flags = if outbound { OUTBOUND } else { INBOUND };
+
+        let mut body = Vec::with_capacity(data.len() + 32);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(nanos as u32).to_le_bytes());
+        let len = u32::try_from(data.len()).unwrap_or(u32::MAX);
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(&len.to_le_bytes());
+        body.extend_from_slice(&data[..len as usize]);
+        pad(&mut body);
+        push_option(&mut body, EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, END_OF_OPTIONS, &[]);
+
+        if let Err(err) = write_block(&mut self.writer, ENHANCED_PACKET, &body) {
+            self.error = Some(err);
+        }
+    }
+}
+
+// Pads a block body to a multiple of 4 bytes
+fn pad(body: &mut Vec<u8>) {
+    body.resize((body.len() + 3) / 4 * 4, 0);
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+fn write_block(writer: &mut dyn Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let len = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_le_bytes())
+}
+
+// Capture registered on a buffer: the data put in it is recorded as sent or
+// received by the captured port
+#[derive(Clone)]
+pub(crate) enum CaptureTap {
+    // Recorded into a sink, until it is dropped
+    Sink {
+        sink: Weak<Mutex<dyn CaptureSink>>,
+        outbound: bool,
+    },
+
+    // Delivered through a channel, until its receiver is dropped
+    Channel {
+        sender: Sender<TrafficEvent>,
+        direction: TrafficDirection,
+    },
+}
+
+impl CaptureTap {
+    /// Creates the taps recording the data sent and received by a port into
+    /// `sink`, which stop once it is dropped.
+    pub(crate) fn pair(sink: Arc<Mutex<dyn CaptureSink>>) -> (Self, Self) {
+        let tap = |outbound| CaptureTap::Sink {
+            sink: Arc::downgrade(&sink),
+            outbound,
+        };
+        (tap(true), tap(false))
+    }
+
+    /// Creates the taps delivering the data sent and received by a port
+    /// through `sender`, which stop once its receiver is dropped.
+    pub(crate) fn channel(sender: Sender<TrafficEvent>) -> (Self, Self) {
+        let tap = |direction| CaptureTap::Channel {
+            sender: sender.clone(),
+            direction,
+        };
+        (tap(TrafficDirection::Sent), tap(TrafficDirection::Received))
+    }
+
+    /// Records the data, returning whether the capture is still running.
+    pub(crate) fn record(&self, data: &[u8], timestamp: Instant) -> bool {
+        match self {
+            CaptureTap::Sink { sink, outbound } => match sink.upgrade() {
+                Some(sink) => {
+                    sink.lock().unwrap().record(data, *outbound, timestamp);
+                    true
+                }
+                None => false,
+            },
+            CaptureTap::Channel { sender, direction } => {
+                data.is_empty()
+                    || sender
+                        .send(TrafficEvent {
+                            direction: *direction,
+                            data: data.to_vec(),
+                            timestamp,
+                        })
+                        .is_ok()
+            }
+        }
+    }
+}
+
+/// Running capture of the traffic of a port, started with
+/// [`VirtualPort::capture_pcapng`]. The capture stops when dropped, or with
+/// [`finish`](Capture::finish) to learn whether it was written fully.
+///
+/// ```no_run
+/// use std::io::Write;
+///
+/// use virtual_serialport::VirtualPort;
+///
+/// let (mut port1, _port2) = VirtualPort::pair(9600, 1024).unwrap();
+/// let capture = port1.capture_pcapng("session.pcapng").unwrap();
+/// port1.write_all(b"hello").unwrap();
+/// capture.finish().unwrap();
+/// ```
+pub struct Capture {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl Capture {
+    /// Stops the capture and flushes it, failing with the first error met
+    /// while writing it.
+    pub fn finish(self) -> io::Result<()> {
+        let mut sink = self.sink.lock().unwrap();
+        if let Some(err) = sink.error.take() {
+            return Err(err);
+        }
+        sink.writer.flush()
+    }
+}
+
+impl VirtualPort {
+    /// Starts capturing the data written and received by the port to a
+    /// pcapng file at `path`, with the [`LINKTYPE_USER0`] link type.
+    pub fn capture_pcapng(&self, path: impl AsRef<Path>) -> io::Result<Capture> {
+        let file = BufWriter::new(File::create(path)?);
+        self.capture_pcapng_to(file, LINKTYPE_USER0)
+    }
+
+    /// Starts capturing the data written and received by the port as pcapng
+    /// to `writer`, with the given link type. The data is captured as sent,
+    /// before the faults of the receiver, and timestamped with the clock of
+    /// the port.
+    pub fn capture_pcapng_to(
+        &self,
+        mut writer: impl Write + Send + 'static,
+        link_type: u16,
+    ) -> io::Result<Capture> {
+        let mut section = Vec::new();
+        section.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        section.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER, &section)?;
+
+        // Nanosecond timestamps, and no snapshot length limit
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&link_type.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut interface, IF_TSRESOL, &[9]);
+        push_option(&mut interface, END_OF_OPTIONS, &[]);
+        write_block(&mut writer, INTERFACE_DESCRIPTION, &interface)?;
+
+        let sink = Arc::new(Mutex::new(Sink {
+            writer: Box::new(writer),
+            start: self.pipe.now(),
+            wall_start: SystemTime::now(),
+            error: None,
+        }));
+        let (outbound, inbound) = CaptureTap::pair(sink.clone());
+        self.pipe.add_capture(outbound, inbound);
+        Ok(Capture { sink })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writer whose output stays readable once moved into a capture
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Splits a capture into its blocks, checking the lengths match
+    fn blocks(data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let block_type = u32::from_le_bytes(rest[0..4].try_into().unwrap());
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            assert_eq!(&rest[len - 4..len], &rest[4..8]);
+            blocks.push((block_type, &rest[8..len - 4]));
+            rest = &rest[len..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_capture() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let output = SharedBuffer::default();
+        let capture = port1.capture_pcapng_to(output.clone(), 200).unwrap();
+
+        port1.write_all(b"ping").unwrap();
+        port2.write_all(b"pong!").unwrap();
+        capture.finish().unwrap();
+
+        // Nothing is recorded once the capture stopped
+        port1.write_all(b"late").unwrap();
+
+        let data = output.0.lock().unwrap();
+        let blocks = blocks(&data);
+        let types: Vec<_> = blocks.iter().map(|(block_type, _)| *block_type).collect();
+        assert_eq!(
+            types,
+            [
+                SECTION_HEADER,
+                INTERFACE_DESCRIPTION,
+                ENHANCED_PACKET,
+                ENHANCED_PACKET
+            ]
+        );
+        assert_eq!(&blocks[1].1[..2], &200u16.to_le_bytes());
+
+        for ((_, body), (payload, flags)) in blocks[2..]
+            .iter()
+            .zip([(&b"ping"[..], OUTBOUND), (&b"pong!"[..], INBOUND)])
+        {
+            let len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+            assert_eq!(&body[20..20 + len], payload);
+            let options = &body[20 + (len + 3) / 4 * 4..];
+            assert_eq!(&options[..4], &[2, 0, 4, 0]);
+            assert_eq!(&options[4..8], &flags.to_le_bytes());
+        }
+    }
+}
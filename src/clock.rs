@@ -0,0 +1,204 @@
+//! Sources of time for the simulation.
+
+use std::{
+    fmt,
+    sync::Mutex,
+    task::Waker,
+    time::{Duration, Instant},
+};
+
+/// Source of time used by the transmission delay, the timeouts and the
+/// scheduled faults of a link, set with
+/// [`VirtualPort::set_clock`](crate::VirtualPort::set_clock).
+///
+/// [`SystemClock`] follows the real time. [`ManualClock`] only moves when
+/// told to, so timing scenarios run instantly and deterministically:
+///
+/// ```
+/// use std::{
+///     io::{Read, Write},
+///     sync::Arc,
+///     time::Duration,
+/// };
+///
+/// use serialport::SerialPort;
+/// use virtual_serialport::{ManualClock, VirtualPort};
+///
+/// let clock = Arc::new(ManualClock::new());
+/// let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+/// port.set_clock(clock.clone());
+/// port.set_simulate_delay(true);
+///
+/// // At 9600 baud a byte takes about a millisecond
+/// port.write_all(b"hello").unwrap();
+/// assert_eq!(port.bytes_to_read().unwrap(), 0);
+/// clock.advance(Duration::from_millis(3));
+/// assert_eq!(port.bytes_to_read().unwrap(), 2);
+///
+/// clock.advance(Duration::from_millis(3));
+/// let mut read_data = [0u8; 5];
+/// port.read_exact(&mut read_data).unwrap();
+/// assert_eq!(&read_data, b"hello");
+/// ```
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Returns whether the time passes on its own, so waiting for it means
+    /// sleeping.
+    fn is_real_time(&self) -> bool {
+        true
+    }
+
+    /// Returns whether the operations can wait for the time to move or for
+    /// other threads. Under a [`Simulation`](crate::Simulation), nothing
+    /// happens while an operation waits, so the ones which would wait fail
+    /// with `TimedOut` instead.
+    fn can_wait(&self) -> bool {
+        true
+    }
+
+    /// Registers a callback to run whenever the time moves forward other than
+    /// in real time. The callback returns whether it wants to be called
+    /// again.
+    fn on_advance(&self, callback: Box<dyn Fn() -> bool + Send + Sync>) {
+        drop(callback);
+    }
+
+    /// Arranges for `waker` to be woken once the time reaches `deadline`,
+    /// e.g., with a timer of the runtime whose time the clock follows. The
+    /// async ports call it while they wait for the data in flight.
+    fn wake_at(&self, _deadline: Instant, _waker: &Waker) {}
+}
+
+/// Clock following the real time (the default).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock following the time of the tokio runtime (requires the `async`
+/// feature).
+///
+/// Under `tokio::time::pause()`, the runtime moves its time straight to the
+/// next timer when it has nothing else to do, and the async ports wait for
+/// the data in flight with such timers, so tests simulating seconds of
+/// traffic complete instantly. The scheduled faults and the link latency
+/// follow the same time. Blocking operations must not be used with this
+/// clock: the data in flight is only delivered by the timers of the runtime,
+/// so a blocking operation isn't woken up as it arrives, nor does it time
+/// out.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use std::{sync::Arc, time::Duration};
+///
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use virtual_serialport::{AsyncVirtualPort, TokioClock};
+///
+/// tokio::time::pause();
+/// let mut port = AsyncVirtualPort::loopback(50, 1024).unwrap();
+/// port.get_mut().set_clock(Arc::new(TokioClock));
+/// port.get_mut().set_simulate_delay(true);
+///
+/// // At 50 baud the data takes a second, which passes instantly
+/// let start = tokio::time::Instant::now();
+/// port.write_all(b"hello").await.unwrap();
+/// let mut read_data = [0u8; 5];
+/// port.read_exact(&mut read_data).await.unwrap();
+/// assert!(start.elapsed() >= Duration::from_secs(1));
+/// # }
+/// ```
+#[cfg(feature = "async")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "async")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    // The time may be paused, so no thread sleeps waiting for it
+    fn is_real_time(&self) -> bool {
+        false
+    }
+
+    fn wake_at(&self, deadline: Instant, waker: &Waker) {
+        // Outside of a runtime, the time is the real one
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let waker = waker.clone();
+            runtime.spawn(async move {
+                tokio::time::sleep_until(deadline.into()).await;
+                waker.wake();
+            });
+        }
+    }
+}
+
+/// Clock which only moves forward when [`advance`](ManualClock::advance) is
+/// called.
+pub struct ManualClock {
+    // Time of the clock when created, and how far it has advanced since
+    start: Instant,
+    elapsed: Mutex<Duration>,
+
+    // Callbacks of the links using the clock
+    callbacks: Mutex<Vec<Box<dyn Fn() -> bool + Send + Sync>>>,
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+            callbacks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Moves the clock forward, waking up the operations waiting for the new
+    /// time.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+        self.callbacks.lock().unwrap().retain(|callback| callback());
+    }
+
+    /// Returns how far the clock has advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn is_real_time(&self) -> bool {
+        false
+    }
+
+    fn on_advance(&self, callback: Box<dyn Fn() -> bool + Send + Sync>) {
+        self.callbacks.lock().unwrap().push(callback);
+    }
+}
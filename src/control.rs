@@ -0,0 +1,139 @@
+//! Notifications of control line changes.
+
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::Instant,
+};
+
+/// Control line of a serial port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlLine {
+    /// Request to send, an output of the port.
+    Rts,
+
+    /// Data terminal ready, an output of the port.
+    Dtr,
+
+    /// Clear to send, an input of the port.
+    Cts,
+
+    /// Data set ready, an input of the port.
+    Dsr,
+
+    /// Carrier detect, an input of the port.
+    Cd,
+
+    /// Ring indicator, an input of the port.
+    Ri,
+}
+
+/// Transition of a control line, delivered to the receivers returned by
+/// [`VirtualPort::subscribe_control_events`](crate::VirtualPort::subscribe_control_events).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ControlEvent {
+    /// The line which changed.
+    pub line: ControlLine,
+
+    /// The new level of the line.
+    pub level: bool,
+
+    /// When the change happened.
+    pub timestamp: Instant,
+}
+
+// Levels of the control lines as seen by one port
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LineLevels {
+    pub(crate) rts: bool,
+    pub(crate) dtr: bool,
+    pub(crate) cts: bool,
+    pub(crate) dsr: bool,
+    pub(crate) cd: bool,
+    pub(crate) ri: bool,
+}
+
+impl LineLevels {
+    fn lines(&self) -> [(ControlLine, bool); 6] {
+        [
+            (ControlLine::Rts, self.rts),
+            (ControlLine::Dtr, self.dtr),
+            (ControlLine::Cts, self.cts),
+            (ControlLine::Dsr, self.dsr),
+            (ControlLine::Cd, self.cd),
+            (ControlLine::Ri, self.ri),
+        ]
+    }
+}
+
+// Subscribers to the control line changes of one port
+#[derive(Default)]
+pub(crate) struct ControlEvents {
+    senders: Vec<Sender<ControlEvent>>,
+}
+
+impl ControlEvents {
+    pub(crate) fn subscribe(&mut self) -> Receiver<ControlEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    // Sends an event stamped with `timestamp` for each line which differs
+    // between the snapshots, forgetting the subscribers which are gone
+    pub(crate) fn publish(&mut self, before: LineLevels, after: LineLevels, timestamp: Instant) {
+        for ((line, old), (_, level)) in before.lines().into_iter().zip(after.lines()) {
+            if old != level {
+                let event = ControlEvent {
+                    line,
+                    level,
+                    timestamp,
+                };
+                self.senders.retain(|sender| sender.send(event).is_ok());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_changes() {
+        let mut events = ControlEvents::default();
+        let receiver = events.subscribe();
+        let dropped = events.subscribe();
+        drop(dropped);
+
+        let before = LineLevels {
+            rts: true,
+            dtr: true,
+            cts: true,
+            dsr: true,
+            cd: true,
+            ri: false,
+        };
+        let after = LineLevels {
+            cts: false,
+            ri: true,
+            ..before
+        };
+        events.publish(before, after, Instant::now());
+        events.publish(after, after, Instant::now());
+
+        let received: Vec<_> = receiver
+            .try_iter()
+            .map(|event| (event.line, event.level))
+            .collect();
+        assert_eq!(
+            received,
+            [(ControlLine::Cts, false), (ControlLine::Ri, true)]
+        );
+        assert_eq!(events.senders.len(), 1);
+    }
+}
@@ -0,0 +1,191 @@
+//! Emulating peripherals attached to a virtual port.
+//!
+//! A [`Device`] reacts to the data it receives, and a [`DeviceRunner`] services
+//! it on a background thread, so tests can emulate the peripheral on the other
+//! end of a pair without writing their own pump loops.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    bridge::{receive_ready, send_all, Bridge, CHUNK_SIZE},
+    VirtualPort,
+};
+
+mod scripted;
+
+#[cfg(feature = "scenario")]
+mod scenario;
+
+pub use scripted::{ResponseFault, ScriptedDevice};
+
+/// A peripheral emulated on one end of a virtual port pair.
+///
+/// The runner calls [`on_start`](Device::on_start) once, then
+/// [`on_receive`](Device::on_receive) for every chunk of data that arrives,
+/// and [`on_poll`](Device::on_poll) after every chunk and periodically while
+/// the line is idle. The data returned by the hooks is written to the port.
+pub trait Device: Send + 'static {
+    /// Handles the data received from the port, returning the response.
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8>;
+
+    /// Called before any data is received, e.g., to send a banner or to set
+    /// up the control lines.
+    fn on_start(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Called after each received chunk and at least every few milliseconds
+    /// while idle, e.g., to emit unsolicited data or to update the control
+    /// lines.
+    fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Called when the runner is stopped with [`DeviceRunner::stop`].
+    fn on_stop(&mut self, _port: &mut VirtualPort) {}
+}
+
+/// Services a [`Device`] attached to a virtual port on a background thread.
+///
+/// Dropping the runner stops the device without calling
+/// [`on_stop`](Device::on_stop).
+pub struct DeviceRunner<D> {
+    bridge: Bridge,
+    device: Arc<Mutex<D>>,
+    port: VirtualPort,
+}
+
+impl<D: Device> DeviceRunner<D> {
+    /// Starts servicing `device` attached to `port`.
+    ///
+    /// Typically `port` is the second port of a pair, while the code under
+    /// test uses the first one.
+    pub fn spawn(port: VirtualPort, device: D) -> Self {
+        let device = Arc::new(Mutex::new(device));
+
+        let mut bridge = Bridge::new();
+        let mut runner_port = port.clone();
+        let runner_device = device.clone();
+        let mut started = false;
+        bridge.spawn(move |running| {
+            let mut output = Vec::new();
+            if !started {
+                output = runner_device.lock().unwrap().on_start(&mut runner_port);
+                started = true;
+            }
+
+            let mut buf = [0u8; CHUNK_SIZE];
+            let len = receive_ready(&mut runner_port, &mut buf)?;
+            {
+                let mut device = runner_device.lock().unwrap();
+                if len > 0 {
+                    output.extend(device.on_receive(&buf[..len]));
+                }
+                output.extend(device.on_poll(&mut runner_port));
+            }
+
+            send_all(&mut runner_port, &output, running)
+        });
+
+        Self {
+            bridge,
+            device,
+            port,
+        }
+    }
+
+    /// Returns whether the device is still serviced.
+    pub fn is_running(&self) -> bool {
+        self.bridge.is_running()
+    }
+
+    /// Calls `f` with the device, e.g., to inspect or change its state while
+    /// it is running.
+    pub fn with_device<R>(&self, f: impl FnOnce(&mut D) -> R) -> R {
+        f(&mut self.device.lock().unwrap())
+    }
+
+    /// Stops servicing the device, calls its [`on_stop`](Device::on_stop)
+    /// hook and returns it, or the error that terminated the runner.
+    pub fn stop(self) -> io::Result<D> {
+        let DeviceRunner {
+            bridge,
+            device,
+            mut port,
+        } = self;
+        bridge.stop()?;
+
+        // The runner thread has finished, so this is the last reference
+        let mut device = match Arc::try_unwrap(device) {
+            Ok(device) => device.into_inner().unwrap(),
+            Err(_) => unreachable!("device is still shared"),
+        };
+        device.on_stop(&mut port);
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    // Echoes the received data in upper case, counting polls
+    #[derive(Default)]
+    struct Shouter {
+        received: Vec<u8>,
+        polls: usize,
+        stopped: bool,
+    }
+
+    impl Device for Shouter {
+        fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+            self.received.extend_from_slice(data);
+            data.to_ascii_uppercase()
+        }
+
+        fn on_start(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+            b"READY\n".to_vec()
+        }
+
+        fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+            self.polls += 1;
+            Vec::new()
+        }
+
+        fn on_stop(&mut self, _port: &mut VirtualPort) {
+            self.stopped = true;
+        }
+    }
+
+    #[test]
+    fn test_device_runner() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(Duration::from_secs(2)).unwrap();
+
+        let runner = DeviceRunner::spawn(port, Shouter::default());
+
+        let mut banner = [0u8; 6];
+        app.read_exact(&mut banner).unwrap();
+        assert_eq!(&banner, b"READY\n");
+
+        let mut buf = [0u8; 5];
+        app.write_all(b"hello").unwrap();
+        app.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"HELLO");
+        assert!(runner.with_device(|device| device.polls) > 0);
+
+        let device = runner.stop().unwrap();
+        assert_eq!(device.received, b"hello");
+        assert!(device.stopped);
+    }
+}
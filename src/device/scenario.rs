@@ -0,0 +1,251 @@
+//! Scenario files describing the behavior of a scripted device.
+//!
+//! A scenario is a TOML document listing the steps of a [`ScriptedDevice`],
+//! so new device behaviors can be added without recompiling the tests.
+
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+use super::{ResponseFault, ScriptedDevice};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    #[serde(default)]
+    step: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Step(StepSpec),
+    AnyOrder { any_order: Vec<Entry> },
+    InSequence { in_sequence: Vec<Entry> },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepSpec {
+    expect: Option<String>,
+    expect_hex: Option<String>,
+    respond: Option<String>,
+    respond_hex: Option<String>,
+    #[serde(default)]
+    delay_ms: u64,
+    times: Option<usize>,
+    #[serde(default)]
+    optional: bool,
+    fault: Option<FaultSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FaultSpec {
+    Drop,
+    Truncate(usize),
+    Corrupt(usize),
+}
+
+impl ScriptedDevice {
+    /// Loads a device from the scenario file at `path` (see
+    /// [`parse_scenario`](ScriptedDevice::parse_scenario)).
+    pub fn load_scenario(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse_scenario(&fs::read_to_string(path)?)
+    }
+
+    /// Creates a device following a scenario written in TOML, failing with
+    /// `InvalidData` if it is malformed.
+    ///
+    /// Each `[[step]]` table is a step of the script, or a group of steps
+    /// under `any_order` or `in_sequence`:
+    ///
+    /// ```toml
+    /// [[step]]
+    /// expect = "PING\r"
+    /// respond_hex = "50 4f 4e 47 0d"
+    /// delay_ms = 20
+    /// times = 2
+    ///
+    /// [[step]]
+    /// expect = "ATI\r"
+    /// respond = "v1.0\r\n"
+    /// fault = { truncate = 2 }
+    ///
+    /// [[step]]
+    /// any_order = [
+    ///     { expect = "A\r", respond = "a" },
+    ///     { in_sequence = [{ expect = "B\r" }, { expect = "C\r", optional = true }] },
+    /// ]
+    /// ```
+    ///
+    /// The data is given as a string (`expect`, `respond`) or, for bytes
+    /// which aren't text, in hex (`expect_hex`, `respond_hex`). A fault is
+    /// `"drop"`, `{ truncate = <len> }` or `{ corrupt = <offset> }` (see
+    /// [`ResponseFault`]).
+    ///
+    /// ```
+    /// use virtual_serialport::{Device, ScriptedDevice};
+    ///
+    /// let mut device = ScriptedDevice::parse_scenario(
+    ///     r#"
+    ///     [[step]]
+    ///     expect = "AT\r"
+    ///     respond = "OK\r\n"
+    ///     "#,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(device.on_receive(b"AT\r"), b"OK\r\n");
+    /// device.verify();
+    /// ```
+    pub fn parse_scenario(text: &str) -> io::Result<Self> {
+        let scenario: Scenario = toml::from_str(text).map_err(invalid_data)?;
+        scenario
+            .step
+            .into_iter()
+            .try_fold(Self::new(), |device, entry| entry.append_to(device))
+    }
+}
+
+impl Entry {
+    // Appends the steps of the entry to the script of `device`
+    fn append_to(self, device: ScriptedDevice) -> io::Result<ScriptedDevice> {
+        let group = |entries: Vec<Entry>| {
+            entries
+                .into_iter()
+                .try_fold(ScriptedDevice::new(), |group, entry| entry.append_to(group))
+        };
+        match self {
+            Entry::Step(step) => step.append_to(device),
+            Entry::AnyOrder { any_order } => Ok(device.any_order(group(any_order)?)),
+            Entry::InSequence { in_sequence } => {
+                let group = group(in_sequence)?;
+                if !group.is_sequence() {
+                    return Err(invalid_data(
+                        "`in_sequence` groups can't contain `any_order` groups",
+                    ));
+                }
+                Ok(device.in_sequence(group))
+            }
+        }
+    }
+}
+
+impl StepSpec {
+    fn append_to(self, device: ScriptedDevice) -> io::Result<ScriptedDevice> {
+        let expected = bytes(self.expect, self.expect_hex, "expect")?
+            .ok_or_else(|| invalid_data("a step needs `expect` or `expect_hex`"))?;
+        let mut device = device.expect_write(&expected);
+        if let Some(response) = bytes(self.respond, self.respond_hex, "respond")? {
+            device = device.respond(&response);
+        }
+        if self.delay_ms > 0 {
+            device = device.delay(Duration::from_millis(self.delay_ms));
+        }
+        if let Some(count) = self.times {
+            device = device.times(count);
+        }
+        if self.optional {
+            device = device.optional();
+        }
+        if let Some(fault) = self.fault {
+            device = device.fault(match fault {
+                FaultSpec::Drop => ResponseFault::Drop,
+                FaultSpec::Truncate(len) => ResponseFault::Truncate(len),
+                FaultSpec::Corrupt(offset) => ResponseFault::Corrupt(offset),
+            });
+        }
+        Ok(device)
+    }
+}
+
+// Returns the data given as text or in hex under the key `name`
+fn bytes(text: Option<String>, hex: Option<String>, name: &str) -> io::Result<Option<Vec<u8>>> {
+    match (text, hex) {
+        (Some(_), Some(_)) => Err(invalid_data(format!(
+            "`{}` and `{}_hex` are exclusive",
+            name, name
+        ))),
+        (Some(text), None) => Ok(Some(text.into_bytes())),
+        (None, Some(hex)) => from_hex(&hex)
+            .map(Some)
+            .ok_or_else(|| invalid_data(format!("invalid hex in `{}_hex`: {}", name, hex))),
+        (None, None) => Ok(None),
+    }
+}
+
+// Parses hex digits, ignoring the whitespace between the bytes
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn invalid_data(message: impl fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid scenario: {}", message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Device;
+
+    #[test]
+    fn test_parse_scenario() {
+        let mut device = ScriptedDevice::parse_scenario(
+            r#"
+            [[step]]
+            expect = "AT\r"
+            respond = "OK\r\n"
+
+            [[step]]
+            expect_hex = "01 02"
+            respond_hex = "0304"
+            times = 2
+
+            [[step]]
+            expect = "ATI\r"
+            respond = "v1.0\r\n"
+            fault = { truncate = 2 }
+
+            [[step]]
+            any_order = [
+                { expect = "A", respond = "a", fault = "drop" },
+                { in_sequence = [{ expect = "B" }, { expect = "C", optional = true }] },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(device.on_receive(b"AT\r"), b"OK\r\n");
+        assert_eq!(device.on_receive(b"\x01\x02\x01\x02"), b"\x03\x04\x03\x04");
+        assert_eq!(device.on_receive(b"ATI\r"), b"v1");
+        assert_eq!(device.on_receive(b"BA"), b"");
+        device.verify();
+    }
+
+    #[test]
+    fn test_invalid_scenario() {
+        for text in [
+            "[[step]]\nrespond = \"OK\"",
+            "[[step]]\nexpect = \"AT\"\nexpect_hex = \"41\"",
+            "[[step]]\nexpect_hex = \"4\"",
+            "[[step]]\nexpect = \"AT\"\nfault = \"explode\"",
+            "[[step]]\nin_sequence = [{ any_order = [{ expect = \"A\" }, { expect = \"B\" }] }]",
+        ] {
+            let err = ScriptedDevice::parse_scenario(text).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,609 @@
+//! Mock-style device following a script of expected writes and responses.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use super::Device;
+use crate::VirtualPort;
+
+/// A device expecting a scripted sequence of writes, answering each with a
+/// canned response.
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use virtual_serialport::{DeviceRunner, ScriptedDevice, VirtualPort};
+///
+/// let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+/// let script = ScriptedDevice::new()
+///     .expect_write(b"AT\r")
+///     .respond(b"OK\r\n");
+/// let runner = DeviceRunner::spawn(port, script);
+///
+/// let mut response = [0u8; 4];
+/// app.write_all(b"AT\r").unwrap();
+/// app.read_exact(&mut response).unwrap();
+/// assert_eq!(&response, b"OK\r\n");
+///
+/// runner.stop().unwrap().verify();
+/// ```
+///
+/// Traffic that does not match the script stops all further responses and
+/// makes [`verify`](ScriptedDevice::verify) fail with a description of the
+/// difference.
+///
+/// Like the expectations of a mocking library, a step can be repeated with
+/// [`times`](ScriptedDevice::times) or made [`optional`](ScriptedDevice::optional),
+/// and the steps of a group built with [`any_order`](ScriptedDevice::any_order)
+/// may happen in any order, while the ones of an
+/// [`in_sequence`](ScriptedDevice::in_sequence) group keep theirs:
+///
+/// ```
+/// use virtual_serialport::{Device, ScriptedDevice};
+///
+/// let mut device = ScriptedDevice::new()
+///     .expect_write(b"PING\r")
+///     .respond(b"PONG\r")
+///     .times(2)
+///     .any_order(
+///         ScriptedDevice::new()
+///             .expect_write(b"LED ON\r")
+///             .in_sequence(
+///                 ScriptedDevice::new()
+///                     .expect_write(b"OPEN\r")
+///                     .expect_write(b"CLOSE\r"),
+///             ),
+///     )
+///     .expect_write(b"QUIT\r")
+///     .optional();
+///
+/// device.on_receive(b"PING\rPING\r");
+/// device.on_receive(b"OPEN\rLED ON\rCLOSE\r");
+/// device.verify();
+/// ```
+#[derive(Debug, Default)]
+pub struct ScriptedDevice {
+    steps: Vec<Step>,
+
+    // Ordered runs of steps, and the groups of runs which may interleave
+    chains: Vec<Chain>,
+    groups: Vec<Group>,
+
+    // Index of the group currently expected
+    current: usize,
+
+    // Received data not matched by a step yet
+    pending: Vec<u8>,
+
+    // Description of the first deviation from the script
+    failure: Option<String>,
+
+    // Delayed responses not sent yet, with the time they are due
+    delayed: VecDeque<(Instant, Vec<u8>)>,
+}
+
+/// Fault a [`ScriptedDevice`] applies to the response of a step, set with
+/// [`ScriptedDevice::fault`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFault {
+    /// The response is not sent.
+    Drop,
+
+    /// Only the given number of bytes of the response is sent.
+    Truncate(usize),
+
+    /// The lowest bit of the byte at the given offset of the response is
+    /// flipped.
+    Corrupt(usize),
+}
+
+impl ResponseFault {
+    fn apply(self, response: &mut Vec<u8>) {
+        match self {
+            ResponseFault::Drop => response.clear(),
+            ResponseFault::Truncate(len) => response.truncate(len),
+            ResponseFault::Corrupt(offset) => {
+                if let Some(byte) = response.get_mut(offset) {
+                    *byte ^= 1;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Step {
+    expected: Vec<u8>,
+    response: Vec<u8>,
+
+    // Time the response takes, and the fault applied to it
+    delay: Duration,
+    fault: Option<ResponseFault>,
+
+    // Bounds of the number of times the step is expected, the number of
+    // times it happened, and the chain it belongs to
+    min: usize,
+    max: usize,
+    count: usize,
+    chain: usize,
+}
+
+// Steps which happen in order, the first of which may still happen
+#[derive(Debug)]
+struct Chain {
+    position: usize,
+    end: usize,
+    group: usize,
+}
+
+// Chains whose steps may interleave
+#[derive(Debug)]
+struct Group {
+    chains: Range<usize>,
+}
+
+impl ScriptedDevice {
+    /// Creates a device with an empty script.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a step expecting `data` to be written to the device.
+    pub fn expect_write(mut self, data: &[u8]) -> Self {
+        self.chains.push(Chain {
+            position: self.steps.len(),
+            end: self.steps.len() + 1,
+            group: self.groups.len(),
+        });
+        self.groups.push(Group {
+            chains: self.chains.len() - 1..self.chains.len(),
+        });
+        self.steps.push(Step {
+            expected: data.to_vec(),
+            response: Vec::new(),
+            delay: Duration::ZERO,
+            fault: None,
+            min: 1,
+            max: 1,
+            count: 0,
+            chain: self.chains.len() - 1,
+        });
+        self
+    }
+
+    /// Sets the response sent once the data of the last step is written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step has been added with
+    /// [`expect_write`](ScriptedDevice::expect_write).
+    pub fn respond(mut self, data: &[u8]) -> Self {
+        self.last_step("respond").response.extend_from_slice(data);
+        self
+    }
+
+    /// Delays the response of the last step, as a slow device would. The
+    /// responses are still sent in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step has been added with
+    /// [`expect_write`](ScriptedDevice::expect_write).
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.last_step("delay").delay = delay;
+        self
+    }
+
+    /// Applies `fault` to the response of the last step, e.g., to test how
+    /// the application handles a lost or damaged answer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step has been added with
+    /// [`expect_write`](ScriptedDevice::expect_write).
+    pub fn fault(mut self, fault: ResponseFault) -> Self {
+        self.last_step("fault").fault = Some(fault);
+        self
+    }
+
+    /// Expects the last step to happen exactly `count` times in a row,
+    /// answered each time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step has been added with
+    /// [`expect_write`](ScriptedDevice::expect_write).
+    pub fn times(mut self, count: usize) -> Self {
+        let step = self.last_step("times");
+        if step.min > 0 {
+            step.min = count;
+        }
+        step.max = count;
+        self
+    }
+
+    /// Lets the last step not happen at all, e.g., for a command the
+    /// application only sends in some cases.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no step has been added with
+    /// [`expect_write`](ScriptedDevice::expect_write).
+    pub fn optional(mut self) -> Self {
+        self.last_step("optional").min = 0;
+        self
+    }
+
+    /// Appends the steps of `group`, which may happen in any order, as a
+    /// single step. The steps of its [`in_sequence`](ScriptedDevice::in_sequence)
+    /// groups keep their order.
+    pub fn any_order(mut self, group: ScriptedDevice) -> Self {
+        let (steps, chains) = (self.steps.len(), self.chains.len());
+        let index = self.groups.len();
+        self.steps.extend(group.steps.into_iter().map(|step| Step {
+            chain: step.chain + chains,
+            ..step
+        }));
+        self.chains
+            .extend(group.chains.into_iter().map(|chain| Chain {
+                position: chain.position + steps,
+                end: chain.end + steps,
+                group: index,
+            }));
+        self.groups.push(Group {
+            chains: chains..self.chains.len(),
+        });
+        self
+    }
+
+    /// Appends the steps of `group`, which happen in order, as a single
+    /// step (which only matters within an [`any_order`](ScriptedDevice::any_order)
+    /// group).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `group` contains an `any_order` group.
+    pub fn in_sequence(mut self, group: ScriptedDevice) -> Self {
+        assert!(
+            group.is_sequence(),
+            "`in_sequence` groups can't contain `any_order` groups"
+        );
+        let steps = self.steps.len();
+        let chain = self.chains.len();
+        self.steps
+            .extend(group.steps.into_iter().map(|step| Step { chain, ..step }));
+        self.chains.push(Chain {
+            position: steps,
+            end: self.steps.len(),
+            group: self.groups.len(),
+        });
+        self.groups.push(Group {
+            chains: chain..chain + 1,
+        });
+        self
+    }
+
+    /// Returns whether the steps of the script all happen in order.
+    pub(super) fn is_sequence(&self) -> bool {
+        self.groups.iter().all(|group| group.chains.len() == 1)
+    }
+
+    // Returns the last step for `method` to modify
+    fn last_step(&mut self, method: &str) -> &mut Step {
+        match self.steps.last_mut() {
+            Some(step) => step,
+            None => panic!("`{}` must follow `expect_write`", method),
+        }
+    }
+
+    /// Returns whether every step has been completed and answered.
+    pub fn is_done(&self) -> bool {
+        self.steps.iter().all(|step| step.count >= step.min)
+            && self.pending.is_empty()
+            && self.delayed.is_empty()
+    }
+
+    /// Checks that the whole script has been followed exactly.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a description of the difference if unexpected data was
+    /// written, or if some of the expected writes have not happened.
+    pub fn verify(&self) {
+        if let Err(message) = self.check() {
+            panic!("{}", message);
+        }
+    }
+
+    /// Checks that the whole script has been followed exactly, returning a
+    /// description of the difference otherwise.
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(failure) = &self.failure {
+            return Err(failure.clone());
+        }
+        match self.steps.iter().position(|step| step.count < step.min) {
+            Some(index) => Err(self.describe("missing write", &[index])),
+            None => Ok(()),
+        }
+    }
+
+    // Returns the steps which may happen next, in the order of the script:
+    // the ones of the current group, and of the following groups as long as
+    // the steps before them may be skipped
+    fn candidates(&self) -> Vec<usize> {
+        let mut candidates = Vec::new();
+        for group in &self.groups[self.current.min(self.groups.len())..] {
+            let mut satisfied = true;
+            for chain in &self.chains[group.chains.clone()] {
+                for index in chain.position..chain.end {
+                    let step = &self.steps[index];
+                    if step.count < step.max {
+                        candidates.push(index);
+                    }
+                    if step.count < step.min {
+                        satisfied = false;
+                        break;
+                    }
+                }
+            }
+            if !satisfied {
+                break;
+            }
+        }
+        candidates
+    }
+
+    // Counts the step as happened, returning its response unless it is
+    // delayed
+    fn complete(&mut self, index: usize) -> Vec<u8> {
+        let step = &mut self.steps[index];
+        step.count += 1;
+        self.pending.drain(..step.expected.len());
+        let chain = &mut self.chains[step.chain];
+        chain.position = index;
+        self.current = chain.group;
+
+        let mut response = step.response.clone();
+        if let Some(fault) = step.fault {
+            fault.apply(&mut response);
+        }
+        if step.delay.is_zero() && self.delayed.is_empty() {
+            return response;
+        }
+        let mut due = Instant::now() + step.delay;
+        if let Some(&(last, _)) = self.delayed.back() {
+            due = due.max(last);
+        }
+        self.delayed.push_back((due, response));
+        Vec::new()
+    }
+
+    // Formats the deviation from the script at the given candidate steps
+    fn describe(&self, problem: &str, candidates: &[usize]) -> String {
+        let first = &self.steps[candidates[0]];
+        let mut message = format!(
+            "scripted device: {} at step {} of {}",
+            problem,
+            candidates[0] + 1,
+            self.steps.len()
+        );
+        if first.max > 1 {
+            let _ = write!(
+                message,
+                " (repetition {} of {})",
+                first.count + 1,
+                first.max
+            );
+        }
+        message.push('\n');
+        for (position, &index) in candidates.iter().enumerate() {
+            let label = if position == 0 {
+                "expected"
+            } else {
+                "      or"
+            };
+            let _ = writeln!(
+                message,
+                "  {}: {}",
+                label,
+                format_bytes(&self.steps[index].expected)
+            );
+        }
+        let _ = writeln!(message, "  received: {}", format_bytes(&self.pending));
+
+        let offset = first
+            .expected
+            .iter()
+            .zip(&self.pending)
+            .take_while(|(expected, received)| expected == received)
+            .count();
+        let _ = write!(message, "  first difference at offset {}", offset);
+        message
+    }
+}
+
+impl Device for ScriptedDevice {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(data);
+        if self.failure.is_some() {
+            return Vec::new();
+        }
+
+        let mut response = Vec::new();
+        while !self.pending.is_empty() {
+            let candidates = self.candidates();
+            if candidates.is_empty() {
+                self.failure = Some(format!(
+                    "scripted device: unexpected write after the last step\n  received: {}",
+                    format_bytes(&self.pending)
+                ));
+                break;
+            }
+
+            let pending = &self.pending;
+            let matched = candidates
+                .iter()
+                .copied()
+                .find(|&index| pending.starts_with(&self.steps[index].expected));
+            if let Some(index) = matched {
+                response.extend(self.complete(index));
+                continue;
+            }
+            if candidates
+                .iter()
+                .any(|&index| self.steps[index].expected.starts_with(pending))
+            {
+                // Wait for the rest of the expected data
+                break;
+            }
+            self.failure = Some(self.describe("unexpected write", &candidates));
+            break;
+        }
+        response
+    }
+
+    fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        let now = Instant::now();
+        let mut response = Vec::new();
+        while self.delayed.front().map_or(false, |&(due, _)| due <= now) {
+            if let Some((_, data)) = self.delayed.pop_front() {
+                response.extend(data);
+            }
+        }
+        response
+    }
+}
+
+// Formats bytes as an escaped string followed by their hex dump
+fn format_bytes(data: &[u8]) -> String {
+    let hex: Vec<String> = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\"{}\" [{}]", data.escape_ascii(), hex.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_in_order() {
+        let mut device = ScriptedDevice::new()
+            .expect_write(b"AT\r")
+            .respond(b"OK\r\n")
+            .expect_write(b"ATI\r")
+            .respond(b"v1.0\r\n");
+
+        // Writes may be split or merged arbitrarily
+        assert_eq!(device.on_receive(b"A"), b"");
+        assert_eq!(device.on_receive(b"T\rATI"), b"OK\r\n");
+        assert!(device.check().is_err());
+        assert_eq!(device.on_receive(b"\r"), b"v1.0\r\n");
+
+        assert!(device.is_done());
+        device.verify();
+    }
+
+    #[test]
+    fn test_script_mismatch() {
+        let mut device = ScriptedDevice::new()
+            .expect_write(b"ATI\r")
+            .respond(b"v1.0\r\n");
+
+        assert_eq!(device.on_receive(b"ATZ\r"), b"");
+        let message = device.check().unwrap_err();
+        assert!(message.contains("unexpected write at step 1 of 1"));
+        assert!(message.contains("\"ATI\\r\" [41 54 49 0d]"));
+        assert!(message.contains("\"ATZ\\r\" [41 54 5a 0d]"));
+        assert!(message.contains("offset 2"));
+
+        // Nothing is answered after a deviation
+        assert_eq!(device.on_receive(b"ATI\r"), b"");
+    }
+
+    #[test]
+    fn test_script_times() {
+        let mut device = ScriptedDevice::new()
+            .expect_write(b"PING\r")
+            .respond(b"PONG\r")
+            .times(3)
+            .expect_write(b"ATZ\r")
+            .optional()
+            .expect_write(b"QUIT\r");
+
+        assert_eq!(device.on_receive(b"PING\rPING\r"), b"PONG\rPONG\r");
+        let message = device.check().unwrap_err();
+        assert!(message.contains("missing write at step 1 of 3 (repetition 3 of 3)"));
+
+        // The optional step is skipped, and a fourth repetition is unexpected
+        assert_eq!(device.on_receive(b"PING\rQUIT\r"), b"PONG\r");
+        device.verify();
+        device.on_receive(b"PING\r");
+        let message = device.check().unwrap_err();
+        assert!(message.contains("unexpected write after the last step"));
+    }
+
+    #[test]
+    fn test_script_any_order() {
+        let script = || {
+            ScriptedDevice::new()
+                .expect_write(b"INIT\r")
+                .any_order(
+                    ScriptedDevice::new()
+                        .expect_write(b"A\r")
+                        .respond(b"a")
+                        .in_sequence(
+                            ScriptedDevice::new()
+                                .expect_write(b"B\r")
+                                .respond(b"b")
+                                .expect_write(b"C\r")
+                                .respond(b"c"),
+                        ),
+                )
+                .expect_write(b"DONE\r")
+        };
+
+        let mut device = script();
+        assert_eq!(device.on_receive(b"INIT\rB\rA\rC\rDONE\r"), b"bac");
+        device.verify();
+
+        // The sequence keeps its order, and the failure lists what was
+        // expected instead
+        let mut device = script();
+        assert_eq!(device.on_receive(b"INIT\rC\r"), b"");
+        let message = device.check().unwrap_err();
+        assert!(message.contains("unexpected write at step 2 of 5"));
+        assert!(message.contains("expected: \"A\\r\""));
+        assert!(message.contains("      or: \"B\\r\""));
+    }
+
+    #[test]
+    fn test_script_delay_and_fault() {
+        let (mut port, _peer) = VirtualPort::pair(9600, 1024).unwrap();
+        let mut device = ScriptedDevice::new()
+            .expect_write(b"A")
+            .respond(b"slow")
+            .delay(Duration::from_millis(20))
+            .expect_write(b"B")
+            .respond(b"\x10\x20")
+            .fault(ResponseFault::Corrupt(1));
+
+        // The responses keep their order behind a delayed one
+        assert_eq!(device.on_receive(b"AB"), b"");
+        assert_eq!(device.on_poll(&mut port), b"");
+        assert!(!device.is_done());
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(device.on_poll(&mut port), b"slow\x10\x21");
+        assert!(device.is_done());
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected write")]
+    fn test_script_extra_write() {
+        let mut device = ScriptedDevice::new().expect_write(b"AT\r");
+        device.on_receive(b"AT\rAT\r");
+        device.verify();
+    }
+}
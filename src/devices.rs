@@ -0,0 +1,21 @@
+//! Built-in emulations of common serial peripherals.
+//!
+//! Every device implements [`Device`](crate::Device), so it is attached to
+//! one end of a pair with [`DeviceRunner`](crate::DeviceRunner), while the
+//! code under test talks to the other end.
+
+mod at_modem;
+mod elm327;
+mod escpos_printer;
+mod gsm_modem;
+mod nmea_gps;
+mod scpi_instrument;
+mod xmodem;
+
+pub use at_modem::{AtModem, DialResult};
+pub use elm327::Elm327;
+pub use escpos_printer::{Alignment, Cut, EscPosPrinter, PrintedLine, Receipt, Span, TextStyle};
+pub use gsm_modem::{GsmModem, NetworkStatus, Sms};
+pub use nmea_gps::{NmeaGps, Satellite};
+pub use scpi_instrument::{ScpiError, ScpiInstrument};
+pub use xmodem::{TransferredFile, XmodemReceiver, XmodemSender};
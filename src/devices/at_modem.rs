@@ -0,0 +1,647 @@
+//! Hayes-compatible AT modem.
+
+use std::time::{Duration, Instant};
+
+use serialport::SerialPort;
+
+use crate::{Device, VirtualPort};
+
+// S-registers with a special meaning
+const S_AUTO_ANSWER: usize = 0;
+const S_RING_COUNT: usize = 1;
+const S_ESCAPE: usize = 2;
+const S_CARRIAGE_RETURN: usize = 3;
+const S_LINE_FEED: usize = 4;
+const S_BACKSPACE: usize = 5;
+const S_GUARD_TIME: usize = 12;
+
+const DEFAULT_REGISTERS: [(usize, u8); 7] = [
+    (S_AUTO_ANSWER, 0),
+    (S_RING_COUNT, 0),
+    (S_ESCAPE, b'+'),
+    (S_CARRIAGE_RETURN, b'\r'),
+    (S_LINE_FEED, b'\n'),
+    (S_BACKSPACE, 8),
+    (S_GUARD_TIME, 50),
+];
+
+const IDENTIFICATION: &str = "virtual-serialport AT modem";
+
+/// Outcome of a dial command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DialResult {
+    /// The remote side answers and the modem goes online (`CONNECT`).
+    Connect,
+
+    /// The line is busy (`BUSY`).
+    Busy,
+
+    /// The remote side does not answer (`NO ANSWER`).
+    NoAnswer,
+
+    /// The connection cannot be established (`NO CARRIER`).
+    NoCarrier,
+
+    /// There is no dial tone (`NO DIALTONE`).
+    NoDialtone,
+}
+
+// Result codes with their numeric forms
+#[derive(Clone, Copy)]
+enum Response {
+    Ok,
+    Connect,
+    Ring,
+    NoCarrier,
+    Error,
+    NoDialtone,
+    Busy,
+    NoAnswer,
+}
+
+impl Response {
+    fn text(self) -> &'static str {
+        match self {
+            Response::Ok => "OK",
+            Response::Connect => "CONNECT",
+            Response::Ring => "RING",
+            Response::NoCarrier => "NO CARRIER",
+            Response::Error => "ERROR",
+            Response::NoDialtone => "NO DIALTONE",
+            Response::Busy => "BUSY",
+            Response::NoAnswer => "NO ANSWER",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Response::Ok => 0,
+            Response::Connect => 1,
+            Response::Ring => 2,
+            Response::NoCarrier => 3,
+            Response::Error => 4,
+            Response::NoDialtone => 6,
+            Response::Busy => 7,
+            Response::NoAnswer => 8,
+        }
+    }
+}
+
+impl From<DialResult> for Response {
+    fn from(result: DialResult) -> Self {
+        match result {
+            DialResult::Connect => Response::Connect,
+            DialResult::Busy => Response::Busy,
+            DialResult::NoAnswer => Response::NoAnswer,
+            DialResult::NoCarrier => Response::NoCarrier,
+            DialResult::NoDialtone => Response::NoDialtone,
+        }
+    }
+}
+
+/// An emulated Hayes-compatible modem.
+///
+/// The modem supports the following subset of the command set:
+///
+/// - `E0`/`E1` (command echo), `Q0`/`Q1` (quiet mode), `V0`/`V1` (numeric
+///   or verbose result codes), `Z` and `&F` (reset), `I` (identification);
+/// - `D<number>` (dial), `A` (answer), `H` (hang up), `O` (return online);
+/// - `S<n>=<value>` and `S<n>?` (S-registers: `S0` auto-answer rings, `S1`
+///   ring count, `S2` escape character, `S3`/`S4`/`S5` line characters and
+///   `S12` escape guard time in 1/50 s);
+/// - the `+++` escape sequence surrounded by the guard time while online.
+///
+/// While connected, the carrier detect (CD) line of the peer is raised, and
+/// it is lowered on hang-up. An incoming call started with
+/// [`ring`](AtModem::ring) raises the ring indicator (RI) until the call is
+/// answered or abandoned. Dropping DTR hangs up an active connection.
+///
+/// The data sent online is collected for the test (see
+/// [`take_remote_data`](AtModem::take_remote_data)), and the remote side
+/// sends data with [`send_from_remote`](AtModem::send_from_remote).
+#[derive(Debug)]
+pub struct AtModem {
+    echo: bool,
+    quiet: bool,
+    verbose: bool,
+    registers: [u8; 256],
+
+    dial_results: Vec<(String, DialResult)>,
+    connect_speed: Option<u32>,
+
+    // Command line being received
+    line: Vec<u8>,
+
+    // Whether a connection is established, and whether the modem is online
+    // (as opposed to in command mode during a connection)
+    connected: bool,
+    online: bool,
+    ringing: bool,
+
+    // Escape sequence characters received after a guard time, and when the
+    // last one or the last data arrived
+    escape_count: usize,
+    last_data: Instant,
+
+    dialed: Vec<String>,
+    remote_rx: Vec<u8>,
+    remote_tx: Vec<u8>,
+
+    // Data to be sent to the port
+    output: Vec<u8>,
+}
+
+impl Default for AtModem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AtModem {
+    /// Creates a modem in command mode with factory settings, which connects
+    /// to every dialed number.
+    pub fn new() -> Self {
+        let mut modem = Self {
+            echo: true,
+            quiet: false,
+            verbose: true,
+            registers: [0; 256],
+            dial_results: Vec::new(),
+            connect_speed: None,
+            line: Vec::new(),
+            connected: false,
+            online: false,
+            ringing: false,
+            escape_count: 0,
+            last_data: Instant::now(),
+            dialed: Vec::new(),
+            remote_rx: Vec::new(),
+            remote_tx: Vec::new(),
+            output: Vec::new(),
+        };
+        modem.reset();
+        modem
+    }
+
+    /// Sets the outcome of dialing `number`.
+    pub fn with_dial_result(mut self, number: &str, result: DialResult) -> Self {
+        self.dial_results.push((number.to_string(), result));
+        self
+    }
+
+    /// Reports `speed` in the verbose `CONNECT` result (e.g., `CONNECT 9600`).
+    pub fn with_connect_speed(mut self, speed: u32) -> Self {
+        self.connect_speed = Some(speed);
+        self
+    }
+
+    /// Returns whether a connection is established.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Returns whether the modem is online, passing data to the remote side.
+    pub fn is_online(&self) -> bool {
+        self.online
+    }
+
+    /// Returns the numbers dialed so far.
+    pub fn dialed(&self) -> &[String] {
+        &self.dialed
+    }
+
+    /// Returns the value of an S-register.
+    pub fn register(&self, index: u8) -> u8 {
+        self.registers[index as usize]
+    }
+
+    /// Takes the data sent to the remote side while online.
+    pub fn take_remote_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.remote_rx)
+    }
+
+    /// Sends data from the remote side, delivered while the modem is online.
+    pub fn send_from_remote(&mut self, data: &[u8]) {
+        self.remote_tx.extend_from_slice(data);
+    }
+
+    /// Starts an incoming call: `RING` is reported and RI is raised.
+    pub fn ring(&mut self) {
+        if self.connected {
+            return;
+        }
+
+        self.ringing = true;
+        self.registers[S_RING_COUNT] = self.registers[S_RING_COUNT].saturating_add(1);
+        self.respond(Response::Ring);
+
+        let auto_answer = self.registers[S_AUTO_ANSWER];
+        if auto_answer > 0 && self.registers[S_RING_COUNT] >= auto_answer {
+            self.answer();
+        }
+    }
+
+    /// Ends the connection or the incoming call from the remote side.
+    pub fn hang_up_remote(&mut self) {
+        self.ringing = false;
+        self.registers[S_RING_COUNT] = 0;
+        if self.connected {
+            self.hang_up();
+            self.respond(Response::NoCarrier);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.echo = true;
+        self.quiet = false;
+        self.verbose = true;
+        self.registers = [0; 256];
+        for (index, value) in DEFAULT_REGISTERS {
+            self.registers[index] = value;
+        }
+    }
+
+    fn guard_time(&self) -> Duration {
+        Duration::from_millis(self.registers[S_GUARD_TIME] as u64 * 20)
+    }
+
+    fn respond(&mut self, response: Response) {
+        if self.quiet {
+            return;
+        }
+
+        let text = match (response, self.connect_speed) {
+            (Response::Connect, Some(speed)) => format!("CONNECT {}", speed),
+            _ => response.text().to_string(),
+        };
+        if self.verbose {
+            self.respond_line(&text);
+        } else {
+            self.output
+                .extend_from_slice(response.code().to_string().as_bytes());
+            self.output.push(self.registers[S_CARRIAGE_RETURN]);
+        }
+    }
+
+    // Sends an information line framed like a verbose result
+    fn respond_line(&mut self, text: &str) {
+        let (cr, lf) = (
+            self.registers[S_CARRIAGE_RETURN],
+            self.registers[S_LINE_FEED],
+        );
+        self.output.extend_from_slice(&[cr, lf]);
+        self.output.extend_from_slice(text.as_bytes());
+        self.output.extend_from_slice(&[cr, lf]);
+    }
+
+    fn connect(&mut self) {
+        self.connected = true;
+        self.online = true;
+        self.ringing = false;
+        self.registers[S_RING_COUNT] = 0;
+        self.escape_count = 0;
+        self.last_data = Instant::now();
+        self.respond(Response::Connect);
+    }
+
+    fn answer(&mut self) {
+        if self.ringing {
+            self.connect();
+        } else {
+            self.respond(Response::NoCarrier);
+        }
+    }
+
+    fn hang_up(&mut self) {
+        self.connected = false;
+        self.online = false;
+    }
+
+    fn receive_online(&mut self, data: &[u8]) {
+        let escape = self.registers[S_ESCAPE];
+        for &byte in data {
+            let now = Instant::now();
+            let guarded = now.duration_since(self.last_data) >= self.guard_time();
+            if byte == escape && self.escape_count < 3 && (self.escape_count > 0 || guarded) {
+                self.escape_count += 1;
+            } else {
+                // Not an escape sequence: the characters were data
+                self.remote_rx
+                    .extend(std::iter::repeat(escape).take(self.escape_count));
+                self.escape_count = 0;
+                self.remote_rx.push(byte);
+            }
+            self.last_data = now;
+        }
+    }
+
+    // Handles the data received in command mode, returning how much of it
+    // was consumed before the modem went online
+    fn receive_command(&mut self, data: &[u8]) -> usize {
+        for (pos, &byte) in data.iter().enumerate() {
+            if self.echo {
+                self.output.push(byte);
+            }
+
+            if byte == self.registers[S_CARRIAGE_RETURN] {
+                let line = std::mem::take(&mut self.line);
+                self.execute(&line);
+
+                // The rest of the data follows a successful `D`, `A` or `O`
+                if self.online {
+                    return pos + 1;
+                }
+            } else if byte == self.registers[S_BACKSPACE] {
+                self.line.pop();
+            } else if byte != self.registers[S_LINE_FEED] {
+                self.line.push(byte);
+            }
+        }
+        data.len()
+    }
+
+    // Executes a command line, ignoring lines without the `AT` prefix
+    fn execute(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line).trim().to_ascii_uppercase();
+        let commands = match line.strip_prefix("AT") {
+            Some(commands) => commands.as_bytes(),
+            None => return,
+        };
+
+        let mut pos = 0;
+        while pos < commands.len() {
+            let command = commands[pos];
+            pos += 1;
+
+            // Numeric argument of the command, if any
+            let number_start = pos;
+            while pos < commands.len() && commands[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            let argument: Option<usize> = std::str::from_utf8(&commands[number_start..pos])
+                .ok()
+                .and_then(|digits| digits.parse().ok());
+
+            let ok = match command {
+                b' ' => true,
+                b'E' => set_flag(&mut self.echo, argument),
+                b'Q' => set_flag(&mut self.quiet, argument),
+                b'V' => set_flag(&mut self.verbose, argument),
+                b'Z' => {
+                    self.reset();
+                    true
+                }
+                b'&' if pos < commands.len() => {
+                    let option = commands[pos];
+                    pos += 1;
+                    while pos < commands.len() && commands[pos].is_ascii_digit() {
+                        pos += 1;
+                    }
+                    if option == b'F' {
+                        self.reset();
+                    }
+                    true
+                }
+                b'I' => {
+                    self.respond_line(IDENTIFICATION);
+                    true
+                }
+                b'H' => {
+                    self.hang_up();
+                    true
+                }
+                b'S' => match (argument, commands.get(pos)) {
+                    (Some(index), Some(b'?')) if index < 256 => {
+                        pos += 1;
+                        let value = format!("{:03}", self.registers[index]);
+                        self.respond_line(&value);
+                        true
+                    }
+                    (Some(index), Some(b'=')) if index < 256 => {
+                        pos += 1;
+                        let value_start = pos;
+                        while pos < commands.len() && commands[pos].is_ascii_digit() {
+                            pos += 1;
+                        }
+                        match std::str::from_utf8(&commands[value_start..pos])
+                            .ok()
+                            .and_then(|digits| digits.parse::<u8>().ok())
+                        {
+                            Some(value) => {
+                                self.registers[index] = value;
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    _ => false,
+                },
+                b'D' => {
+                    // The number takes the rest of the line
+                    let number: String = String::from_utf8_lossy(&commands[number_start..])
+                        .trim_start_matches(['T', 'P'])
+                        .chars()
+                        .filter(|c| !c.is_whitespace())
+                        .collect();
+                    self.dial(number);
+                    return;
+                }
+                b'A' => {
+                    self.answer();
+                    return;
+                }
+                b'O' => {
+                    if self.connected {
+                        self.online = true;
+                        self.last_data = Instant::now();
+                        self.respond(Response::Connect);
+                    } else {
+                        self.respond(Response::NoCarrier);
+                    }
+                    return;
+                }
+                _ => false,
+            };
+
+            if !ok {
+                self.respond(Response::Error);
+                return;
+            }
+        }
+        self.respond(Response::Ok);
+    }
+
+    fn dial(&mut self, number: String) {
+        let result = self
+            .dial_results
+            .iter()
+            .find(|(dialed, _)| *dialed == number)
+            .map(|(_, result)| *result)
+            .unwrap_or(DialResult::Connect);
+        self.dialed.push(number);
+
+        if result == DialResult::Connect {
+            self.connect();
+        } else {
+            self.respond(result.into());
+        }
+    }
+}
+
+// Applies a `0`/`1` argument to a flag, returning whether it was valid
+fn set_flag(flag: &mut bool, argument: Option<usize>) -> bool {
+    match argument.unwrap_or(0) {
+        0 => *flag = false,
+        1 => *flag = true,
+        _ => return false,
+    }
+    true
+}
+
+impl Device for AtModem {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        let consumed = if self.online {
+            0
+        } else {
+            self.receive_command(data)
+        };
+        if consumed < data.len() {
+            self.receive_online(&data[consumed..]);
+        }
+        std::mem::take(&mut self.output)
+    }
+
+    fn on_start(&mut self, port: &mut VirtualPort) -> Vec<u8> {
+        port.set_carrier_detect(Some(false));
+        port.set_ring_indicator(false);
+        Vec::new()
+    }
+
+    fn on_poll(&mut self, port: &mut VirtualPort) -> Vec<u8> {
+        // Completes the escape sequence after the trailing guard time
+        if self.online && self.escape_count == 3 && self.last_data.elapsed() >= self.guard_time() {
+            self.online = false;
+            self.escape_count = 0;
+            self.respond(Response::Ok);
+        }
+
+        // Dropping DTR hangs up
+        if self.connected && !port.read_data_set_ready().unwrap_or(true) {
+            self.hang_up();
+            self.respond(Response::NoCarrier);
+        }
+
+        if self.online {
+            self.output.append(&mut self.remote_tx);
+        }
+
+        port.set_carrier_detect(Some(self.connected));
+        port.set_ring_indicator(self.ringing);
+        std::mem::take(&mut self.output)
+    }
+
+    fn on_stop(&mut self, port: &mut VirtualPort) {
+        port.set_carrier_detect(None);
+        port.set_ring_indicator(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+    };
+
+    use super::*;
+    use crate::DeviceRunner;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn expect(port: &mut VirtualPort, expected: &[u8]) {
+        let mut buf = vec![0u8; expected.len()];
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            buf.escape_ascii().to_string(),
+            expected.escape_ascii().to_string()
+        );
+    }
+
+    #[test]
+    fn test_at_modem_commands() {
+        let mut modem = AtModem::new();
+
+        assert_eq!(modem.on_receive(b"AT\r"), b"AT\r\r\nOK\r\n");
+        assert_eq!(modem.on_receive(b"ATE0\r"), b"ATE0\r\r\nOK\r\n");
+        assert_eq!(modem.on_receive(b"ats0=2 s0?\r"), b"\r\n002\r\n\r\nOK\r\n");
+        assert_eq!(modem.register(0), 2);
+        assert_eq!(modem.on_receive(b"ATX\r"), b"\r\nERROR\r\n");
+
+        // Numeric result codes
+        assert_eq!(modem.on_receive(b"ATV0\r"), b"0\r");
+        assert_eq!(modem.on_receive(b"ATDT123\r"), b"1\r");
+        assert_eq!(modem.dialed(), ["123"]);
+        assert!(modem.is_online());
+    }
+
+    #[test]
+    fn test_at_modem_dial_results() {
+        let mut modem = AtModem::new()
+            .with_dial_result("555", DialResult::Busy)
+            .with_connect_speed(9600);
+
+        // The command line is echoed before `E0` takes effect
+        assert_eq!(modem.on_receive(b"ATE0D555\r"), b"ATE0D555\r\r\nBUSY\r\n");
+        assert!(!modem.is_connected());
+        assert_eq!(
+            modem.on_receive(b"ATD556\rpayload"),
+            b"\r\nCONNECT 9600\r\n"
+        );
+        assert_eq!(modem.take_remote_data(), b"payload");
+    }
+
+    #[test]
+    fn test_at_modem_session() {
+        let (mut app, port) = VirtualPort::pair(9600, 1024).unwrap();
+        app.set_timeout(TIMEOUT).unwrap();
+        let runner = DeviceRunner::spawn(port, AtModem::new());
+
+        // A short guard time keeps the test fast
+        app.write_all(b"ATE0S12=2\r").unwrap();
+        expect(&mut app, b"ATE0S12=2\r\r\nOK\r\n");
+        assert!(!app.read_carrier_detect().unwrap());
+
+        app.write_all(b"ATD5551234\r").unwrap();
+        expect(&mut app, b"\r\nCONNECT\r\n");
+        assert!(app.read_carrier_detect().unwrap());
+
+        // Online data reaches the remote side and back
+        app.write_all(b"data+").unwrap();
+        runner.with_device(|modem| modem.send_from_remote(b"reply"));
+        expect(&mut app, b"reply");
+
+        thread::sleep(Duration::from_millis(100));
+        app.write_all(b"+++").unwrap();
+        expect(&mut app, b"\r\nOK\r\n");
+        assert!(runner.with_device(|modem| modem.is_connected() && !modem.is_online()));
+        assert_eq!(runner.with_device(AtModem::take_remote_data), b"data+");
+
+        app.write_all(b"ATH\r").unwrap();
+        expect(&mut app, b"\r\nOK\r\n");
+        assert!(!app.read_carrier_detect().unwrap());
+
+        // Incoming call
+        runner.with_device(AtModem::ring);
+        expect(&mut app, b"\r\nRING\r\n");
+        assert!(app.read_ring_indicator().unwrap());
+        app.write_all(b"ATA\r").unwrap();
+        expect(&mut app, b"\r\nCONNECT\r\n");
+        assert!(!app.read_ring_indicator().unwrap());
+
+        // Dropping DTR hangs up
+        app.write_data_terminal_ready(false).unwrap();
+        expect(&mut app, b"\r\nNO CARRIER\r\n");
+
+        runner.stop().unwrap();
+    }
+}
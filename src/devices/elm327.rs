@@ -0,0 +1,542 @@
+//! ELM327 OBD-II adapter.
+
+use crate::Device;
+
+const VERSION: &str = "ELM327 v1.5";
+const DESCRIPTION: &str = "virtual-serialport OBD-II adapter";
+const PROMPT: u8 = b'>';
+
+// CAN protocol with 11-bit identifiers at 500 kbit/s
+const DEFAULT_PROTOCOL: u8 = 6;
+
+const PROTOCOLS: [&str; 9] = [
+    "SAE J1850 PWM",
+    "SAE J1850 VPW",
+    "ISO 9141-2",
+    "ISO 14230-4 (KWP 5BAUD)",
+    "ISO 14230-4 (KWP FAST)",
+    "ISO 15765-4 (CAN 11/500)",
+    "ISO 15765-4 (CAN 29/500)",
+    "ISO 15765-4 (CAN 11/250)",
+    "ISO 15765-4 (CAN 29/250)",
+];
+
+fn is_can(protocol: u8) -> bool {
+    (6..=9).contains(&protocol)
+}
+
+// CRC-8/SAE-J1850 of the legacy J1850 frames
+fn crc8_j1850(data: &[u8]) -> u8 {
+    !data.iter().fold(0xFF, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc: u8, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x1D
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// An emulated ELM327 OBD-II adapter connected to a vehicle.
+///
+/// The adapter handles the usual initialization commands (`ATZ`, `ATD`,
+/// `ATI`, `AT@1`, `ATE`, `ATL`, `ATS`, `ATH`, `ATSP`, `ATTP`, `ATDP`,
+/// `ATDPN`, `ATRV`; timing and filtering commands such as `ATST` or `ATAT`
+/// are accepted and ignored), and answers OBD requests with the responses
+/// configured for the emulated ECU. Mode 01 requests for the supported PIDs
+/// (`0100`, `0120`, ...) are answered from the configured PIDs, and the
+/// responses longer than a CAN frame are split as ISO-TP messages.
+///
+/// With automatic protocol selection (the default), the first request
+/// searches for the protocol of the ECU. When another protocol is selected,
+/// the ECU does not answer.
+///
+/// ```
+/// use virtual_serialport::{devices::Elm327, Device};
+///
+/// // 1726 rpm
+/// let mut elm = Elm327::new().with_pid(0x0C, &[0x1A, 0xF8]);
+///
+/// assert_eq!(elm.on_receive(b"ATE0\r"), b"ATE0\rOK\r\r>");
+/// assert_eq!(elm.on_receive(b"010C\r"), b"SEARCHING...\r41 0C 1A F8\r\r>");
+/// ```
+#[derive(Debug)]
+pub struct Elm327 {
+    ecu_protocol: u8,
+    responses: Vec<(Vec<u8>, Vec<u8>)>,
+    voltage: f32,
+
+    echo: bool,
+    linefeeds: bool,
+    spaces: bool,
+    headers: bool,
+
+    // Selected protocol, 0 for automatic selection
+    protocol: u8,
+    connected: bool,
+
+    line: Vec<u8>,
+    last_command: String,
+    requests: Vec<Vec<u8>>,
+}
+
+impl Default for Elm327 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Elm327 {
+    /// Creates an adapter connected to a CAN (11-bit, 500 kbit/s) ECU which
+    /// does not answer any request yet.
+    pub fn new() -> Self {
+        let mut elm = Self {
+            ecu_protocol: DEFAULT_PROTOCOL,
+            responses: Vec::new(),
+            voltage: 12.6,
+            echo: true,
+            linefeeds: false,
+            spaces: true,
+            headers: false,
+            protocol: 0,
+            connected: false,
+            line: Vec::new(),
+            last_command: String::new(),
+            requests: Vec::new(),
+        };
+        elm.reset();
+        elm
+    }
+
+    /// Sets the protocol of the ECU as an `ATSP` number (1 to 9).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `protocol` is not between 1 and 9.
+    pub fn with_protocol(mut self, protocol: u8) -> Self {
+        assert!((1..=9).contains(&protocol), "invalid OBD-II protocol");
+        self.ecu_protocol = protocol;
+        self
+    }
+
+    /// Sets the data returned by the ECU for the mode 01 `pid`.
+    pub fn with_pid(mut self, pid: u8, data: &[u8]) -> Self {
+        self.set_pid(pid, data);
+        self
+    }
+
+    /// Sets the response of the ECU to an arbitrary `request`, e.g.,
+    /// `[0x09, 0x02]` (VIN) or `[0x03]` (trouble codes). The response starts
+    /// with the mode byte (`0x49`, `0x43`).
+    pub fn with_response(mut self, request: &[u8], response: &[u8]) -> Self {
+        self.set_response(request, response);
+        self
+    }
+
+    /// Sets the battery voltage reported by `ATRV`.
+    pub fn with_voltage(mut self, voltage: f32) -> Self {
+        self.voltage = voltage;
+        self
+    }
+
+    /// Changes the data returned for the mode 01 `pid`, e.g., to emulate a
+    /// changing engine speed.
+    pub fn set_pid(&mut self, pid: u8, data: &[u8]) {
+        let mut response = vec![0x41, pid];
+        response.extend_from_slice(data);
+        self.set_response(&[0x01, pid], &response);
+    }
+
+    /// Changes the response of the ECU to `request`.
+    pub fn set_response(&mut self, request: &[u8], response: &[u8]) {
+        self.responses.retain(|(known, _)| known != request);
+        self.responses.push((request.to_vec(), response.to_vec()));
+    }
+
+    /// Returns the OBD requests received so far.
+    pub fn requests(&self) -> &[Vec<u8>] {
+        &self.requests
+    }
+
+    fn reset(&mut self) {
+        self.echo = true;
+        self.linefeeds = false;
+        self.spaces = true;
+        self.headers = false;
+        self.protocol = 0;
+        self.connected = false;
+    }
+
+    // Response lines followed by an empty line and the prompt
+    fn respond(&self, lines: &[String]) -> Vec<u8> {
+        let eol: &[u8] = if self.linefeeds { b"\r\n" } else { b"\r" };
+        let mut output = Vec::new();
+        for line in lines {
+            output.extend_from_slice(line.as_bytes());
+            output.extend_from_slice(eol);
+        }
+        output.extend_from_slice(eol);
+        output.push(PROMPT);
+        output
+    }
+
+    fn format_bytes(&self, bytes: &[u8]) -> String {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        hex.join(if self.spaces { " " } else { "" })
+    }
+
+    fn execute(&mut self, command: &str) -> Vec<String> {
+        match command.strip_prefix("AT") {
+            Some(command) => self.execute_at(command),
+            None => self.execute_obd(command),
+        }
+    }
+
+    fn execute_at(&mut self, command: &str) -> Vec<String> {
+        let ok = vec!["OK".to_string()];
+        let flag = |argument: &str| match argument {
+            "0" => Some(false),
+            "1" => Some(true),
+            _ => None,
+        };
+        let (name, argument) = command.split_at(command.len().min(1));
+
+        match (name, argument) {
+            ("Z", "") => {
+                self.reset();
+                return vec![String::new(), VERSION.to_string()];
+            }
+            ("D", "") => {
+                self.reset();
+                return ok;
+            }
+            ("I", "") => return vec![VERSION.to_string()],
+            ("@", "1") => return vec![DESCRIPTION.to_string()],
+            ("R", "V") => return vec![format!("{:.1}V", self.voltage)],
+            ("D", "P") => {
+                let name = PROTOCOLS[self.active_protocol() as usize - 1];
+                return vec![if self.protocol == 0 {
+                    format!("AUTO, {}", name)
+                } else {
+                    name.to_string()
+                }];
+            }
+            ("D", "PN") => {
+                let auto = if self.protocol == 0 { "A" } else { "" };
+                return vec![format!("{}{}", auto, self.active_protocol())];
+            }
+            _ => {}
+        }
+
+        let set = |field: &mut bool| match flag(argument) {
+            Some(value) => {
+                *field = value;
+                true
+            }
+            None => false,
+        };
+        let valid = match name {
+            "E" => set(&mut self.echo),
+            "L" => set(&mut self.linefeeds),
+            "S" if argument.len() == 1 => set(&mut self.spaces),
+            "H" => set(&mut self.headers),
+            _ => {
+                // `ATSP` selects the protocol, `ATTP` tries it (with
+                // automatic fallback); `A` prefixes the fallback too
+                let protocol = command
+                    .strip_prefix("SP")
+                    .or_else(|| command.strip_prefix("TP"))
+                    .map(|protocol| (protocol.strip_prefix('A'), protocol));
+                match protocol {
+                    Some((fallback, protocol)) => {
+                        match u8::from_str_radix(fallback.unwrap_or(protocol), 16) {
+                            Ok(number) if number <= 9 => {
+                                let auto = fallback.is_some() || command.starts_with("TP");
+                                self.protocol = if auto { 0 } else { number };
+                                self.connected = false;
+                                true
+                            }
+                            _ => false,
+                        }
+                    }
+                    // Timing, filtering and formatting settings are ignored
+                    None => ["ST", "AT", "SH", "CAF", "AL", "M", "CRA"]
+                        .iter()
+                        .any(|prefix| command.starts_with(prefix)),
+                }
+            }
+        };
+
+        if valid {
+            ok
+        } else {
+            vec!["?".to_string()]
+        }
+    }
+
+    // Protocol in use, or the one the ECU uses while searching
+    fn active_protocol(&self) -> u8 {
+        if self.protocol == 0 {
+            self.ecu_protocol
+        } else {
+            self.protocol
+        }
+    }
+
+    fn execute_obd(&mut self, command: &str) -> Vec<String> {
+        // An odd digit at the end limits the number of responses
+        let digits = &command[..command.len() & !1];
+        let request: Option<Vec<u8>> = (0..digits.len())
+            .step_by(2)
+            .map(|pos| u8::from_str_radix(&digits[pos..pos + 2], 16).ok())
+            .collect();
+        let request = match request {
+            Some(request) if !request.is_empty() => request,
+            _ => return vec!["?".to_string()],
+        };
+        self.requests.push(request.clone());
+
+        if self.protocol != 0 && self.protocol != self.ecu_protocol {
+            let error = if is_can(self.protocol) {
+                "CAN ERROR"
+            } else {
+                "BUS INIT: ...ERROR"
+            };
+            return vec![error.to_string()];
+        }
+
+        let mut lines = Vec::new();
+        if !self.connected {
+            if self.protocol == 0 {
+                lines.push("SEARCHING...".to_string());
+            }
+            self.connected = true;
+        }
+
+        match self.response(&request) {
+            Some(response) => lines.extend(self.format_response(&response)),
+            None => lines.push("NO DATA".to_string()),
+        }
+        lines
+    }
+
+    fn response(&self, request: &[u8]) -> Option<Vec<u8>> {
+        if let Some((_, response)) = self.responses.iter().find(|(known, _)| known == request) {
+            return Some(response.clone());
+        }
+
+        // Supported PIDs in the range following `base`
+        match *request {
+            [0x01, base] if base % 0x20 == 0 => {
+                let mut mask = 0u32;
+                for (known, _) in &self.responses {
+                    if let [0x01, pid] = known[..] {
+                        // The last bit reports a supported PID in the next ranges
+                        let offset = pid.wrapping_sub(base) as u32;
+                        if pid > base {
+                            mask |= if offset <= 32 { 1 << (32 - offset) } else { 1 };
+                        }
+                    }
+                }
+                if mask == 0 && base != 0 {
+                    return None;
+                }
+                let mut response = vec![0x41, base];
+                response.extend_from_slice(&mask.to_be_bytes());
+                Some(response)
+            }
+            _ => None,
+        }
+    }
+
+    fn format_response(&self, response: &[u8]) -> Vec<String> {
+        let protocol = self.active_protocol();
+        if !is_can(protocol) {
+            let mut frame = if protocol == 1 {
+                vec![0x41, 0x6B, 0x10]
+            } else {
+                vec![0x48, 0x6B, 0x10]
+            };
+            frame.extend_from_slice(response);
+            if protocol <= 2 {
+                frame.push(crc8_j1850(&frame));
+            } else {
+                frame.push(frame.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+            }
+            let frame = if self.headers {
+                &frame[..]
+            } else {
+                &frame[3..frame.len() - 1]
+            };
+            return vec![self.format_bytes(frame)];
+        }
+
+        let header = if protocol % 2 == 0 {
+            "7E8".to_string()
+        } else {
+            self.format_bytes(&[0x18, 0xDA, 0xF1, 0x10])
+        };
+        let separator = if self.spaces { " " } else { "" };
+
+        // Single frame
+        if response.len() <= 7 {
+            return vec![if self.headers {
+                let mut frame = vec![response.len() as u8];
+                frame.extend_from_slice(response);
+                format!("{}{}{}", header, separator, self.format_bytes(&frame))
+            } else {
+                self.format_bytes(response)
+            }];
+        }
+
+        // First frame with six bytes, then consecutive frames with seven
+        let mut frames = vec![(
+            vec![0x10 | (response.len() >> 8) as u8, response.len() as u8],
+            &response[..6],
+        )];
+        for (index, chunk) in response[6..].chunks(7).enumerate() {
+            frames.push((vec![0x20 | ((index + 1) % 16) as u8], chunk));
+        }
+
+        if self.headers {
+            frames
+                .into_iter()
+                .map(|(mut frame, data)| {
+                    frame.extend_from_slice(data);
+                    format!("{}{}{}", header, separator, self.format_bytes(&frame))
+                })
+                .collect()
+        } else {
+            let mut lines = vec![format!("{:03X}", response.len())];
+            for (index, (_, data)) in frames.into_iter().enumerate() {
+                lines.push(format!(
+                    "{:X}:{}{}",
+                    index % 16,
+                    separator,
+                    self.format_bytes(data)
+                ));
+            }
+            lines
+        }
+    }
+}
+
+impl Device for Elm327 {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for &byte in data {
+            if self.echo {
+                output.push(byte);
+            }
+            if byte != b'\r' {
+                if byte.is_ascii_graphic() {
+                    self.line.push(byte.to_ascii_uppercase());
+                }
+                continue;
+            }
+
+            // An empty line repeats the last command
+            let line = std::mem::take(&mut self.line);
+            let mut command = String::from_utf8_lossy(&line).into_owned();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            let lines = self.execute(&command);
+            self.last_command = command;
+            output.extend(self.respond(&lines));
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ask(elm: &mut Elm327, command: &str) -> String {
+        String::from_utf8(elm.on_receive(format!("{}\r", command).as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_elm327_init_sequence() {
+        let mut elm = Elm327::new()
+            .with_pid(0x0C, &[0x1A, 0xF8])
+            .with_pid(0x0D, &[0x3C])
+            .with_pid(0x21, &[0x00, 0x00]);
+
+        assert_eq!(ask(&mut elm, "ATZ"), "ATZ\r\rELM327 v1.5\r\r>");
+        assert_eq!(ask(&mut elm, "ate0"), "ate0\rOK\r\r>");
+        assert_eq!(ask(&mut elm, "AT L1"), "OK\r\n\r\n>");
+        assert_eq!(ask(&mut elm, "ATL0"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "ATSP0"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "ATDPN"), "A6\r\r>");
+        assert_eq!(ask(&mut elm, "ATXYZ"), "?\r\r>");
+
+        assert_eq!(
+            ask(&mut elm, "0100"),
+            "SEARCHING...\r41 00 00 18 00 01\r\r>"
+        );
+        assert_eq!(ask(&mut elm, "0120"), "41 20 80 00 00 00\r\r>");
+        assert_eq!(ask(&mut elm, "0140"), "NO DATA\r\r>");
+        assert_eq!(ask(&mut elm, "ATS0"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "010D1"), "410D3C\r\r>");
+
+        // A live value changes, and an empty line repeats the request
+        elm.set_pid(0x0D, &[0x50]);
+        assert_eq!(ask(&mut elm, ""), "410D50\r\r>");
+        assert_eq!(elm.requests().len(), 5);
+    }
+
+    #[test]
+    fn test_elm327_headers_and_multi_frame() {
+        let vin = b"\x49\x02\x011D4GP00R55B123456";
+        let mut elm = Elm327::new()
+            .with_response(&[0x09, 0x02], vin)
+            .with_pid(0x0C, &[0x1A, 0xF8]);
+
+        assert_eq!(ask(&mut elm, "ATE0"), "ATE0\rOK\r\r>");
+        assert_eq!(ask(&mut elm, "ATSP6"), "OK\r\r>");
+        assert_eq!(
+            ask(&mut elm, "0902"),
+            "014\r0: 49 02 01 31 44 34\r1: 47 50 30 30 52 35 35\r2: 42 31 32 33 34 35 36\r\r>"
+        );
+
+        assert_eq!(ask(&mut elm, "ATH1"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "010C"), "7E8 04 41 0C 1A F8\r\r>");
+        assert_eq!(
+            ask(&mut elm, "0902"),
+            "7E8 10 14 49 02 01 31 44 34\r7E8 21 47 50 30 30 52 35 35\r7E8 22 42 31 32 33 34 35 36\r\r>"
+        );
+    }
+
+    #[test]
+    fn test_elm327_protocols() {
+        let mut elm = Elm327::new().with_protocol(3).with_pid(0x0D, &[0x3C]);
+
+        ask(&mut elm, "ATE0");
+        assert_eq!(ask(&mut elm, "ATSP6"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "010D"), "CAN ERROR\r\r>");
+
+        assert_eq!(ask(&mut elm, "ATSPA6"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "ATDP"), "AUTO, ISO 9141-2\r\r>");
+        assert_eq!(ask(&mut elm, "010D"), "SEARCHING...\r41 0D 3C\r\r>");
+
+        // Legacy frames end with a checksum
+        assert_eq!(ask(&mut elm, "ATH1"), "OK\r\r>");
+        assert_eq!(ask(&mut elm, "010D"), "48 6B 10 41 0D 3C 4D\r\r>");
+
+        let mut elm = Elm327::new().with_protocol(2).with_pid(0x0D, &[0x3C]);
+        ask(&mut elm, "ATE0");
+        ask(&mut elm, "ATH1");
+        let frame = [0x48, 0x6B, 0x10, 0x41, 0x0D, 0x3C];
+        assert_eq!(
+            ask(&mut elm, "010D"),
+            format!(
+                "SEARCHING...\r48 6B 10 41 0D 3C {:02X}\r\r>",
+                crc8_j1850(&frame)
+            )
+        );
+        assert_eq!(crc8_j1850(b"123456789"), 0x4B);
+    }
+}
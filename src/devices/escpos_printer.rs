@@ -0,0 +1,587 @@
+//! ESC/POS receipt printer.
+
+use crate::Device;
+
+const LF: u8 = 0x0A;
+const HT: u8 = 0x09;
+const CR: u8 = 0x0D;
+const DLE: u8 = 0x10;
+const ESC: u8 = 0x1B;
+const FS: u8 = 0x1C;
+const GS: u8 = 0x1D;
+
+const EOT: u8 = 0x04;
+
+// Paper width of an 80 mm printer with the standard font
+const DEFAULT_COLUMNS: usize = 42;
+
+const TAB_WIDTH: usize = 8;
+
+// Fixed bits of the real-time status bytes
+const STATUS_FIXED: u8 = 0x12;
+const STATUS_PAPER_END: u8 = 0x60;
+
+/// Character formatting of printed text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextStyle {
+    /// Emphasized (bold) text.
+    pub bold: bool,
+
+    /// Underlined text.
+    pub underline: bool,
+
+    /// Characters twice as wide.
+    pub double_width: bool,
+
+    /// Characters twice as high.
+    pub double_height: bool,
+}
+
+/// Justification of a printed line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Alignment::Left
+    }
+}
+
+/// A run of text printed with the same style.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// Printed characters.
+    pub text: String,
+
+    /// Style of the characters.
+    pub style: TextStyle,
+}
+
+/// A line of printed text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrintedLine {
+    /// Runs of text with their styles.
+    pub spans: Vec<Span>,
+
+    /// Justification in effect when the line was printed.
+    pub alignment: Alignment,
+}
+
+impl PrintedLine {
+    /// Returns the text of the line without formatting.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    // Number of columns taken by the line
+    fn width(&self) -> usize {
+        self.spans
+            .iter()
+            .map(|span| span.text.chars().count() * if span.style.double_width { 2 } else { 1 })
+            .sum()
+    }
+
+    fn push(&mut self, c: char, style: TextStyle) {
+        match self.spans.last_mut() {
+            Some(span) if span.style == style => span.text.push(c),
+            _ => self.spans.push(Span {
+                text: c.to_string(),
+                style,
+            }),
+        }
+    }
+}
+
+/// How the paper was cut.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cut {
+    /// The paper is cut completely (`GS V 0`).
+    Full,
+
+    /// A point is left uncut (`GS V 1`, `ESC i`, `ESC m`).
+    Partial,
+}
+
+/// The content printed between two cuts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Receipt {
+    /// Printed lines, including the empty lines fed.
+    pub lines: Vec<PrintedLine>,
+
+    /// Data of the printed barcodes.
+    pub barcodes: Vec<Vec<u8>>,
+
+    /// Number of printed raster and bit images.
+    pub images: usize,
+
+    /// Cut ending the receipt, `None` while it is being printed.
+    pub cut: Option<Cut>,
+
+    columns: usize,
+}
+
+impl Receipt {
+    /// Returns the text of the lines without formatting.
+    pub fn text(&self) -> String {
+        let lines: Vec<String> = self.lines.iter().map(PrintedLine::text).collect();
+        lines.join("\n")
+    }
+
+    /// Renders the lines as plain text justified on the paper width, with
+    /// double-width characters followed by a space.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for line in &self.lines {
+            let padding = self.columns.saturating_sub(line.width());
+            let indent = match line.alignment {
+                Alignment::Left => 0,
+                Alignment::Center => padding / 2,
+                Alignment::Right => padding,
+            };
+
+            let mut text = " ".repeat(indent);
+            for span in &line.spans {
+                for c in span.text.chars() {
+                    text.push(c);
+                    if span.style.double_width {
+                        text.push(' ');
+                    }
+                }
+            }
+            output.push_str(text.trim_end());
+            output.push('\n');
+        }
+        output
+    }
+}
+
+/// An emulated ESC/POS thermal receipt printer.
+///
+/// The printer interprets text, line feeds and tabs, and the commands
+/// setting the character style (`ESC !`, `ESC E`, `ESC -`, `GS !`), the
+/// justification (`ESC a`), feeding (`ESC d`, `ESC J`), initializing
+/// (`ESC @`), cutting (`GS V`, `ESC i`, `ESC m`) and kicking the cash drawer
+/// (`ESC p`). Barcodes (`GS k`) and images (`ESC *`, `GS v 0`) are
+/// recorded, other known commands are skipped with their parameters. Text
+/// longer than the paper width wraps, and bytes above 0x7F are taken as
+/// Latin-1 characters.
+///
+/// The real-time status requests `DLE EOT` and `GS r` report whether the
+/// paper has run out (see [`set_paper_out`](EscPosPrinter::set_paper_out)),
+/// in which case nothing is printed.
+///
+/// ```
+/// use virtual_serialport::{devices::EscPosPrinter, Device};
+///
+/// let mut printer = EscPosPrinter::new().with_columns(16);
+/// printer.on_receive(b"\x1b@\x1ba\x01\x1bE\x01TOTAL\x1bE\x00\n 9.99\n\x1dV\x00");
+///
+/// let receipt = &printer.receipts()[0];
+/// assert_eq!(receipt.render(), "     TOTAL\n      9.99\n");
+/// assert!(receipt.lines[0].spans[0].style.bold);
+/// ```
+#[derive(Debug)]
+pub struct EscPosPrinter {
+    columns: usize,
+    paper_out: bool,
+
+    style: TextStyle,
+    alignment: Alignment,
+
+    line: PrintedLine,
+    current: Receipt,
+    receipts: Vec<Receipt>,
+    drawer_kicks: usize,
+
+    // Data of an incomplete command
+    pending: Vec<u8>,
+}
+
+impl Default for EscPosPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EscPosPrinter {
+    /// Creates a printer with paper 42 columns wide.
+    pub fn new() -> Self {
+        Self {
+            columns: DEFAULT_COLUMNS,
+            paper_out: false,
+            style: TextStyle::default(),
+            alignment: Alignment::Left,
+            line: PrintedLine::default(),
+            current: Receipt {
+                columns: DEFAULT_COLUMNS,
+                ..Receipt::default()
+            },
+            receipts: Vec::new(),
+            drawer_kicks: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Sets the paper width in characters of the standard font.
+    pub fn with_columns(mut self, columns: usize) -> Self {
+        self.columns = columns;
+        self.current.columns = columns;
+        self
+    }
+
+    /// Emulates running out of paper (or reloading it).
+    pub fn set_paper_out(&mut self, paper_out: bool) {
+        self.paper_out = paper_out;
+    }
+
+    /// Returns the receipts printed and cut so far.
+    pub fn receipts(&self) -> &[Receipt] {
+        &self.receipts
+    }
+
+    /// Takes the receipts printed and cut so far.
+    pub fn take_receipts(&mut self) -> Vec<Receipt> {
+        std::mem::take(&mut self.receipts)
+    }
+
+    /// Returns the content printed since the last cut.
+    pub fn current_receipt(&self) -> &Receipt {
+        &self.current
+    }
+
+    /// Returns the current character style.
+    pub fn style(&self) -> TextStyle {
+        self.style
+    }
+
+    /// Returns the current justification.
+    pub fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
+    /// Returns how many times the cash drawer has been kicked.
+    pub fn drawer_kicks(&self) -> usize {
+        self.drawer_kicks
+    }
+
+    fn initialize(&mut self) {
+        self.style = TextStyle::default();
+        self.alignment = Alignment::Left;
+        self.line = PrintedLine::default();
+    }
+
+    fn print_char(&mut self, c: char) {
+        if self.paper_out {
+            return;
+        }
+
+        let width = if self.style.double_width { 2 } else { 1 };
+        if self.line.width() + width > self.columns {
+            self.print_line();
+        }
+        self.line.push(c, self.style);
+    }
+
+    // Prints the buffered line and feeds the paper by one line
+    fn print_line(&mut self) {
+        if self.paper_out {
+            return;
+        }
+
+        let mut line = std::mem::take(&mut self.line);
+        line.alignment = self.alignment;
+        self.current.lines.push(line);
+    }
+
+    fn cut(&mut self, cut: Cut) {
+        if !self.line.spans.is_empty() {
+            self.print_line();
+        }
+
+        let next = Receipt {
+            columns: self.columns,
+            ..Receipt::default()
+        };
+        let mut receipt = std::mem::replace(&mut self.current, next);
+        receipt.cut = Some(cut);
+        self.receipts.push(receipt);
+    }
+
+    fn status(&self, paper_status: bool) -> u8 {
+        if self.paper_out && paper_status {
+            STATUS_FIXED | STATUS_PAPER_END
+        } else {
+            STATUS_FIXED
+        }
+    }
+
+    // Interprets the command at the start of `data`, adding any response to
+    // `output`, and returns its length, or `None` if it is incomplete
+    fn parse(&mut self, data: &[u8], output: &mut Vec<u8>) -> Option<usize> {
+        let byte = |index: usize| data.get(index).copied();
+
+        // Length of a command with a parameter block following `prefix` bytes
+        let block = |prefix: usize, length: usize| {
+            if data.len() >= prefix + length {
+                Some(prefix + length)
+            } else {
+                None
+            }
+        };
+
+        match data[0] {
+            LF => self.print_line(),
+            CR => {}
+            HT => {
+                let spaces = TAB_WIDTH - self.line.width() % TAB_WIDTH;
+                for _ in 0..spaces {
+                    self.print_char(' ');
+                }
+            }
+            DLE => {
+                let (command, n) = (byte(1)?, byte(2)?);
+                return match command {
+                    EOT => {
+                        output.push(self.status(n == 2 || n == 4));
+                        Some(3)
+                    }
+                    // DLE DC4 takes two more parameters
+                    0x14 => block(3, 2),
+                    _ => Some(3),
+                };
+            }
+            ESC => return self.parse_esc(data),
+            GS => return self.parse_gs(data, output),
+            // Kanji and NV image commands are not supported
+            FS => return block(2, 0),
+            byte if byte >= 0x20 && byte != 0x7F => self.print_char(byte as char),
+            _ => {}
+        }
+        Some(1)
+    }
+
+    fn parse_esc(&mut self, data: &[u8]) -> Option<usize> {
+        let command = *data.get(1)?;
+        let n = match command {
+            // Commands without a parameter
+            b'@' | b'2' | b'i' | b'm' | b'<' => None,
+            b'p' => {
+                data.get(4)?;
+                self.drawer_kicks += 1;
+                return Some(5);
+            }
+            b'*' => {
+                let columns = *data.get(3)? as usize | (*data.get(4)? as usize) << 8;
+                let len = 5 + columns * if data[2] < 32 { 1 } else { 3 };
+                if data.len() < len {
+                    return None;
+                }
+                if !self.paper_out {
+                    self.current.images += 1;
+                }
+                return Some(len);
+            }
+            _ => Some(*data.get(2)?),
+        };
+
+        match (command, n.unwrap_or(0)) {
+            (b'@', _) => self.initialize(),
+            (b'i', _) | (b'm', _) => self.cut(Cut::Partial),
+            (b'!', n) => {
+                self.style = TextStyle {
+                    bold: n & 0x08 != 0,
+                    double_height: n & 0x10 != 0,
+                    double_width: n & 0x20 != 0,
+                    underline: n & 0x80 != 0,
+                };
+            }
+            (b'E', n) => self.style.bold = n & 1 != 0,
+            (b'-', n) => self.style.underline = matches!(n, 1 | 2 | b'1' | b'2'),
+            (b'a', n) => {
+                self.alignment = match n {
+                    1 | b'1' => Alignment::Center,
+                    2 | b'2' => Alignment::Right,
+                    _ => Alignment::Left,
+                };
+            }
+            (b'd', n) => {
+                if n > 0 || !self.line.spans.is_empty() {
+                    self.print_line();
+                }
+                for _ in 1..n {
+                    self.print_line();
+                }
+            }
+            // Feeding by dots prints the buffer as well
+            (b'J', _) => self.print_line(),
+            _ => {}
+        }
+        Some(if n.is_some() { 3 } else { 2 })
+    }
+
+    fn parse_gs(&mut self, data: &[u8], output: &mut Vec<u8>) -> Option<usize> {
+        let byte = |index: usize| data.get(index).copied();
+        match byte(1)? {
+            b'!' => {
+                let size = byte(2)?;
+                self.style.double_width = size & 0xF0 != 0;
+                self.style.double_height = size & 0x0F != 0;
+            }
+            b'V' => {
+                let mode = byte(2)?;
+                let cut = if matches!(mode, 1 | 49 | 66) {
+                    Cut::Partial
+                } else {
+                    Cut::Full
+                };
+                // Function B feeds the paper by a parameter first
+                let len = if mode >= 65 { 4 } else { 3 };
+                data.get(len - 1)?;
+                self.cut(cut);
+                return Some(len);
+            }
+            b'r' => {
+                let n = byte(2)?;
+                output.push(if n == 1 && self.paper_out { 0x0C } else { 0 });
+            }
+            b'k' => {
+                // Barcode data is terminated by NUL or preceded by its length
+                let kind = byte(2)?;
+                let (start, end, len) = if kind <= 6 {
+                    let end = 3 + data[3..].iter().position(|&byte| byte == 0)?;
+                    (3, end, end + 1)
+                } else {
+                    let end = 4 + byte(3)? as usize;
+                    (4, end, end)
+                };
+                if data.len() < len {
+                    return None;
+                }
+                if !self.paper_out {
+                    self.current.barcodes.push(data[start..end].to_vec());
+                }
+                return Some(len);
+            }
+            b'v' => {
+                // GS v 0 m xL xH yL yH, followed by the raster data
+                let width = byte(4)? as usize | (byte(5)? as usize) << 8;
+                let height = byte(6)? as usize | (byte(7)? as usize) << 8;
+                let len = 8 + width * height;
+                if data.len() < len {
+                    return None;
+                }
+                if !self.paper_out {
+                    self.current.images += 1;
+                }
+                return Some(len);
+            }
+            b'(' => {
+                let len = 5 + (byte(3)? as usize | (byte(4)? as usize) << 8);
+                return if data.len() < len { None } else { Some(len) };
+            }
+            b'L' | b'W' => {
+                byte(3)?;
+                return Some(4);
+            }
+            _ => {
+                byte(2)?;
+            }
+        }
+        Some(3)
+    }
+}
+
+impl Device for EscPosPrinter {
+    fn on_receive(&mut self, received: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(received);
+        let mut output = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            match self.parse(&data[pos..], &mut output) {
+                Some(len) => pos += len,
+                None => break,
+            }
+        }
+        self.pending = data.split_off(pos);
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escpos_formatting() {
+        let mut printer = EscPosPrinter::new().with_columns(20);
+        printer.on_receive(b"\x1b@\x1b!\x38SHOP\x1b!\x00\n");
+        printer.on_receive(b"\x1ba\x02Right\n\x1ba\x00Tea\tx2\n\x1bd\x02");
+        printer.on_receive(b"\x1d!\x11Big\x1d!\x00 \x1b-\x01small\x1b-\x00\n");
+
+        let receipt = printer.current_receipt();
+        assert_eq!(receipt.text(), "SHOP\nRight\nTea     x2\n\n\nBig small");
+        assert_eq!(
+            receipt.lines[0].spans[0].style,
+            TextStyle {
+                bold: true,
+                underline: false,
+                double_width: true,
+                double_height: true,
+            }
+        );
+        assert_eq!(receipt.lines[1].alignment, Alignment::Right);
+        assert!(receipt.lines[5].spans[2].style.underline);
+        assert_eq!(
+            receipt.render(),
+            "S H O P\n               Right\nTea     x2\n\n\nB i g  small\n"
+        );
+
+        // Long text wraps
+        printer.on_receive(b"0123456789012345678901234\n");
+        let lines = &printer.current_receipt().lines;
+        assert_eq!(lines[lines.len() - 2].text(), "01234567890123456789");
+        assert_eq!(lines[lines.len() - 1].text(), "01234");
+    }
+
+    #[test]
+    fn test_escpos_cut_barcode_and_drawer() {
+        let mut printer = EscPosPrinter::new();
+
+        // Commands split across writes
+        printer.on_receive(b"Receipt 1\n\x1dk\x04");
+        printer.on_receive(b"12345\x00\x1dk\x49\x03ABC\x1dv0\x00\x02\x00\x02");
+        printer.on_receive(b"\x00\xff\xff\xff\xff\x1b");
+        printer.on_receive(b"p\x00\x19\xfa\x1dV\x42\x03Receipt 2\x1dV0");
+
+        let receipts = printer.take_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert_eq!(receipts[0].text(), "Receipt 1");
+        assert_eq!(receipts[0].barcodes, [b"12345".to_vec(), b"ABC".to_vec()]);
+        assert_eq!(receipts[0].images, 1);
+        assert_eq!(receipts[0].cut, Some(Cut::Partial));
+        assert_eq!(receipts[1].text(), "Receipt 2");
+        assert_eq!(receipts[1].cut, Some(Cut::Full));
+        assert_eq!(printer.drawer_kicks(), 1);
+    }
+
+    #[test]
+    fn test_escpos_status() {
+        let mut printer = EscPosPrinter::new();
+        assert_eq!(
+            printer.on_receive(b"\x10\x04\x01\x10\x04\x04"),
+            [0x12, 0x12]
+        );
+
+        printer.set_paper_out(true);
+        assert_eq!(
+            printer.on_receive(b"lost\n\x10\x04\x04\x1dr\x01"),
+            [0x72, 0x0C]
+        );
+        assert!(printer.current_receipt().lines.is_empty());
+    }
+}
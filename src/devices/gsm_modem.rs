@@ -0,0 +1,698 @@
+//! SIM800-style GSM/GPRS cellular module.
+
+use crate::{Device, VirtualPort};
+
+const CTRL_Z: u8 = 0x1A;
+const ESC: u8 = 0x1B;
+
+const IDENTIFICATION: &str = "SIM800 R14.18";
+const LOCAL_ADDRESS: &str = "10.0.0.2";
+
+// Service centre time stamp of the received messages
+const TIMESTAMP: &str = "24/01/01,12:00:00+00";
+
+/// Network registration status reported by `+CREG`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkStatus {
+    /// Not registered and not searching (0).
+    NotRegistered,
+
+    /// Registered on the home network (1).
+    Home,
+
+    /// Not registered, searching for a network (2).
+    Searching,
+
+    /// Registration denied (3).
+    Denied,
+
+    /// Registered while roaming (5).
+    Roaming,
+}
+
+impl NetworkStatus {
+    fn code(self) -> u8 {
+        match self {
+            NetworkStatus::NotRegistered => 0,
+            NetworkStatus::Home => 1,
+            NetworkStatus::Searching => 2,
+            NetworkStatus::Denied => 3,
+            NetworkStatus::Roaming => 5,
+        }
+    }
+
+    fn is_registered(self) -> bool {
+        matches!(self, NetworkStatus::Home | NetworkStatus::Roaming)
+    }
+}
+
+/// A short message sent or received by the module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sms {
+    /// Phone number of the recipient or of the sender.
+    pub number: String,
+
+    /// Message text.
+    pub text: String,
+}
+
+#[derive(Debug)]
+struct StoredSms {
+    sms: Sms,
+    read: bool,
+}
+
+// What the received data is interpreted as
+#[derive(Debug)]
+enum Input {
+    Command,
+    // Text of a message, terminated by Ctrl-Z
+    SmsText(String),
+    // Data sent over the connection, terminated by Ctrl-Z unless the length
+    // is given
+    TcpData(Option<usize>),
+}
+
+/// An emulated SIM800-style GSM/GPRS module.
+///
+/// Besides the basic commands (`AT`, `ATE0`/`ATE1`, `ATI`, `AT+CPIN?`,
+/// `AT+CSQ`, `AT+CGATT?`), the module supports:
+///
+/// - network registration with `AT+CREG=<n>`, `AT+CREG?` and the `+CREG`
+///   unsolicited result code on changes (see
+///   [`set_registration`](GsmModem::set_registration));
+/// - text mode SMS: `AT+CMGF=1`, `AT+CMGS="<number>"` with the `> ` prompt
+///   (the text ends with Ctrl-Z, or is discarded with ESC), and received
+///   messages announced with `+CMTI` and handled with `AT+CMGR`, `AT+CMGL`
+///   and `AT+CMGD`;
+/// - a single TCP connection: `AT+CSTT`, `AT+CIICR`, `AT+CIFSR`,
+///   `AT+CIPSTART="TCP","<host>",<port>`, `AT+CIPSEND[=<length>]`,
+///   `AT+CIPHEAD`, `AT+CIPSTATUS`, `AT+CIPCLOSE` and `AT+CIPSHUT`.
+///
+/// The data sent over the connection is collected for the test (see
+/// [`take_remote_data`](GsmModem::take_remote_data)), and the remote host
+/// sends data with [`send_from_remote`](GsmModem::send_from_remote).
+#[derive(Debug)]
+pub struct GsmModem {
+    echo: bool,
+    text_mode: bool,
+
+    registration: NetworkStatus,
+    registration_reports: u8,
+    signal_quality: u8,
+
+    input: Input,
+    line: Vec<u8>,
+
+    sent_sms: Vec<Sms>,
+    message_reference: u8,
+    storage: Vec<Option<StoredSms>>,
+
+    // Setup of the packet data context: `CSTT`, `CIICR`, `CIFSR` done
+    context: u8,
+    connection: Option<(String, u16)>,
+    refused_hosts: Vec<String>,
+    ip_header: bool,
+    remote_rx: Vec<u8>,
+
+    // Data to be sent to the port
+    output: Vec<u8>,
+}
+
+impl Default for GsmModem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GsmModem {
+    /// Creates a module registered on its home network.
+    pub fn new() -> Self {
+        Self {
+            echo: true,
+            text_mode: false,
+            registration: NetworkStatus::Home,
+            registration_reports: 0,
+            signal_quality: 20,
+            input: Input::Command,
+            line: Vec::new(),
+            sent_sms: Vec::new(),
+            message_reference: 0,
+            storage: Vec::new(),
+            context: 0,
+            connection: None,
+            refused_hosts: Vec::new(),
+            ip_header: false,
+            remote_rx: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Sets the initial network registration status.
+    pub fn with_registration(mut self, status: NetworkStatus) -> Self {
+        self.registration = status;
+        self
+    }
+
+    /// Sets the signal quality reported by `AT+CSQ` (0 to 31).
+    pub fn with_signal_quality(mut self, rssi: u8) -> Self {
+        self.signal_quality = rssi;
+        self
+    }
+
+    /// Makes connections to `host` fail with `CONNECT FAIL`.
+    pub fn refuse_host(mut self, host: &str) -> Self {
+        self.refused_hosts.push(host.to_string());
+        self
+    }
+
+    /// Changes the network registration status, reporting it with `+CREG`
+    /// if enabled. Losing the registration closes the connection.
+    pub fn set_registration(&mut self, status: NetworkStatus) {
+        if status == self.registration {
+            return;
+        }
+
+        self.registration = status;
+        match self.registration_reports {
+            1 => self.respond_line(&format!("+CREG: {}", status.code())),
+            2 => self.respond_line(&format!("+CREG: {},\"0001\",\"0001\"", status.code())),
+            _ => {}
+        }
+        if !status.is_registered() && self.connection.take().is_some() {
+            self.respond_line("CLOSED");
+        }
+    }
+
+    /// Returns the messages sent with `AT+CMGS`.
+    pub fn sent_sms(&self) -> &[Sms] {
+        &self.sent_sms
+    }
+
+    /// Stores an incoming message, announcing it with `+CMTI`.
+    pub fn receive_sms(&mut self, number: &str, text: &str) {
+        let stored = StoredSms {
+            sms: Sms {
+                number: number.to_string(),
+                text: text.to_string(),
+            },
+            read: false,
+        };
+        let index = match self.storage.iter().position(Option::is_none) {
+            Some(index) => {
+                self.storage[index] = Some(stored);
+                index
+            }
+            None => {
+                self.storage.push(Some(stored));
+                self.storage.len() - 1
+            }
+        };
+        self.respond_line(&format!("+CMTI: \"SM\",{}", index + 1));
+    }
+
+    /// Returns the host and port of the open connection.
+    pub fn connection(&self) -> Option<(&str, u16)> {
+        self.connection
+            .as_ref()
+            .map(|(host, port)| (host.as_str(), *port))
+    }
+
+    /// Takes the data sent over the connection.
+    pub fn take_remote_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.remote_rx)
+    }
+
+    /// Delivers data from the remote host if a connection is open.
+    pub fn send_from_remote(&mut self, data: &[u8]) {
+        if self.connection.is_none() {
+            return;
+        }
+        if self.ip_header {
+            self.output
+                .extend_from_slice(format!("\r\n+IPD,{}:", data.len()).as_bytes());
+        }
+        self.output.extend_from_slice(data);
+    }
+
+    /// Closes the connection from the remote side.
+    pub fn close_remote(&mut self) {
+        if self.connection.take().is_some() {
+            self.respond_line("CLOSED");
+        }
+    }
+
+    fn respond_line(&mut self, text: &str) {
+        self.output.extend_from_slice(b"\r\n");
+        self.output.extend_from_slice(text.as_bytes());
+        self.output.extend_from_slice(b"\r\n");
+    }
+
+    fn prompt(&mut self) {
+        self.output.extend_from_slice(b"\r\n> ");
+    }
+
+    fn receive_command(&mut self, byte: u8) {
+        match byte {
+            b'\r' => {
+                let line = std::mem::take(&mut self.line);
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if !line.is_empty() {
+                    self.execute(&line);
+                }
+            }
+            b'\n' => {}
+            8 => {
+                self.line.pop();
+            }
+            _ => self.line.push(byte),
+        }
+    }
+
+    fn execute(&mut self, line: &str) {
+        let upper = line.to_ascii_uppercase();
+        let command = match upper.strip_prefix("AT") {
+            Some(command) => command,
+            None => return,
+        };
+        // Quoted arguments keep their case
+        let arguments = &line[line.len() - command.len()..];
+        let (name, argument) = match command.find(['=', '?']) {
+            Some(pos) => (&command[..pos], &arguments[pos..]),
+            None => (command, ""),
+        };
+
+        let ok = match (name, argument) {
+            ("", "") => true,
+            ("E0", "") | ("E1", "") => {
+                self.echo = name == "E1";
+                true
+            }
+            ("I", "") => {
+                self.respond_line(IDENTIFICATION);
+                true
+            }
+            ("+CPIN", "?") => {
+                self.respond_line("+CPIN: READY");
+                true
+            }
+            ("+CSQ", "") => {
+                self.respond_line(&format!("+CSQ: {},0", self.signal_quality));
+                true
+            }
+            ("+CREG", "?") => {
+                let status = self.registration.code();
+                let text = format!("+CREG: {},{}", self.registration_reports, status);
+                self.respond_line(&text);
+                true
+            }
+            ("+CREG", _) => match argument {
+                "=0" | "=1" | "=2" => {
+                    self.registration_reports = argument.as_bytes()[1] - b'0';
+                    true
+                }
+                _ => false,
+            },
+            ("+CGATT", "?") => {
+                let attached = self.registration.is_registered() as u8;
+                self.respond_line(&format!("+CGATT: {}", attached));
+                true
+            }
+            ("+CMGF", "?") => {
+                let text = format!("+CMGF: {}", self.text_mode as u8);
+                self.respond_line(&text);
+                true
+            }
+            ("+CMGF", "=1") => {
+                self.text_mode = true;
+                true
+            }
+            ("+CMGF", "=0") => {
+                self.text_mode = false;
+                true
+            }
+            ("+CMGS", _) => return self.start_sms(argument),
+            ("+CMGR", _) => return self.read_sms(argument),
+            ("+CMGL", _) => return self.list_sms(argument),
+            ("+CMGD", _) => {
+                let index = parse_arguments(argument)
+                    .first()
+                    .and_then(|index| index.parse::<usize>().ok());
+                match index.and_then(|index| self.storage.get_mut(index.wrapping_sub(1))) {
+                    Some(slot) => {
+                        *slot = None;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            ("+CSTT", _) if argument.starts_with('=') => {
+                self.context = 1;
+                true
+            }
+            ("+CIICR", "") if self.context >= 1 && self.registration.is_registered() => {
+                self.context = 2;
+                true
+            }
+            ("+CIFSR", "") if self.context >= 2 => {
+                self.context = 3;
+                // The address is reported without `OK`
+                return self.respond_line(LOCAL_ADDRESS);
+            }
+            ("+CIPHEAD", "=0") | ("+CIPHEAD", "=1") => {
+                self.ip_header = argument == "=1";
+                true
+            }
+            ("+CIPSTART", _) => return self.connect(argument),
+            ("+CIPSEND", _) => {
+                if self.connection.is_none() {
+                    return self.respond_line("ERROR");
+                }
+                let length = match argument.strip_prefix('=') {
+                    Some(length) => match length.trim().parse() {
+                        Ok(length) => Some(length),
+                        Err(_) => return self.respond_line("ERROR"),
+                    },
+                    None => None,
+                };
+                self.input = Input::TcpData(length);
+                return self.prompt();
+            }
+            ("+CIPSTATUS", "") => {
+                self.respond_line("OK");
+                let state = match (&self.connection, self.context) {
+                    (Some(_), _) => "CONNECT OK",
+                    (None, 0) => "IP INITIAL",
+                    (None, 1) => "IP START",
+                    (None, 2) => "IP GPRSACT",
+                    (None, _) => "IP STATUS",
+                };
+                return self.respond_line(&format!("STATE: {}", state));
+            }
+            ("+CIPCLOSE", _) => {
+                return if self.connection.take().is_some() {
+                    self.respond_line("CLOSE OK")
+                } else {
+                    self.respond_line("ERROR")
+                };
+            }
+            ("+CIPSHUT", "") => {
+                self.connection = None;
+                self.context = 0;
+                return self.respond_line("SHUT OK");
+            }
+            _ => false,
+        };
+        self.respond_line(if ok { "OK" } else { "ERROR" });
+    }
+
+    fn start_sms(&mut self, argument: &str) {
+        // Messages can only be composed in text mode
+        let number = parse_arguments(argument).into_iter().next();
+        match number {
+            Some(number) if self.text_mode => {
+                self.input = Input::SmsText(number);
+                self.prompt();
+            }
+            _ => self.respond_line("ERROR"),
+        }
+    }
+
+    fn send_sms(&mut self, number: String, text: Vec<u8>) {
+        if !self.registration.is_registered() {
+            // No network service
+            return self.respond_line("+CMS ERROR: 331");
+        }
+
+        self.sent_sms.push(Sms {
+            number,
+            text: String::from_utf8_lossy(&text).into_owned(),
+        });
+        self.message_reference = self.message_reference.wrapping_add(1);
+        self.respond_line(&format!("+CMGS: {}", self.message_reference));
+        self.respond_line("OK");
+    }
+
+    fn read_sms(&mut self, argument: &str) {
+        let index = parse_arguments(argument)
+            .first()
+            .and_then(|index| index.parse::<usize>().ok());
+        let stored = match index {
+            Some(index) if index > 0 => self.storage.get_mut(index - 1),
+            _ => return self.respond_line("ERROR"),
+        };
+
+        // An empty slot is just acknowledged
+        if let Some(Some(stored)) = stored {
+            let header = format!(
+                "+CMGR: \"{}\",\"{}\",\"\",\"{}\"",
+                if stored.read {
+                    "REC READ"
+                } else {
+                    "REC UNREAD"
+                },
+                stored.sms.number,
+                TIMESTAMP
+            );
+            let text = format!("{}\r\n{}", header, stored.sms.text);
+            stored.read = true;
+            self.respond_line(&text);
+        }
+        self.respond_line("OK");
+    }
+
+    fn list_sms(&mut self, argument: &str) {
+        let filter = parse_arguments(argument)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "REC UNREAD".to_string())
+            .to_ascii_uppercase();
+        let unread_only = match filter.as_str() {
+            "ALL" => false,
+            "REC UNREAD" => true,
+            _ => return self.respond_line("ERROR"),
+        };
+
+        let mut lines = Vec::new();
+        for (index, stored) in self.storage.iter_mut().enumerate() {
+            let stored = match stored {
+                Some(stored) if !(unread_only && stored.read) => stored,
+                _ => continue,
+            };
+            lines.push(format!(
+                "+CMGL: {},\"{}\",\"{}\",\"\",\"{}\"\r\n{}",
+                index + 1,
+                if stored.read {
+                    "REC READ"
+                } else {
+                    "REC UNREAD"
+                },
+                stored.sms.number,
+                TIMESTAMP,
+                stored.sms.text
+            ));
+            stored.read = true;
+        }
+        for line in lines {
+            self.respond_line(&line);
+        }
+        self.respond_line("OK");
+    }
+
+    fn connect(&mut self, argument: &str) {
+        let arguments = parse_arguments(argument);
+        let (host, port) = match &arguments[..] {
+            [protocol, host, port] if protocol.eq_ignore_ascii_case("TCP") => {
+                match port.parse::<u16>() {
+                    Ok(port) => (host.clone(), port),
+                    Err(_) => return self.respond_line("ERROR"),
+                }
+            }
+            _ => return self.respond_line("ERROR"),
+        };
+        if self.connection.is_some() {
+            return self.respond_line("ALREADY CONNECT");
+        }
+
+        self.respond_line("OK");
+        if !self.registration.is_registered() || self.refused_hosts.contains(&host) {
+            self.respond_line("CONNECT FAIL");
+        } else {
+            self.context = self.context.max(3);
+            self.connection = Some((host, port));
+            self.respond_line("CONNECT OK");
+        }
+    }
+
+    fn receive_text(&mut self, byte: u8) {
+        let input = std::mem::replace(&mut self.input, Input::Command);
+        match (input, byte) {
+            (Input::SmsText(_), ESC) | (Input::TcpData(None), ESC) => {}
+            (Input::SmsText(number), CTRL_Z) => {
+                let text = std::mem::take(&mut self.line);
+                self.send_sms(number, text);
+            }
+            (Input::TcpData(None), CTRL_Z) => self.finish_send(),
+            (Input::TcpData(Some(length)), _) => {
+                self.line.push(byte);
+                if self.line.len() == length {
+                    self.finish_send();
+                } else {
+                    self.input = Input::TcpData(Some(length));
+                }
+            }
+            (input, _) => {
+                self.line.push(byte);
+                self.input = input;
+            }
+        }
+        if matches!(self.input, Input::Command) {
+            self.line.clear();
+        }
+    }
+
+    fn finish_send(&mut self) {
+        let data = std::mem::take(&mut self.line);
+        if self.connection.is_some() {
+            self.remote_rx.extend_from_slice(&data);
+            self.respond_line("SEND OK");
+        } else {
+            self.respond_line("SEND FAIL");
+        }
+    }
+}
+
+// Splits comma-separated arguments following `=`, removing the quotes
+fn parse_arguments(argument: &str) -> Vec<String> {
+    match argument.strip_prefix('=') {
+        Some(arguments) => arguments
+            .split(',')
+            .map(|argument| argument.trim().trim_matches('"').to_string())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+impl Device for GsmModem {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        for &byte in data {
+            if self.echo && byte != CTRL_Z && byte != ESC {
+                self.output.push(byte);
+            }
+            if matches!(self.input, Input::Command) {
+                self.receive_command(byte);
+            } else {
+                self.receive_text(byte);
+            }
+        }
+        std::mem::take(&mut self.output)
+    }
+
+    fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(modem: &mut GsmModem, data: &str) -> String {
+        String::from_utf8(modem.on_receive(data.as_bytes())).unwrap()
+    }
+
+    // Takes the unsolicited output sent on the next poll
+    fn take_output(modem: &mut GsmModem) -> String {
+        String::from_utf8(std::mem::take(&mut modem.output)).unwrap()
+    }
+
+    #[test]
+    fn test_gsm_sms() {
+        let mut modem = GsmModem::new();
+        assert_eq!(send(&mut modem, "ATE0\r"), "ATE0\r\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT+CMGS=\"+123\"\r"), "\r\nERROR\r\n");
+        assert_eq!(send(&mut modem, "AT+CMGF=1\r"), "\r\nOK\r\n");
+
+        assert_eq!(send(&mut modem, "AT+CMGS=\"+15551234\"\r"), "\r\n> ");
+        assert_eq!(send(&mut modem, "Hello\x1a"), "\r\n+CMGS: 1\r\n\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT+CMGS=\"+1\"\rdiscarded\x1b"), "\r\n> ");
+        assert_eq!(
+            modem.sent_sms(),
+            [Sms {
+                number: "+15551234".to_string(),
+                text: "Hello".to_string()
+            }]
+        );
+
+        modem.receive_sms("+4420", "Ping");
+        assert_eq!(take_output(&mut modem), "\r\n+CMTI: \"SM\",1\r\n");
+        assert_eq!(
+            send(&mut modem, "AT+CMGR=1\r"),
+            "\r\n+CMGR: \"REC UNREAD\",\"+4420\",\"\",\"24/01/01,12:00:00+00\"\r\nPing\r\n\r\nOK\r\n"
+        );
+        assert_eq!(send(&mut modem, "AT+CMGL\r"), "\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT+CMGD=1\r"), "\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT+CMGR=1\r"), "\r\nOK\r\n");
+    }
+
+    #[test]
+    fn test_gsm_registration() {
+        let mut modem = GsmModem::new().with_registration(NetworkStatus::Searching);
+        assert_eq!(send(&mut modem, "ATE0\r"), "ATE0\r\r\nOK\r\n");
+        assert_eq!(
+            send(&mut modem, "AT+CREG?\r"),
+            "\r\n+CREG: 0,2\r\n\r\nOK\r\n"
+        );
+        assert_eq!(send(&mut modem, "AT+CREG=1\r"), "\r\nOK\r\n");
+
+        modem.set_registration(NetworkStatus::Roaming);
+        assert_eq!(take_output(&mut modem), "\r\n+CREG: 5\r\n");
+        assert_eq!(
+            send(&mut modem, "AT+CGATT?\r"),
+            "\r\n+CGATT: 1\r\n\r\nOK\r\n"
+        );
+
+        // Messages need a network
+        modem.set_registration(NetworkStatus::Denied);
+        send(&mut modem, "AT+CMGF=1\r");
+        send(&mut modem, "AT+CMGS=\"+1\"\r");
+        assert!(send(&mut modem, "x\x1a").ends_with("+CMS ERROR: 331\r\n"));
+    }
+
+    #[test]
+    fn test_gsm_tcp() {
+        let mut modem = GsmModem::new().refuse_host("down.example");
+        send(&mut modem, "ATE0\r");
+        assert_eq!(send(&mut modem, "AT+CSTT=\"internet\"\r"), "\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT+CIICR\r"), "\r\nOK\r\n");
+        assert_eq!(send(&mut modem, "AT+CIFSR\r"), "\r\n10.0.0.2\r\n");
+        assert_eq!(
+            send(&mut modem, "AT+CIPSTART=\"TCP\",\"down.example\",80\r"),
+            "\r\nOK\r\n\r\nCONNECT FAIL\r\n"
+        );
+        assert_eq!(
+            send(&mut modem, "AT+CIPSTART=\"TCP\",\"Example.com\",8080\r"),
+            "\r\nOK\r\n\r\nCONNECT OK\r\n"
+        );
+        assert_eq!(modem.connection(), Some(("Example.com", 8080)));
+
+        assert_eq!(send(&mut modem, "AT+CIPSEND\r"), "\r\n> ");
+        assert_eq!(send(&mut modem, "GET /\x1a"), "\r\nSEND OK\r\n");
+        assert_eq!(send(&mut modem, "AT+CIPSEND=3\r"), "\r\n> ");
+        assert_eq!(send(&mut modem, "\x1a\x1b!"), "\r\nSEND OK\r\n");
+        assert_eq!(modem.take_remote_data(), b"GET /\x1a\x1b!");
+
+        send(&mut modem, "AT+CIPHEAD=1\r");
+        modem.send_from_remote(b"200");
+        assert_eq!(take_output(&mut modem), "\r\n+IPD,3:200");
+        assert_eq!(
+            send(&mut modem, "AT+CIPSTATUS\r"),
+            "\r\nOK\r\n\r\nSTATE: CONNECT OK\r\n"
+        );
+
+        modem.close_remote();
+        assert_eq!(take_output(&mut modem), "\r\nCLOSED\r\n");
+        assert_eq!(send(&mut modem, "AT+CIPCLOSE\r"), "\r\nERROR\r\n");
+        assert_eq!(send(&mut modem, "AT+CIPSHUT\r"), "\r\nSHUT OK\r\n");
+    }
+}
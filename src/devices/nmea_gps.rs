@@ -0,0 +1,407 @@
+//! NMEA 0183 GPS receiver.
+
+use std::{
+    fmt::Write as _,
+    time::{Duration, Instant},
+};
+
+use crate::{Device, VirtualPort};
+
+// Mean radius of the Earth in meters
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// Meters per second in a knot
+const KNOT: f64 = 1852.0 / 3600.0;
+
+// 2024-01-01T00:00:00Z
+const DEFAULT_START_TIME: u64 = 1_704_067_200;
+
+/// A satellite reported in GSV sentences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Satellite {
+    /// Pseudo-random noise number of the satellite.
+    pub prn: u8,
+
+    /// Elevation in degrees (0 to 90).
+    pub elevation: u8,
+
+    /// Azimuth in degrees (0 to 359).
+    pub azimuth: u16,
+
+    /// Signal-to-noise ratio in dB-Hz.
+    pub snr: u8,
+}
+
+/// An emulated GPS receiver streaming NMEA 0183 sentences.
+///
+/// Every fix produces a GGA, an RMC and a set of GSV sentences with valid
+/// checksums. The receiver moves from its starting position at a constant
+/// speed and course, and the time of the fixes advances by the update
+/// interval, independently of the wall clock, so the output is
+/// deterministic.
+///
+/// ```
+/// use virtual_serialport::devices::NmeaGps;
+///
+/// let mut gps = NmeaGps::new(48.1173, 11.5167).with_talker("GN");
+/// let fix = String::from_utf8(gps.next_fix()).unwrap();
+/// assert!(fix.starts_with("$GNGGA,000000.00,4807.0380,N,01131.0020,E,1,"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct NmeaGps {
+    talker: String,
+    interval: Duration,
+
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+    speed: f64,
+    course: f64,
+
+    start_time: u64,
+    satellites: Vec<Satellite>,
+
+    // Number of fixes produced so far
+    fixes: u32,
+
+    // When the runner is due to send the next fix
+    next_due: Option<Instant>,
+}
+
+impl NmeaGps {
+    /// Creates a stationary receiver at the given position (in degrees,
+    /// positive to the north and east), sending a fix every second.
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            talker: "GP".to_string(),
+            interval: Duration::from_secs(1),
+            latitude,
+            longitude,
+            altitude: 0.0,
+            speed: 0.0,
+            course: 0.0,
+            start_time: DEFAULT_START_TIME,
+            satellites: vec![
+                Satellite {
+                    prn: 2,
+                    elevation: 62,
+                    azimuth: 45,
+                    snr: 42,
+                },
+                Satellite {
+                    prn: 5,
+                    elevation: 35,
+                    azimuth: 120,
+                    snr: 38,
+                },
+                Satellite {
+                    prn: 12,
+                    elevation: 18,
+                    azimuth: 210,
+                    snr: 31,
+                },
+                Satellite {
+                    prn: 15,
+                    elevation: 71,
+                    azimuth: 300,
+                    snr: 45,
+                },
+                Satellite {
+                    prn: 21,
+                    elevation: 9,
+                    azimuth: 15,
+                    snr: 24,
+                },
+                Satellite {
+                    prn: 24,
+                    elevation: 44,
+                    azimuth: 260,
+                    snr: 40,
+                },
+            ],
+            fixes: 0,
+            next_due: None,
+        }
+    }
+
+    /// Sets the talker ID of the sentences (e.g., `GP` or `GN`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `talker` is not two ASCII characters.
+    pub fn with_talker(mut self, talker: &str) -> Self {
+        assert!(
+            talker.len() == 2 && talker.is_ascii(),
+            "talker ID must be two ASCII characters"
+        );
+        self.talker = talker.to_string();
+        self
+    }
+
+    /// Sets the interval between fixes (the update rate).
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the altitude above the mean sea level in meters.
+    pub fn with_altitude(mut self, altitude: f64) -> Self {
+        self.altitude = altitude;
+        self
+    }
+
+    /// Makes the receiver move at `speed` knots along `course` degrees from
+    /// the true north.
+    pub fn with_velocity(mut self, speed: f64, course: f64) -> Self {
+        self.speed = speed;
+        self.course = course.rem_euclid(360.0);
+        self
+    }
+
+    /// Sets the time of the first fix as seconds since the Unix epoch.
+    pub fn with_start_time(mut self, unix_time: u64) -> Self {
+        self.start_time = unix_time;
+        self
+    }
+
+    /// Sets the satellites in view.
+    pub fn with_satellites(mut self, satellites: Vec<Satellite>) -> Self {
+        self.satellites = satellites;
+        self
+    }
+
+    /// Returns the position (latitude, longitude) of the next fix.
+    pub fn position(&self) -> (f64, f64) {
+        let distance = self.speed * KNOT * self.elapsed().as_secs_f64();
+        let course = self.course.to_radians();
+
+        // Constant course over short distances (flat Earth approximation)
+        let latitude = self.latitude + (distance * course.cos() / EARTH_RADIUS).to_degrees();
+        let longitude = self.longitude
+            + (distance * course.sin() / (EARTH_RADIUS * self.latitude.to_radians().cos()))
+                .to_degrees();
+        (latitude, (longitude + 540.0).rem_euclid(360.0) - 180.0)
+    }
+
+    /// Produces the sentences of the next fix.
+    pub fn next_fix(&mut self) -> Vec<u8> {
+        let (latitude, longitude) = self.position();
+        let elapsed = self.elapsed();
+        let seconds = self.start_time + elapsed.as_secs();
+        let (time, date) = (
+            format_time(seconds, elapsed.subsec_millis()),
+            format_date(seconds),
+        );
+        let (latitude, longitude) = (format_latitude(latitude), format_longitude(longitude));
+
+        let mut output = String::new();
+        self.push_sentence(
+            &mut output,
+            &format!(
+                "GGA,{},{},{},1,{:02},0.9,{:.1},M,0.0,M,,",
+                time,
+                latitude,
+                longitude,
+                self.satellites.len().min(12),
+                self.altitude
+            ),
+        );
+        self.push_sentence(
+            &mut output,
+            &format!(
+                "RMC,{},A,{},{},{:.1},{:.1},{},,,A",
+                time, latitude, longitude, self.speed, self.course, date
+            ),
+        );
+
+        let messages = ((self.satellites.len() + 3) / 4).max(1);
+        for message in 0..messages {
+            let mut body = format!(
+                "GSV,{},{},{:02}",
+                messages,
+                message + 1,
+                self.satellites.len()
+            );
+            for satellite in self.satellites.iter().skip(message * 4).take(4) {
+                let _ = write!(
+                    body,
+                    ",{:02},{:02},{:03},{:02}",
+                    satellite.prn, satellite.elevation, satellite.azimuth, satellite.snr
+                );
+            }
+            self.push_sentence(&mut output, &body);
+        }
+
+        self.fixes += 1;
+        output.into_bytes()
+    }
+
+    // Simulated time elapsed until the next fix
+    fn elapsed(&self) -> Duration {
+        self.interval * self.fixes
+    }
+
+    fn push_sentence(&self, output: &mut String, body: &str) {
+        let sentence = format!("{}{}", self.talker, body);
+        let checksum = sentence.bytes().fold(0, |checksum, byte| checksum ^ byte);
+        let _ = write!(output, "${}*{:02X}\r\n", sentence, checksum);
+    }
+}
+
+impl Device for NmeaGps {
+    /// Commands sent to the receiver are ignored.
+    fn on_receive(&mut self, _data: &[u8]) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        let now = Instant::now();
+        match self.next_due {
+            Some(due) if now < due => Vec::new(),
+            _ => {
+                self.next_due = Some(self.next_due.unwrap_or(now) + self.interval);
+                self.next_fix()
+            }
+        }
+    }
+}
+
+// Formats the time of day as `hhmmss.ss`
+fn format_time(unix_time: u64, millis: u32) -> String {
+    let seconds = unix_time % 86_400;
+    format!(
+        "{:02}{:02}{:02}.{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        millis / 10
+    )
+}
+
+// Formats the date as `ddmmyy`
+fn format_date(unix_time: u64) -> String {
+    // Converts days since the epoch to the civil date (proleptic Gregorian
+    // calendar), following Howard Hinnant's `civil_from_days`
+    let days = (unix_time / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:02}{:02}{:02}", day, month, year % 100)
+}
+
+// Formats an angle as degrees and minutes with four decimal places
+fn format_angle(angle: f64, degree_digits: usize) -> String {
+    let total = (angle.abs() * 600_000.0).round() as u64;
+    format!(
+        "{:0width$}{:02}.{:04}",
+        total / 600_000,
+        total % 600_000 / 10_000,
+        total % 10_000,
+        width = degree_digits
+    )
+}
+
+fn format_latitude(latitude: f64) -> String {
+    let hemisphere = if latitude < 0.0 { 'S' } else { 'N' };
+    format!("{},{}", format_angle(latitude, 2), hemisphere)
+}
+
+fn format_longitude(longitude: f64) -> String {
+    let hemisphere = if longitude < 0.0 { 'W' } else { 'E' };
+    format!("{},{}", format_angle(longitude, 3), hemisphere)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks the framing and the checksum of a sentence, returning its fields
+    fn parse(sentence: &str) -> Vec<String> {
+        let sentence = sentence.strip_suffix("\r\n").unwrap();
+        let (body, checksum) = sentence[1..].split_once('*').unwrap();
+        let expected = body.bytes().fold(0, |checksum, byte| checksum ^ byte);
+        assert_eq!(u8::from_str_radix(checksum, 16).unwrap(), expected);
+        body.split(',').map(String::from).collect()
+    }
+
+    fn sentences(fix: Vec<u8>) -> Vec<String> {
+        String::from_utf8(fix)
+            .unwrap()
+            .split_inclusive("\r\n")
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_nmea_sentences() {
+        let mut gps = NmeaGps::new(-33.8568, 151.2153)
+            .with_altitude(12.5)
+            .with_start_time(1_709_210_096); // 2024-02-29T12:34:56Z
+
+        let fix = sentences(gps.next_fix());
+        assert_eq!(fix.len(), 4);
+
+        let gga = parse(&fix[0]);
+        assert_eq!(gga[0], "GPGGA");
+        assert_eq!(
+            gga[1..6],
+            ["123456.00", "3351.4080", "S", "15112.9180", "E"]
+        );
+        assert_eq!(gga[7], "06");
+        assert_eq!(gga[9], "12.5");
+
+        let rmc = parse(&fix[1]);
+        assert_eq!(rmc[0], "GPRMC");
+        assert_eq!(rmc[2], "A");
+        assert_eq!(rmc[9], "290224");
+
+        let gsv: Vec<_> = fix[2..].iter().map(|sentence| parse(sentence)).collect();
+        assert_eq!(gsv[0][..4], ["GPGSV", "2", "1", "06"]);
+        assert_eq!(gsv[1][..4], ["GPGSV", "2", "2", "06"]);
+        assert_eq!(gsv[1].len(), 4 + 2 * 4);
+    }
+
+    #[test]
+    fn test_nmea_checksum() {
+        let gps = NmeaGps::new(0.0, 0.0);
+        let mut output = String::new();
+        gps.push_sentence(
+            &mut output,
+            "GGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+        );
+        assert_eq!(
+            output,
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n"
+        );
+    }
+
+    #[test]
+    fn test_nmea_trajectory() {
+        // 3600 knots to the east along the equator: about a degree per minute
+        let mut gps = NmeaGps::new(0.0, 179.5)
+            .with_velocity(3600.0, 90.0)
+            .with_interval(Duration::from_secs(60))
+            .with_start_time(DEFAULT_START_TIME + 86_400 - 60);
+
+        gps.next_fix();
+        let (latitude, longitude) = gps.position();
+        assert!(latitude.abs() < 1e-9);
+        assert!((longitude + 179.5).abs() < 0.01, "{}", longitude);
+
+        // The date rolls over with the time
+        let rmc = parse(&sentences(gps.next_fix())[1]);
+        assert_eq!(rmc[1], "000000.00");
+        assert_eq!(rmc[6], "W");
+        assert_eq!(rmc[9], "020124");
+    }
+}
@@ -0,0 +1,507 @@
+//! SCPI (IEEE 488.2) instrument.
+
+use std::{collections::VecDeque, fmt};
+
+use crate::Device;
+
+// Minimum depth of the error queue required by SCPI is 2, instruments
+// typically hold a few more
+const ERROR_QUEUE_CAPACITY: usize = 16;
+
+// Bits of the standard event status register
+const ESR_OPERATION_COMPLETE: u8 = 0x01;
+const ESR_QUERY_ERROR: u8 = 0x04;
+const ESR_DEVICE_ERROR: u8 = 0x08;
+const ESR_EXECUTION_ERROR: u8 = 0x10;
+const ESR_COMMAND_ERROR: u8 = 0x20;
+
+// Bits of the status byte
+const STB_ERROR_AVAILABLE: u8 = 0x04;
+const STB_EVENT_STATUS: u8 = 0x20;
+
+/// An entry of the SCPI error queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScpiError {
+    /// Error number, negative for the errors defined by SCPI.
+    pub code: i16,
+
+    /// Error description.
+    pub message: String,
+}
+
+impl ScpiError {
+    /// Creates an error with the given number and description.
+    pub fn new(code: i16, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn undefined_header() -> Self {
+        Self::new(-113, "Undefined header")
+    }
+
+    fn queue_overflow() -> Self {
+        Self::new(-350, "Queue overflow")
+    }
+
+    // Bit of the event status register reporting the error
+    fn event_bit(&self) -> u8 {
+        match self.code {
+            -199..=-100 => ESR_COMMAND_ERROR,
+            -299..=-200 => ESR_EXECUTION_ERROR,
+            -399..=-300 => ESR_DEVICE_ERROR,
+            -499..=-400 => ESR_QUERY_ERROR,
+            _ => ESR_DEVICE_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ScpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},\"{}\"", self.code, self.message)
+    }
+}
+
+// Handles the parameters of a program message unit, returning the response
+// of a query
+type Handler = Box<dyn FnMut(&[&str]) -> Result<String, ScpiError> + Send>;
+
+// Node of a header pattern, such as `VOLTage` in `SOURce:VOLTage[:LEVel]`
+struct Mnemonic {
+    short: String,
+    long: String,
+    optional: bool,
+}
+
+impl Mnemonic {
+    fn matches(&self, node: &str) -> bool {
+        let node = node.to_ascii_uppercase();
+        node == self.short || node == self.long
+    }
+}
+
+enum Action {
+    Handler(Handler),
+    Builtin(Builtin),
+}
+
+#[derive(Clone, Copy)]
+enum Builtin {
+    Identify,
+    Reset,
+    Clear,
+    EventStatus,
+    EventEnable,
+    EventEnableQuery,
+    StatusByte,
+    OperationComplete,
+    OperationCompleteQuery,
+    Wait,
+    SelfTest,
+    NextError,
+    ErrorCount,
+}
+
+const BUILTINS: [(&str, Builtin); 13] = [
+    ("*IDN?", Builtin::Identify),
+    ("*RST", Builtin::Reset),
+    ("*CLS", Builtin::Clear),
+    ("*ESR?", Builtin::EventStatus),
+    ("*ESE", Builtin::EventEnable),
+    ("*ESE?", Builtin::EventEnableQuery),
+    ("*STB?", Builtin::StatusByte),
+    ("*OPC", Builtin::OperationComplete),
+    ("*OPC?", Builtin::OperationCompleteQuery),
+    ("*WAI", Builtin::Wait),
+    ("*TST?", Builtin::SelfTest),
+    ("SYSTem:ERRor[:NEXT]?", Builtin::NextError),
+    ("SYSTem:ERRor:COUNt?", Builtin::ErrorCount),
+];
+
+struct Command {
+    pattern: Vec<Mnemonic>,
+    query: bool,
+    action: Action,
+}
+
+impl Command {
+    fn new(pattern: &str, action: Action) -> Self {
+        let (pattern, query) = match pattern.strip_suffix('?') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+
+        let mut mnemonics = Vec::new();
+        let mut optional = false;
+        let mut current = String::new();
+        for c in pattern.chars().chain(Some(':')) {
+            if !matches!(c, '[' | ']' | ':') {
+                current.push(c);
+                continue;
+            }
+
+            if !current.is_empty() {
+                mnemonics.push(Mnemonic {
+                    short: current
+                        .chars()
+                        .filter(|c| !c.is_ascii_lowercase())
+                        .collect(),
+                    long: current.to_ascii_uppercase(),
+                    optional,
+                });
+                current.clear();
+            }
+            match c {
+                '[' => optional = true,
+                ']' => optional = false,
+                _ => {}
+            }
+        }
+
+        Self {
+            pattern: mnemonics,
+            query,
+            action,
+        }
+    }
+
+    fn matches(&self, header: &[&str], query: bool) -> bool {
+        self.query == query && matches(&self.pattern, header)
+    }
+}
+
+// Matches header nodes against a pattern, where optional nodes may be omitted
+fn matches(pattern: &[Mnemonic], header: &[&str]) -> bool {
+    match (pattern.split_first(), header.split_first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        (Some((mnemonic, rest)), node) => {
+            (mnemonic.optional && matches(rest, header))
+                || node.map_or(false, |(node, header)| {
+                    mnemonic.matches(node) && matches(rest, header)
+                })
+        }
+    }
+}
+
+/// An emulated instrument controlled with SCPI commands.
+///
+/// Program messages are terminated by a line feed and may hold several
+/// commands separated by semicolons, where a command without a leading colon
+/// continues from the path of the previous one (`SOUR:VOLT 1;CURR 0.1`).
+/// The responses of the queries in a message are sent together, separated by
+/// semicolons.
+///
+/// Commands are registered with [`command`](ScpiInstrument::command) and
+/// [`query`](ScpiInstrument::query) using the SCPI notation, where the
+/// upper-case part of a mnemonic is its short form and optional nodes are in
+/// brackets, e.g., `MEASure:VOLTage[:DC]?`. Besides, the instrument handles
+/// the following commands, unless they are registered:
+///
+/// - `*IDN?`, `*RST`, `*CLS`, `*OPC`, `*OPC?`, `*WAI`, `*TST?`;
+/// - `*ESR?`, `*ESE`, `*ESE?` and `*STB?` (status reporting);
+/// - `SYSTem:ERRor[:NEXT]?` and `SYSTem:ERRor:COUNt?` (error queue).
+///
+/// An unknown header or an error returned by a handler is added to the
+/// error queue, sets the matching bit of the event status register, and
+/// discards the rest of the message.
+///
+/// ```
+/// use virtual_serialport::{devices::ScpiInstrument, Device};
+///
+/// let mut dmm = ScpiInstrument::new("ACME", "DMM-100", "0001", "1.0")
+///     .query("MEASure:VOLTage[:DC]?", |_| Ok("1.234".to_string()));
+///
+/// assert_eq!(dmm.on_receive(b"meas:volt?;*IDN?\n"), b"1.234;ACME,DMM-100,0001,1.0\n");
+/// assert_eq!(dmm.on_receive(b"MEAS:CURR?\n"), b"");
+/// assert_eq!(dmm.on_receive(b"SYST:ERR?\n"), b"-113,\"Undefined header\"\n");
+/// ```
+pub struct ScpiInstrument {
+    identification: String,
+    commands: Vec<Command>,
+
+    errors: VecDeque<ScpiError>,
+    event_status: u8,
+    event_enable: u8,
+
+    // Message being received
+    line: Vec<u8>,
+}
+
+impl ScpiInstrument {
+    /// Creates an instrument identified by `*IDN?` with the given
+    /// manufacturer, model, serial number and firmware version.
+    pub fn new(manufacturer: &str, model: &str, serial: &str, firmware: &str) -> Self {
+        let commands = BUILTINS
+            .iter()
+            .map(|&(pattern, builtin)| Command::new(pattern, Action::Builtin(builtin)))
+            .collect();
+        Self {
+            identification: format!("{},{},{},{}", manufacturer, model, serial, firmware),
+            commands,
+            errors: VecDeque::new(),
+            event_status: 0,
+            event_enable: 0,
+            line: Vec::new(),
+        }
+    }
+
+    /// Registers a command setting the instrument state, called with the
+    /// parameters of the command.
+    pub fn command<F>(mut self, pattern: &str, mut handler: F) -> Self
+    where
+        F: FnMut(&[&str]) -> Result<(), ScpiError> + Send + 'static,
+    {
+        let handler = move |parameters: &[&str]| handler(parameters).map(|()| String::new());
+        self.register(pattern, Box::new(handler));
+        self
+    }
+
+    /// Registers a query (the pattern ends with `?`), called with the
+    /// parameters of the query and returning the response.
+    pub fn query<F>(mut self, pattern: &str, handler: F) -> Self
+    where
+        F: FnMut(&[&str]) -> Result<String, ScpiError> + Send + 'static,
+    {
+        self.register(pattern, Box::new(handler));
+        self
+    }
+
+    /// Adds an error to the error queue, e.g., to emulate a device fault.
+    pub fn push_error(&mut self, error: ScpiError) {
+        self.event_status |= error.event_bit();
+        if self.errors.len() < ERROR_QUEUE_CAPACITY {
+            self.errors.push_back(error);
+        } else if let Some(last) = self.errors.back_mut() {
+            *last = ScpiError::queue_overflow();
+        }
+    }
+
+    /// Returns the errors in the queue, oldest first.
+    pub fn errors(&self) -> impl Iterator<Item = &ScpiError> {
+        self.errors.iter()
+    }
+
+    // Registered commands take precedence over the built-in ones
+    fn register(&mut self, pattern: &str, handler: Handler) {
+        self.commands
+            .insert(0, Command::new(pattern, Action::Handler(handler)));
+    }
+
+    fn status_byte(&self) -> u8 {
+        let mut status = 0;
+        if !self.errors.is_empty() {
+            status |= STB_ERROR_AVAILABLE;
+        }
+        if self.event_status & self.event_enable != 0 {
+            status |= STB_EVENT_STATUS;
+        }
+        status
+    }
+
+    // Executes a program message, returning the responses of its queries
+    fn execute(&mut self, message: &str) -> Vec<String> {
+        let mut responses = Vec::new();
+        let mut path: Vec<String> = Vec::new();
+
+        for unit in message.split(';') {
+            let unit = unit.trim();
+            if unit.is_empty() {
+                continue;
+            }
+
+            let (header, parameters) = match unit.split_once(char::is_whitespace) {
+                Some((header, parameters)) => (header, parameters.trim()),
+                None => (unit, ""),
+            };
+            let parameters: Vec<&str> = if parameters.is_empty() {
+                Vec::new()
+            } else {
+                parameters.split(',').map(str::trim).collect()
+            };
+            let (header, query) = match header.strip_suffix('?') {
+                Some(header) => (header, true),
+                None => (header, false),
+            };
+
+            // Common commands do not change the current path
+            let nodes: Vec<String> = if header.starts_with('*') {
+                vec![header.to_string()]
+            } else {
+                if header.starts_with(':') {
+                    path.clear();
+                }
+                let mut nodes = path.clone();
+                nodes.extend(
+                    header
+                        .split(':')
+                        .filter(|node| !node.is_empty())
+                        .map(String::from),
+                );
+                path = nodes[..nodes.len().saturating_sub(1)].to_vec();
+                nodes
+            };
+            let nodes: Vec<&str> = nodes.iter().map(String::as_str).collect();
+
+            match self.dispatch(&nodes, query, &parameters) {
+                Ok(response) => {
+                    if query {
+                        responses.push(response);
+                    }
+                }
+                Err(error) => {
+                    self.push_error(error);
+                    break;
+                }
+            }
+        }
+        responses
+    }
+
+    fn dispatch(
+        &mut self,
+        nodes: &[&str],
+        query: bool,
+        parameters: &[&str],
+    ) -> Result<String, ScpiError> {
+        let builtin = match self
+            .commands
+            .iter_mut()
+            .find(|command| command.matches(nodes, query))
+            .map(|command| &mut command.action)
+        {
+            Some(Action::Handler(handler)) => return handler(parameters),
+            Some(Action::Builtin(builtin)) => *builtin,
+            None => return Err(ScpiError::undefined_header()),
+        };
+
+        let response = match builtin {
+            Builtin::Identify => self.identification.clone(),
+            Builtin::Reset | Builtin::Wait => String::new(),
+            Builtin::Clear => {
+                self.errors.clear();
+                self.event_status = 0;
+                String::new()
+            }
+            Builtin::EventStatus => std::mem::take(&mut self.event_status).to_string(),
+            Builtin::EventEnable => {
+                self.event_enable = match parameters {
+                    [value] => value
+                        .parse()
+                        .map_err(|_| ScpiError::new(-222, "Data out of range"))?,
+                    _ => return Err(ScpiError::new(-109, "Missing parameter")),
+                };
+                String::new()
+            }
+            Builtin::EventEnableQuery => self.event_enable.to_string(),
+            Builtin::StatusByte => self.status_byte().to_string(),
+            Builtin::OperationComplete => {
+                self.event_status |= ESR_OPERATION_COMPLETE;
+                String::new()
+            }
+            Builtin::OperationCompleteQuery => "1".to_string(),
+            Builtin::SelfTest => "0".to_string(),
+            Builtin::NextError => self
+                .errors
+                .pop_front()
+                .unwrap_or_else(|| ScpiError::new(0, "No error"))
+                .to_string(),
+            Builtin::ErrorCount => self.errors.len().to_string(),
+        };
+        Ok(response)
+    }
+}
+
+impl Device for ScpiInstrument {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for &byte in data {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let responses = self.execute(String::from_utf8_lossy(&line).trim());
+            if !responses.is_empty() {
+                output.extend_from_slice(responses.join(";").as_bytes());
+                output.push(b'\n');
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn instrument() -> ScpiInstrument {
+        ScpiInstrument::new("ACME", "PSU-1", "42", "2.1")
+    }
+
+    #[test]
+    fn test_scpi_command_tree() {
+        let voltage = Arc::new(Mutex::new(0.0f64));
+        let set_voltage = voltage.clone();
+        let get_voltage = voltage.clone();
+        let mut psu = instrument()
+            .command(
+                "[SOURce]:VOLTage[:LEVel]",
+                move |parameters| match parameters {
+                    [value] => value
+                        .parse()
+                        .map(|value| *set_voltage.lock().unwrap() = value)
+                        .map_err(|_| ScpiError::new(-224, "Illegal parameter value")),
+                    _ => Err(ScpiError::new(-109, "Missing parameter")),
+                },
+            )
+            .query("[SOURce]:VOLTage[:LEVel]?", move |_| {
+                Ok(format!("{:.3}", get_voltage.lock().unwrap()))
+            })
+            .command("SOURce:CURRent", |_| Ok(()));
+
+        assert_eq!(psu.on_receive(b"SOURCE:VOLTAGE:LEVEL 1.5\n"), b"");
+        assert_eq!(*voltage.lock().unwrap(), 1.5);
+        assert_eq!(psu.on_receive(b"volt 2.5\r\n"), b"");
+        assert_eq!(psu.on_receive(b"sour:volt?\n"), b"2.500\n");
+
+        // Relative paths, split writes and multiple responses
+        assert_eq!(psu.on_receive(b"SOUR:VOLT 3;CURR 1;VOLT?;:VOLT?"), b"");
+        assert_eq!(psu.on_receive(b";*OPC?\n"), b"3.000;3.000;1\n");
+
+        // `CURR` at the root is undefined
+        assert_eq!(psu.on_receive(b"SOUR:VOLT 4;:CURR 1;VOLT 5\n"), b"");
+        assert_eq!(*voltage.lock().unwrap(), 4.0);
+        assert_eq!(psu.on_receive(b"SYST:ERR:COUN?\n"), b"1\n");
+    }
+
+    #[test]
+    fn test_scpi_error_queue() {
+        let mut psu =
+            instrument().command("OUTPut", |_| Err(ScpiError::new(-221, "Settings conflict")));
+
+        assert_eq!(psu.on_receive(b"*STB?;SYST:ERR?\n"), b"0;0,\"No error\"\n");
+        psu.on_receive(b"OUTP ON\n");
+        psu.on_receive(b"BOGUS\n");
+        assert_eq!(psu.on_receive(b"*ESE 32;*STB?\n"), b"36\n");
+        assert_eq!(psu.on_receive(b"*ESR?;*ESR?\n"), b"48;0\n");
+        assert_eq!(
+            psu.on_receive(b"SYST:ERR?;:SYST:ERR:NEXT?;:SYST:ERR?\n"),
+            b"-221,\"Settings conflict\";-113,\"Undefined header\";0,\"No error\"\n"
+        );
+
+        // The last entry of a full queue reports the overflow
+        for _ in 0..ERROR_QUEUE_CAPACITY + 5 {
+            psu.push_error(ScpiError::new(-300, "Device-specific error"));
+        }
+        assert_eq!(psu.errors().count(), ERROR_QUEUE_CAPACITY);
+        assert_eq!(psu.errors().last().unwrap().code, -350);
+
+        assert_eq!(psu.on_receive(b"*CLS;SYST:ERR:COUN?\n"), b"0\n");
+    }
+}
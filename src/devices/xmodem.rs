@@ -0,0 +1,649 @@
+//! XMODEM-CRC and YMODEM file transfer peers.
+
+use std::time::{Duration, Instant};
+
+use crate::{Device, VirtualPort};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+
+// Requests a transfer with CRC-16 instead of the arithmetic checksum
+const CRC_REQUEST: u8 = b'C';
+
+// Pads the last block of an XMODEM transfer
+const PADDING: u8 = 0x1A;
+
+const MAX_RETRIES: u32 = 10;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A file transferred with XMODEM or YMODEM.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransferredFile {
+    /// File name, empty with XMODEM.
+    pub name: String,
+
+    /// File contents. With XMODEM, they include the padding of the last
+    /// block.
+    pub data: Vec<u8>,
+}
+
+// CRC-16/XMODEM (polynomial 0x1021, initial value 0)
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn packet(number: u8, payload: &[u8], size: usize, padding: u8) -> Vec<u8> {
+    let mut packet = vec![if size == 1024 { STX } else { SOH }, number, !number];
+    packet.extend_from_slice(payload);
+    packet.resize(3 + size, padding);
+    let crc = crc16(&packet[3..]);
+    packet.extend_from_slice(&crc.to_be_bytes());
+    packet
+}
+
+// Removes `index` from a list of pending faults, returning whether it was
+// there
+fn take_fault(faults: &mut Vec<usize>, index: usize) -> bool {
+    match faults.iter().position(|&fault| fault == index) {
+        Some(pos) => {
+            faults.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+enum Step {
+    // Waiting for the receiver to request the next block
+    Start,
+    Block { index: usize, packet: Vec<u8> },
+    Eot,
+}
+
+/// An emulated sender of XMODEM-CRC or YMODEM transfers, testing the
+/// receiving side of an application.
+///
+/// The sender starts when the receiver requests a CRC transfer with `C`,
+/// retransmits a block or EOT when it is rejected with NAK or no response
+/// arrives within the timeout, and cancels the transfer with CAN after ten
+/// failed attempts.
+///
+/// Faults are injected into the first transmission of selected blocks,
+/// counted from 0 in the order they are sent (including YMODEM header
+/// blocks).
+pub struct XmodemSender {
+    steps: Vec<Step>,
+    current: usize,
+
+    // Whether the current step has been sent and awaits a response
+    awaiting: bool,
+    sent_at: Instant,
+    retries: u32,
+    timeout: Duration,
+
+    corrupt_blocks: Vec<usize>,
+    withheld_blocks: Vec<usize>,
+
+    // Consecutive CAN characters received
+    cancels: usize,
+    cancelled: bool,
+
+    output: Vec<u8>,
+}
+
+impl XmodemSender {
+    /// Creates a sender of `data` with XMODEM-CRC (128-byte blocks).
+    pub fn xmodem_crc(data: &[u8]) -> Self {
+        let mut steps = vec![Step::Start];
+        for (index, chunk) in data.chunks(128).enumerate() {
+            steps.push(Step::Block {
+                index,
+                packet: packet((index + 1) as u8, chunk, 128, PADDING),
+            });
+        }
+        steps.push(Step::Eot);
+        Self::new(steps)
+    }
+
+    /// Creates a sender of a batch of `(name, data)` files with YMODEM
+    /// (1024-byte blocks).
+    pub fn ymodem(files: &[(&str, &[u8])]) -> Self {
+        let mut steps = Vec::new();
+        let mut index = 0;
+        let mut push_block = |steps: &mut Vec<Step>, packet| {
+            steps.push(Step::Block { index, packet });
+            index += 1;
+        };
+
+        for (name, data) in files {
+            let header = format!("{}\0{}", name, data.len());
+            let size = if header.len() < 128 { 128 } else { 1024 };
+            steps.push(Step::Start);
+            push_block(&mut steps, packet(0, header.as_bytes(), size, 0));
+
+            steps.push(Step::Start);
+            for (number, chunk) in data.chunks(1024).enumerate() {
+                push_block(&mut steps, packet((number + 1) as u8, chunk, 1024, PADDING));
+            }
+            steps.push(Step::Eot);
+        }
+
+        // An empty header ends the batch
+        steps.push(Step::Start);
+        push_block(&mut steps, packet(0, &[], 128, 0));
+        Self::new(steps)
+    }
+
+    fn new(steps: Vec<Step>) -> Self {
+        Self {
+            steps,
+            current: 0,
+            awaiting: false,
+            sent_at: Instant::now(),
+            retries: 0,
+            timeout: DEFAULT_TIMEOUT,
+            corrupt_blocks: Vec::new(),
+            withheld_blocks: Vec::new(),
+            cancels: 0,
+            cancelled: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Sets how long to wait for a response before retransmitting.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the block `index` with a wrong CRC the first time.
+    pub fn corrupt_block(mut self, index: usize) -> Self {
+        self.corrupt_blocks.push(index);
+        self
+    }
+
+    /// Omits the first transmission of the block `index`, so the receiver
+    /// has to time out.
+    pub fn withhold_block(mut self, index: usize) -> Self {
+        self.withheld_blocks.push(index);
+        self
+    }
+
+    /// Returns whether every block has been acknowledged.
+    pub fn is_done(&self) -> bool {
+        self.current == self.steps.len()
+    }
+
+    /// Returns whether the transfer has been cancelled by either side.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        if byte == CAN {
+            self.cancels += 1;
+            self.cancelled |= self.cancels >= 2;
+            return;
+        }
+        self.cancels = 0;
+
+        match (&self.steps[self.current], self.awaiting) {
+            (Step::Start, _) if byte == CRC_REQUEST => self.advance(),
+            (Step::Block { .. }, true) | (Step::Eot, true) => match byte {
+                ACK => self.advance(),
+                // A repeated request means the block was not received
+                NAK | CRC_REQUEST => self.retry(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn advance(&mut self) {
+        self.current += 1;
+        self.awaiting = false;
+        self.retries = 0;
+        if !matches!(self.steps.get(self.current), None | Some(Step::Start)) {
+            self.transmit();
+        }
+    }
+
+    fn retry(&mut self) {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.output.extend_from_slice(&[CAN, CAN]);
+            self.cancelled = true;
+        } else {
+            self.transmit();
+        }
+    }
+
+    fn transmit(&mut self) {
+        self.awaiting = true;
+        self.sent_at = Instant::now();
+        match &self.steps[self.current] {
+            Step::Start => {}
+            Step::Block { index, packet } => {
+                if take_fault(&mut self.withheld_blocks, *index) {
+                    return;
+                }
+                self.output.extend_from_slice(packet);
+                if take_fault(&mut self.corrupt_blocks, *index) {
+                    let last = self.output.len() - 1;
+                    self.output[last] ^= 0xFF;
+                }
+            }
+            Step::Eot => self.output.push(EOT),
+        }
+    }
+
+    fn poll(&mut self) -> Vec<u8> {
+        if !self.is_done()
+            && !self.cancelled
+            && self.awaiting
+            && self.sent_at.elapsed() >= self.timeout
+        {
+            self.retry();
+        }
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Device for XmodemSender {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        for &byte in data {
+            if self.is_done() || self.cancelled {
+                break;
+            }
+            self.receive_byte(byte);
+        }
+        std::mem::take(&mut self.output)
+    }
+
+    fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        self.poll()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    // Expecting a YMODEM header block
+    Header,
+    Data,
+    Done,
+    Cancelled,
+}
+
+/// An emulated receiver of XMODEM-CRC or YMODEM transfers, testing the
+/// sending side of an application.
+///
+/// The receiver requests a CRC transfer with `C`, acknowledges valid blocks
+/// (including duplicates of the last one), rejects invalid blocks with NAK,
+/// sends NAK or repeats the request when nothing arrives within the timeout,
+/// and cancels the transfer with CAN after ten failed attempts. With YMODEM,
+/// the first EOT of every file is rejected, as the protocol requires.
+///
+/// Faults are injected into the first reception of selected blocks, counted
+/// from 0 in the order they are accepted (including YMODEM header blocks).
+pub struct XmodemReceiver {
+    ymodem: bool,
+    timeout: Duration,
+    phase: Phase,
+    files: Vec<TransferredFile>,
+
+    // Size of the current YMODEM file, if announced in its header
+    size: Option<usize>,
+    expected: u8,
+    last_accepted: Option<u8>,
+
+    // Whether no block has been accepted since requesting the transfer
+    requesting: bool,
+    eot_rejected: bool,
+
+    packet: Vec<u8>,
+    last_activity: Option<Instant>,
+    retries: u32,
+
+    // Number of blocks accepted so far
+    blocks: usize,
+    nak_blocks: Vec<usize>,
+    ignored_blocks: Vec<usize>,
+
+    // Consecutive CAN characters received
+    cancels: usize,
+
+    output: Vec<u8>,
+}
+
+impl XmodemReceiver {
+    /// Creates a receiver of an XMODEM-CRC transfer.
+    pub fn xmodem_crc() -> Self {
+        let mut receiver = Self::new(false, Phase::Data);
+        receiver.files.push(TransferredFile::default());
+        receiver
+    }
+
+    /// Creates a receiver of a YMODEM batch transfer.
+    pub fn ymodem() -> Self {
+        Self::new(true, Phase::Header)
+    }
+
+    fn new(ymodem: bool, phase: Phase) -> Self {
+        Self {
+            ymodem,
+            timeout: DEFAULT_TIMEOUT,
+            phase,
+            files: Vec::new(),
+            size: None,
+            expected: if phase == Phase::Header { 0 } else { 1 },
+            last_accepted: None,
+            requesting: true,
+            eot_rejected: false,
+            packet: Vec::new(),
+            last_activity: None,
+            retries: 0,
+            blocks: 0,
+            nak_blocks: Vec::new(),
+            ignored_blocks: Vec::new(),
+            cancels: 0,
+            output: Vec::new(),
+        }
+    }
+
+    /// Sets how long to wait for data before sending NAK or repeating the
+    /// request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Rejects the valid block `index` with NAK the first time.
+    pub fn nak_block(mut self, index: usize) -> Self {
+        self.nak_blocks.push(index);
+        self
+    }
+
+    /// Does not respond to the block `index` the first time, so the sender
+    /// has to time out.
+    pub fn ignore_block(mut self, index: usize) -> Self {
+        self.ignored_blocks.push(index);
+        self
+    }
+
+    /// Returns the files received so far.
+    pub fn files(&self) -> &[TransferredFile] {
+        &self.files
+    }
+
+    /// Returns whether the transfer has completed.
+    pub fn is_done(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    /// Returns whether the transfer has been cancelled by either side.
+    pub fn is_cancelled(&self) -> bool {
+        self.phase == Phase::Cancelled
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        self.last_activity = Some(Instant::now());
+        if !self.packet.is_empty() {
+            self.packet.push(byte);
+            let size = if self.packet[0] == STX { 1024 } else { 128 };
+            if self.packet.len() == 3 + size + 2 {
+                let packet = std::mem::take(&mut self.packet);
+                self.receive_packet(&packet[1..]);
+            }
+            return;
+        }
+
+        if byte == CAN {
+            self.cancels += 1;
+            if self.cancels >= 2 {
+                self.phase = Phase::Cancelled;
+            }
+            return;
+        }
+        self.cancels = 0;
+
+        // Anything else between packets is line noise
+        match byte {
+            SOH | STX => self.packet.push(byte),
+            EOT => self.end_of_file(),
+            _ => {}
+        }
+    }
+
+    // Handles a packet without its start byte
+    fn receive_packet(&mut self, packet: &[u8]) {
+        let (number, payload) = (packet[0], &packet[2..packet.len() - 2]);
+        let crc = u16::from_be_bytes([packet[packet.len() - 2], packet[packet.len() - 1]]);
+        if packet[1] != !number || crc16(payload) != crc {
+            return self.fail(NAK);
+        }
+        if Some(number) == self.last_accepted {
+            // The acknowledgment was lost
+            self.output.push(ACK);
+            return;
+        }
+        if number != self.expected {
+            return self.fail(NAK);
+        }
+
+        let index = self.blocks;
+        if take_fault(&mut self.ignored_blocks, index) {
+            return;
+        }
+        if take_fault(&mut self.nak_blocks, index) {
+            self.output.push(NAK);
+            return;
+        }
+
+        self.blocks += 1;
+        self.retries = 0;
+        self.requesting = false;
+        self.last_accepted = Some(number);
+        self.output.push(ACK);
+
+        if self.phase == Phase::Header {
+            self.receive_header(payload);
+            return;
+        }
+
+        self.expected = self.expected.wrapping_add(1);
+        let file = self.files.last_mut().unwrap();
+        file.data.extend_from_slice(payload);
+        if let Some(size) = self.size {
+            file.data.truncate(size);
+        }
+    }
+
+    // Handles a YMODEM header with the file name and, optionally, its size
+    fn receive_header(&mut self, payload: &[u8]) {
+        let mut fields = payload.split(|&byte| byte == 0);
+        let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+        if name.is_empty() {
+            self.phase = Phase::Done;
+            return;
+        }
+
+        self.size = fields
+            .next()
+            .and_then(|field| field.split(|&byte| byte == b' ').next())
+            .and_then(|size| std::str::from_utf8(size).ok()?.parse().ok());
+        self.files.push(TransferredFile {
+            name,
+            data: Vec::new(),
+        });
+        self.request(Phase::Data);
+    }
+
+    fn end_of_file(&mut self) {
+        if self.phase != Phase::Data {
+            return self.fail(NAK);
+        }
+
+        if !self.ymodem {
+            self.output.push(ACK);
+            self.phase = Phase::Done;
+        } else if !self.eot_rejected {
+            self.eot_rejected = true;
+            self.output.push(NAK);
+        } else {
+            self.eot_rejected = false;
+            self.output.push(ACK);
+            self.request(Phase::Header);
+        }
+    }
+
+    // Requests the blocks of the next phase
+    fn request(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.expected = if phase == Phase::Header { 0 } else { 1 };
+        self.requesting = true;
+
+        // A repeated header is still recognized in the data phase
+        if phase == Phase::Header {
+            self.last_accepted = None;
+        }
+        self.output.push(CRC_REQUEST);
+    }
+
+    // Sends `response` after a failed attempt, or cancels the transfer
+    fn fail(&mut self, response: u8) {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.output.extend_from_slice(&[CAN, CAN]);
+            self.phase = Phase::Cancelled;
+        } else {
+            self.output.push(response);
+        }
+    }
+
+    fn poll(&mut self) -> Vec<u8> {
+        let due = self
+            .last_activity
+            .map_or(true, |time| time.elapsed() >= self.timeout);
+        if matches!(self.phase, Phase::Header | Phase::Data) && due {
+            if self.last_activity.is_none() {
+                self.output.push(CRC_REQUEST);
+            } else {
+                // Discards an incomplete packet
+                self.packet.clear();
+                self.fail(if self.requesting { CRC_REQUEST } else { NAK });
+            }
+            self.last_activity = Some(Instant::now());
+        }
+        std::mem::take(&mut self.output)
+    }
+}
+
+impl Device for XmodemReceiver {
+    fn on_receive(&mut self, data: &[u8]) -> Vec<u8> {
+        for &byte in data {
+            if matches!(self.phase, Phase::Done | Phase::Cancelled) {
+                break;
+            }
+            self.receive_byte(byte);
+        }
+        std::mem::take(&mut self.output)
+    }
+
+    fn on_poll(&mut self, _port: &mut VirtualPort) -> Vec<u8> {
+        self.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const SHORT_TIMEOUT: Duration = Duration::from_millis(20);
+
+    // Runs a sender against a receiver until both are finished
+    fn transfer(sender: &mut XmodemSender, receiver: &mut XmodemReceiver) {
+        let start = Instant::now();
+        let mut to_sender = receiver.poll();
+        while !(sender.is_done() && receiver.is_done()) {
+            assert!(!sender.is_cancelled() && !receiver.is_cancelled());
+            assert!(start.elapsed() < Duration::from_secs(5), "transfer stalled");
+
+            let mut to_receiver = sender.on_receive(&to_sender);
+            to_receiver.extend(sender.poll());
+            to_sender = receiver.on_receive(&to_receiver);
+            to_sender.extend(receiver.poll());
+            if to_sender.is_empty() && to_receiver.is_empty() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn test_xmodem_crc_transfer() {
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut sender = XmodemSender::xmodem_crc(&data)
+            .corrupt_block(0)
+            .withhold_block(2);
+        let mut receiver = XmodemReceiver::xmodem_crc()
+            .nak_block(1)
+            .with_timeout(SHORT_TIMEOUT);
+
+        transfer(&mut sender, &mut receiver);
+
+        let file = &receiver.files()[0];
+        assert_eq!(file.data.len(), 384);
+        assert_eq!(file.data[..300], data[..]);
+        assert!(file.data[300..].iter().all(|&byte| byte == PADDING));
+    }
+
+    #[test]
+    fn test_ymodem_batch_transfer() {
+        let big: Vec<u8> = (0..2500).map(|i| (i % 251) as u8).collect();
+        let mut sender = XmodemSender::ymodem(&[("big.bin", &big), ("small.txt", b"hello")])
+            .with_timeout(SHORT_TIMEOUT);
+        let mut receiver = XmodemReceiver::ymodem().ignore_block(2).nak_block(4);
+
+        transfer(&mut sender, &mut receiver);
+
+        let files = receiver.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].name.as_str(), &files[0].data), ("big.bin", &big));
+        assert_eq!(files[1].name, "small.txt");
+        assert_eq!(files[1].data, b"hello");
+    }
+
+    #[test]
+    fn test_xmodem_retry_limit() {
+        let mut sender = XmodemSender::xmodem_crc(b"data");
+        assert_eq!(sender.on_receive(b"C")[0], SOH);
+        for _ in 0..MAX_RETRIES {
+            assert_eq!(sender.on_receive(&[NAK])[0], SOH);
+        }
+        assert_eq!(sender.on_receive(&[NAK]), [CAN, CAN]);
+        assert!(sender.is_cancelled());
+
+        let mut receiver = XmodemReceiver::xmodem_crc();
+        assert_eq!(receiver.poll(), b"C");
+        assert_eq!(receiver.on_receive(&[EOT]), [ACK]);
+        assert!(receiver.is_done());
+    }
+}
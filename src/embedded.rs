@@ -0,0 +1,269 @@
+//! Embedded HAL trait implementations.
+//!
+//! With the `embedded-hal-nb` feature, [`VirtualPort`] implements the
+//! non-blocking `embedded_hal_nb::serial::Read` and `Write` traits, so
+//! firmware drivers written against these abstractions can be unit-tested on
+//! the host. Reading returns `WouldBlock` while no data is available (and
+//! fails once the peer is closed or the link is down, so `nb::block!` doesn't
+//! spin forever), and writing returns `WouldBlock` while the peer buffer is
+//! full.
+//!
+//! With the `embedded-io` feature, [`VirtualPort`] implements the blocking
+//! `embedded_io` traits: a read waits (up to the port timeout) until at least
+//! one byte is available and returns whatever has arrived. The
+//! `embedded-io-async` feature implements the `embedded_io_async` traits for
+//! [`AsyncVirtualPort`](crate::AsyncVirtualPort), so drivers written for
+//! async embedded frameworks (e.g., Embassy) can be exercised in host tests.
+
+use std::io;
+
+use crate::VirtualPort;
+
+#[cfg(feature = "embedded-hal-nb")]
+use crate::LineError;
+
+#[cfg(feature = "embedded-hal-nb")]
+use embedded_hal_nb::{
+    nb,
+    serial::{self, ErrorKind},
+};
+
+#[cfg(feature = "embedded-io-async")]
+use std::task::Context;
+
+#[cfg(feature = "embedded-io-async")]
+use crate::{async_port::PollFn, AsyncVirtualPort};
+
+// Maps errors of the underlying port to the serial error kinds
+#[cfg(feature = "embedded-hal-nb")]
+fn nb_error(err: io::Error) -> nb::Error<ErrorKind> {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => nb::Error::WouldBlock,
+        io::ErrorKind::InvalidData => match LineError::from_io(&err) {
+            Some(LineError::Framing | LineError::Break) => nb::Error::Other(ErrorKind::FrameFormat),
+            Some(LineError::Parity) => nb::Error::Other(ErrorKind::Parity),
+            None => nb::Error::Other(ErrorKind::Other),
+        },
+        _ => nb::Error::Other(ErrorKind::Other),
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl serial::ErrorType for VirtualPort {
+    type Error = ErrorKind;
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl serial::Read for VirtualPort {
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut word = [0u8; 1];
+        match self.try_read(&mut word).map_err(nb_error)? {
+            // The peer is closed, so no data will ever arrive
+            0 => Err(nb::Error::Other(ErrorKind::Other)),
+            _ => Ok(word[0]),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl serial::Write for VirtualPort {
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.pipe.try_write(&[word]).map_err(nb_error)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        // Done once the data written has been transmitted
+        if self.simulate_tx_delay() && self.pipe.write_in_flight_len() > 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ErrorType for VirtualPort {
+    type Error = io::Error;
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Read for VirtualPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receive(buf, 1)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::ReadReady for VirtualPort {
+    fn read_ready(&mut self) -> io::Result<bool> {
+        Ok(self.pipe.read_buffer_len() > 0)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::Write for VirtualPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::Write::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(self)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl embedded_io::WriteReady for VirtualPort {
+    fn write_ready(&mut self) -> io::Result<bool> {
+        Ok(self.pipe.is_writable())
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io::ErrorType for AsyncVirtualPort {
+    type Error = io::Error;
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Read for AsyncVirtualPort {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        PollFn(|cx: &mut Context<'_>| self.poll_read_into(cx, buf)).await
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Write for AsyncVirtualPort {
+    async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        PollFn(|cx: &mut Context<'_>| self.get_ref().pipe.poll_write(cx, buf)).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        PollFn(|cx: &mut Context<'_>| self.poll_flush_port(cx)).await
+    }
+}
+
+#[cfg(all(test, feature = "embedded-hal-nb"))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use embedded_hal_nb::serial::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_nb_read_write() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+
+        assert_eq!(Read::read(&mut port2), Err(nb::Error::WouldBlock));
+
+        for &word in b"hi" {
+            nb::block!(Write::write(&mut port1, word)).unwrap();
+        }
+        nb::block!(Write::flush(&mut port1)).unwrap();
+
+        assert_eq!(nb::block!(Read::read(&mut port2)), Ok(b'h'));
+        assert_eq!(nb::block!(Read::read(&mut port2)), Ok(b'i'));
+        assert_eq!(Read::read(&mut port2), Err(nb::Error::WouldBlock));
+    }
+
+    #[test]
+    fn test_nb_read_after_hangup() {
+        let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+        drop(port2);
+        assert_eq!(nb::block!(Read::read(&mut port1)), Err(ErrorKind::Other));
+
+        // A link going down fails the reads as well
+        let (mut port1, _port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.disconnect();
+        assert_eq!(
+            Read::read(&mut port1),
+            Err(nb::Error::Other(ErrorKind::Other))
+        );
+    }
+
+    #[test]
+    fn test_nb_flush_waits_for_transmission() {
+        let (mut port1, _port2) = VirtualPort::pair(1200, 1024).unwrap();
+        port1.set_simulate_tx_delay(true);
+
+        nb::block!(Write::write(&mut port1, 0x55)).unwrap();
+        assert_eq!(Write::flush(&mut port1), Err(nb::Error::WouldBlock));
+        assert_eq!(nb::block!(Write::flush(&mut port1)), Ok(()));
+        assert_eq!(serialport::SerialPort::bytes_to_write(&port1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_nb_write_to_full_buffer() {
+        let mut port = VirtualPort::loopback(9600, 1).unwrap();
+
+        assert_eq!(Write::write(&mut port, 1), Ok(()));
+        assert_eq!(Write::write(&mut port, 2), Err(nb::Error::WouldBlock));
+        assert_eq!(Read::read(&mut port), Ok(1));
+        assert_eq!(Write::write(&mut port, 2), Ok(()));
+
+        // Blocking writes don't make the write wait for space
+        port.set_blocking_writes(true);
+        serialport::SerialPort::set_timeout(&mut port, Duration::from_secs(10)).unwrap();
+        let start = Instant::now();
+        assert_eq!(Write::write(&mut port, 3), Err(nb::Error::WouldBlock));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
+
+#[cfg(all(test, feature = "embedded-io"))]
+mod embedded_io_tests {
+    use std::time::Duration;
+
+    use embedded_io::{Read, ReadReady, Write, WriteReady};
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_embedded_io_read_returns_available_data() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_timeout(Duration::from_millis(100)).unwrap();
+
+        assert!(!port2.read_ready().unwrap());
+        port1.write_all(b"abc").unwrap();
+        assert!(port2.read_ready().unwrap());
+
+        let mut buf = [0u8; 16];
+        assert_eq!(Read::read(&mut port2, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+
+        assert_eq!(
+            Read::read(&mut port2, &mut buf).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn test_embedded_io_write_ready() {
+        let mut port = VirtualPort::loopback(9600, 2).unwrap();
+
+        assert!(port.write_ready().unwrap());
+        port.write_all(b"ab").unwrap();
+        assert!(!port.write_ready().unwrap());
+    }
+}
+
+#[cfg(all(test, feature = "embedded-io-async"))]
+mod embedded_io_async_tests {
+    use embedded_io_async::{Read, Write};
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_embedded_io_async_pair() {
+        let (mut port1, mut port2) = AsyncVirtualPort::pair(9600, 1024).unwrap();
+
+        block_on(async {
+            port1.write_all(b"hello").await.unwrap();
+
+            let mut buf = [0u8; 16];
+            let len = Read::read(&mut port2, &mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"hello");
+        });
+    }
+}
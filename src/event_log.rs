@@ -0,0 +1,247 @@
+//! In-memory logs of the events of a port, queried by tests.
+
+use std::{
+    sync::{mpsc::Receiver, Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use crate::{
+    capture::{CaptureSink, CaptureTap},
+    ControlEvent, ControlLine, LineError, VirtualPort,
+};
+
+/// Fault met by the data received by a port, logged by an [`EventLog`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultEvent {
+    /// A read reported a line error.
+    LineError(LineError),
+
+    /// The given number of bytes was discarded because the receive buffer
+    /// was full.
+    Dropped(usize),
+
+    /// The link went down.
+    Disconnected,
+
+    /// The link came back up.
+    Reconnected,
+}
+
+/// Event of a port, as seen by the port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogEvent {
+    /// The port wrote the data.
+    Sent(Vec<u8>),
+
+    /// The port received the data.
+    Received(Vec<u8>),
+
+    /// A control line of the port changed to the given level.
+    Line(ControlLine, bool),
+
+    /// The data received by the port met a fault.
+    Fault(FaultEvent),
+}
+
+/// Event of an [`EventLog`], with its time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    /// When the event happened, by the clock of the port.
+    pub timestamp: Instant,
+
+    /// What happened.
+    pub event: LogEvent,
+}
+
+// Data logged so far, timestamped with the clock of the port
+struct Data {
+    entries: Vec<LogEntry>,
+}
+
+impl CaptureSink for Data {
+    fn record(&mut self, data: &[u8], outbound: bool, timestamp: Instant) {
+        let data = data.to_vec();
+        let event = if outbound {
+            LogEvent::Sent(data)
+        } else {
+            LogEvent::Received(data)
+        };
+        self.entries.push(LogEntry { timestamp, event });
+    }
+}
+
+// Events collected so far, and the sources of the events not collected yet
+struct State {
+    entries: Vec<LogEntry>,
+    control_events: Receiver<ControlEvent>,
+    faults: Receiver<(Instant, FaultEvent)>,
+}
+
+/// Log of the data, the control line changes and the faults of a port,
+/// started with [`VirtualPort::start_event_log`] and kept in memory until
+/// dropped, so a test can check the timing of the events afterwards.
+///
+/// ```
+/// use std::{io::Write, time::Duration};
+///
+/// use serialport::SerialPort;
+/// use virtual_serialport::{ControlLine, LogEvent, VirtualPort};
+///
+/// let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+/// let log = port1.start_event_log();
+///
+/// port2.write_request_to_send(false).unwrap();
+/// port2.write_request_to_send(true).unwrap();
+/// port2.write_all(b"ok").unwrap();
+///
+/// let cts = log
+///     .find(|entry| entry.event == LogEvent::Line(ControlLine::Cts, true))
+///     .unwrap();
+/// let response = log
+///     .find_after(cts.timestamp, |entry| {
+///         matches!(entry.event, LogEvent::Received(_))
+///     })
+///     .unwrap();
+/// assert!(response.timestamp - cts.timestamp < Duration::from_millis(50));
+/// ```
+pub struct EventLog {
+    data: Arc<Mutex<Data>>,
+    state: Mutex<State>,
+}
+
+impl EventLog {
+    /// Returns the events logged so far, ordered by their time.
+    pub fn entries(&self) -> Vec<LogEntry> {
+        self.filter(|_| true)
+    }
+
+    /// Returns the events logged so far for which `predicate` holds, ordered
+    /// by their time.
+    pub fn filter(&self, mut predicate: impl FnMut(&LogEntry) -> bool) -> Vec<LogEntry> {
+        self.collect()
+            .entries
+            .iter()
+            .filter(|entry| predicate(entry))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the first event logged for which `predicate` holds.
+    pub fn find(&self, predicate: impl FnMut(&LogEntry) -> bool) -> Option<LogEntry> {
+        self.filter(predicate).into_iter().next()
+    }
+
+    /// Returns the first event logged at or after `since` for which
+    /// `predicate` holds.
+    pub fn find_after(
+        &self,
+        since: Instant,
+        mut predicate: impl FnMut(&LogEntry) -> bool,
+    ) -> Option<LogEntry> {
+        self.find(|entry| entry.timestamp >= since && predicate(entry))
+    }
+
+    /// Forgets the events logged so far.
+    pub fn clear(&self) {
+        self.collect().entries.clear();
+    }
+
+    // Moves the pending events into the log
+    fn collect(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap();
+        let State {
+            entries,
+            control_events,
+            faults,
+        } = &mut *state;
+        entries.append(&mut self.data.lock().unwrap().entries);
+        entries.extend(control_events.try_iter().map(|event| LogEntry {
+            timestamp: event.timestamp,
+            event: LogEvent::Line(event.line, event.level),
+        }));
+        entries.extend(faults.try_iter().map(|(timestamp, fault)| LogEntry {
+            timestamp,
+            event: LogEvent::Fault(fault),
+        }));
+
+        // The events are only ordered by source
+        entries.sort_by_key(|entry| entry.timestamp);
+        state
+    }
+}
+
+impl VirtualPort {
+    /// Starts logging the data written and received by the port, the changes
+    /// of its control lines and the faults met by the data it receives,
+    /// timestamped with the clock of the port. The logging stops once the
+    /// log is dropped.
+    pub fn start_event_log(&self) -> EventLog {
+        let data = Arc::new(Mutex::new(Data {
+            entries: Vec::new(),
+        }));
+        let (outbound, inbound) = CaptureTap::pair(data.clone());
+        self.pipe.add_capture(outbound, inbound);
+        EventLog {
+            data,
+            state: Mutex::new(State {
+                entries: Vec::new(),
+                control_events: self.subscribe_control_events(),
+                faults: self.pipe.subscribe_faults(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use super::*;
+    use crate::{Clock, ManualClock, OverrunPolicy};
+
+    #[test]
+    fn test_event_log() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 4).unwrap();
+        let clock = Arc::new(ManualClock::new());
+        port1.set_clock(clock.clone());
+        port2.set_clock(clock.clone());
+        port2.set_overrun_policy(OverrunPolicy::DropNewest);
+        let log = port2.start_event_log();
+        let start = clock.now();
+
+        port1.write_all(b"abcdef").unwrap();
+        clock.advance(Duration::from_millis(10));
+        port1.disconnect();
+        port1.reconnect();
+
+        // The link drop also shows as control line changes
+        assert_eq!(
+            log.filter(|entry| !matches!(entry.event, LogEvent::Line(..)))
+                .into_iter()
+                .map(|entry| (entry.timestamp - start, entry.event))
+                .collect::<Vec<_>>(),
+            [
+                (Duration::ZERO, LogEvent::Received(b"abcdef".to_vec())),
+                (Duration::ZERO, LogEvent::Fault(FaultEvent::Dropped(2))),
+                (
+                    Duration::from_millis(10),
+                    LogEvent::Fault(FaultEvent::Disconnected)
+                ),
+                (
+                    Duration::from_millis(10),
+                    LogEvent::Fault(FaultEvent::Reconnected)
+                ),
+            ]
+        );
+
+        let after = start + Duration::from_millis(5);
+        let fault = log.find_after(after, |entry| matches!(entry.event, LogEvent::Fault(_)));
+        assert_eq!(
+            fault.map(|entry| entry.event),
+            Some(LogEvent::Fault(FaultEvent::Disconnected))
+        );
+
+        log.clear();
+        assert!(log.entries().is_empty());
+    }
+}
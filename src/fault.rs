@@ -0,0 +1,1058 @@
+//! Fault injection on the data path.
+//!
+//! Faults describe how the link damages the data travelling towards a port.
+//! They are applied while the bytes written by the peer are stored in the
+//! receive buffer of the port, so the writer sees its data accepted while the
+//! reader observes the damaged stream.
+//!
+//! All random decisions on the data received by a port, including the noise
+//! simulated on configuration mismatch, are drawn from one generator, which
+//! can be seeded to replay a failure.
+//!
+//! When line errors are reported, the damaged bytes which the receiver would
+//! detect are marked, and reading stops at them to report a [`LineError`].
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt, io,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
+
+/// Loss of bytes on the way to a port, set with
+/// [`VirtualPort::set_byte_loss`](crate::VirtualPort::set_byte_loss).
+///
+/// Lost bytes are silently discarded: the writer sees them as written, but
+/// the reader never receives them, as with characters missed by a UART.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteLoss {
+    /// No bytes are lost.
+    None,
+
+    /// Each byte is lost with the given probability (from 0.0 to 1.0).
+    Random(f64),
+
+    /// Every n-th byte is lost (e.g., `EveryNth(3)` loses the 3rd, 6th, 9th
+    /// byte and so on).
+    EveryNth(u64),
+
+    /// The pattern is repeated over the stream, and the bytes matching `true`
+    /// are lost (e.g., `[false, false, true, true]` loses the 3rd and the 4th
+    /// byte of every four).
+    Pattern(Vec<bool>),
+}
+
+impl ByteLoss {
+    // Panics on parameters which can't describe a loss
+    pub(crate) fn validate(&self) {
+        match self {
+            ByteLoss::Random(probability) => validate_probability(*probability, "loss"),
+            ByteLoss::EveryNth(n) => assert!(*n > 0, "loss period must be positive"),
+            ByteLoss::Pattern(pattern) => assert!(!pattern.is_empty(), "loss pattern is empty"),
+            ByteLoss::None => (),
+        }
+    }
+
+    // Decides whether the byte at `offset` (counting from 0) is lost
+    fn is_lost(&self, offset: u64, rng: &mut dyn RngCore) -> bool {
+        match self {
+            ByteLoss::None => false,
+            ByteLoss::Random(probability) => rng.gen_bool(*probability),
+            ByteLoss::EveryNth(n) => (offset + 1) % n == 0,
+            ByteLoss::Pattern(pattern) => pattern[(offset % pattern.len() as u64) as usize],
+        }
+    }
+}
+
+/// Loss of the whole link, set on the receiving port with
+/// [`VirtualPort::set_link_drop`](crate::VirtualPort::set_link_drop).
+///
+/// Once the link drops, the buffered data of both directions is lost, reads
+/// fail with `NotConnected` and writes with `BrokenPipe` on both ends until
+/// [`VirtualPort::reconnect`](crate::VirtualPort::reconnect) is called, as
+/// with an unplugged cable or a USB adapter reset.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LinkDrop {
+    /// The link never drops by itself.
+    Never,
+
+    /// The link drops before each received byte with the given probability
+    /// (from 0.0 to 1.0).
+    Random(f64),
+
+    /// The link drops once the given number of bytes has been received
+    /// (counting from when the fault is set), and the fault is cleared.
+    AfterBytes(u64),
+}
+
+impl LinkDrop {
+    // Panics on a probability out of range
+    pub(crate) fn validate(&self) {
+        if let LinkDrop::Random(probability) = self {
+            validate_probability(*probability, "link drop");
+        }
+    }
+}
+
+/// Faults executed at chosen points of the data received by a port, set with
+/// [`VirtualPort::set_fault_schedule`](crate::VirtualPort::set_fault_schedule).
+///
+/// Points are byte offsets (counting from 0) or times, both measured from
+/// when the schedule is set. A fault scheduled at a time applies to the bytes
+/// received after that time. Scheduled faults are deterministic, so they can
+/// target the failure point of a regression test.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use virtual_serialport::FaultSchedule;
+///
+/// let schedule = FaultSchedule::new()
+///     .corrupt_at(100, 1)
+///     .drop_after(Duration::from_millis(200), 5)
+///     .disconnect_at(1024);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FaultSchedule {
+    events: Vec<ScheduledFault>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct ScheduledFault {
+    trigger: Trigger,
+    action: Action,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Trigger {
+    Offset(u64),
+    Time(Duration),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Action {
+    Corrupt(u64),
+    Drop(u64),
+    Disconnect,
+}
+
+impl FaultSchedule {
+    /// Creates an empty schedule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Corrupts `count` bytes starting from `offset`, flipping their lowest
+    /// bit (a parity error if the receiver checks parity).
+    pub fn corrupt_at(self, offset: u64, count: u64) -> Self {
+        self.with(Trigger::Offset(offset), Action::Corrupt(count))
+    }
+
+    /// Corrupts `count` bytes received after `time`.
+    pub fn corrupt_after(self, time: Duration, count: u64) -> Self {
+        self.with(Trigger::Time(time), Action::Corrupt(count))
+    }
+
+    /// Drops `count` bytes starting from `offset`.
+    pub fn drop_at(self, offset: u64, count: u64) -> Self {
+        self.with(Trigger::Offset(offset), Action::Drop(count))
+    }
+
+    /// Drops `count` bytes received after `time`.
+    pub fn drop_after(self, time: Duration, count: u64) -> Self {
+        self.with(Trigger::Time(time), Action::Drop(count))
+    }
+
+    /// Takes the link down before the byte at `offset` (see [`LinkDrop`]).
+    pub fn disconnect_at(self, offset: u64) -> Self {
+        self.with(Trigger::Offset(offset), Action::Disconnect)
+    }
+
+    /// Takes the link down when a byte arrives after `time`.
+    pub fn disconnect_after(self, time: Duration) -> Self {
+        self.with(Trigger::Time(time), Action::Disconnect)
+    }
+
+    fn with(mut self, trigger: Trigger, action: Action) -> Self {
+        self.events.push(ScheduledFault { trigger, action });
+        self
+    }
+}
+
+/// Conditions of one direction of a link, set with
+/// [`VirtualPort::set_incoming_conditions`](crate::VirtualPort::set_incoming_conditions)
+/// or [`VirtualPort::set_outgoing_conditions`](crate::VirtualPort::set_outgoing_conditions).
+///
+/// Real links often have asymmetric quality, so each direction of a pair has
+/// its own conditions. The fields correspond to the setters of the
+/// receiving port, e.g., `byte_loss` to
+/// [`VirtualPort::set_byte_loss`](crate::VirtualPort::set_byte_loss).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkConditions {
+    /// Whether the transmission delay is simulated.
+    pub simulate_delay: bool,
+
+    /// Propagation latency added to the transmission delay.
+    pub latency: Duration,
+
+    /// Variation of the time to transmit each byte.
+    pub timing_jitter: Jitter,
+
+    /// Variation of the latency of each byte.
+    pub latency_jitter: Jitter,
+
+    /// Whether the data is replaced with noise if the physical settings of
+    /// the ports don't match.
+    pub noise_on_config_mismatch: bool,
+
+    /// Loss of bytes.
+    pub byte_loss: ByteLoss,
+
+    /// Probability of a byte being received twice.
+    pub byte_duplication: f64,
+
+    /// Probability of a random byte being received before a byte.
+    pub byte_insertion: f64,
+
+    /// Model of burst errors, if any.
+    pub burst_errors: Option<GilbertElliott>,
+}
+
+impl Default for LinkConditions {
+    /// Returns the conditions of a perfect link, which are the defaults of a
+    /// port.
+    fn default() -> Self {
+        Self {
+            simulate_delay: false,
+            latency: Duration::ZERO,
+            timing_jitter: Jitter::None,
+            latency_jitter: Jitter::None,
+            noise_on_config_mismatch: false,
+            byte_loss: ByteLoss::None,
+            byte_duplication: 0.0,
+            byte_insertion: 0.0,
+            burst_errors: None,
+        }
+    }
+}
+
+impl LinkConditions {
+    // Panics on parameters which can't describe the faults
+    pub(crate) fn validate(&self) {
+        self.byte_loss.validate();
+        validate_probability(self.byte_duplication, "duplication");
+        validate_probability(self.byte_insertion, "insertion");
+        self.timing_jitter.validate();
+        self.latency_jitter.validate();
+        if let Some(model) = &self.burst_errors {
+            model.validate();
+        }
+    }
+}
+
+/// Random variation of the timing of the data received by a port, set with
+/// [`VirtualPort::set_timing_jitter`](crate::VirtualPort::set_timing_jitter)
+/// and [`VirtualPort::set_latency_jitter`](crate::VirtualPort::set_latency_jitter).
+///
+/// The variation is drawn for each byte, relative to the nominal delay, so
+/// the data arrives irregularly as over USB adapters and radio links. The
+/// bytes still arrive in order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Jitter {
+    /// The delay is exact.
+    None,
+
+    /// The delay varies uniformly by up to the given fraction of it either
+    /// way (e.g., `Uniform(0.1)` for ±10%, at most 1.0).
+    Uniform(f64),
+
+    /// The delay varies following a normal distribution with the given
+    /// standard deviation, as a fraction of the delay. A delay never becomes
+    /// negative.
+    Normal(f64),
+}
+
+impl Jitter {
+    // Panics on parameters which can't describe a variation
+    pub(crate) fn validate(&self) {
+        match *self {
+            Jitter::Uniform(fraction) => assert!(
+                (0.0..=1.0).contains(&fraction),
+                "jitter fraction must be between 0.0 and 1.0"
+            ),
+            Jitter::Normal(deviation) => assert!(
+                deviation.is_finite() && deviation >= 0.0,
+                "jitter deviation must be non-negative"
+            ),
+            Jitter::None => (),
+        }
+    }
+
+    // Varies `delay` randomly
+    pub(crate) fn apply(&self, delay: Duration, rng: &mut dyn RngCore) -> Duration {
+        let variation = match *self {
+            Jitter::None => return delay,
+            Jitter::Uniform(fraction) => rng.gen_range(-fraction..=fraction),
+            Jitter::Normal(deviation) => {
+                // Box-Muller transform of two uniform samples, the first one
+                // kept away from 0
+                let u1 = 1.0 - rng.gen::<f64>();
+                let u2 = rng.gen::<f64>();
+                deviation * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+            }
+        };
+        delay.mul_f64((1.0 + variation).max(0.0))
+    }
+}
+
+/// Two-state Gilbert-Elliott channel model, set with
+/// [`VirtualPort::set_burst_errors`](crate::VirtualPort::set_burst_errors).
+///
+/// The channel alternates between a good and a bad state, switching with the
+/// given probabilities on every byte, and corrupts a byte (flipping one of its
+/// bits) with the error rate of the current state. With a low probability of
+/// entering the bad state and a high error rate in it, errors arrive in
+/// bursts, as with cable or RF interference, rather than uniformly.
+///
+/// ```
+/// use virtual_serialport::GilbertElliott;
+///
+/// // Bursts of about 10 bytes, on average every 1000 bytes, with half of the
+/// // bytes of a burst corrupted
+/// let model = GilbertElliott::new(0.001, 0.1).with_error_rates(0.0, 0.5);
+/// assert_eq!(model.bad_error_rate, 0.5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GilbertElliott {
+    /// Probability of switching from the good to the bad state.
+    pub good_to_bad: f64,
+
+    /// Probability of switching from the bad to the good state.
+    pub bad_to_good: f64,
+
+    /// Probability of corrupting a byte in the good state.
+    pub good_error_rate: f64,
+
+    /// Probability of corrupting a byte in the bad state.
+    pub bad_error_rate: f64,
+}
+
+impl GilbertElliott {
+    /// Creates a model with the given transition probabilities, corrupting
+    /// every byte in the bad state and none in the good state (the classic
+    /// Gilbert model).
+    pub fn new(good_to_bad: f64, bad_to_good: f64) -> Self {
+        Self {
+            good_to_bad,
+            bad_to_good,
+            good_error_rate: 0.0,
+            bad_error_rate: 1.0,
+        }
+    }
+
+    /// Sets the probabilities of corrupting a byte in the good and in the
+    /// bad state.
+    pub fn with_error_rates(mut self, good: f64, bad: f64) -> Self {
+        self.good_error_rate = good;
+        self.bad_error_rate = bad;
+        self
+    }
+
+    // Panics on probabilities out of range
+    pub(crate) fn validate(&self) {
+        validate_probability(self.good_to_bad, "transition");
+        validate_probability(self.bad_to_good, "transition");
+        validate_probability(self.good_error_rate, "error");
+        validate_probability(self.bad_error_rate, "error");
+    }
+}
+
+/// Error detected by the receiver in a character, reported (when enabled with
+/// [`VirtualPort::set_report_line_errors`](crate::VirtualPort::set_report_line_errors))
+/// as an `io::Error` of kind `InvalidData` wrapping this type.
+///
+/// ```
+/// use std::io::{self, Read, Write};
+///
+/// use serialport::SerialPort;
+/// use virtual_serialport::{LineError, VirtualPort};
+///
+/// let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+/// port2.set_report_line_errors(true);
+/// port2.set_noise_on_config_mismatch(true);
+/// port2.set_baud_rate(19200).unwrap();
+///
+/// port1.write_all(b"hello").unwrap();
+/// let err = port2.read(&mut [0u8; 5]).unwrap_err();
+/// assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+/// assert_eq!(LineError::from_io(&err), Some(LineError::Framing));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineError {
+    /// The stop bit of a character was not found, e.g., because of a baud
+    /// rate mismatch or a glitch on the line.
+    Framing,
+
+    /// The parity bit of a character doesn't match its data bits.
+    Parity,
+
+    /// The line was held low for longer than a character, i.e. the peer sent
+    /// a break (received as a `0x00` byte, see
+    /// [`VirtualPort::set_break_as_data`](crate::VirtualPort::set_break_as_data)).
+    Break,
+}
+
+impl LineError {
+    /// Extracts the line error reported by a read, if any.
+    pub fn from_io(err: &io::Error) -> Option<LineError> {
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<LineError>())
+            .copied()
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineError::Framing => f.write_str("framing error"),
+            LineError::Parity => f.write_str("parity error"),
+            LineError::Break => f.write_str("break condition"),
+        }
+    }
+}
+
+impl Error for LineError {}
+
+impl From<LineError> for io::Error {
+    fn from(err: LineError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+// Line errors and address marks (the 9th bit of multidrop links) of the
+// bytes stored in a receive buffer
+pub(crate) struct Marks {
+    // Stream index of the first byte in the buffer
+    start: u64,
+
+    // Stream indices of the erroneous bytes and of the address bytes, in
+    // ascending order
+    errors: VecDeque<(u64, LineError)>,
+    addresses: VecDeque<u64>,
+}
+
+impl Marks {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            errors: VecDeque::new(),
+            addresses: VecDeque::new(),
+        }
+    }
+
+    /// Marks the byte at `position` in the buffer as an address.
+    pub(crate) fn push_address(&mut self, position: usize) {
+        self.addresses.push_back(self.start + position as u64);
+    }
+
+    /// Returns the positions in the buffer of the address bytes among the
+    /// first `len` bytes.
+    pub(crate) fn addresses(&self, len: usize) -> impl Iterator<Item = usize> + '_ {
+        let end = self.start + len as u64;
+        self.addresses
+            .iter()
+            .take_while(move |&&index| index < end)
+            .map(move |&index| (index - self.start) as usize)
+    }
+
+    /// Marks the byte at `position` in the buffer.
+    pub(crate) fn push(&mut self, position: usize, error: LineError) {
+        self.errors.push_back((self.start + position as u64, error));
+    }
+
+    /// Marks the byte at `position` in the buffer unless it is marked
+    /// already, keeping the marks in order.
+    pub(crate) fn insert(&mut self, position: usize, error: LineError) {
+        let index = self.start + position as u64;
+        let at = self.errors.partition_point(|&(marked, _)| marked < index);
+        if self
+            .errors
+            .get(at)
+            .map_or(true, |&(marked, _)| marked != index)
+        {
+            self.errors.insert(at, (index, error));
+        }
+    }
+
+    /// Returns the position in the buffer and the error of the first marked
+    /// byte.
+    pub(crate) fn first(&self) -> Option<(usize, LineError)> {
+        self.errors
+            .front()
+            .map(|&(index, error)| ((index - self.start) as usize, error))
+    }
+
+    /// Accounts for the buffer being truncated to `len` bytes.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        let end = self.start + len as u64;
+        while matches!(self.errors.back(), Some(&(index, _)) if index >= end) {
+            self.errors.pop_back();
+        }
+        while matches!(self.addresses.back(), Some(&index) if index >= end) {
+            self.addresses.pop_back();
+        }
+    }
+
+    /// Accounts for `len` bytes removed from the front of the buffer.
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.start += len as u64;
+        while matches!(self.errors.front(), Some(&(index, _)) if index < self.start) {
+            self.errors.pop_front();
+        }
+        while matches!(self.addresses.front(), Some(&index) if index < self.start) {
+            self.addresses.pop_front();
+        }
+    }
+}
+
+// Panics if `probability` is not between 0.0 and 1.0
+pub(crate) fn validate_probability(probability: f64, what: &str) {
+    assert!(
+        (0.0..=1.0).contains(&probability),
+        "{} probability must be between 0.0 and 1.0",
+        what
+    );
+}
+
+/// Counters of the faults injected into the data received by a port, part
+/// of the [`PortStats`](crate::PortStats) returned by
+/// [`VirtualPort::stats`](crate::VirtualPort::stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Number of bits flipped by the corruptions and the burst errors.
+    pub bits_flipped: u64,
+
+    /// Number of bytes replaced with noise (e.g., because of mismatched
+    /// settings or a collision).
+    pub bytes_garbled: u64,
+
+    /// Number of bytes lost on the way.
+    pub bytes_dropped: u64,
+
+    /// Number of bytes received twice.
+    pub bytes_duplicated: u64,
+
+    /// Number of random bytes inserted.
+    pub bytes_inserted: u64,
+
+    /// Number of line errors raised, marked on the received bytes or
+    /// reported to the reads.
+    pub errors_raised: u64,
+
+    /// Number of times the link was dropped.
+    pub link_drops: u64,
+}
+
+/// Faults applied to the data written into one receive buffer.
+pub(crate) struct Faults {
+    pub(crate) loss: ByteLoss,
+
+    // Probability of a byte being received twice
+    pub(crate) duplication: f64,
+
+    // Probability of a random byte appearing before a byte
+    pub(crate) insertion: f64,
+
+    // Model of corruption in bursts, and whether it is in the bad state
+    pub(crate) burst_errors: Option<GilbertElliott>,
+    bad_state: bool,
+
+    // Source of all random decisions
+    pub(crate) rng: Box<dyn RngCore + Send>,
+
+    // Whether detectable errors are marked, and whether the receiver checks
+    // parity (and so detects corrupted bits)
+    report_errors: bool,
+    pub(crate) parity_check: bool,
+
+    // Line errors of the bytes in the buffer
+    pub(crate) marks: Marks,
+
+    // Loss of the link, and the offset at which a scheduled loss happens
+    link_drop: LinkDrop,
+    drop_offset: u64,
+
+    // Scheduled faults not executed yet, the offset and the time the schedule
+    // counts from, and the number of bytes still to be dropped or corrupted
+    schedule: Vec<ScheduledFault>,
+    schedule_start: (u64, Instant),
+    scheduled_drops: u64,
+    scheduled_corruptions: u64,
+
+    // Number of bytes written into the buffer so far, including lost ones
+    offset: u64,
+
+    // Counters of the injected faults
+    pub(crate) stats: FaultStats,
+}
+
+impl Faults {
+    pub(crate) fn new() -> Self {
+        Self {
+            loss: ByteLoss::None,
+            duplication: 0.0,
+            insertion: 0.0,
+            burst_errors: None,
+            bad_state: false,
+            rng: Box::new(SmallRng::from_entropy()),
+            report_errors: false,
+            parity_check: false,
+            marks: Marks::new(),
+            link_drop: LinkDrop::Never,
+            drop_offset: 0,
+            schedule: Vec::new(),
+            schedule_start: (0, Instant::now()),
+            scheduled_drops: 0,
+            scheduled_corruptions: 0,
+            offset: 0,
+            stats: FaultStats::default(),
+        }
+    }
+
+    pub(crate) fn link_drop(&self) -> LinkDrop {
+        self.link_drop.clone()
+    }
+
+    pub(crate) fn set_link_drop(&mut self, link_drop: LinkDrop) {
+        if let LinkDrop::AfterBytes(len) = link_drop {
+            self.drop_offset = self.offset.saturating_add(len);
+        }
+        self.link_drop = link_drop;
+    }
+
+    // Decides whether the link drops before the next byte
+    fn link_drops(&mut self) -> bool {
+        let drops = match self.link_drop {
+            LinkDrop::Never => false,
+            LinkDrop::Random(probability) => self.rng.gen_bool(probability),
+            LinkDrop::AfterBytes(_) => self.offset >= self.drop_offset,
+        };
+        if drops && matches!(self.link_drop, LinkDrop::AfterBytes(_)) {
+            self.link_drop = LinkDrop::Never;
+        }
+        drops
+    }
+
+    /// Returns the conditions described by the faults, leaving the fields
+    /// which are not faults at their defaults.
+    pub(crate) fn conditions(&self) -> LinkConditions {
+        LinkConditions {
+            byte_loss: self.loss.clone(),
+            byte_duplication: self.duplication,
+            byte_insertion: self.insertion,
+            burst_errors: self.burst_errors,
+            ..LinkConditions::default()
+        }
+    }
+
+    /// Applies the faults of the conditions.
+    pub(crate) fn set_conditions(&mut self, conditions: &LinkConditions) {
+        self.loss = conditions.byte_loss.clone();
+        self.duplication = conditions.byte_duplication;
+        self.insertion = conditions.byte_insertion;
+        self.burst_errors = conditions.burst_errors;
+    }
+
+    /// Replaces the scheduled faults, cancelling the pending ones, and counts
+    /// their times from `now`.
+    pub(crate) fn set_schedule(&mut self, schedule: FaultSchedule, now: Instant) {
+        self.schedule = schedule.events;
+        self.schedule_start = (self.offset, now);
+        self.scheduled_drops = 0;
+        self.scheduled_corruptions = 0;
+    }
+
+    // Executes the scheduled faults due before the next byte, returning
+    // whether the link drops
+    fn run_schedule(&mut self, now: Instant) -> bool {
+        if self.schedule.is_empty() {
+            return false;
+        }
+
+        let (start_offset, start_time) = self.schedule_start;
+        let offset = self.offset - start_offset;
+        let elapsed = now.saturating_duration_since(start_time);
+
+        let mut disconnect = false;
+        let (drops, corruptions) = (&mut self.scheduled_drops, &mut self.scheduled_corruptions);
+        self.schedule.retain(|event| {
+            let due = match event.trigger {
+                Trigger::Offset(at) => offset >= at,
+                Trigger::Time(after) => elapsed >= after,
+            };
+            if due {
+                match event.action {
+                    Action::Corrupt(count) => *corruptions += count,
+                    Action::Drop(count) => *drops += count,
+                    Action::Disconnect => disconnect = true,
+                }
+            }
+            !due
+        });
+        disconnect
+    }
+
+    /// Moves bytes from `input` into `output` until it holds `capacity`
+    /// bytes, damaging them on the way. Returns the number of consumed input
+    /// bytes and whether the link dropped, which stops the transfer. The
+    /// scheduled faults due by `now` are executed on the way.
+    ///
+    /// Spurious bytes which don't fit are discarded, as if they overflowed
+    /// the receiver.
+    pub(crate) fn transfer(
+        &mut self,
+        input: &[u8],
+        output: &mut VecDeque<u8>,
+        capacity: usize,
+        now: Instant,
+    ) -> (usize, bool) {
+        // Without faults, the bytes are copied at once
+        if self.is_clean() {
+            let len = input.len().min(capacity.saturating_sub(output.len()));
+            output.extend(&input[..len]);
+            self.offset += len as u64;
+            return (len, false);
+        }
+
+        let mut consumed = 0;
+        for &byte in input {
+            if output.len() >= capacity {
+                break;
+            }
+            if self.link_drops() || self.run_schedule(now) {
+                self.stats.link_drops += 1;
+                return (consumed, true);
+            }
+            consumed += 1;
+
+            let rng = &mut *self.rng;
+
+            let offset = self.offset;
+            self.offset += 1;
+
+            if take_one(&mut self.scheduled_drops) {
+                self.stats.bytes_dropped += 1;
+                continue;
+            }
+            let (byte, scheduled_error) = if take_one(&mut self.scheduled_corruptions) {
+                self.stats.bits_flipped += 1;
+                (byte ^ 1, self.parity_check.then(|| LineError::Parity))
+            } else {
+                (byte, None)
+            };
+
+            // Received bytes with the errors detected in them
+            let mut received = Vec::with_capacity(3);
+            if self.insertion > 0.0 && rng.gen_bool(self.insertion) {
+                self.stats.bytes_inserted += 1;
+                received.push((rng.gen(), Some(LineError::Framing)));
+            }
+            if self.loss.is_lost(offset, rng) {
+                self.stats.bytes_dropped += 1;
+            } else {
+                let (byte, error) =
+                    if burst_error(self.burst_errors.as_ref(), &mut self.bad_state, rng) {
+                        self.stats.bits_flipped += 1;
+                        let error = self.parity_check.then(|| LineError::Parity);
+                        (byte ^ (1 << rng.gen_range(0..8)), error)
+                    } else {
+                        (byte, scheduled_error)
+                    };
+                received.push((byte, error));
+                if self.duplication > 0.0 && rng.gen_bool(self.duplication) {
+                    self.stats.bytes_duplicated += 1;
+                    received.push((byte, error));
+                }
+            }
+
+            for (byte, error) in received {
+                if output.len() >= capacity {
+                    break;
+                }
+                if let (true, Some(error)) = (self.report_errors, error) {
+                    self.marks.push(output.len(), error);
+                    self.stats.errors_raised += 1;
+                }
+                output.push_back(byte);
+            }
+        }
+        (consumed, false)
+    }
+
+    // Whether no fault can hit the next bytes
+    fn is_clean(&self) -> bool {
+        matches!(self.loss, ByteLoss::None)
+            && self.duplication == 0.0
+            && self.insertion == 0.0
+            && self.burst_errors.is_none()
+            && matches!(self.link_drop, LinkDrop::Never)
+            && self.schedule.is_empty()
+            && self.scheduled_drops == 0
+            && self.scheduled_corruptions == 0
+    }
+
+    pub(crate) fn report_errors(&self) -> bool {
+        self.report_errors
+    }
+
+    /// Enables or disables marking of the detectable errors, forgetting the
+    /// marks of the buffered bytes when disabled.
+    pub(crate) fn set_report_errors(&mut self, value: bool) {
+        self.report_errors = value;
+        if !value {
+            self.marks.errors.clear();
+        }
+    }
+
+    /// Replaces the bytes with random values.
+    pub(crate) fn fill_with_noise(&mut self, buf: &mut [u8]) {
+        self.rng.fill_bytes(buf);
+        self.stats.bytes_garbled += buf.len() as u64;
+    }
+}
+
+// Decrements a non-zero counter, returning whether it was non-zero
+fn take_one(counter: &mut u64) -> bool {
+    if *counter == 0 {
+        return false;
+    }
+    *counter -= 1;
+    true
+}
+
+// Advances the burst error model (if any) by one byte and decides whether the
+// byte is corrupted
+fn burst_error(
+    model: Option<&GilbertElliott>,
+    bad_state: &mut bool,
+    rng: &mut dyn RngCore,
+) -> bool {
+    let model = match model {
+        Some(model) => model,
+        None => return false,
+    };
+
+    let switch = if *bad_state {
+        model.bad_to_good
+    } else {
+        model.good_to_bad
+    };
+    if rng.gen_bool(switch) {
+        *bad_state = !*bad_state;
+    }
+
+    let error_rate = if *bad_state {
+        model.bad_error_rate
+    } else {
+        model.good_error_rate
+    };
+    rng.gen_bool(error_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(faults: &mut Faults, input: &[u8], capacity: usize) -> (usize, Vec<u8>) {
+        let mut output = VecDeque::new();
+        let (consumed, _) = faults.transfer(input, &mut output, capacity, Instant::now());
+        (consumed, output.into_iter().collect())
+    }
+
+    #[test]
+    fn test_periodic_loss_spans_writes() {
+        let mut faults = Faults::new();
+        faults.loss = ByteLoss::EveryNth(3);
+
+        assert_eq!(transfer(&mut faults, b"abcd", 16), (4, b"abd".to_vec()));
+        assert_eq!(transfer(&mut faults, b"efgh", 16), (4, b"egh".to_vec()));
+
+        faults.loss = ByteLoss::Pattern(vec![true, false]);
+        assert_eq!(transfer(&mut faults, b"ijkl", 16), (4, b"jl".to_vec()));
+    }
+
+    #[test]
+    fn test_clean_transfer() {
+        // Without faults, the bytes are copied up to the capacity at once,
+        // still counting the offsets of the faults enabled later
+        let mut faults = Faults::new();
+        assert_eq!(transfer(&mut faults, b"abcd", 3), (3, b"abc".to_vec()));
+        faults.loss = ByteLoss::EveryNth(5);
+        assert_eq!(transfer(&mut faults, b"defg", 16), (4, b"dfg".to_vec()));
+    }
+
+    #[test]
+    fn test_lost_bytes_take_no_space() {
+        let mut faults = Faults::new();
+        faults.loss = ByteLoss::EveryNth(2);
+
+        // Three bytes fit, the lost ones are consumed along the way
+        assert_eq!(transfer(&mut faults, b"abcdefgh", 3), (5, b"ace".to_vec()));
+
+        faults.loss = ByteLoss::Random(1.0);
+        assert_eq!(transfer(&mut faults, b"abcd", 3), (4, Vec::new()));
+    }
+
+    #[test]
+    fn test_duplication_and_insertion() {
+        let mut faults = Faults::new();
+        faults.duplication = 1.0;
+        assert_eq!(transfer(&mut faults, b"abc", 16), (3, b"aabbcc".to_vec()));
+
+        // The duplicate of the last byte overflows
+        assert_eq!(transfer(&mut faults, b"abc", 5), (3, b"aabbc".to_vec()));
+
+        faults.duplication = 0.0;
+        faults.insertion = 1.0;
+        let (consumed, received) = transfer(&mut faults, b"abc", 16);
+        assert_eq!(consumed, 3);
+        assert_eq!(received.len(), 6);
+        assert_eq!([received[1], received[3], received[5]], *b"abc");
+    }
+
+    #[test]
+    fn test_burst_errors() {
+        // The channel alternates between the states on every byte, and only
+        // the bytes received in the bad state are corrupted
+        let mut faults = Faults::new();
+        faults.burst_errors = Some(GilbertElliott::new(1.0, 1.0));
+
+        let input = [0u8; 6];
+        let (_, received) = transfer(&mut faults, &input, 16);
+        for (i, byte) in received.iter().enumerate() {
+            if i % 2 == 0 {
+                assert_eq!(byte.count_ones(), 1);
+            } else {
+                assert_eq!(*byte, 0);
+            }
+        }
+
+        // Once in the bad state, the channel never recovers
+        faults.burst_errors = Some(GilbertElliott::new(1.0, 0.0).with_error_rates(0.0, 1.0));
+        let (_, received) = transfer(&mut faults, &input, 16);
+        assert!(received[1..].iter().all(|byte| *byte != 0));
+    }
+
+    #[test]
+    fn test_error_marks() {
+        let mut faults = Faults::new();
+        faults.set_report_errors(true);
+        faults.burst_errors = Some(GilbertElliott::new(1.0, 1.0));
+
+        // Corrupted bits go unnoticed without a parity check
+        transfer(&mut faults, b"ab", 16);
+        assert_eq!(faults.marks.first(), None);
+
+        // Every other byte is corrupted
+        let mut faults = Faults::new();
+        faults.set_report_errors(true);
+        faults.parity_check = true;
+        faults.burst_errors = Some(GilbertElliott::new(1.0, 1.0));
+        transfer(&mut faults, b"abcd", 16);
+        assert_eq!(faults.marks.first(), Some((0, LineError::Parity)));
+
+        faults.marks.consume(1);
+        assert_eq!(faults.marks.first(), Some((1, LineError::Parity)));
+        faults.marks.consume(2);
+        assert_eq!(faults.marks.first(), None);
+    }
+
+    #[test]
+    fn test_scheduled_link_drop() {
+        let mut faults = Faults::new();
+        transfer(&mut faults, b"ab", 16);
+        faults.set_link_drop(LinkDrop::AfterBytes(3));
+
+        let mut output = VecDeque::new();
+        assert_eq!(
+            faults.transfer(b"cd", &mut output, 16, Instant::now()),
+            (2, false)
+        );
+        assert_eq!(
+            faults.transfer(b"efg", &mut output, 16, Instant::now()),
+            (1, true)
+        );
+        assert_eq!(faults.link_drop(), LinkDrop::Never);
+        assert_eq!(
+            faults.transfer(b"fg", &mut output, 16, Instant::now()),
+            (2, false)
+        );
+    }
+
+    #[test]
+    fn test_fault_schedule() {
+        let mut faults = Faults::new();
+        transfer(&mut faults, b"ab", 16);
+        faults.set_schedule(
+            FaultSchedule::new()
+                .corrupt_at(1, 2)
+                .drop_at(4, 1)
+                .drop_after(Duration::ZERO, 1)
+                .disconnect_at(8),
+            Instant::now(),
+        );
+
+        let mut output = VecDeque::new();
+        assert_eq!(
+            faults.transfer(b"abcdefghij", &mut output, 16, Instant::now()),
+            (8, true)
+        );
+        assert_eq!(output, b"cbdfgh".to_vec());
+
+        // Executed faults don't repeat
+        assert_eq!(transfer(&mut faults, b"ab", 16), (2, b"ab".to_vec()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_faults() {
+        let conditions = LinkConditions {
+            latency: Duration::from_millis(20),
+            timing_jitter: Jitter::Normal(0.1),
+            byte_loss: ByteLoss::Pattern(vec![false, true]),
+            burst_errors: Some(GilbertElliott {
+                good_to_bad: 0.01,
+                bad_to_good: 0.2,
+                good_error_rate: 0.0,
+                bad_error_rate: 0.5,
+            }),
+            ..LinkConditions::default()
+        };
+        let json = serde_json::to_string(&conditions).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LinkConditions>(&json).unwrap(),
+            conditions
+        );
+
+        let schedule = FaultSchedule::new()
+            .corrupt_at(10, 2)
+            .disconnect_after(Duration::from_secs(1));
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert_eq!(
+            serde_json::from_str::<FaultSchedule>(&json).unwrap(),
+            schedule
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Golden transcripts of the sessions of a port, for protocol regression
+//! tests.
+//!
+//! A test records the session of a port with a [`Recorder`], and compares
+//! the transcript of the data exchanged with a golden file kept with the
+//! tests. Running the tests with the `UPDATE_GOLDEN` environment variable set
+//! writes the golden files instead, e.g., after an intended protocol change.
+
+use std::{env, fs, path::Path};
+
+use crate::{hexdump::hexdump_at, RecordedEvent, Recorder, Recording};
+
+// Environment variable making the comparisons update the golden files
+const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+impl Recording {
+    /// Returns the transcript of the data exchanged during the session,
+    /// without the timing: hexdumps of the runs of data sent (`tx`) and
+    /// received (`rx`) by the port, with the offsets of the bytes in their
+    /// direction, so the transcript doesn't depend on how the data was split
+    /// into writes.
+    ///
+    /// ```text
+    /// tx 0000  41 54 0d                                         |AT.|
+    /// rx 0000  4f 4b 0d 0a                                      |OK..|
+    /// ```
+    pub fn transcript(&self) -> String {
+        // Runs of data in the same direction
+        let mut runs: Vec<(bool, Vec<u8>)> = Vec::new();
+        for record in self.records() {
+            let (outbound, data) = match &record.event {
+                RecordedEvent::Sent(data) => (true, data),
+                RecordedEvent::Received(data) => (false, data),
+                _ => continue,
+            };
+            match runs.last_mut() {
+                Some((run_outbound, run)) if *run_outbound == outbound => run.extend(data),
+                _ => runs.push((outbound, data.clone())),
+            }
+        }
+
+        let (mut sent, mut received) = (0, 0);
+        let mut transcript = String::new();
+        for (outbound, data) in runs {
+            let (direction, offset) = if outbound {
+                ("tx", &mut sent)
+            } else {
+                ("rx", &mut received)
+            };
+            for line in hexdump_at(&data, *offset) {
+                transcript += &format!("{} {}\n", direction, line);
+            }
+            *offset += data.len();
+        }
+        transcript
+    }
+
+    /// Asserts that the transcript of the session (see
+    /// [`transcript`](Recording::transcript)) matches the golden file at
+    /// `path`. With the `UPDATE_GOLDEN` environment variable set, the golden
+    /// file is written instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the transcript differs, showing a diff of the lines, or if
+    /// the golden file can't be read.
+    #[track_caller]
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        self.check_golden(path.as_ref(), env::var_os(UPDATE_VAR).is_some());
+    }
+
+    // Compares the transcript with the golden file, or writes the file
+    #[track_caller]
+    fn check_golden(&self, path: &Path, update: bool) {
+        let transcript = self.transcript();
+        if update {
+            if let Err(err) = fs::write(path, &transcript) {
+                panic!("failed to write {}: {}", path.display(), err);
+            }
+            return;
+        }
+
+        let golden = match fs::read_to_string(path) {
+            Ok(golden) => golden,
+            Err(err) => panic!(
+                "failed to read {}: {} (set {} to create it)",
+                path.display(),
+                err,
+                UPDATE_VAR
+            ),
+        };
+        if transcript != golden {
+            panic!(
+                "transcript differs from {} (- golden, + actual)\n{}",
+                path.display(),
+                diff_lines(&golden, &transcript)
+            );
+        }
+    }
+}
+
+impl Recorder {
+    /// Stops recording and asserts that the transcript of the session
+    /// matches the golden file at `path` (see [`Recording::assert_golden`]).
+    ///
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    ///
+    /// use virtual_serialport::VirtualPort;
+    ///
+    /// let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+    /// let recorder = port1.start_recording();
+    /// port1.write_all(b"AT\r").unwrap();
+    /// port2.write_all(b"OK\r\n").unwrap();
+    /// recorder.assert_golden("tests/golden/at.txt");
+    /// ```
+    #[track_caller]
+    pub fn assert_golden(self, path: impl AsRef<Path>) {
+        self.finish().assert_golden(path);
+    }
+}
+
+// Formats a diff of the lines of the texts, marking the lines only in
+// `expected` with `-` and the ones only in `actual` with `+`
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+
+    // Lengths of the longest common subsequences of the remaining lines
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!("  {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1])
+        {
+            lines.push(format!("- {}", expected[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", actual[j]));
+            j += 1;
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+    use crate::VirtualPort;
+
+    // Records a short exchange, the response split into several writes
+    fn session() -> Recording {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let recorder = port1.start_recording();
+        port1.write_all(b"AT\r").unwrap();
+        port2.write_all(b"OK").unwrap();
+        port2.write_all(b"\r\n").unwrap();
+        let mut read_data = [0u8; 4];
+        port1.read_exact(&mut read_data).unwrap();
+        port1.write_all(b"ATI\r").unwrap();
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_transcript() {
+        assert_eq!(
+            session().transcript(),
+            "tx 0000  41 54 0d                                         |AT.|\n\
+             rx 0000  4f 4b 0d 0a                                      |OK..|\n\
+             tx 0003  41 54 49 0d                                      |ATI.|\n"
+        );
+    }
+
+    #[test]
+    fn test_golden() {
+        let path = env::temp_dir().join(format!("vserial-golden-{}.txt", std::process::id()));
+        session().check_golden(&path, true);
+        session().check_golden(&path, false);
+        let golden = fs::read_to_string(&path).unwrap();
+        fs::write(
+            &path,
+            golden.replace("ATI.", "ATZ.").replace("49 0d", "5a 0d"),
+        )
+        .unwrap();
+
+        let message = std::panic::catch_unwind(|| session().check_golden(&path, false))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(message.ends_with(
+            "  tx 0000  41 54 0d                                         |AT.|\n  \
+             rx 0000  4f 4b 0d 0a                                      |OK..|\n\
+             - tx 0003  41 54 5a 0d                                      |ATZ.|\n\
+             + tx 0003  41 54 49 0d                                      |ATI.|"
+        ));
+    }
+}
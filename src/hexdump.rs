@@ -0,0 +1,133 @@
+//! Hexdumps of the traffic of the ports, logged through the `log` crate and
+//! shown by the failed assertions.
+
+#[cfg(feature = "log")]
+use crate::VirtualPort;
+
+// Bytes shown on each line of a hexdump
+const LINE_LEN: usize = 16;
+
+/// Logs a hexdump of the data moving through the port named `name` in the
+/// given direction (`"tx"` or `"rx"`) at the debug level.
+#[cfg(feature = "log")]
+pub(crate) fn log(name: &str, direction: &str, data: &[u8]) {
+    if data.is_empty() || !log::log_enabled!(log::Level::Debug) {
+        return;
+    }
+    for line in hexdump(data) {
+        log::debug!("{} {}: {}", name, direction, line);
+    }
+}
+
+/// Formats the data as lines of an offset, the bytes in hex and the
+/// printable ones as ASCII.
+pub(crate) fn hexdump(data: &[u8]) -> Vec<String> {
+    hexdump_at(data, 0)
+}
+
+/// Formats the data found at `offset` of a stream as a hexdump.
+pub(crate) fn hexdump_at(data: &[u8], offset: usize) -> Vec<String> {
+    data.chunks(LINE_LEN)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let hex: Vec<_> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7e => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            format!(
+                "{:04x}  {:<width$}  |{}|",
+                offset + index * LINE_LEN,
+                hex.join(" "),
+                ascii,
+                width = LINE_LEN * 3 - 1
+            )
+        })
+        .collect()
+}
+
+/// Formats the hexdumps of the expected and the actual data as a diff, with
+/// the lines only in the expected one marked with `-` and the lines only in
+/// the actual one with `+`.
+pub(crate) fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let expected = hexdump(expected);
+    let actual = hexdump(actual);
+    let mut lines = Vec::new();
+    for index in 0..expected.len().max(actual.len()) {
+        match (expected.get(index), actual.get(index)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                lines.push(format!("  {}", expected));
+            }
+            (expected, actual) => {
+                lines.extend(expected.map(|line| format!("- {}", line)));
+                lines.extend(actual.map(|line| format!("+ {}", line)));
+            }
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(feature = "log")]
+impl VirtualPort {
+    /// Returns the name under which the traffic of the port is logged, if
+    /// logging is enabled.
+    pub fn traffic_log(&self) -> Option<String> {
+        self.pipe.log_name()
+    }
+
+    /// Logs hexdumps of the data written and read by the port at the debug
+    /// level, tagged with `name` and the direction (`tx` or `rx`), or stops
+    /// logging if `name` is `None`. The setting is shared by all the handles
+    /// of the port.
+    pub fn set_traffic_log(&mut self, name: Option<&str>) {
+        self.pipe.set_log_name(name.map(str::to_owned));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        let lines = hexdump(b"hello,\r\nworld! 0123456789");
+        assert_eq!(
+            lines,
+            [
+                "0000  68 65 6c 6c 6f 2c 0d 0a 77 6f 72 6c 64 21 20 30  |hello,..world! 0|",
+                "0010  31 32 33 34 35 36 37 38 39                       |123456789|",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let expected = b"hello,\r\nworld! 0123456789";
+        assert_eq!(
+            diff(expected, b"hello,\r\nworld! 0123"),
+            [
+                "  0000  68 65 6c 6c 6f 2c 0d 0a 77 6f 72 6c 64 21 20 30  |hello,..world! 0|",
+                "- 0010  31 32 33 34 35 36 37 38 39                       |123456789|",
+                "+ 0010  31 32 33                                         |123|",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            diff(b"", b"ok"),
+            "+ 0000  6f 6b                                            |ok|"
+        );
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_traffic_log() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.set_traffic_log(Some("VCOM1"));
+        assert_eq!(port.clone().traffic_log().as_deref(), Some("VCOM1"));
+        port.set_traffic_log(None);
+        assert_eq!(port.traffic_log(), None);
+    }
+}
@@ -16,19 +16,337 @@
 //! Additional features include:
 //!
 //! - **Control Signal Simulation**: Simulates control signals (RTS/CTS,
-//!   DTR/DSR/CD). Note that actual flow control based on these signals is not
-//!   implemented.
+//!   DTR/DSR/CD). With `FlowControl::Hardware`, a port stops transmitting while
+//!   its CTS input is deasserted: writes fail with `WouldBlock` and
+//!   `VirtualPort::wait_writable` waits until the peer asserts RTS again.
+//!   With `FlowControl::Software`, XON/XOFF characters received from the peer
+//!   are filtered out of the data and resume or pause the transmission, and
+//!   `VirtualPort::set_xoff_watermarks` makes a port send XOFF automatically
+//!   when its receive buffer fills up. `VirtualPort::set_rts_watermarks` does
+//!   the same with RTS under `FlowControl::Hardware`, as UART auto-RTS does.
+//!   `VirtualPort::flow_control_stats` counts the pauses and the held back
+//!   data, showing whether flow control actually engaged.
+//!   The inputs of a single port can be forced regardless of the peer with
+//!   `force_cts`, `force_dsr`, `force_cd` and `force_ri`, and
+//!   `VirtualPort::set_wiring` replaces the null-modem wiring of its inputs with
+//!   a straight-through cable or a custom map (e.g., RTS to CD).
+//!   `SerialPort::set_break` sends a break condition to the peer, seen with
+//!   `VirtualPort::break_received` or as a `0x00` byte (`set_break_as_data`).
+//!   `VirtualPort::subscribe_control_events` delivers timestamped line
+//!   transitions, so device emulators can react to toggles instead of polling.
 //!
-//! - **Transmission Delay Simulation**: When enabled, simulates transmission delay
-//!   based on the baud rate. This is implemented in a simplified manner by adding
-//!   a fixed delay for each symbol read (the delay is calculated according to the
-//!   baud rate).
+//! - **Transmission Delay Simulation**: When enabled, the written data arrives
+//!   at the peer progressively at the baud rate, moved by a background
+//!   scheduler: `bytes_to_read` grows over time like on real hardware, and
+//!   reads overlap with the transmission.
+//!   `VirtualPort::set_simulate_tx_delay` delays the data on the write path
+//!   instead, at the baud rate of the writer: `bytes_to_write` reports the data
+//!   not transmitted yet, and `flush` waits for it to drain.
+//!   `set_inter_byte_gap` and `set_frame_gap` add idle time between the bytes
+//!   or after every N bytes, e.g., the 3.5 character silences of Modbus RTU.
+//!   `set_link_latency` adds a fixed propagation latency on top, as cables,
+//!   radio links and modems do, to test request/response timeouts.
+//!   `set_timing_jitter` and `set_latency_jitter` vary the timing of each byte
+//!   (uniformly or normally, see `Jitter`) to fuzz timing-sensitive parsers.
+//!   `VirtualPort::set_split_baud_rates` gives the transmitter and the receiver
+//!   of a port different rates, as on split-speed modems, with the delay and the
+//!   noise computed for each direction.
+//!   `VirtualPort::set_half_duplex` lets only one end transmit at a time, with
+//!   a turnaround delay and optional collisions, as with RS-485 transceivers.
+//!   `VirtualPort::set_clock` with a `ManualClock` replaces the real time, so
+//!   tests advance the simulated time instantly and run timing scenarios
+//!   deterministically.
+//!   `Simulation` goes further for single-threaded tests: its ports share a
+//!   clock moved only by `Simulation::step`, start no background threads, and
+//!   fail the operations which would wait with `TimedOut`, so the tests are
+//!   byte-exact and never flake.
+//!
+//! - **Bulk settings**: `VirtualPort::settings` captures the baud rate, data
+//!   bits, parity, stop bits, flow control and timeout in a `Settings` value, and
+//!   `VirtualPort::apply_settings` applies one at once, as drivers do. A `Settings`
+//!   also parses from the classic shorthand, e.g., `"115200,8N1"`.
+//!
+//! - **Peeking**: `VirtualPort::peek` inspects the received data without
+//!   removing it, e.g., to detect a protocol before committing to a read.
+//!
+//! - **Partial reads**: A read which times out fails with `TimedOut` and leaves
+//!   the data which arrived for the next read. With
+//!   `VirtualPort::set_partial_reads`, it returns that data instead, only failing
+//!   if nothing arrived, as most platform drivers do.
+//!
+//! - **Draining**: `VirtualPort::drain` waits until the peer has read everything
+//!   written, as `tcdrain()` does.
 //!
 //! - **Noise Simulation**: If enabled, simulates noise when the physical settings
 //!   (baud rate, data bits, parity, and stop bits) of paired ports do not match.
 //!   This helps test how the system handles corrupted or invalid data under
 //!   mismatched configurations.
 //!
+//! - **Fault Injection**: Faults damage the data on the way to a port. They are
+//!   configured on the receiving port, so each direction of a pair can be
+//!   damaged independently (`VirtualPort::set_outgoing_conditions` sets the
+//!   delay, noise and faults of the data sent by a port from the same end):
+//!   - `set_byte_loss`: silently drops bytes, randomly or following a pattern,
+//!     to test resynchronization after lost characters;
+//!   - `set_byte_duplication` and `set_byte_insertion`: repeat bytes or inject
+//!     random ones, as glitchy UARTs and EMI do;
+//!   - `set_burst_errors`: corrupts bytes in bursts following a Gilbert-Elliott
+//!     channel model;
+//!   - `set_link_drop` and `disconnect`: take the link down at a random or
+//!     scheduled point until `reconnect` is called, dropping CTS, DSR and CD,
+//!     while `set_disconnect_behavior` chooses whether the reads and the
+//!     writes fail meanwhile or silently time out and lose the data;
+//!   - `set_fault_schedule`: corrupts, drops or disconnects at chosen byte
+//!     offsets or times;
+//!   - `set_report_line_errors`: reports the damage a receiver would detect as
+//!     framing or parity errors (`LineError`) instead of delivering garbage;
+//!   - `set_noise_seed`: makes the noise and the faults reproducible, so
+//!     failures can be replayed.
+//!
+//! - **Overrun Simulation**: `VirtualPort::set_overrun_policy` (also available
+//!   as `set_buffer_full_policy`) chooses whether a full receive buffer holds the
+//!   writer back (the default), drops the newest or the oldest data, or records a
+//!   hardware overrun error, and `VirtualPort::receive_stats` reports the
+//!   received and the lost data. With `VirtualPort::set_blocking_writes`, a held
+//!   back write waits (up to the port timeout) for the peer to catch up instead
+//!   of failing. `VirtualPort::set_buffer_capacity` resizes the buffers at
+//!   runtime, e.g., to model a small hardware FIFO and then relax it.
+//!
+//! - **Statistics**: `VirtualPort::stats` returns a `PortStats` with the bytes
+//!   transmitted and received, the number of reads, writes and timeouts, the
+//!   overruns, the line errors reported to the reads and the flow control pauses
+//!   of a port, and `VirtualPort::reset_stats` resets them. Its `FaultStats` count
+//!   exactly what the fault injection did (bits flipped, bytes dropped, garbled,
+//!   duplicated or inserted, errors raised and link drops), so the observed
+//!   behavior can be correlated with the injected faults.
+//!
+//! - **Hangup**: Once every handle of one end of a pair is dropped, the other
+//!   end reads the remaining data and then gets end of file (or the error set
+//!   with `VirtualPort::set_hangup_error`) instead of waiting for the timeout,
+//!   and its writes fail with `BrokenPipe`, so shutdown paths can be tested.
+//!
+//! - **Multidrop addressing**: `VirtualPort::write_address` sends a byte with the
+//!   9th bit set, as the master of an RS-485 bus selecting a slave does, and
+//!   `VirtualPort::set_address_filter` makes a port ignore the data until its
+//!   address arrives. `VirtualPort::read_words` reads 9-bit characters, telling
+//!   the address bytes apart.
+//!
+//! - **Buses**: `VirtualBus` attaches any number of ports to a shared line, as
+//!   an RS-485 multidrop network, each receiving what the others send. In
+//!   half-duplex mode, overlapping transmissions collide: the overlapping bytes
+//!   are garbled and `VirtualBus::subscribe_collisions` reports the collision,
+//!   so arbitration and retry logic can be tested. Ports can be attached and
+//!   detached while the bus runs, with `VirtualBus::subscribe_events` reporting
+//!   the devices joining and leaving. `VirtualBus::set_direction` restricts a
+//!   port to transmitting, as a beacon, or to receiving, as a sniffer, failing
+//!   the other operations with `PermissionDenied`.
+//!
+//! - **Monitoring**: `VirtualPort::pair_with_monitor` opens a pair with a third,
+//!   read-only port which receives the data sent in both directions, and
+//!   `VirtualPort::subscribe_traffic` tags it with its sender, so protocol
+//!   analyzers can be tested without changing either end.
+//!   `VirtualPort::splitter` and `VirtualPort::add_listener` wire the transmit
+//!   line of a port to several receivers, as a Y cable does.
+//!   `VirtualPort::monitor` delivers the data written and received by any port,
+//!   timestamped, to a channel, so another thread can observe the traffic
+//!   without wrapping the ports.
+//!
+//! - **Capture**: `VirtualPort::capture_pcapng` records the data written and
+//!   received by a port, timestamped, to a pcapng file, so the session can be
+//!   inspected with Wireshark and its serial protocol dissectors.
+//!
+//! - **Record and replay**: `VirtualPort::start_recording` records the data,
+//!   the control line changes and the line settings of a port with their timing,
+//!   and `Recording::save` writes them to a file. `Replayer` plays the peer of
+//!   a recording back against a port with the original timing, so a trace of a
+//!   session with real hardware becomes a reproducible test.
+//!   `Recorder::assert_golden` compares the transcript of a session (hexdumps of
+//!   the data sent and received) with a golden file, showing a diff of the
+//!   lines, so a protocol regression test is a one-liner; setting
+//!   `UPDATE_GOLDEN` writes the golden files instead.
+//!
+//! - **Event log**: `VirtualPort::start_event_log` keeps the data, the control
+//!   line changes and the faults (line errors, dropped data, link drops) of a
+//!   port in memory with their timestamps, and `EventLog::filter` and
+//!   `EventLog::find_after` query them after the test, e.g., to check that a
+//!   device responded within 50 ms of CTS asserting.
+//!
+//! - **Assertions**: `VirtualPort::assert_transmitted` checks that the peer
+//!   received exactly the given bytes from the port, taking them out of its
+//!   buffer, and `VirtualPort::assert_idle` that no data moves for a while. A
+//!   failure shows a diff of the hexdumps.
+//!   `VirtualPort::pending` and `VirtualPort::drain_pending` return the bytes
+//!   left unread in the receive buffer, e.g., to check for leftovers at the end
+//!   of a scenario.
+//!
+//! - **Model checking**: Built with `--cfg loom`, the ports synchronize through
+//!   the primitives of `loom`, and `model` runs a test under the loom model
+//!   checker, so the concurrent use of the ports (clones, concurrent reads and
+//!   writes, control lines) can be checked for every interleaving of the
+//!   threads.
+//!
+//! - **Uninitialized reads**: Built with `--cfg nightly` on a nightly
+//!   toolchain, the ports implement `Read::read_buf`, reading into uninitialized
+//!   memory without zero-filling it first.
+//!
+//! - **Wait strategies**: `VirtualPort::set_wait_strategy` makes the blocking
+//!   operations spin or yield instead of parking the thread, for low-latency
+//!   tests.
+//!
+//! - **Topologies**: `Topology` declares nodes and directed links between them
+//!   and builds the interconnected ports at once, so harnesses such as a gateway
+//!   talking to several devices can be set up declaratively.
+//!   `Topology::ring` wires the nodes in a ring, as daisy-chained instruments
+//!   are, with `Topology::set_hop_delay` setting the delay of each hop.
+//!
+//! - **Named ports**: `VirtualPort::register` makes a port available under a
+//!   name (e.g., `"VCOM1"`) in a process-global registry, and
+//!   `VirtualPort::open` opens it by that name, so code which opens ports by path
+//!   can be tested with only a configuration change.
+//!   `virtual_serialport::available_ports` lists the registered ports like
+//!   `serialport::available_ports`, as USB adapters with test IDs by default, to
+//!   exercise device discovery code.
+//!
+//! - **Bridging**: A virtual port can be bridged to another (e.g., physical)
+//!   serial port with `VirtualPort::bridge_to`, placing the simulator into a
+//!   live hardware link. `VirtualPort::bridge_to_with` additionally passes the
+//!   forwarded data through a tap for capture or fault injection.
+//!
+//! - **Stream bridging**: `VirtualPort::bridge_to_stdio` connects a port to the
+//!   standard input and output of the process, and `VirtualPort::bridge_to_io`
+//!   to arbitrary `Read`/`Write` handles, for expect-style CLI test harnesses
+//!   and pipelines.
+//!
+//! - **RFC 2217 server**: `VirtualPort::serve_rfc2217` exposes a virtual port
+//!   over TCP using the Telnet COM port control protocol, so network serial
+//!   clients (e.g., pyserial `rfc2217://` URLs) can exchange data with it and
+//!   change its line settings and control lines.
+//!
+//! - **UDP bridge**: `VirtualPort::bridge_to_udp` maps the data written by the
+//!   peer of a port onto datagrams sent to a remote endpoint and delivers
+//!   received datagrams to it, e.g., to test radio-modem-over-UDP gateways.
+//!
+//! - **Cross-process pairs** (Unix only): `VirtualPort::accept_remote` and
+//!   `VirtualPort::connect_remote` create the two ends of a pair in different
+//!   processes, linked through a Unix domain socket, so an application under
+//!   test and a device simulator can run as separate binaries.
+//!
+//! - **Device emulation**: Implementing the `Device` trait and attaching it to
+//!   one end of a pair with `DeviceRunner::spawn` emulates a peripheral on a
+//!   background thread, without hand-written pump loops.
+//!
+//! - **Scripted mocks**: `ScriptedDevice` answers an expected sequence of
+//!   writes (`expect_write(b"AT\r").respond(b"OK\r\n")`) and reports any
+//!   unmatched or out-of-order traffic with a readable diff. Steps can be
+//!   repeated with `times(n)` or made `optional()`, and grouped with `any_order`
+//!   and `in_sequence` to specify complex handshakes precisely. A response can
+//!   be delayed or damaged with `delay` and `fault`.
+//!
+//! - **Built-in devices**: The `devices` module provides ready-made peripheral
+//!   emulations:
+//!   - `AtModem`: a Hayes-compatible modem;
+//!   - `Elm327`: an ELM327 OBD-II adapter;
+//!   - `EscPosPrinter`: an ESC/POS receipt printer capturing the printed output;
+//!   - `GsmModem`: a SIM800-style cellular module with SMS and TCP over AT
+//!     commands;
+//!   - `NmeaGps`: an NMEA 0183 GPS receiver;
+//!   - `ScpiInstrument`: a SCPI lab instrument;
+//!   - `XmodemSender` and `XmodemReceiver`: XMODEM-CRC and YMODEM file
+//!     transfer peers with fault injection.
+//!
+//! ## Feature Flags
+//!
+//! - `async`: Provides [`AsyncVirtualPort`], implementing the tokio
+//!   `AsyncRead` and `AsyncWrite` traits, and [`VirtualSerialStream`],
+//!   mirroring the API of `tokio_serial::SerialStream`. With [`TokioClock`],
+//!   the delays follow the time of tokio, so tests under
+//!   `tokio::time::pause()` simulating seconds of traffic complete instantly.
+//!
+//! - `futures`: Implements the runtime-agnostic `futures::io::AsyncRead` and
+//!   `AsyncWrite` traits for [`AsyncVirtualPort`].
+//!
+//! - `async-std`: Enables `futures`. It remains for compatibility, as the
+//!   transmission delay no longer needs the timer of a runtime.
+//!
+//! - `cli` (Unix only): Builds the `virtual-serialport` binary, which creates
+//!   pseudo-terminal pairs, TCP and RFC 2217 bridges, and replays captured data
+//!   from the command line (`virtual-serialport --help`). Enables `pty`.
+//!
+//! - `embedded-hal-nb`: Implements the non-blocking
+//!   `embedded_hal_nb::serial::Read` and `Write` traits for [`VirtualPort`].
+//!
+//! - `embedded-io`: Implements the `embedded_io` traits for [`VirtualPort`].
+//!
+//! - `embedded-io-async`: Implements the `embedded_io_async` traits for
+//!   [`AsyncVirtualPort`].
+//!
+//! - `log`: Provides `VirtualPort::set_traffic_log`, which logs hexdumps of
+//!   the data written and read by a port at the debug level, tagged with a name
+//!   and the direction, so the traffic of a failing test can be inspected.
+//!
+//! - `metrics`: Provides `VirtualPort::set_metrics_label`, which publishes the
+//!   throughput, the receive buffer occupancy, the error counts and the latency
+//!   of a port through the `metrics` facade, so long-running simulation rigs can
+//!   be monitored with any `metrics` exporter (e.g., Prometheus).
+//!
+//! - `mio` (Unix only): Implements `mio::event::Source` for [`VirtualPort`],
+//!   so it can be registered in a `mio` event loop with readable and writable
+//!   interest. Events are edge-triggered: read until `WouldBlock` (e.g., with
+//!   `VirtualPort::try_read`) or write until `WouldBlock` before polling again.
+//!
+//! - `raw-fd` (Unix and Windows): Implements `AsRawFd` (Unix) or
+//!   `AsRawHandle` (Windows) for [`VirtualPort`]. The descriptor only signals
+//!   readiness and carries no data: it becomes readable (the event becomes
+//!   signalled) while data is available, so an event loop can `select()` or
+//!   wait on it, but the data must still be read through the port. On Unix
+//!   it is also writable while the peer buffer has free space.
+//!
+//! - `named-pipe` (Windows only): Provides `VirtualPort::serve_named_pipe`,
+//!   which exposes the port as the named pipe `\\.\pipe\vserial-NAME`, so
+//!   external test tools can attach to the simulated link.
+//!
+//! - `proptest`: Implements `proptest::arbitrary::Arbitrary` for `Settings`,
+//!   `LinkConditions` (and its parts), `FaultSchedule` and `Recording`, so
+//!   property-based tests can explore random port configurations, faults and
+//!   traffic patterns.
+//!
+//! - `pty` (Unix only): Provides `VirtualPort::open_pty`, which creates a
+//!   pseudo-terminal (e.g., `/dev/pts/3`) pumped to and from the port, so
+//!   non-Rust programs like `minicom` or pyserial scripts can connect to the
+//!   simulation.
+//!
+//! - `scenario`: Provides `ScriptedDevice::load_scenario`, which loads the
+//!   expected requests, the responses, the delays and the faults of a scripted
+//!   device from a TOML file, so new device behaviors can be added without
+//!   recompiling the tests.
+//!
+//! - `serde`: Implements `Serialize` and `Deserialize` for the configuration
+//!   of the ports (`Settings`, `LinkConditions` and its parts, `FaultSchedule`,
+//!   `LinkDrop`, `OverrunPolicy`, `Wiring`, ...) and for `Topology`, so the
+//!   configuration of a harness can live in files and be logged with the test
+//!   artifacts.
+//!
+//! - `websocket`: Provides `VirtualPort::serve_websocket`, which serves the
+//!   port to a WebSocket client, so a browser UI can act as the remote device
+//!   or monitor live traffic.
+//!
+//! ## Minimum Supported Rust Version
+//!
+//! The crate requires Rust 1.60 with the default features. Optional features
+//! raise the requirement to what their dependencies need:
+//!
+//! - `async` and `mio`: Rust 1.70 (current `tokio` and `mio` releases).
+//!
+//! - `embedded-io-async`: Rust 1.75 (the traits use `async fn`).
+//!
+//! - `metrics`: Rust 1.71 (current `metrics` releases).
+//!
+//! - `proptest`: Rust 1.88 (current `proptest` releases).
+//!
+//! - `scenario`: Rust 1.66 (current `toml` releases).
+//!
+//! - `websocket`: Rust 1.85 (current `tungstenite` releases).
+//!
 //! ## Example Usage
 //!
 //! ### Loopback Example
@@ -61,6 +379,8 @@
 //! assert_eq!(&read_data, write_data);
 //! ```
 
+#![cfg_attr(nightly, feature(read_buf, core_io_borrowed_buf))]
+
 // To run doc tests on examples from README.md and verify their correctness
 #[cfg(doctest)]
 #[doc = include_str!("../README.md")]
@@ -68,20 +388,127 @@ struct ReadMe;
 
 use std::{
     io,
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use rand::Rng;
+use rand::{rngs::SmallRng, RngCore, SeedableRng};
 
 use serialport::{ClearBuffer, DataBits, FlowControl, Parity, Result, SerialPort, StopBits};
 
-use mockpipe::MockPipe;
+mod assertions;
+mod bridge;
+mod bus;
+mod capture;
+mod clock;
+mod control;
+mod device;
+mod event_log;
+mod fault;
+mod golden;
+mod hexdump;
+mod monitor;
+mod pipe;
+mod recorder;
+mod registry;
+mod settings;
+mod simulation;
+mod sync;
+mod topology;
+mod wiring;
+
+pub mod devices;
+
+pub use bridge::{Bridge, Direction};
+pub use bus::{BusCollision, BusDirection, BusEvent, VirtualBus};
+pub use capture::{Capture, LINKTYPE_USER0};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use control::{ControlEvent, ControlLine};
+pub use device::{Device, DeviceRunner, ResponseFault, ScriptedDevice};
+pub use event_log::{EventLog, FaultEvent, LogEntry, LogEvent};
+pub use fault::{
+    ByteLoss, FaultSchedule, FaultStats, GilbertElliott, Jitter, LineError, LinkConditions,
+    LinkDrop,
+};
+pub use monitor::{PairEnd, Traffic, TrafficDirection, TrafficEvent};
+pub use recorder::{Record, RecordedEvent, Recorder, Recording, Replayer};
+pub use registry::available_ports;
+pub use settings::Settings;
+pub use simulation::Simulation;
+pub use topology::{Node, Topology};
+pub use wiring::Wiring;
+
+#[cfg(all(feature = "pty", unix))]
+pub use bridge::Pty;
+
+#[cfg(all(feature = "pty", not(unix)))]
+compile_error!("the `pty` feature is only supported on Unix");
+
+#[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+mod async_port;
+
+#[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+pub use async_port::AsyncVirtualPort;
+
+#[cfg(feature = "async")]
+mod stream;
+
+#[cfg(feature = "metrics")]
+mod telemetry;
+
+#[cfg(feature = "proptest")]
+mod arbitrary;
+
+#[cfg(feature = "async")]
+pub use stream::VirtualSerialStream;
 
+#[cfg(feature = "async")]
+pub use clock::TokioClock;
+
+#[cfg(loom)]
+pub use sync::model;
+
+#[cfg(any(
+    feature = "embedded-hal-nb",
+    feature = "embedded-io",
+    feature = "embedded-io-async"
+))]
+mod embedded;
+
+#[cfg(any(
+    all(any(feature = "mio", feature = "raw-fd"), unix),
+    all(feature = "raw-fd", windows)
+))]
+mod readiness;
+
+#[cfg(all(feature = "raw-fd", not(any(unix, windows))))]
+compile_error!("the `raw-fd` feature is only supported on Unix and Windows");
+
+use control::{ControlEvents, LineLevels};
+use pipe::{ByteTime, Pipe};
+use sync::{AtomicU64, Mutex, Ordering};
+
+pub use pipe::{
+    DisconnectBehavior, FlowControlStats, HalfDuplex, OverrunPolicy, PortStats, ReceiveStats,
+    WaitStrategy,
+};
+
+/// Behavior of a port whose receive buffer is full, under the name used by
+/// [`VirtualPort::set_buffer_full_policy`].
+pub type BufferFullPolicy = OverrunPolicy;
+
+#[derive(Clone)]
 struct Config {
-    // Baud rate in symbols per second
+    // Baud rate in symbols per second, of the transmitter and, unless split
+    // rates are set, of the receiver
     baud_rate: u32,
 
+    // Baud rate of the receiver if it differs from the transmitter
+    rx_baud_rate: Option<u32>,
+
     // Number of bits per character
     data_bits: DataBits,
 
@@ -95,28 +522,37 @@ struct Config {
     stop_bits: StopBits,
 
     // Whether to simulate the delay of data transmission based on baud rate.
-    // If enabled, this will add a fixed delay for each symbol read to simulate
-    // the transmission delay. Note that this is a simplified simulation: in a real
-    // serial communication, transmission would continue even when read operations
-    // are not performed, allowing some data to be available immediately when
-    // a read is executed. This simulation does not account for such behavior and
-    // only introduces a delay per symbol as if transmission was paused during reads
+    // If enabled, the data written by the peer arrives into the receive buffer
+    // byte by byte at the baud rate of this port, as on a real line, so the
+    // number of bytes to read grows over time and reads overlap with the
+    // transmission
     simulate_delay: bool,
 
+    // Whether to simulate the delay of transmitting the written data through
+    // the shift register of this port: the data leaves at the baud rate of
+    // this port, and `flush` waits for it to drain
+    simulate_tx_delay: bool,
+
     // Whether to simulate corrupted symbols if physical settings don't match
     noise_on_config_mismatch: bool,
+
+    // Subscribers to the changes of the line settings
+    settings_subscribers: Vec<Sender<(Instant, Settings)>>,
 }
 
 impl Config {
     fn new(baud_rate: u32) -> Self {
         Self {
             baud_rate,
+            rx_baud_rate: None,
             data_bits: DataBits::Eight,
             flow_control: FlowControl::None,
             parity: Parity::None,
             stop_bits: StopBits::One,
             simulate_delay: false,
+            simulate_tx_delay: false,
             noise_on_config_mismatch: false,
+            settings_subscribers: Vec::new(),
         }
     }
 
@@ -142,17 +578,33 @@ impl Config {
         }
     }
 
-    // Calculates the time to transmit one byte in microseconds.
-    fn byte_duration(&self) -> Option<Duration> {
-        self.simulate_delay.then(|| {
-            Duration::from_micros(((1_000_000 / self.baud_rate) * self.bits_per_byte()) as u64)
-        })
+    fn rx_baud_rate(&self) -> u32 {
+        self.rx_baud_rate.unwrap_or(self.baud_rate)
+    }
+
+    // Calculates the time to transmit one byte at the given baud rate, exact
+    // to a fraction of a nanosecond.
+    fn byte_time(&self, baud_rate: u32) -> ByteTime {
+        ByteTime::new(self.bits_per_byte(), baud_rate)
     }
 
-    /// Compares relevant physical settings between two configs.
+    // Returns the time to transmit one byte from the port configured by
+    // `writer` to the one configured by `self`, if any delay is simulated
+    fn byte_duration(&self, writer: &Config) -> Option<ByteTime> {
+        if self.simulate_delay {
+            Some(self.byte_time(self.rx_baud_rate()))
+        } else {
+            writer
+                .simulate_tx_delay
+                .then(|| writer.byte_time(writer.baud_rate))
+        }
+    }
+
+    /// Compares relevant physical settings between the receiver configured by
+    /// `self` and the transmitter configured by `other`.
     /// Returns `true` if they don't match, `false` otherwise.
     fn physical_settings_mismatch(&self, other: &Config) -> bool {
-        self.baud_rate != other.baud_rate
+        self.rx_baud_rate() != other.baud_rate
             || self.data_bits != other.data_bits
             || self.parity != other.parity
             || self.stop_bits != other.stop_bits
@@ -175,8 +627,13 @@ impl Config {
 /// │     │         └▶│ CD  │
 /// │ DSR │◂┬─────────┤ DTR │
 /// │ CD  │◂┘         │     │
-/// │ RI  ├───────────┤ RI  │
+/// │ RI  │◂─────────▸│ RI  │
 /// └─────┘           └─────┘
+///
+/// A port can also drive the CD and RI inputs of its peer directly with
+/// [`set_carrier_detect`](VirtualPort::set_carrier_detect) and
+/// [`set_ring_indicator`](VirtualPort::set_ring_indicator), e.g., to emulate
+/// a modem.
 #[derive(Clone)]
 pub struct VirtualPort {
     // Configuration settings (baud rate, data bits etc.)
@@ -185,51 +642,134 @@ pub struct VirtualPort {
     // Reference to the paired port's configuration
     paired_port_config: Option<Arc<Mutex<Config>>>,
 
-    pipe: MockPipe,
+    // Version of the configurations of both ends, increased on every change
+    // affecting the noise on configuration mismatch, and the noise decision
+    // of this handle with the version it was made for, so the reads don't
+    // lock the configurations
+    config_version: Arc<AtomicU64>,
+    noise: Option<(u64, Option<LineError>)>,
 
-    // Control lines (RTS<-->CTS, DTR<-->DSR/CD)
-    // RI (ring indicator) is always true in this implementation
+    pipe: Pipe,
+
+    // Control lines (RTS<-->CTS, DTR<-->DSR/CD with the default wiring)
     rts: Arc<Mutex<bool>>,
     cts: Arc<Mutex<bool>>,
     dtr: Arc<Mutex<bool>>,
     dsr_cd: Arc<Mutex<bool>>,
+
+    // CD and RI inputs of this port and of the peer, driven by the other
+    // side. CD follows DTR of the other side unless overridden.
+    cd: Arc<Mutex<Option<bool>>>,
+    peer_cd: Arc<Mutex<Option<bool>>>,
+    ri: Arc<Mutex<bool>>,
+    peer_ri: Arc<Mutex<bool>>,
+
+    // Input lines of this port and of the peer forced to a level regardless
+    // of the other side
+    forced: Arc<Mutex<ForcedLines>>,
+    peer_forced: Arc<Mutex<ForcedLines>>,
+
+    // Outputs of the other side driving the inputs of this port and of the
+    // peer
+    wiring: Arc<Mutex<Wiring>>,
+    peer_wiring: Arc<Mutex<Wiring>>,
+
+    // Subscribers to the control line changes of this port and of the peer
+    events: Arc<Mutex<ControlEvents>>,
+    peer_events: Arc<Mutex<ControlEvents>>,
+
+    // Link to the other end of a cross-process pair, running while any clone
+    // of the port exists
+    #[cfg(unix)]
+    link: Option<Arc<Bridge>>,
+}
+
+// Levels of the input lines forced by the user, `None` meaning the line is
+// driven by the peer
+#[derive(Clone, Copy, Default)]
+struct ForcedLines {
+    cts: Option<bool>,
+    dsr: Option<bool>,
+    cd: Option<bool>,
+    ri: Option<bool>,
 }
 
 impl VirtualPort {
     /// Opens a single loopback virtual port with the specified baud rate.
-    pub fn loopback(baud_rate: u32, buffer_capacity: u32) -> Result<Self> {
+    pub fn loopback(baud_rate: u32, buffer_capacity: usize) -> Result<Self> {
+        Self::with_own_lines(baud_rate, Pipe::loopback(buffer_capacity))
+    }
+
+    // Opens a port on `pipe` whose control line inputs follow its own outputs
+    fn with_own_lines(baud_rate: u32, pipe: Pipe) -> Result<Self> {
         let rts_cts = Arc::new(Mutex::new(true));
         let dtr_dsr_cd = Arc::new(Mutex::new(true));
+        let cd = Arc::new(Mutex::new(None));
+        let ri = Arc::new(Mutex::new(false));
+        let forced = Arc::new(Mutex::new(ForcedLines::default()));
+        let wiring = Arc::new(Mutex::new(Wiring::default()));
+        let events = Arc::new(Mutex::new(ControlEvents::default()));
 
-        Ok(Self {
+        Self {
             config: Arc::new(Mutex::new(Config::new(baud_rate))),
             paired_port_config: None,
+            config_version: Arc::default(),
+            noise: None,
 
-            pipe: MockPipe::loopback(buffer_capacity as usize),
+            pipe,
 
             rts: rts_cts.clone(),
             cts: rts_cts.clone(),
             dtr: dtr_dsr_cd.clone(),
             dsr_cd: dtr_dsr_cd.clone(),
-        })
+
+            cd: cd.clone(),
+            peer_cd: cd,
+            ri: ri.clone(),
+            peer_ri: ri,
+
+            forced: forced.clone(),
+            peer_forced: forced,
+            wiring: wiring.clone(),
+            peer_wiring: wiring,
+            events: events.clone(),
+            peer_events: events,
+
+            #[cfg(unix)]
+            link: None,
+        }
+        .finish_open()
     }
 
     /// Opens a pair of connected virtual ports with the specified baud rate.
     /// These ports can simulate a communication between two devices.
-    pub fn pair(baud_rate: u32, buffer_capacity: u32) -> Result<(Self, Self)> {
+    pub fn pair(baud_rate: u32, buffer_capacity: usize) -> Result<(Self, Self)> {
         let config1 = Arc::new(Mutex::new(Config::new(baud_rate)));
         let config2 = Arc::new(Mutex::new(Config::new(baud_rate)));
+        let config_version = Arc::new(AtomicU64::new(0));
 
-        let (pipe1, pipe2) = MockPipe::pair(buffer_capacity as usize);
+        let (pipe1, pipe2) = Pipe::pair(buffer_capacity);
 
         let rts = Arc::new(Mutex::new(true));
         let cts = Arc::new(Mutex::new(true));
         let dtr = Arc::new(Mutex::new(true));
         let dsr_cd = Arc::new(Mutex::new(true));
+        let cd1 = Arc::new(Mutex::new(None));
+        let cd2 = Arc::new(Mutex::new(None));
+        let ri1 = Arc::new(Mutex::new(false));
+        let ri2 = Arc::new(Mutex::new(false));
+        let forced1 = Arc::new(Mutex::new(ForcedLines::default()));
+        let forced2 = Arc::new(Mutex::new(ForcedLines::default()));
+        let wiring1 = Arc::new(Mutex::new(Wiring::default()));
+        let wiring2 = Arc::new(Mutex::new(Wiring::default()));
+        let events1 = Arc::new(Mutex::new(ControlEvents::default()));
+        let events2 = Arc::new(Mutex::new(ControlEvents::default()));
 
         let port1 = Self {
             config: config1.clone(),
             paired_port_config: Some(config2.clone()),
+            config_version: config_version.clone(),
+            noise: None,
 
             pipe: pipe1,
 
@@ -237,11 +777,28 @@ impl VirtualPort {
             cts: cts.clone(),
             dtr: dtr.clone(),
             dsr_cd: dsr_cd.clone(),
+
+            cd: cd1.clone(),
+            peer_cd: cd2.clone(),
+            ri: ri1.clone(),
+            peer_ri: ri2.clone(),
+
+            forced: forced1.clone(),
+            peer_forced: forced2.clone(),
+            wiring: wiring1.clone(),
+            peer_wiring: wiring2.clone(),
+            events: events1.clone(),
+            peer_events: events2.clone(),
+
+            #[cfg(unix)]
+            link: None,
         };
 
         let port2 = Self {
             config: config2,
             paired_port_config: Some(config1),
+            config_version,
+            noise: None,
 
             pipe: pipe2,
 
@@ -249,9 +806,33 @@ impl VirtualPort {
             cts: rts.clone(),
             dtr: dsr_cd.clone(),
             dsr_cd: dtr.clone(),
+
+            cd: cd2,
+            peer_cd: cd1,
+            ri: ri2,
+            peer_ri: ri1,
+
+            forced: forced2,
+            peer_forced: forced1,
+            wiring: wiring2,
+            peer_wiring: wiring1,
+            events: events2,
+            peer_events: events1,
+
+            #[cfg(unix)]
+            link: None,
         };
 
-        Ok((port1, port2))
+        Ok((port1.finish_open()?, port2.finish_open()?))
+    }
+
+    // Finishes opening the port, allocating the operating system resources
+    // required by the enabled features
+    fn finish_open(self) -> Result<Self> {
+        #[cfg(all(feature = "raw-fd", any(unix, windows)))]
+        self.pipe.readiness_handle()?;
+
+        Ok(self)
     }
 
     /// Boxes the instance as a `SerialPort`.
@@ -264,258 +845,1824 @@ impl VirtualPort {
         self.config.lock().unwrap().simulate_delay
     }
 
-    /// Sets whether to simulate the transmission delay for reading operations.
+    /// Sets whether to simulate the transmission delay: the data written by
+    /// the peer then arrives progressively at the baud rate of this port.
     pub fn set_simulate_delay(&mut self, value: bool) {
         self.config.lock().unwrap().simulate_delay = value;
+        self.sync_byte_times();
     }
 
-    /// Returns whether to simulate corrupted symbols if physical settings don't match.
-    pub fn noise_on_config_mismatch(&self) -> bool {
-        self.config.lock().unwrap().noise_on_config_mismatch
+    /// Returns the propagation latency of the data received by this port.
+    pub fn link_latency(&self) -> Duration {
+        self.pipe.latency()
     }
 
-    /// Sets whether to simulate corrupted symbols if physical settings don't match.
-    pub fn set_noise_on_config_mismatch(&mut self, value: bool) {
-        self.config.lock().unwrap().noise_on_config_mismatch = value;
+    /// Sets the propagation latency of the data received by this port, as
+    /// added by a long cable, a radio link or a modem. It applies on top of
+    /// the transmission delay (whether simulated or not), so a response
+    /// arrives no sooner than the latency after it was written.
+    pub fn set_link_latency(&mut self, latency: Duration) {
+        self.pipe.set_latency(latency);
     }
-}
 
-impl io::Read for VirtualPort {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let bytes_to_read = self.pipe.read(buf)?;
-
-        // Lock the configuration once and get necessary parameters
-        let (noise_required, delay_per_byte) = {
-            let config = self.config.lock().unwrap();
-
-            // Determine if noise simulation is needed
-            let noise_required = if config.noise_on_config_mismatch {
-                if let Some(paired_port_config) = &self.paired_port_config {
-                    let paired_config = paired_port_config.lock().unwrap();
-                    config.physical_settings_mismatch(&paired_config)
-                } else {
-                    false
-                }
-            } else {
-                false
-            };
+    /// Returns the variation of the time to transmit each byte to this port.
+    pub fn timing_jitter(&self) -> Jitter {
+        self.pipe.jitter().0
+    }
 
-            // Get the delay per byte
-            let delay_per_byte = config.byte_duration();
+    /// Sets the variation of the time to transmit each byte to this port, so
+    /// parsers can be tested against irregular arrival patterns. It only
+    /// matters if the transmission delay is simulated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameter of the variation is out of range (see
+    /// [`Jitter`]).
+    pub fn set_timing_jitter(&mut self, jitter: Jitter) {
+        jitter.validate();
+        let (_, latency_jitter) = self.pipe.jitter();
+        self.pipe.set_jitter(jitter, latency_jitter);
+    }
 
-            (noise_required, delay_per_byte)
-        };
+    /// Returns the variation of the latency of each byte received by this
+    /// port.
+    pub fn latency_jitter(&self) -> Jitter {
+        self.pipe.jitter().1
+    }
 
-        // Fill the buffer with noise if required
-        if noise_required {
-            let mut rng = rand::thread_rng();
-            buf.iter_mut()
-                .take(bytes_to_read)
-                .for_each(|byte| *byte = rng.gen());
-        }
+    /// Sets the variation of the latency of each byte received by this port
+    /// (see [`set_link_latency`](VirtualPort::set_link_latency)).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameter of the variation is out of range (see
+    /// [`Jitter`]).
+    pub fn set_latency_jitter(&mut self, jitter: Jitter) {
+        jitter.validate();
+        let (timing_jitter, _) = self.pipe.jitter();
+        self.pipe.set_jitter(timing_jitter, jitter);
+    }
 
-        // Simulate the delay of data transmission based on baud rate
-        if let Some(delay) = delay_per_byte {
-            std::thread::sleep(delay * bytes_to_read as u32);
-        }
+    /// Returns whether transmit-side delay simulation is enabled.
+    pub fn simulate_tx_delay(&self) -> bool {
+        self.config.lock().unwrap().simulate_tx_delay
+    }
 
-        Ok(bytes_to_read)
+    /// Sets whether to simulate the transmit-side delay: the written data
+    /// drains from the transmitter at the baud rate of this port,
+    /// `bytes_to_write` reports the data not transmitted yet, and `flush`
+    /// waits (at most for the port timeout) until all of it is transmitted.
+    pub fn set_simulate_tx_delay(&mut self, value: bool) {
+        self.config.lock().unwrap().simulate_tx_delay = value;
+        self.sync_byte_times();
     }
-}
 
-impl io::Write for VirtualPort {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.pipe.write(buf)
+    /// Returns the idle time this port leaves after each byte it sends.
+    pub fn inter_byte_gap(&self) -> Duration {
+        self.pipe.transmit_gaps().0
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        self.pipe.flush()
+    /// Sets the idle time this port leaves after each byte it sends, on top of
+    /// the transmission delay (if simulated).
+    pub fn set_inter_byte_gap(&mut self, gap: Duration) {
+        let (_, frame_gap) = self.pipe.transmit_gaps();
+        self.pipe.set_transmit_gaps(gap, frame_gap);
     }
-}
 
-impl SerialPort for VirtualPort {
-    fn name(&self) -> Option<String> {
-        None
+    /// Returns the idle time this port leaves after every given number of
+    /// bytes it sends, if set.
+    pub fn frame_gap(&self) -> Option<(usize, Duration)> {
+        self.pipe.transmit_gaps().1
     }
 
-    fn baud_rate(&self) -> Result<u32> {
-        Ok(self.config.lock().unwrap().baud_rate)
+    /// Sets the idle time `(frame_len, gap)` this port leaves after every
+    /// `frame_len` bytes it sends, e.g., to produce the 3.5 character silence
+    /// separating Modbus RTU frames. `None` (the default) disables it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frame_len` is 0.
+    pub fn set_frame_gap(&mut self, frame_gap: Option<(usize, Duration)>) {
+        if let Some((frame_len, _)) = frame_gap {
+            assert!(frame_len > 0, "frame length must be positive");
+        }
+        let (inter_byte_gap, _) = self.pipe.transmit_gaps();
+        self.pipe.set_transmit_gaps(inter_byte_gap, frame_gap);
     }
 
-    fn data_bits(&self) -> Result<DataBits> {
-        Ok(self.config.lock().unwrap().data_bits)
+    /// Sets the clock driving the transmission delay, the timeouts, the
+    /// scheduled faults and the control event timestamps of the link, shared
+    /// with the peer. With a [`ManualClock`], the simulated time only moves
+    /// when the test advances it, so timing scenarios run instantly and
+    /// deterministically (see [`Clock`]).
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.pipe.set_clock(clock);
     }
 
-    fn flow_control(&self) -> Result<FlowControl> {
-        Ok(self.config.lock().unwrap().flow_control)
+    /// Returns the half-duplex mode of the link, if set.
+    pub fn half_duplex(&self) -> Option<HalfDuplex> {
+        self.pipe.half_duplex()
     }
 
-    fn parity(&self) -> Result<Parity> {
-        Ok(self.config.lock().unwrap().parity)
+    /// Makes the link half-duplex (for both ends), so only one end transmits
+    /// at a time, as with RS-485 transceivers and IR links, or full-duplex
+    /// again (the default) if `mode` is `None`. See [`HalfDuplex`] for the
+    /// turnaround delay and the collisions.
+    pub fn set_half_duplex(&mut self, mode: Option<HalfDuplex>) {
+        self.pipe.set_half_duplex(mode);
     }
 
-    fn stop_bits(&self) -> Result<StopBits> {
-        Ok(self.config.lock().unwrap().stop_bits)
+    /// Returns the baud rate at which this port transmits, which is also the
+    /// one reported by `SerialPort::baud_rate`.
+    pub fn tx_baud_rate(&self) -> u32 {
+        self.config.lock().unwrap().baud_rate
     }
 
-    fn timeout(&self) -> Duration {
-        self.pipe.timeout().unwrap_or(Duration::MAX)
+    /// Returns the baud rate at which this port receives.
+    pub fn rx_baud_rate(&self) -> u32 {
+        self.config.lock().unwrap().rx_baud_rate()
     }
 
-    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
-        self.config.lock().unwrap().baud_rate = baud_rate;
-        Ok(())
+    /// Sets different baud rates for the transmitter and the receiver of this
+    /// port, as on split-speed modems (e.g., 1200/75 baud for V.23). The
+    /// transmission delay and the noise on configuration mismatch are then
+    /// computed for each direction from the rates of its ends.
+    /// `SerialPort::set_baud_rate` sets both rates to the same value again.
+    pub fn set_split_baud_rates(&mut self, tx_baud_rate: u32, rx_baud_rate: u32) {
+        let mut config = self.config.lock().unwrap();
+        config.baud_rate = tx_baud_rate;
+        config.rx_baud_rate = Some(rx_baud_rate);
+        drop(config);
+        self.sync_byte_times();
+        self.publish_settings();
     }
 
-    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()> {
-        self.config.lock().unwrap().flow_control = flow_control;
-        Ok(())
+    /// Returns whether to simulate corrupted symbols if physical settings don't match.
+    pub fn noise_on_config_mismatch(&self) -> bool {
+        self.config.lock().unwrap().noise_on_config_mismatch
     }
 
-    fn set_parity(&mut self, parity: Parity) -> Result<()> {
-        self.config.lock().unwrap().parity = parity;
-        Ok(())
+    /// Sets whether to simulate corrupted symbols if physical settings don't match.
+    pub fn set_noise_on_config_mismatch(&mut self, value: bool) {
+        self.config.lock().unwrap().noise_on_config_mismatch = value;
+        self.config_changed();
     }
 
-    fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
-        self.config.lock().unwrap().data_bits = data_bits;
-        Ok(())
+    /// Returns the conditions of the data received by this port.
+    pub fn incoming_conditions(&self) -> LinkConditions {
+        let config = self.config.lock().unwrap();
+        let (timing_jitter, latency_jitter) = self.pipe.jitter();
+        LinkConditions {
+            simulate_delay: config.simulate_delay,
+            latency: self.pipe.latency(),
+            timing_jitter,
+            latency_jitter,
+            noise_on_config_mismatch: config.noise_on_config_mismatch,
+            ..self.pipe.with_faults(|faults| faults.conditions())
+        }
     }
 
-    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()> {
-        self.config.lock().unwrap().stop_bits = stop_bits;
-        Ok(())
+    /// Sets the conditions of the data received by this port at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a probability is not between 0.0 and 1.0, or the byte loss
+    /// or a jitter is invalid (see [`set_byte_loss`](VirtualPort::set_byte_loss)
+    /// and [`Jitter`]).
+    pub fn set_incoming_conditions(&mut self, conditions: LinkConditions) {
+        conditions.validate();
+        let mut config = self.config.lock().unwrap();
+        config.simulate_delay = conditions.simulate_delay;
+        config.noise_on_config_mismatch = conditions.noise_on_config_mismatch;
+        drop(config);
+        self.pipe.set_latency(conditions.latency);
+        self.pipe
+            .set_jitter(conditions.timing_jitter, conditions.latency_jitter);
+        self.pipe
+            .with_faults(|faults| faults.set_conditions(&conditions));
+        self.sync_byte_times();
     }
 
-    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
-        self.pipe.set_timeout(match timeout {
-            Duration::MAX => None,
-            duration => Some(duration),
-        });
-        Ok(())
+    /// Returns the conditions of the data sent by this port, which are the
+    /// incoming conditions of the peer.
+    pub fn outgoing_conditions(&self) -> LinkConditions {
+        let config = self.peer_config().lock().unwrap();
+        let (timing_jitter, latency_jitter) = self.pipe.peer_jitter();
+        LinkConditions {
+            simulate_delay: config.simulate_delay,
+            latency: self.pipe.peer_latency(),
+            timing_jitter,
+            latency_jitter,
+            noise_on_config_mismatch: config.noise_on_config_mismatch,
+            ..self.pipe.with_peer_faults(|faults| faults.conditions())
+        }
     }
 
-    fn write_request_to_send(&mut self, level: bool) -> Result<()> {
-        *self.rts.lock().unwrap() = level;
-        Ok(())
+    /// Sets the conditions of the data sent by this port, which are the
+    /// incoming conditions of the peer, e.g., to configure both directions
+    /// of a pair from one end.
+    ///
+    /// # Panics
+    ///
+    /// Panics on invalid conditions, as
+    /// [`set_incoming_conditions`](VirtualPort::set_incoming_conditions).
+    pub fn set_outgoing_conditions(&mut self, conditions: LinkConditions) {
+        conditions.validate();
+        let mut config = self.peer_config().lock().unwrap();
+        config.simulate_delay = conditions.simulate_delay;
+        config.noise_on_config_mismatch = conditions.noise_on_config_mismatch;
+        drop(config);
+        self.pipe.set_peer_latency(conditions.latency);
+        self.pipe
+            .set_peer_jitter(conditions.timing_jitter, conditions.latency_jitter);
+        self.pipe
+            .with_peer_faults(|faults| faults.set_conditions(&conditions));
+        self.sync_byte_times();
     }
 
-    fn write_data_terminal_ready(&mut self, level: bool) -> Result<()> {
-        *self.dtr.lock().unwrap() = level;
-        Ok(())
+    // Returns the configuration of the receiving end of the data sent by this
+    // port
+    fn peer_config(&self) -> &Arc<Mutex<Config>> {
+        self.paired_port_config.as_ref().unwrap_or(&self.config)
     }
 
-    fn read_clear_to_send(&mut self) -> Result<bool> {
-        Ok(*self.cts.lock().unwrap())
+    // Applies the transmission delay of the configurations of both ends to
+    // the data they receive
+    fn sync_byte_times(&self) {
+        // Clone the configurations, as both are the same for a loopback
+        let config = self.config.lock().unwrap().clone();
+        let peer_config = self.peer_config().lock().unwrap().clone();
+        self.pipe.set_byte_time(config.byte_duration(&peer_config));
+        self.pipe
+            .set_peer_byte_time(peer_config.byte_duration(&config));
+        self.config_changed();
     }
 
-    fn read_data_set_ready(&mut self) -> Result<bool> {
-        Ok(*self.dsr_cd.lock().unwrap())
+    // Invalidates the noise decisions of the handles of both ends
+    fn config_changed(&self) {
+        self.config_version.fetch_add(1, Ordering::Release);
     }
 
-    fn read_ring_indicator(&mut self) -> Result<bool> {
-        Ok(false)
+    /// Returns the loss of bytes on the way to this port.
+    pub fn byte_loss(&self) -> ByteLoss {
+        self.pipe.with_faults(|faults| faults.loss.clone())
     }
 
-    fn read_carrier_detect(&mut self) -> Result<bool> {
-        Ok(*self.dsr_cd.lock().unwrap())
+    /// Sets the loss of bytes on the way to this port, e.g., to test how a
+    /// protocol resynchronizes after missed characters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability of a random loss is not between 0.0 and
+    /// 1.0, the period of a periodic loss is zero, or the loss pattern is
+    /// empty.
+    pub fn set_byte_loss(&mut self, loss: ByteLoss) {
+        loss.validate();
+        self.pipe.with_faults(|faults| faults.loss = loss);
     }
 
-    fn bytes_to_read(&self) -> Result<u32> {
-        // The `buffer_capacity` argument in the constructor methods of `VirtualPort`
-        // is limited to u32, ensuring that the number of bytes in the buffers never
-        // exceeds u32. Therefore, we can safely unwrap the result of `try_from`.
-        Ok(u32::try_from(self.pipe.read_buffer_len()).unwrap())
+    /// Returns the probability of a byte being received twice by this port.
+    pub fn byte_duplication(&self) -> f64 {
+        self.pipe.with_faults(|faults| faults.duplication)
     }
 
-    fn bytes_to_write(&self) -> Result<u32> {
-        // Safe to unwrap: see comment in `bytes_to_read`.
-        Ok(u32::try_from(self.pipe.write_buffer_len()).unwrap())
+    /// Sets the probability of a byte being received twice by this port, as
+    /// with a glitchy UART.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not between 0.0 and 1.0.
+    pub fn set_byte_duplication(&mut self, probability: f64) {
+        fault::validate_probability(probability, "duplication");
+        self.pipe
+            .with_faults(|faults| faults.duplication = probability);
     }
 
-    fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()> {
-        match buffer_to_clear {
-            ClearBuffer::Input => self.pipe.clear_read(),
-            ClearBuffer::Output => self.pipe.clear_write(),
-            ClearBuffer::All => self.pipe.clear(),
-        }
-        Ok(())
+    /// Returns the probability of a random byte being received by this port
+    /// before a byte sent by the peer.
+    pub fn byte_insertion(&self) -> f64 {
+        self.pipe.with_faults(|faults| faults.insertion)
     }
 
-    fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
-        Ok(Box::new(self.clone()))
+    /// Sets the probability of a random byte being received by this port
+    /// before a byte sent by the peer, simulating spurious characters caused
+    /// by interference.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `probability` is not between 0.0 and 1.0.
+    pub fn set_byte_insertion(&mut self, probability: f64) {
+        fault::validate_probability(probability, "insertion");
+        self.pipe
+            .with_faults(|faults| faults.insertion = probability);
     }
 
-    fn set_break(&self) -> Result<()> {
-        Ok(())
+    /// Returns the model of burst errors in the data received by this port.
+    pub fn burst_errors(&self) -> Option<GilbertElliott> {
+        self.pipe.with_faults(|faults| faults.burst_errors)
     }
 
-    fn clear_break(&self) -> Result<()> {
-        Ok(())
+    /// Sets the model of burst errors in the data received by this port, or
+    /// disables them if `model` is `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a probability of the model is not between 0.0 and 1.0.
+    pub fn set_burst_errors(&mut self, model: Option<GilbertElliott>) {
+        if let Some(model) = &model {
+            model.validate();
+        }
+        self.pipe.with_faults(|faults| faults.burst_errors = model);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::io::{Read, Write};
+    /// Seeds the random generator behind the noise, the faults and the jitter
+    /// of the data received by this port, so a failing test can be replayed.
+    ///
+    /// Each port has its own fast generator (`SmallRng`), independent of the
+    /// other ports and of the rest of the test code using `rand`: the same
+    /// seed and the same traffic produce the same damage on a given platform.
+    /// By default, the generator is seeded from the operating system.
+    pub fn set_noise_seed(&mut self, seed: u64) {
+        self.set_noise_rng(SmallRng::seed_from_u64(seed));
+    }
 
-    use super::*;
+    /// Replaces the random generator behind the noise, the faults and the
+    /// jitter of the data received by this port.
+    pub fn set_noise_rng(&mut self, rng: impl RngCore + Send + 'static) {
+        self.pipe.with_faults(|faults| faults.rng = Box::new(rng));
+    }
 
-    #[test]
-    fn test_control_lines() {
-        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+    /// Returns the loss of the link configured on this port.
+    pub fn link_drop(&self) -> LinkDrop {
+        self.pipe.with_faults(|faults| faults.link_drop())
+    }
 
-        port.write_request_to_send(true).unwrap();
-        assert!(port.read_clear_to_send().unwrap());
+    /// Sets when the link drops while this port receives data, e.g., to test
+    /// the reconnection logic of an application (see [`LinkDrop`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the probability of a random drop is not between 0.0 and
+    /// 1.0.
+    pub fn set_link_drop(&mut self, link_drop: LinkDrop) {
+        link_drop.validate();
+        self.pipe
+            .with_faults(|faults| faults.set_link_drop(link_drop));
+    }
 
-        port.write_data_terminal_ready(true).unwrap();
-        assert!(port.read_data_set_ready().unwrap());
+    /// Replaces the faults scheduled on the data received by this port,
+    /// counting offsets and times from now (see [`FaultSchedule`]).
+    pub fn set_fault_schedule(&mut self, schedule: FaultSchedule) {
+        let now = self.pipe.now();
+        self.pipe
+            .with_faults(|faults| faults.set_schedule(schedule, now));
+    }
 
-        port.write_request_to_send(false).unwrap();
-        assert!(!port.read_clear_to_send().unwrap());
+    /// Returns whether the link to the peer is up.
+    pub fn is_connected(&self) -> bool {
+        self.pipe.is_connected()
+    }
 
-        port.write_data_terminal_ready(false).unwrap();
-        assert!(!port.read_data_set_ready().unwrap());
+    /// Takes the link down, as if the cable was unplugged. The buffered data
+    /// of both directions is lost, and the inputs driven by the other side
+    /// (CTS, DSR, CD and RI) drop unless forced, until [`reconnect`] is
+    /// called. By default, reads fail with `NotConnected` and writes with
+    /// `BrokenPipe` on both ends meanwhile (see
+    /// [`set_disconnect_behavior`](VirtualPort::set_disconnect_behavior)).
+    ///
+    /// [`reconnect`]: VirtualPort::reconnect
+    pub fn disconnect(&self) {
+        self.change_lines(|port| port.pipe.disconnect());
     }
 
-    #[test]
-    fn test_buffer_clearing() {
-        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
-        port.set_timeout(Duration::from_millis(100)).unwrap();
-        let write_data = b"test";
-        let mut read_data = [0u8; 4];
+    /// Brings the link up again after a disconnection, restoring the control
+    /// lines.
+    pub fn reconnect(&self) {
+        self.change_lines(|port| port.pipe.reconnect());
+    }
 
-        port.write_all(write_data).unwrap();
-        port.clear(ClearBuffer::All).unwrap();
+    /// Returns how the reads and the writes of this port behave while the
+    /// link is down.
+    pub fn disconnect_behavior(&self) -> DisconnectBehavior {
+        self.pipe.disconnect_behavior()
+    }
 
-        assert_eq!(
-            port.read_exact(&mut read_data).unwrap_err().kind(),
-            io::ErrorKind::TimedOut
-        );
+    /// Sets how the reads and the writes of this port behave while the link
+    /// is down: whether they fail, as with an adapter which is unplugged, or
+    /// the reads time out and the written data vanishes, as with a cable
+    /// which is pulled out (see [`DisconnectBehavior`]).
+    pub fn set_disconnect_behavior(&mut self, behavior: DisconnectBehavior) {
+        self.pipe.set_disconnect_behavior(behavior);
     }
 
-    #[test]
-    fn test_clone() {
-        let port = VirtualPort::loopback(9600, 1024).unwrap();
-        let port_clone = port.try_clone().unwrap();
+    /// Returns whether every handle of the peer has been dropped.
+    ///
+    /// Once the peer is closed, the data it sent can still be read, then the
+    /// reads return 0 (end of file) or fail with the
+    /// [`hangup_error`](VirtualPort::set_hangup_error) instead of waiting,
+    /// and the writes fail with `BrokenPipe`.
+    pub fn is_peer_closed(&self) -> bool {
+        self.pipe.is_peer_closed()
+    }
 
-        assert_eq!(port.baud_rate().unwrap(), port_clone.baud_rate().unwrap());
+    /// Returns the error failing the reads once the peer is closed, `None`
+    /// meaning end of file.
+    pub fn hangup_error(&self) -> Option<io::ErrorKind> {
+        self.pipe.hangup_error()
     }
 
-    #[test]
-    fn test_config_change() {
-        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+    /// Sets the error failing the reads once the peer is closed and its data
+    /// has been read (e.g., `BrokenPipe`, as some drivers report a hangup),
+    /// `None` meaning that the reads return 0 (end of file).
+    pub fn set_hangup_error(&mut self, kind: Option<io::ErrorKind>) {
+        self.pipe.set_hangup_error(kind);
+    }
 
-        port.set_baud_rate(19200).unwrap();
-        assert_eq!(port.baud_rate().unwrap(), 19200);
+    /// Returns whether writes block while the peer can't accept data.
+    pub fn blocking_writes(&self) -> bool {
+        self.pipe.blocking_writes()
+    }
 
-        port.set_data_bits(DataBits::Seven).unwrap();
-        assert_eq!(port.data_bits().unwrap(), DataBits::Seven);
+    /// Sets whether writes block while the receive buffer of the peer is full
+    /// (or the port is paused by flow control) instead of failing with
+    /// `WouldBlock`. A blocked write waits at most for the port timeout and
+    /// then fails with `TimedOut`, which helps reproducing pacing bugs
+    /// between a fast producer and a slow consumer.
+    pub fn set_blocking_writes(&mut self, enabled: bool) {
+        self.pipe.set_blocking_writes(enabled);
+    }
 
-        port.set_flow_control(FlowControl::Software).unwrap();
-        assert_eq!(port.flow_control().unwrap(), FlowControl::Software);
+    /// Returns whether timed-out reads return the data which arrived.
+    pub fn partial_reads(&self) -> bool {
+        self.pipe.partial_reads()
+    }
 
-        port.set_parity(Parity::Odd).unwrap();
+    /// Sets whether a read which times out returns the bytes which arrived
+    /// in the meantime (fewer than requested) instead of failing with
+    /// `TimedOut`, which then only happens if no data arrived. This matches
+    /// the drivers of most platforms, while by default the data is left for
+    /// the next read.
+    pub fn set_partial_reads(&mut self, enabled: bool) {
+        self.pipe.set_partial_reads(enabled);
+    }
+
+    /// Returns how the blocking operations of this handle wait.
+    pub fn wait_strategy(&self) -> WaitStrategy {
+        self.pipe.wait_strategy()
+    }
+
+    /// Sets how the blocking reads, writes, drains and readiness waits of
+    /// this handle wait (see [`WaitStrategy`]): low-latency tests can spin
+    /// or yield, while long soak tests keep parking the threads. Like the
+    /// timeout, the strategy belongs to this handle and to the clones made
+    /// from it afterwards.
+    pub fn set_wait_strategy(&mut self, strategy: WaitStrategy) {
+        self.pipe.set_wait_strategy(strategy);
+    }
+
+    /// Returns the receive buffer levels at which this port deasserts and
+    /// reasserts RTS, if set.
+    pub fn rts_watermarks(&self) -> Option<(usize, usize)> {
+        self.pipe.rts_watermarks()
+    }
+
+    /// Sets the levels `(high, low)` at which this port, like a UART with
+    /// auto-RTS, deasserts its RTS output (the CTS input of the peer) when
+    /// its receive buffer fills up and reasserts it once the buffer drains,
+    /// while using `FlowControl::Hardware`. `None` (the default) disables
+    /// this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the low watermark is above the high one.
+    pub fn set_rts_watermarks(&mut self, watermarks: Option<(usize, usize)>) {
+        if let Some((high, low)) = watermarks {
+            assert!(
+                low <= high,
+                "RTS low watermark must not exceed high watermark"
+            );
+        }
+        self.pipe.set_rts_watermarks(watermarks);
+    }
+
+    /// Returns the counters of the flow control applied to the data sent by
+    /// this port, which show whether the flow control actually engaged.
+    pub fn flow_control_stats(&self) -> FlowControlStats {
+        self.pipe.flow_control_stats()
+    }
+
+    /// Resets the counters of the flow control applied to the data sent by
+    /// this port.
+    pub fn reset_flow_control_stats(&mut self) {
+        self.pipe.reset_flow_control_stats();
+    }
+
+    /// Returns the behavior of this port when the data written by the peer
+    /// doesn't fit into its receive buffer.
+    pub fn overrun_policy(&self) -> OverrunPolicy {
+        self.pipe.overrun_policy()
+    }
+
+    /// Sets the behavior of this port when the data written by the peer
+    /// doesn't fit into its receive buffer (see [`OverrunPolicy`]).
+    pub fn set_overrun_policy(&mut self, policy: OverrunPolicy) {
+        self.pipe.set_overrun_policy(policy);
+    }
+
+    /// Returns the behavior of this port when its receive buffer is full
+    /// (the same as [`overrun_policy`](VirtualPort::overrun_policy)).
+    pub fn buffer_full_policy(&self) -> BufferFullPolicy {
+        self.overrun_policy()
+    }
+
+    /// Sets the behavior of this port when its receive buffer is full, to
+    /// match the semantics of the target hardware driver (the same as
+    /// [`set_overrun_policy`](VirtualPort::set_overrun_policy)).
+    pub fn set_buffer_full_policy(&mut self, policy: BufferFullPolicy) {
+        self.set_overrun_policy(policy);
+    }
+
+    /// Returns the capacity of the receive buffer of this port.
+    pub fn rx_buffer_capacity(&self) -> usize {
+        self.pipe.capacity()
+    }
+
+    /// Returns the capacity of the transmit buffer of this port, which is the
+    /// receive buffer of the peer.
+    pub fn tx_buffer_capacity(&self) -> usize {
+        self.pipe.peer_capacity()
+    }
+
+    /// Sets the capacity of both the receive and the transmit buffer of this
+    /// port, as the constructors do.
+    pub fn set_buffer_capacity(&mut self, capacity: usize) {
+        self.set_rx_buffer_capacity(capacity);
+        self.set_tx_buffer_capacity(capacity);
+    }
+
+    /// Sets the capacity of the receive buffer of this port, e.g., to model a
+    /// small hardware FIFO and then relax it.
+    ///
+    /// Shrinking the buffer below the data it holds loses nothing: the
+    /// buffer takes no more data until it drains below the new capacity, and
+    /// the data which doesn't fit meanwhile is handled according to the
+    /// overrun policy.
+    pub fn set_rx_buffer_capacity(&mut self, capacity: usize) {
+        self.pipe.set_capacity(capacity);
+    }
+
+    /// Sets the capacity of the transmit buffer of this port, which is the
+    /// receive buffer of the peer (see
+    /// [`set_rx_buffer_capacity`](VirtualPort::set_rx_buffer_capacity)).
+    pub fn set_tx_buffer_capacity(&mut self, capacity: usize) {
+        self.pipe.set_peer_capacity(capacity);
+    }
+
+    /// Returns the counters of the data received by this port.
+    pub fn receive_stats(&self) -> ReceiveStats {
+        self.pipe.receive_stats()
+    }
+
+    /// Resets the counters of the data received by this port.
+    pub fn reset_receive_stats(&mut self) {
+        self.pipe.reset_receive_stats();
+    }
+
+    /// Returns the counters of the activity of this port: the data it
+    /// transmitted and received, its reads and writes, and the errors and
+    /// the flow control pauses it met.
+    pub fn stats(&self) -> PortStats {
+        self.pipe.port_stats()
+    }
+
+    /// Resets all the counters of this port, including the ones of
+    /// [`receive_stats`](VirtualPort::receive_stats) and
+    /// [`flow_control_stats`](VirtualPort::flow_control_stats).
+    pub fn reset_stats(&mut self) {
+        self.pipe.reset_port_stats();
+    }
+
+    /// Returns the receive buffer levels at which this port sends XOFF and
+    /// XON, if set.
+    pub fn xoff_watermarks(&self) -> Option<(usize, usize)> {
+        self.pipe.xoff_watermarks()
+    }
+
+    /// Sets the levels `(high, low)` at which this port automatically sends
+    /// XOFF when its receive buffer fills up and XON once it drains, while
+    /// using `FlowControl::Software`. `None` (the default) disables this, so
+    /// only the XON/XOFF characters written explicitly pause the peer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the low watermark is above the high one.
+    pub fn set_xoff_watermarks(&mut self, watermarks: Option<(usize, usize)>) {
+        if let Some((high, low)) = watermarks {
+            assert!(low <= high, "XON watermark must not exceed XOFF watermark");
+        }
+        self.pipe.set_xoff_watermarks(watermarks);
+    }
+
+    /// Returns whether line errors are reported by reads.
+    pub fn report_line_errors(&self) -> bool {
+        self.pipe.with_faults(|faults| faults.report_errors())
+    }
+
+    /// Sets whether line errors are reported by reads, as real drivers report
+    /// framing and parity errors, instead of delivering the damaged bytes.
+    ///
+    /// When enabled, a read stops before a byte the receiver detects as
+    /// damaged, and the next read discards it and fails with a [`LineError`]
+    /// wrapped in an `io::Error` of kind `InvalidData`. Detected are the
+    /// noise caused by a configuration mismatch (which discards the whole
+    /// read), the bytes injected by [`set_byte_insertion`] (framing errors),
+    /// and the bits corrupted by [`set_burst_errors`] if parity is enabled
+    /// (parity errors).
+    ///
+    /// [`set_byte_insertion`]: VirtualPort::set_byte_insertion
+    /// [`set_burst_errors`]: VirtualPort::set_burst_errors
+    pub fn set_report_line_errors(&mut self, value: bool) {
+        self.pipe
+            .with_faults(|faults| faults.set_report_errors(value));
+    }
+
+    /// Returns whether this port received a break condition (sent by the peer
+    /// with `SerialPort::set_break`) since the last call.
+    pub fn break_received(&self) -> bool {
+        self.pipe.take_break()
+    }
+
+    /// Returns whether a received break is also delivered as a `0x00` byte.
+    pub fn break_as_data(&self) -> bool {
+        self.pipe.break_as_data()
+    }
+
+    /// Sets whether a received break is also delivered as a `0x00` byte, as
+    /// most UARTs do. If line errors are reported, reading the byte fails
+    /// with [`LineError::Break`] instead.
+    pub fn set_break_as_data(&mut self, enabled: bool) {
+        self.pipe.set_break_as_data(enabled);
+    }
+
+    /// Sends an address byte, i.e. a character with the 9th bit set, as the
+    /// master of a multidrop (e.g., RS-485) link does to select a slave.
+    /// Fails as a write does, or with `WouldBlock` if the byte doesn't fit.
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// use virtual_serialport::VirtualPort;
+    ///
+    /// let (mut master, mut slave) = VirtualPort::pair(9600, 1024).unwrap();
+    /// slave.set_address_filter(Some(0x42));
+    ///
+    /// // The data sent to another slave is ignored
+    /// master.write_address(0x17).unwrap();
+    /// master.write_all(b"other").unwrap();
+    /// master.write_address(0x42).unwrap();
+    /// master.write_all(b"mine").unwrap();
+    ///
+    /// let mut words = [0u16; 8];
+    /// let len = slave.read_words(&mut words).unwrap();
+    /// assert_eq!(&words[..len], &[0x142, 0x6d, 0x69, 0x6e, 0x65]);
+    /// ```
+    pub fn write_address(&mut self, address: u8) -> io::Result<()> {
+        self.pipe.write_address(address)
+    }
+
+    /// Returns the address this port wakes up on, if any.
+    pub fn address_filter(&self) -> Option<u8> {
+        self.pipe.address_filter()
+    }
+
+    /// Sets the address this port wakes up on, as the multiprocessor mode of
+    /// a UART does: the received data is discarded until an address byte
+    /// matching `address` arrives, and again after a different one. `None`
+    /// (the default) receives all the data. The port starts asleep.
+    pub fn set_address_filter(&mut self, address: Option<u8>) {
+        self.pipe.set_address_filter(address);
+    }
+
+    /// Reads 9-bit characters, with bit 8 set for the address bytes (see
+    /// [`write_address`](VirtualPort::write_address)). Waits until at least
+    /// one character is available, or fails with `TimedOut` once the timeout
+    /// expires, and reads up to `words.len()` characters, failing as a read
+    /// does on line errors.
+    pub fn read_words(&mut self, words: &mut [u16]) -> io::Result<usize> {
+        let mut buf = vec![0u8; words.len()];
+        let (len, addresses) = self.pipe.read_min_addressed(&mut buf, 1)?;
+        if let Some(error) = self.receive_noise() {
+            self.damage(&mut buf[..len], error)?;
+        }
+
+        for (word, &byte) in words.iter_mut().zip(&buf[..len]) {
+            *word = u16::from(byte);
+        }
+        for position in addresses {
+            words[position] |= 0x100;
+        }
+        Ok(len)
+    }
+
+    /// Drives the carrier detect (CD) input of the peer port, or restores the
+    /// wiring, where it follows the DTR output of this port by default, if
+    /// `level` is `None`.
+    pub fn set_carrier_detect(&mut self, level: Option<bool>) {
+        self.change_lines(|port| *port.peer_cd.lock().unwrap() = level);
+    }
+
+    /// Drives the ring indicator (RI) input of the peer port.
+    pub fn set_ring_indicator(&mut self, level: bool) {
+        self.change_lines(|port| *port.peer_ri.lock().unwrap() = level);
+    }
+
+    /// Forces the clear to send (CTS) input of this port to `level`
+    /// regardless of the peer, or lets the peer drive it again if `level` is
+    /// `None`. With `FlowControl::Hardware`, a forced CTS also pauses and
+    /// resumes the transmission.
+    pub fn force_cts(&mut self, level: Option<bool>) {
+        self.change_lines(|port| {
+            port.forced.lock().unwrap().cts = level;
+            port.pipe.force_clear_to_send(level);
+        });
+    }
+
+    /// Forces the data set ready (DSR) input of this port to `level`
+    /// regardless of the peer, or lets the peer drive it again if `level` is
+    /// `None`.
+    pub fn force_dsr(&mut self, level: Option<bool>) {
+        self.change_lines(|port| port.forced.lock().unwrap().dsr = level);
+    }
+
+    /// Forces the carrier detect (CD) input of this port to `level`
+    /// regardless of the peer, or lets the peer drive it again if `level` is
+    /// `None`.
+    pub fn force_cd(&mut self, level: Option<bool>) {
+        self.change_lines(|port| port.forced.lock().unwrap().cd = level);
+    }
+
+    /// Forces the ring indicator (RI) input of this port to `level`
+    /// regardless of the peer, or lets the peer drive it again if `level` is
+    /// `None`.
+    pub fn force_ri(&mut self, level: Option<bool>) {
+        self.change_lines(|port| port.forced.lock().unwrap().ri = level);
+    }
+
+    /// Returns a receiver of the changes of the control lines of this port,
+    /// both of its outputs and of its inputs, so device emulators can react to
+    /// line toggles instead of polling. RTS deasserted automatically by
+    /// [`set_rts_watermarks`](VirtualPort::set_rts_watermarks) is not
+    /// reported.
+    ///
+    /// ```
+    /// use serialport::SerialPort;
+    /// use virtual_serialport::{ControlLine, VirtualPort};
+    ///
+    /// let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+    /// let events = port2.subscribe_control_events();
+    ///
+    /// port1.write_request_to_send(false).unwrap();
+    /// let event = events.recv().unwrap();
+    /// assert_eq!((event.line, event.level), (ControlLine::Cts, false));
+    /// ```
+    pub fn subscribe_control_events(&self) -> Receiver<ControlEvent> {
+        self.events.lock().unwrap().subscribe()
+    }
+
+    // Levels of the control lines as seen by this port and by the peer
+    fn line_levels(&self) -> (LineLevels, LineLevels) {
+        let rts = *self.rts.lock().unwrap();
+        let cts = *self.cts.lock().unwrap();
+        let dtr = *self.dtr.lock().unwrap();
+        let dsr = *self.dsr_cd.lock().unwrap();
+        let forced = *self.forced.lock().unwrap();
+        let peer_forced = *self.peer_forced.lock().unwrap();
+        let wiring = *self.wiring.lock().unwrap();
+        let peer_wiring = *self.peer_wiring.lock().unwrap();
+
+        // Outputs of the peer (`cts` and `dsr` hold its RTS and DTR) and of
+        // this port, with RTS held deasserted by the watermarks
+        let peer_rts = cts && !self.pipe.peer_rts_held();
+        let own_rts = rts && !self.pipe.rts_held();
+
+        // While the link is down, the inputs driven by the other side drop
+        let connected = self.pipe.is_connected();
+        let inputs = |wiring: Wiring, rts: bool, dtr: bool, cd: Option<bool>, ri: bool| {
+            let level = |input| connected && wiring.level(input, rts, dtr);
+            (
+                level(ControlLine::Cts),
+                level(ControlLine::Dsr),
+                cd.map_or_else(|| level(ControlLine::Cd), |cd| connected && cd),
+                (connected && ri) || level(ControlLine::Ri),
+            )
+        };
+
+        let (local_cts, local_dsr, local_cd, local_ri) = inputs(
+            wiring,
+            peer_rts,
+            dsr,
+            *self.cd.lock().unwrap(),
+            *self.ri.lock().unwrap(),
+        );
+        let local = LineLevels {
+            rts,
+            dtr,
+            cts: forced.cts.unwrap_or(local_cts),
+            dsr: forced.dsr.unwrap_or(local_dsr),
+            cd: forced.cd.unwrap_or(local_cd),
+            ri: forced.ri.unwrap_or(local_ri),
+        };
+
+        let (peer_cts, peer_dsr, peer_cd, peer_ri) = inputs(
+            peer_wiring,
+            own_rts,
+            dtr,
+            *self.peer_cd.lock().unwrap(),
+            *self.peer_ri.lock().unwrap(),
+        );
+        let peer = LineLevels {
+            rts: cts,
+            dtr: dsr,
+            cts: peer_forced.cts.unwrap_or(peer_cts),
+            dsr: peer_forced.dsr.unwrap_or(peer_dsr),
+            cd: peer_forced.cd.unwrap_or(peer_cd),
+            ri: peer_forced.ri.unwrap_or(peer_ri),
+        };
+        (local, peer)
+    }
+
+    // Applies a change of the control lines, notifying the subscribers of
+    // both ports of the resulting transitions
+    fn change_lines(&self, change: impl FnOnce(&Self)) {
+        let subscribed =
+            !self.events.lock().unwrap().is_empty() || !self.peer_events.lock().unwrap().is_empty();
+        if !subscribed {
+            change(self);
+            return;
+        }
+
+        let before = self.line_levels();
+        change(self);
+        let after = self.line_levels();
+
+        let now = self.pipe.now();
+        self.events.lock().unwrap().publish(before.0, after.0, now);
+        if !Arc::ptr_eq(&self.events, &self.peer_events) {
+            self.peer_events
+                .lock()
+                .unwrap()
+                .publish(before.1, after.1, now);
+        }
+    }
+
+    /// Blocks until data is available for reading or the peer is closed,
+    /// failing with `TimedOut` once `timeout` expires (`None` means waiting
+    /// indefinitely).
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.pipe.wait_readable(timeout)
+    }
+
+    /// Blocks until the receiving buffer has free space for writing, failing
+    /// with `TimedOut` once `timeout` expires (`None` means waiting
+    /// indefinitely).
+    pub fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.pipe.wait_writable(timeout)
+    }
+
+    /// Blocks until the peer has read everything written to it, as
+    /// `tcdrain()` does, e.g., before turning the line around on a
+    /// half-duplex bus. Fails with `TimedOut` once `timeout` expires (`None`
+    /// means waiting indefinitely), or with `BrokenPipe` if the link is down.
+    ///
+    /// Unlike `flush`, which only waits for the data to arrive at the peer,
+    /// this also waits for the peer to consume it.
+    pub fn drain(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.pipe.wait_drained(timeout)
+    }
+
+    /// Copies the available bytes into `buf` without removing them from the
+    /// receive buffer or waiting, returning 0 if there is no data, e.g., for
+    /// protocol detection which inspects the data before committing to a
+    /// read.
+    ///
+    /// A line error is reported as the reads report it, but leaves the
+    /// damaged byte in place. The noise of a configuration mismatch is only
+    /// applied by the reads.
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.peek(buf)
+    }
+
+    /// Returns a copy of the data which has arrived at the port and hasn't
+    /// been read yet, without removing it, e.g., to check for leftover bytes
+    /// at the end of a test. Unlike [`peek`](VirtualPort::peek), the bytes
+    /// with a line error are included.
+    pub fn pending(&self) -> Vec<u8> {
+        self.pipe.pending()
+    }
+
+    /// Removes the data which has arrived at the port and hasn't been read
+    /// yet, and returns it, e.g., to assert on the unconsumed bytes at the
+    /// end of a scenario. The bytes with a line error are included, and the
+    /// noise of a configuration mismatch isn't applied.
+    pub fn drain_pending(&mut self) -> Vec<u8> {
+        self.pipe.drain_pending()
+    }
+
+    /// Reads the available bytes into `buf` without waiting, returning
+    /// `WouldBlock` if there is no data, or 0 once the peer is closed.
+    ///
+    /// With transmission delay simulation, only the bytes which have already
+    /// arrived are available.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.pipe.try_read(buf)?;
+        if bytes_read == 0 && !buf.is_empty() && !self.pipe.at_eof() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        if let Some(error) = self.receive_noise() {
+            self.damage(&mut buf[..bytes_read], error)?;
+        }
+
+        Ok(bytes_read)
+    }
+
+    // Waits until at least `min_len` bytes are available and reads up to
+    // `buf.len()` bytes, simulating noise
+    fn receive(&mut self, buf: &mut [u8], min_len: usize) -> io::Result<usize> {
+        let bytes_to_read = self.pipe.read_min(buf, min_len)?;
+
+        // Fill the buffer with noise if required
+        if let Some(error) = self.receive_noise() {
+            self.damage(&mut buf[..bytes_to_read], error)?;
+        }
+
+        Ok(bytes_to_read)
+    }
+
+    // Determines whether received data must be replaced with noise (and the
+    // error the receiver detects in it), locking the configurations only if
+    // they have changed since the last read
+    fn receive_noise(&mut self) -> Option<LineError> {
+        let version = self.config_version.load(Ordering::Acquire);
+        match self.noise {
+            Some((noise_version, noise)) if noise_version == version => noise,
+            _ => {
+                let noise = self.config_noise();
+                self.noise = Some((version, noise));
+                noise
+            }
+        }
+    }
+
+    // Compares the configurations of both ends for `receive_noise`
+    fn config_noise(&self) -> Option<LineError> {
+        let config = self.config.lock().unwrap();
+
+        // Determine if noise simulation is needed
+        if config.noise_on_config_mismatch {
+            if let Some(paired_port_config) = &self.paired_port_config {
+                let paired_config = paired_port_config.lock().unwrap();
+                config.physical_settings_mismatch(&paired_config).then(|| {
+                    if config.parity == paired_config.parity {
+                        LineError::Framing
+                    } else {
+                        LineError::Parity
+                    }
+                })
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+
+    // Replaces received bytes with random values, or discards them and fails
+    // with `error` if line errors are reported. An empty read (e.g., at EOF)
+    // has nothing to damage.
+    fn damage(&self, buf: &mut [u8], error: LineError) -> io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.pipe.with_faults(|faults| {
+            if faults.report_errors() {
+                faults.stats.errors_raised += 1;
+                return Err(error.into());
+            }
+            faults.fill_with_noise(buf);
+            Ok(())
+        })
+    }
+}
+
+impl io::Read for VirtualPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receive(buf, buf.len())
+    }
+
+    #[cfg(nightly)]
+    fn read_buf(&mut self, mut cursor: io::BorrowedCursor<'_>) -> io::Result<()> {
+        // The noise replaces the bytes read, so they go through a buffer
+        if self.receive_noise().is_some() {
+            let mut buf = vec![0; cursor.capacity()];
+            let len = self.read(&mut buf)?;
+            cursor.append(&buf[..len]);
+            return Ok(());
+        }
+
+        let min_len = cursor.capacity();
+        self.pipe.read_min_buf(&mut cursor, min_len).map(drop)
+    }
+}
+
+impl io::Write for VirtualPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pipe.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.simulate_tx_delay() {
+            return self.pipe.wait_transmitted();
+        }
+        self.pipe.flush()
+    }
+}
+
+impl SerialPort for VirtualPort {
+    fn name(&self) -> Option<String> {
+        None
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        Ok(self.config.lock().unwrap().baud_rate)
+    }
+
+    fn data_bits(&self) -> Result<DataBits> {
+        Ok(self.config.lock().unwrap().data_bits)
+    }
+
+    fn flow_control(&self) -> Result<FlowControl> {
+        Ok(self.config.lock().unwrap().flow_control)
+    }
+
+    fn parity(&self) -> Result<Parity> {
+        Ok(self.config.lock().unwrap().parity)
+    }
+
+    fn stop_bits(&self) -> Result<StopBits> {
+        Ok(self.config.lock().unwrap().stop_bits)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.pipe.timeout().unwrap_or(Duration::MAX)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        let mut config = self.config.lock().unwrap();
+        config.baud_rate = baud_rate;
+        config.rx_baud_rate = None;
+        drop(config);
+        self.sync_byte_times();
+        self.publish_settings();
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()> {
+        self.config.lock().unwrap().flow_control = flow_control;
+        self.pipe.set_flow_control(flow_control);
+        self.publish_settings();
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> Result<()> {
+        self.config.lock().unwrap().parity = parity;
+        self.sync_byte_times();
+        self.pipe
+            .with_faults(|faults| faults.parity_check = parity != Parity::None);
+        self.publish_settings();
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
+        self.config.lock().unwrap().data_bits = data_bits;
+        self.sync_byte_times();
+        self.publish_settings();
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()> {
+        self.config.lock().unwrap().stop_bits = stop_bits;
+        self.sync_byte_times();
+        self.publish_settings();
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.pipe.set_timeout(match timeout {
+            Duration::MAX => None,
+            duration => Some(duration),
+        });
+        self.publish_settings();
+        Ok(())
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> Result<()> {
+        self.change_lines(|port| {
+            *port.rts.lock().unwrap() = level;
+            port.pipe.set_request_to_send(level);
+        });
+        Ok(())
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<()> {
+        self.change_lines(|port| {
+            *port.dtr.lock().unwrap() = level;
+            port.pipe.set_data_terminal_ready(level);
+        });
+        Ok(())
+    }
+
+    fn read_clear_to_send(&mut self) -> Result<bool> {
+        Ok(self.line_levels().0.cts)
+    }
+
+    fn read_data_set_ready(&mut self) -> Result<bool> {
+        Ok(self.line_levels().0.dsr)
+    }
+
+    fn read_ring_indicator(&mut self) -> Result<bool> {
+        Ok(self.line_levels().0.ri)
+    }
+
+    fn read_carrier_detect(&mut self) -> Result<bool> {
+        Ok(self.line_levels().0.cd)
+    }
+
+    fn bytes_to_read(&self) -> Result<u32> {
+        // The buffers may hold more than `u32::MAX` bytes, reported as the
+        // maximum
+        Ok(saturate(self.pipe.read_buffer_len()))
+    }
+
+    fn bytes_to_write(&self) -> Result<u32> {
+        let len = if self.simulate_tx_delay() {
+            self.pipe.write_in_flight_len()
+        } else {
+            self.pipe.write_buffer_len()
+        };
+        Ok(saturate(len))
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()> {
+        match buffer_to_clear {
+            ClearBuffer::Input => self.pipe.clear_read(),
+            ClearBuffer::Output => self.pipe.clear_write(),
+            ClearBuffer::All => self.pipe.clear(),
+        }
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn set_break(&self) -> Result<()> {
+        self.pipe.set_break();
+        Ok(())
+    }
+
+    fn clear_break(&self) -> Result<()> {
+        self.pipe.clear_break();
+        Ok(())
+    }
+}
+
+// Converts a number of bytes for the `SerialPort` methods, saturating at
+// `u32::MAX`
+fn saturate(len: usize) -> u32 {
+    u32::try_from(len).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    #[test]
+    fn test_control_lines() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+
+        port.write_request_to_send(true).unwrap();
+        assert!(port.read_clear_to_send().unwrap());
+
+        port.write_data_terminal_ready(true).unwrap();
+        assert!(port.read_data_set_ready().unwrap());
+
+        port.write_request_to_send(false).unwrap();
+        assert!(!port.read_clear_to_send().unwrap());
+
+        port.write_data_terminal_ready(false).unwrap();
+        assert!(!port.read_data_set_ready().unwrap());
+    }
+
+    #[test]
+    fn test_hardware_flow_control() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let timeout = Some(Duration::from_millis(50));
+
+        // Without flow control, CTS is ignored
+        port2.write_request_to_send(false).unwrap();
+        port1.write_all(b"ab").unwrap();
+
+        port1.set_flow_control(FlowControl::Hardware).unwrap();
+        assert!(!port1.read_clear_to_send().unwrap());
+        assert_eq!(
+            port1.write(b"cd").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(
+            port1.wait_writable(timeout).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // The writer resumes once the peer asserts RTS
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            port2.write_request_to_send(true).unwrap();
+            port2
+        });
+        port1.wait_writable(None).unwrap();
+        port1.write_all(b"cd").unwrap();
+
+        let mut port2 = reader.join().unwrap();
+        let mut read_data = [0u8; 4];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+
+        let stats = port1.flow_control_stats();
+        assert_eq!(stats.cts_pauses, 1);
+        assert!(stats.paused_time >= Duration::from_millis(50));
+        assert_eq!(stats.delayed_bytes, 2);
+
+        port1.reset_flow_control_stats();
+        assert_eq!(port1.flow_control_stats(), FlowControlStats::default());
+    }
+
+    #[test]
+    fn test_software_flow_control() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_flow_control(FlowControl::Software).unwrap();
+        port2.set_flow_control(FlowControl::Software).unwrap();
+
+        // XOFF from the peer pauses the transmission and is not received
+        port2.write_all(b"a\x13b").unwrap();
+        assert_eq!(
+            port1.write(b"cd").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        let mut read_data = [0u8; 2];
+        port1.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ab");
+
+        // XON resumes it
+        port2.write_all(b"\x11").unwrap();
+        assert_eq!(port1.bytes_to_read().unwrap(), 0);
+        port1.write_all(b"cd").unwrap();
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"cd");
+
+        let stats = port1.flow_control_stats();
+        assert_eq!(stats.xoff_pauses, 1);
+        assert_eq!(stats.cts_pauses, 0);
+        assert_eq!(stats.delayed_bytes, 2);
+        assert_eq!(port2.flow_control_stats(), FlowControlStats::default());
+    }
+
+    #[test]
+    fn test_xoff_watermarks() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_flow_control(FlowControl::Software).unwrap();
+        port2.set_flow_control(FlowControl::Software).unwrap();
+        port2.set_xoff_watermarks(Some((4, 2)));
+
+        port1.write_all(b"abcdef").unwrap();
+        assert_eq!(
+            port1.write(b"g").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // XON is sent once the buffer drains to the low watermark
+        let mut read_data = [0u8; 3];
+        port2.read_exact(&mut read_data).unwrap();
+        assert!(port1.write(b"g").is_err());
+        port2.read_exact(&mut read_data[..1]).unwrap();
+        port1.write_all(b"g").unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_rts_watermarks() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_flow_control(FlowControl::Hardware).unwrap();
+        port2.set_flow_control(FlowControl::Hardware).unwrap();
+        port2.set_rts_watermarks(Some((4, 2)));
+
+        port1.write_all(b"abcd").unwrap();
+        assert!(!port1.read_clear_to_send().unwrap());
+        assert_eq!(
+            port1.write(b"e").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        // RTS is reasserted once the buffer drains to the low watermark
+        let mut read_data = [0u8; 2];
+        port2.read_exact(&mut read_data[..1]).unwrap();
+        assert!(!port1.read_clear_to_send().unwrap());
+        port2.read_exact(&mut read_data[..1]).unwrap();
+        assert!(port1.read_clear_to_send().unwrap());
+        port1.write_all(b"e").unwrap();
+
+        // Discarding a damaged byte drains the buffer too
+        let mut rest = [0u8; 3];
+        port2.read_exact(&mut rest).unwrap();
+        port2.set_report_line_errors(true);
+        port1.set_parity(Parity::Even).unwrap();
+        port2.set_parity(Parity::Even).unwrap();
+        port2.set_burst_errors(Some(GilbertElliott::new(1.0, 0.0)));
+        port1.write_all(b"fghi").unwrap();
+        assert!(!port1.read_clear_to_send().unwrap());
+        assert!(port2.read(&mut read_data[..1]).is_err());
+        assert!(!port1.read_clear_to_send().unwrap());
+        assert!(port2.read(&mut read_data[..1]).is_err());
+        assert!(port1.read_clear_to_send().unwrap());
+    }
+
+    #[cfg(nightly)]
+    #[test]
+    fn test_read_buf() {
+        use std::{io::BorrowedBuf, mem::MaybeUninit};
+
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.write_all(b"hello, world").unwrap();
+
+        let mut storage = [MaybeUninit::uninit(); 5];
+        let mut buf = BorrowedBuf::from(&mut storage[..]);
+        port2.read_buf(buf.unfilled()).unwrap();
+        assert_eq!(buf.filled(), b"hello");
+
+        // With partial reads, a larger buffer gets the rest of the data
+        port2.set_timeout(Duration::from_millis(20)).unwrap();
+        port2.set_partial_reads(true);
+        let mut storage = [MaybeUninit::uninit(); 16];
+        let mut buf = BorrowedBuf::from(&mut storage[..]);
+        port2.read_buf(buf.unfilled()).unwrap();
+        assert_eq!(buf.filled(), b", world");
+    }
+
+    #[test]
+    fn test_partial_reads() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_timeout(Duration::from_millis(20)).unwrap();
+        assert!(!port2.partial_reads());
+
+        port1.write_all(b"abc").unwrap();
+        let mut read_data = [0u8; 8];
+        assert_eq!(
+            port2.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // The data left by the failed read is returned by a partial one
+        port2.set_partial_reads(true);
+        assert_eq!(port2.read(&mut read_data).unwrap(), 3);
+        assert_eq!(&read_data[..3], b"abc");
+        assert_eq!(
+            port2.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(port2.stats().timeouts, 2);
+    }
+
+    #[test]
+    fn test_wait_strategy() {
+        for strategy in [WaitStrategy::Park, WaitStrategy::Yield, WaitStrategy::Spin] {
+            let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+            port2.set_wait_strategy(strategy);
+            assert_eq!(port2.wait_strategy(), strategy);
+            assert_eq!(port2.clone().wait_strategy(), strategy);
+
+            port2.set_timeout(Duration::from_millis(10)).unwrap();
+            let mut read_data = [0u8; 2];
+            assert_eq!(
+                port2.read_exact(&mut read_data).unwrap_err().kind(),
+                io::ErrorKind::TimedOut
+            );
+
+            port2.set_timeout(Duration::MAX).unwrap();
+            let writer = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                port1.write_all(b"ok").unwrap();
+            });
+            port2.read_exact(&mut read_data).unwrap();
+            assert_eq!(&read_data, b"ok");
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_blocking_writes() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 4).unwrap();
+        port1.set_blocking_writes(true);
+        port1.set_timeout(Duration::from_millis(50)).unwrap();
+
+        port1.write_all(b"abcd").unwrap();
+        assert_eq!(
+            port1.write(b"e").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // The write completes once the reader catches up
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            let mut read_data = [0u8; 8];
+            port2.read_exact(&mut read_data[..4]).unwrap();
+            port2.read_exact(&mut read_data[4..]).unwrap();
+            read_data
+        });
+        port1.set_timeout(Duration::MAX).unwrap();
+        port1.write_all(b"efgh").unwrap();
+        assert_eq!(&reader.join().unwrap(), b"abcdefgh");
+    }
+
+    #[test]
+    fn test_buffer_capacity() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 8).unwrap();
+        port1.write_all(b"abcdefgh").unwrap();
+
+        // Shrinking keeps the buffered data, but takes no more
+        port2.set_rx_buffer_capacity(4);
+        assert_eq!(port1.tx_buffer_capacity(), 4);
+        assert_eq!(port2.bytes_to_read().unwrap(), 8);
+        assert_eq!(
+            port1.write(b"i").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let mut read_data = [0u8; 6];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(port1.write(b"ijk").unwrap(), 2);
+
+        // Growing takes the rest
+        port1.set_buffer_capacity(16);
+        assert_eq!(port2.rx_buffer_capacity(), 16);
+        assert_eq!(port1.rx_buffer_capacity(), 16);
+        port1.write_all(b"k").unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 5);
+    }
+
+    #[test]
+    fn test_capacity_beyond_u32() {
+        // The capacity isn't allocated upfront
+        let (port1, port2) = VirtualPort::pair(9600, usize::MAX).unwrap();
+        assert_eq!(port1.tx_buffer_capacity(), usize::MAX);
+        assert_eq!(port2.rx_buffer_capacity(), usize::MAX);
+        assert_eq!(saturate(1 << 40), u32::MAX);
+        assert_eq!(saturate(5), 5);
+    }
+
+    #[test]
+    fn test_forced_lines() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+
+        port1.force_cts(Some(false));
+        port1.force_dsr(Some(false));
+        port1.force_cd(Some(false));
+        port1.force_ri(Some(true));
+        assert!(!port1.read_clear_to_send().unwrap());
+        assert!(!port1.read_data_set_ready().unwrap());
+        assert!(!port1.read_carrier_detect().unwrap());
+        assert!(port1.read_ring_indicator().unwrap());
+
+        // The peer doesn't see the forced levels, nor can it change them
+        port1.write_data_terminal_ready(true).unwrap();
+        assert!(port2.read_data_set_ready().unwrap());
+        port2.write_data_terminal_ready(true).unwrap();
+        assert!(!port1.read_data_set_ready().unwrap());
+
+        // A forced CTS pauses a writer using hardware flow control
+        port1.set_flow_control(FlowControl::Hardware).unwrap();
+        assert_eq!(
+            port1.write(b"a").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        port1.force_cts(None);
+        port1.force_dsr(None);
+        port1.force_cd(None);
+        port1.force_ri(None);
+        assert!(port1.read_clear_to_send().unwrap());
+        assert!(port1.read_data_set_ready().unwrap());
+        assert!(port1.read_carrier_detect().unwrap());
+        assert!(!port1.read_ring_indicator().unwrap());
+        port1.write_all(b"a").unwrap();
+    }
+
+    #[test]
+    fn test_break() {
+        let (port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        assert!(!port2.break_received());
+
+        port1.set_break().unwrap();
+        port1.set_break().unwrap();
+        port1.clear_break().unwrap();
+        assert!(port2.break_received());
+        assert!(!port2.break_received());
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+
+        // A break can also be received in the data stream
+        port2.set_break_as_data(true);
+        port1.set_break().unwrap();
+        port1.clear_break().unwrap();
+        port2.set_report_line_errors(true);
+        port1.set_break().unwrap();
+        port1.clear_break().unwrap();
+
+        let mut read_data = [0xffu8; 1];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(read_data, [0]);
+        let err = port2.read(&mut read_data).unwrap_err();
+        assert_eq!(LineError::from_io(&err), Some(LineError::Break));
+    }
+
+    #[test]
+    fn test_control_events() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let events1 = port1.subscribe_control_events();
+        let events2 = port2.subscribe_control_events();
+
+        port1.write_data_terminal_ready(false).unwrap();
+        port1.write_data_terminal_ready(false).unwrap();
+        port2.force_cts(Some(false));
+        port1.set_ring_indicator(true);
+
+        let lines = |events: &Receiver<ControlEvent>| -> Vec<_> {
+            events
+                .try_iter()
+                .map(|event| (event.line, event.level))
+                .collect()
+        };
+        assert_eq!(lines(&events1), [(ControlLine::Dtr, false)]);
+        assert_eq!(
+            lines(&events2),
+            [
+                (ControlLine::Dsr, false),
+                (ControlLine::Cd, false),
+                (ControlLine::Cts, false),
+                (ControlLine::Ri, true),
+            ]
+        );
+
+        // A loopback port sees both ends of the wire
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        let events = port.subscribe_control_events();
+        port.write_request_to_send(false).unwrap();
+        assert_eq!(
+            lines(&events),
+            [(ControlLine::Rts, false), (ControlLine::Cts, false)]
+        );
+    }
+
+    #[test]
+    fn test_carrier_detect_and_ring_indicator() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+
+        // By default CD follows DTR of the peer
+        assert!(port1.read_carrier_detect().unwrap());
+        assert!(!port1.read_ring_indicator().unwrap());
+
+        port2.set_carrier_detect(Some(false));
+        port2.set_ring_indicator(true);
+        assert!(!port1.read_carrier_detect().unwrap());
+        assert!(port1.read_data_set_ready().unwrap());
+        assert!(port1.read_ring_indicator().unwrap());
+        assert!(!port2.read_ring_indicator().unwrap());
+
+        port2.set_carrier_detect(None);
+        port2.write_data_terminal_ready(false).unwrap();
+        assert!(!port1.read_carrier_detect().unwrap());
+    }
+
+    #[test]
+    fn test_buffer_clearing() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.set_timeout(Duration::from_millis(100)).unwrap();
+        let write_data = b"test";
+        let mut read_data = [0u8; 4];
+
+        port.write_all(write_data).unwrap();
+        port.clear(ClearBuffer::All).unwrap();
+
+        assert_eq!(
+            port.read_exact(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn test_wait_readable_and_writable() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 4).unwrap();
+        let timeout = Some(Duration::from_millis(50));
+
+        assert_eq!(
+            port2.wait_readable(timeout).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // The reader is woken up by a write from another thread
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            port1.write_all(b"ping").unwrap();
+            port1
+        });
+        port2.wait_readable(None).unwrap();
+        let port1 = writer.join().unwrap();
+
+        // The buffer of port2 is full until it is read
+        assert_eq!(
+            port1.wait_writable(timeout).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        let mut read_data = [0u8; 4];
+        port2.read_exact(&mut read_data).unwrap();
+        port1.wait_writable(timeout).unwrap();
+    }
+
+    #[test]
+    fn test_drain() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let timeout = Some(Duration::from_millis(50));
+        port1.drain(timeout).unwrap();
+
+        port1.write_all(b"ping").unwrap();
+        assert_eq!(
+            port1.drain(timeout).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // The writer is woken up once the peer has read everything
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            let mut read_data = [0u8; 4];
+            port2.read_exact(&mut read_data).unwrap();
+        });
+        port1.drain(None).unwrap();
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn test_peer_closed() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_timeout(Duration::from_secs(10)).unwrap();
+        port2.write_all(b"bye").unwrap();
+        let port2_clone = port2.clone();
+        drop(port2);
+        assert!(!port1.is_peer_closed());
+        drop(port2_clone);
+        assert!(port1.is_peer_closed());
+
+        // The remaining data is read, then the reads return EOF at once
+        let mut read_data = [0u8; 8];
+        assert_eq!(port1.read(&mut read_data).unwrap(), 3);
+        assert_eq!(&read_data[..3], b"bye");
+        assert_eq!(port1.read(&mut read_data).unwrap(), 0);
+        assert_eq!(port1.try_read(&mut read_data).unwrap(), 0);
+        port1.wait_readable(None).unwrap();
+        assert_eq!(
+            port1.write(b"hello").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        port1.set_hangup_error(Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(
+            port1.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn test_peer_closed_with_config_mismatch() {
+        // No noise is made up once there is no data left
+        let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_noise_on_config_mismatch(true);
+        port1.set_report_line_errors(true);
+        port1.set_baud_rate(19200).unwrap();
+        drop(port2);
+
+        let mut read_data = [0u8; 8];
+        assert_eq!(port1.read(&mut []).unwrap(), 0);
+        assert_eq!(port1.read(&mut read_data).unwrap(), 0);
+        assert_eq!(port1.try_read(&mut read_data).unwrap(), 0);
+        let stats = port1.stats();
+        assert_eq!((stats.faults.errors_raised, stats.line_errors), (0, 0));
+    }
+
+    #[test]
+    fn test_address_filter() {
+        let (mut master, mut slave) = VirtualPort::pair(9600, 1024).unwrap();
+        assert_eq!(slave.address_filter(), None);
+
+        // Without a filter, everything arrives, with the addresses marked
+        master.write_address(0x01).unwrap();
+        master.write_all(b"a").unwrap();
+        let mut words = [0u16; 4];
+        assert_eq!(slave.read_words(&mut words).unwrap(), 2);
+        assert_eq!(&words[..2], &[0x101, u16::from(b'a')]);
+
+        // A sleeping slave ignores the data, and is put to sleep again by
+        // another address
+        slave.set_address_filter(Some(0x02));
+        master.write_all(b"b").unwrap();
+        master.write_address(0x02).unwrap();
+        master.write_all(b"c").unwrap();
+        master.write_address(0x03).unwrap();
+        master.write_all(b"d").unwrap();
+        let mut read_data = [0u8; 3];
+        slave.set_timeout(Duration::from_millis(10)).unwrap();
+        assert_eq!(
+            slave.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(slave.bytes_to_read().unwrap(), 2);
+        assert_eq!(slave.read_words(&mut words).unwrap(), 2);
+        assert_eq!(&words[..2], &[0x102, u16::from(b'c')]);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        let mut peeked = [0u8; 2];
+        assert_eq!(port.peek(&mut peeked).unwrap(), 0);
+
+        port.write_all(b"abc").unwrap();
+        assert_eq!(port.peek(&mut peeked).unwrap(), 2);
+        assert_eq!(&peeked, b"ab");
+
+        // The peeked data is still there to read
+        assert_eq!(port.bytes_to_read().unwrap(), 3);
+        let mut read_data = [0u8; 3];
+        port.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abc");
+    }
+
+    #[test]
+    fn test_pending() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_clock(clock.clone());
+        port2.set_simulate_delay(true);
+        port2.set_parity(Parity::Even).unwrap();
+        port2.set_report_line_errors(true);
+        port2.set_fault_schedule(FaultSchedule::new().corrupt_at(1, 1));
+        assert!(port2.pending().is_empty());
+
+        // Only the bytes which have arrived are pending, the damaged one too
+        port1.write_all(b"abcd").unwrap();
+        clock.advance(Duration::from_millis(3));
+        assert_eq!(port2.pending(), b"a\x63");
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+        assert_eq!(port2.drain_pending(), b"a\x63");
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+
+        clock.advance(Duration::from_millis(3));
+        let mut read_data = [0u8; 2];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"cd");
+        assert!(port2.drain_pending().is_empty());
+    }
+
+    #[test]
+    fn test_clone() {
+        let port = VirtualPort::loopback(9600, 1024).unwrap();
+        let port_clone = port.try_clone().unwrap();
+
+        assert_eq!(port.baud_rate().unwrap(), port_clone.baud_rate().unwrap());
+    }
+
+    #[test]
+    fn test_config_change() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+
+        port.set_baud_rate(19200).unwrap();
+        assert_eq!(port.baud_rate().unwrap(), 19200);
+
+        port.set_data_bits(DataBits::Seven).unwrap();
+        assert_eq!(port.data_bits().unwrap(), DataBits::Seven);
+
+        port.set_flow_control(FlowControl::Software).unwrap();
+        assert_eq!(port.flow_control().unwrap(), FlowControl::Software);
+
+        port.set_parity(Parity::Odd).unwrap();
         assert_eq!(port.parity().unwrap(), Parity::Odd);
 
         port.set_stop_bits(StopBits::Two).unwrap();
@@ -550,6 +2697,627 @@ mod tests {
         assert!(duration.as_millis() > 700);
     }
 
+    #[test]
+    fn test_data_arrives_over_time() {
+        let (mut port1, mut port2) = VirtualPort::pair(1200, 1024).unwrap();
+        port2.set_simulate_delay(true);
+
+        // At 1200 baud a byte takes about 8 ms
+        port1.write_all(b"0123456789").unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+        std::thread::sleep(Duration::from_millis(45));
+        let arrived = port2.bytes_to_read().unwrap();
+        assert!((3..10).contains(&arrived), "{arrived} bytes arrived");
+
+        // A read returns the arrived data without waiting for the rest
+        let mut read_data = [0u8; 10];
+        let len = port2.try_read(&mut read_data).unwrap();
+        assert_eq!(&read_data[..len], &b"0123456789"[..len]);
+        port2.read_exact(&mut read_data[len..]).unwrap();
+        assert_eq!(&read_data, b"0123456789");
+    }
+
+    #[test]
+    fn test_delay_interrupted() {
+        use std::time::Instant;
+
+        // At 300 baud the data takes more than 3 seconds to arrive
+        let (mut port1, mut port2) = VirtualPort::pair(300, 1024).unwrap();
+        port2.set_simulate_delay(true);
+        port2.set_timeout(Duration::from_millis(50)).unwrap();
+        port1.write_all(&[0x55; 100]).unwrap();
+
+        // A read doesn't wait past its timeout
+        let mut read_data = [0u8; 100];
+        let start = Instant::now();
+        assert_eq!(
+            port2.read_exact(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Nor past a disconnection
+        port2.set_timeout(Duration::MAX).unwrap();
+        let disconnector = port1.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            disconnector.disconnect();
+        });
+        assert_eq!(
+            port2.read_exact(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_tx_delay_simulation() {
+        let (mut port1, port2) = VirtualPort::pair(1200, 1024).unwrap();
+        port1.set_simulate_tx_delay(true);
+        assert!(port1.simulate_tx_delay());
+
+        // At 1200 baud a byte takes about 8 ms
+        port1.write_all(b"0123456789").unwrap();
+        assert!(port1.bytes_to_write().unwrap() > 5);
+        assert!(port2.bytes_to_read().unwrap() < 5);
+
+        // Flushing waits for the transmitter to drain
+        port1.set_timeout(Duration::from_millis(10)).unwrap();
+        assert_eq!(port1.flush().unwrap_err().kind(), io::ErrorKind::TimedOut);
+        port1.set_timeout(Duration::from_secs(1)).unwrap();
+        port1.flush().unwrap();
+        assert_eq!(port1.bytes_to_write().unwrap(), 0);
+        assert_eq!(port2.bytes_to_read().unwrap(), 10);
+    }
+
+    #[test]
+    fn test_transmit_gaps() {
+        use std::time::Instant;
+
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.set_frame_gap(Some((4, Duration::from_millis(50))));
+        assert_eq!(port.frame_gap(), Some((4, Duration::from_millis(50))));
+
+        // The second frame follows after the gap
+        port.write_all(b"abcdefgh").unwrap();
+        assert_eq!(port.bytes_to_read().unwrap(), 4);
+        let mut read_data = [0u8; 8];
+        let start = Instant::now();
+        port.read_exact(&mut read_data).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(&read_data, b"abcdefgh");
+
+        port.set_frame_gap(None);
+        port.set_inter_byte_gap(Duration::from_millis(20));
+        port.write_all(b"abc").unwrap();
+        let start = Instant::now();
+        port.read_exact(&mut read_data[..3]).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        use std::{sync::mpsc, time::Instant};
+
+        let clock = Arc::new(ManualClock::new());
+        let (mut port1, mut port2) = VirtualPort::pair(300, 1024).unwrap();
+        port1.set_clock(clock.clone());
+        port2.set_simulate_delay(true);
+
+        // A byte takes about 33 ms at 300 baud, which only passes on advance
+        port1.write_all(b"abcd").unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+        clock.advance(Duration::from_millis(70));
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+
+        // The timeout expires in simulated time, without waiting for real
+        let start = Instant::now();
+        port2.set_timeout(Duration::from_secs(10)).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut read_data = [0u8; 8];
+            sender.send(port2.read_exact(&mut read_data)).unwrap();
+        });
+        let result = loop {
+            clock.advance(Duration::from_secs(1));
+            if let Ok(result) = receiver.recv_timeout(Duration::from_millis(10)) {
+                break result;
+            }
+        };
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_link_latency() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut port1, mut port2) = VirtualPort::pair(300, 1024).unwrap();
+        port1.set_clock(clock.clone());
+        port2.set_link_latency(Duration::from_millis(100));
+        assert_eq!(port2.link_latency(), Duration::from_millis(100));
+        assert_eq!(
+            port1.outgoing_conditions().latency,
+            Duration::from_millis(100)
+        );
+
+        // The data arrives after the latency, even without the transmission
+        // delay
+        port1.write_all(b"ab").unwrap();
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+
+        // Only the data received by port2 is delayed
+        port2.write_all(b"cd").unwrap();
+        assert_eq!(port1.bytes_to_read().unwrap(), 2);
+
+        // The latency adds to the transmission delay of about 33 ms per byte
+        port2.set_simulate_delay(true);
+        port1.write_all(b"ef").unwrap();
+        clock.advance(Duration::from_millis(120));
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(port2.bytes_to_read().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_clock(clock.clone());
+        port2.set_noise_seed(7);
+        port2.set_link_latency(Duration::from_millis(100));
+        port2.set_latency_jitter(Jitter::Uniform(0.5));
+        port2.set_timing_jitter(Jitter::Normal(0.2));
+        assert_eq!(port2.latency_jitter(), Jitter::Uniform(0.5));
+        assert_eq!(
+            port1.outgoing_conditions().timing_jitter,
+            Jitter::Normal(0.2)
+        );
+
+        // The latency varies within the bounds, and the bytes stay in order
+        let data: Vec<u8> = (0..64).collect();
+        port1.write_all(&data).unwrap();
+        clock.advance(Duration::from_millis(49));
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+        clock.advance(Duration::from_millis(101));
+        let mut read_data = [0u8; 64];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data[..], &data[..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "jitter fraction")]
+    fn test_invalid_jitter() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.set_latency_jitter(Jitter::Uniform(1.5));
+    }
+
+    #[test]
+    fn test_half_duplex() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_clock(clock.clone());
+        port1.set_simulate_delay(true);
+        port2.set_simulate_delay(true);
+        port1.set_half_duplex(Some(HalfDuplex::new(Duration::from_millis(10))));
+        assert_eq!(
+            port2.half_duplex(),
+            Some(HalfDuplex::new(Duration::from_millis(10)))
+        );
+
+        // The answer waits for the request (about 2 ms) and the turnaround
+        port1.write_all(b"ab").unwrap();
+        port2.write_all(b"c").unwrap();
+        clock.advance(Duration::from_millis(12));
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+        assert_eq!(port1.bytes_to_read().unwrap(), 0);
+        clock.advance(Duration::from_millis(2));
+        assert_eq!(port1.bytes_to_read().unwrap(), 1);
+
+        let mut read_data = [0u8; 2];
+        port1.read_exact(&mut read_data[..1]).unwrap();
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ab");
+
+        // Overlapping transmissions garble both directions
+        port1.set_half_duplex(Some(HalfDuplex::new(Duration::ZERO).with_collisions(true)));
+        port1.set_report_line_errors(true);
+        port2.set_report_line_errors(true);
+        port1.write_all(b"ab").unwrap();
+        port2.write_all(b"cd").unwrap();
+        clock.advance(Duration::from_millis(5));
+        for port in [&mut port1, &mut port2] {
+            assert!(port.read(&mut read_data[..1]).is_err());
+            assert!(port.read(&mut read_data[..1]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_byte_loss() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_timeout(Duration::from_millis(50)).unwrap();
+        assert_eq!(port2.byte_loss(), ByteLoss::None);
+
+        // Only the data received by port2 is affected
+        port2.set_byte_loss(ByteLoss::EveryNth(4));
+        assert_eq!(port1.byte_loss(), ByteLoss::None);
+
+        // Lost bytes are reported as written
+        assert_eq!(port1.write(b"abcdefgh").unwrap(), 8);
+        assert_eq!(port2.bytes_to_read().unwrap(), 6);
+
+        let mut read_data = [0u8; 6];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcefg");
+
+        port2.set_byte_loss(ByteLoss::None);
+        port1.write_all(b"mnop").unwrap();
+        let mut read_data = [0u8; 4];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"mnop");
+    }
+
+    #[test]
+    fn test_byte_duplication_and_insertion() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+
+        // Faults are configured independently for each direction
+        port1.set_byte_duplication(1.0);
+        port2.set_byte_insertion(1.0);
+        assert_eq!(port1.byte_insertion(), 0.0);
+        assert_eq!(port2.byte_duplication(), 0.0);
+
+        port2.write_all(b"ab").unwrap();
+        let mut read_data = [0u8; 4];
+        port1.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"aabb");
+
+        port1.write_all(b"ab").unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 4);
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!([read_data[1], read_data[3]], *b"ab");
+    }
+
+    #[test]
+    fn test_noise_seed() {
+        fn damage(seed: u64) -> Vec<u8> {
+            let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+            port2.set_noise_seed(seed);
+            port2.set_byte_loss(ByteLoss::Random(0.3));
+            port2.set_byte_insertion(0.3);
+            port2.set_noise_on_config_mismatch(true);
+
+            port1.write_all(&[0x55; 64]).unwrap();
+            let mut received = vec![0u8; port2.bytes_to_read().unwrap() as usize];
+            port2.read_exact(&mut received).unwrap();
+
+            port2.set_baud_rate(19200).unwrap();
+            port1.write_all(&[0x55; 8]).unwrap();
+            let mut noise = vec![0u8; port2.bytes_to_read().unwrap() as usize];
+            port2.read_exact(&mut noise).unwrap();
+
+            received.extend(noise);
+            received
+        }
+
+        assert_eq!(damage(42), damage(42));
+        assert_ne!(damage(42), damage(43));
+    }
+
+    #[test]
+    fn test_report_line_errors() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_report_line_errors(true);
+        port2.set_parity(Parity::Even).unwrap();
+        port1.set_parity(Parity::Even).unwrap();
+
+        // Every other byte is corrupted
+        port2.set_burst_errors(Some(GilbertElliott::new(1.0, 1.0)));
+        port1.write_all(b"abcd").unwrap();
+
+        let mut read_data = [0u8; 4];
+        let err = port2.read(&mut read_data).unwrap_err();
+        assert_eq!(LineError::from_io(&err), Some(LineError::Parity));
+        assert_eq!(port2.read(&mut read_data).unwrap(), 1);
+        assert_eq!(read_data[0], b'b');
+
+        // Peeking reports the error without consuming the damaged byte
+        assert_eq!(
+            port2.peek(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            port2.try_read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(port2.try_read(&mut read_data).unwrap(), 1);
+        assert_eq!(read_data[0], b'd');
+
+        // Without a parity check the corruption goes unnoticed
+        port2.set_parity(Parity::None).unwrap();
+        port1.set_parity(Parity::None).unwrap();
+        port1.write_all(b"ab").unwrap();
+        assert_eq!(port2.read(&mut read_data[..2]).unwrap(), 2);
+        assert_ne!(read_data[0], b'a');
+    }
+
+    #[test]
+    fn test_link_drop() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_link_drop(LinkDrop::AfterBytes(4));
+
+        // The bytes accepted before the link dropped are lost with it
+        assert_eq!(port1.write(b"abcdef").unwrap(), 4);
+        assert!(!port1.is_connected());
+        assert!(!port2.is_connected());
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+
+        assert_eq!(
+            port1.write(b"ef").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert_eq!(
+            port2.write(b"ef").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        let mut read_data = [0u8; 2];
+        assert_eq!(
+            port1.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+
+        // A blocked reader is woken up by the disconnection
+        port2.reconnect();
+        let reader = std::thread::spawn(move || {
+            let kind = port2.read(&mut read_data).unwrap_err().kind();
+            (port2, kind)
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        port1.disconnect();
+        let (_port2, kind) = reader.join().unwrap();
+        assert_eq!(kind, io::ErrorKind::NotConnected);
+
+        port1.reconnect();
+        assert_eq!(port1.link_drop(), LinkDrop::Never);
+        port1.write_all(b"ef").unwrap();
+    }
+
+    #[test]
+    fn test_disconnect_drops_lines() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let events = port1.subscribe_control_events();
+        assert!(port1.read_carrier_detect().unwrap());
+
+        port2.disconnect();
+        assert!(!port1.read_carrier_detect().unwrap());
+        assert!(!port1.read_data_set_ready().unwrap());
+        assert!(!port2.read_clear_to_send().unwrap());
+        assert_eq!(events.try_iter().count(), 3);
+
+        port1.reconnect();
+        assert!(port1.read_carrier_detect().unwrap());
+        assert!(port2.read_clear_to_send().unwrap());
+        assert_eq!(events.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn test_silent_disconnect() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_disconnect_behavior(DisconnectBehavior::Silent);
+        port1.set_timeout(Duration::from_millis(20)).unwrap();
+        port1.disconnect();
+
+        // The data written by port1 vanishes, and its reads time out
+        assert_eq!(port1.write(b"lost").unwrap(), 4);
+        let mut read_data = [0u8; 4];
+        assert_eq!(
+            port1.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+
+        // port2 still notices
+        assert_eq!(
+            port2.write(b"ab").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        port1.reconnect();
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+        port2.write_all(b"ab").unwrap();
+        assert_eq!(port1.read(&mut read_data[..2]).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_overrun_error() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 4).unwrap();
+        assert_eq!(port2.overrun_policy(), OverrunPolicy::Block);
+        assert_eq!(port1.write(b"abcdef").unwrap(), 4);
+        port2.clear(ClearBuffer::Input).unwrap();
+
+        // The writer is not held back, and the lost data is accounted
+        port2.set_buffer_full_policy(BufferFullPolicy::Error);
+        assert_eq!(port2.overrun_policy(), OverrunPolicy::Error);
+        port1.write_all(b"abcdef").unwrap();
+        port1.wait_writable(Some(Duration::ZERO)).unwrap();
+
+        let mut read_data = [0u8; 4];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+        assert_eq!(
+            port2.receive_stats(),
+            ReceiveStats {
+                received: 10,
+                dropped: 2,
+                overruns: 1,
+            }
+        );
+
+        port2.reset_receive_stats();
+        assert_eq!(port2.receive_stats(), ReceiveStats::default());
+    }
+
+    #[test]
+    fn test_port_stats() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_timeout(Duration::ZERO).unwrap();
+        port1.set_parity(Parity::Even).unwrap();
+        port2.set_parity(Parity::Even).unwrap();
+        port2.set_report_line_errors(true);
+        port2.set_fault_schedule(FaultSchedule::new().corrupt_at(0, 1));
+
+        port1.write_all(b"abc").unwrap();
+        port1.write_all(b"de").unwrap();
+        let mut read_data = [0u8; 8];
+        let err = port2.read(&mut read_data).unwrap_err();
+        assert_eq!(LineError::from_io(&err), Some(LineError::Parity));
+        assert_eq!(
+            port2.read(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert_eq!(port2.read(&mut read_data[..1]).unwrap(), 1);
+
+        assert_eq!(
+            port1.stats(),
+            PortStats {
+                bytes_transmitted: 5,
+                writes: 2,
+                ..PortStats::default()
+            }
+        );
+        let stats = port2.stats();
+        assert_eq!((stats.bytes_received, stats.reads), (5, 1));
+        assert_eq!(
+            (stats.faults.bits_flipped, stats.faults.errors_raised),
+            (1, 1)
+        );
+        assert_eq!((stats.timeouts, stats.line_errors), (1, 1));
+
+        port2.reset_stats();
+        assert_eq!(port2.stats(), PortStats::default());
+        assert_eq!(port2.receive_stats(), ReceiveStats::default());
+    }
+
+    #[test]
+    fn test_fault_schedule() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_fault_schedule(FaultSchedule::new().drop_at(2, 3).disconnect_at(8));
+
+        port1.write_all(b"abcdefg").unwrap();
+        let mut read_data = [0u8; 4];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abfg");
+
+        assert_eq!(port1.write(b"hij").unwrap(), 1);
+        assert!(!port2.is_connected());
+
+        // The injected faults are accounted exactly
+        assert_eq!(
+            port2.stats().faults,
+            FaultStats {
+                bytes_dropped: 3,
+                link_drops: 1,
+                ..FaultStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_asymmetric_conditions() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+
+        let lossy = LinkConditions {
+            simulate_delay: true,
+            byte_loss: ByteLoss::EveryNth(2),
+            ..LinkConditions::default()
+        };
+        port1.set_outgoing_conditions(lossy.clone());
+        assert_eq!(port2.incoming_conditions(), lossy);
+        assert!(port2.simulate_delay());
+        assert_eq!(port1.incoming_conditions(), LinkConditions::default());
+
+        port1.write_all(b"abcd").unwrap();
+        port2.write_all(b"abcd").unwrap();
+        assert_eq!(port1.bytes_to_read().unwrap(), 4);
+        let mut read_data = [0u8; 2];
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 0);
+
+        port2.set_incoming_conditions(LinkConditions::default());
+        assert_eq!(port1.outgoing_conditions(), LinkConditions::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "loss probability")]
+    fn test_invalid_byte_loss() {
+        let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.set_byte_loss(ByteLoss::Random(1.5));
+    }
+
+    #[test]
+    fn test_split_baud_rates() {
+        let clock = Arc::new(ManualClock::new());
+        let (mut port1, mut port2) = VirtualPort::pair(1200, 1024).unwrap();
+        port1.set_clock(clock.clone());
+        port1.set_split_baud_rates(1200, 75);
+        port2.set_split_baud_rates(75, 1200);
+        assert_eq!(port1.rx_baud_rate(), 75);
+        assert_eq!(port2.baud_rate().unwrap(), 75);
+        for port in [&mut port1, &mut port2] {
+            port.set_simulate_delay(true);
+            port.set_noise_on_config_mismatch(true);
+            port.set_report_line_errors(true);
+        }
+
+        // A byte takes about 8 ms at 1200 baud and 133 ms at 75 baud
+        port1.write_all(b"ab").unwrap();
+        port2.write_all(b"ab").unwrap();
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+        assert_eq!(port1.bytes_to_read().unwrap(), 0);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(port1.bytes_to_read().unwrap(), 2);
+
+        // Both directions match, so the data is intact
+        let mut read_data = [0u8; 2];
+        port1.read_exact(&mut read_data).unwrap();
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ab");
+
+        // Only the direction whose rates differ is noisy
+        port2.set_baud_rate(1200).unwrap();
+        assert_eq!(port2.rx_baud_rate(), 1200);
+        port1.write_all(b"c").unwrap();
+        port2.write_all(b"c").unwrap();
+        clock.advance(Duration::from_millis(150));
+        assert_eq!(port2.read(&mut read_data[..1]).unwrap(), 1);
+        assert!(port1.read(&mut read_data[..1]).is_err());
+    }
+
+    #[test]
+    fn test_noise_follows_peer_settings() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port2.set_noise_on_config_mismatch(true);
+        port2.set_report_line_errors(true);
+        let mut reader = port2.clone();
+        let mut read_data = [0u8; 2];
+
+        // The noise decision made by the first read is redone once the peer
+        // changes its settings
+        port1.write_all(b"ok").unwrap();
+        reader.read_exact(&mut read_data).unwrap();
+        port1.set_parity(Parity::Even).unwrap();
+        port1.write_all(b"ok").unwrap();
+        let err = reader.read_exact(&mut read_data).unwrap_err();
+        assert_eq!(LineError::from_io(&err), Some(LineError::Parity));
+
+        port1.set_parity(Parity::None).unwrap();
+        port1.write_all(b"ok").unwrap();
+        reader.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"ok");
+    }
+
     #[test]
     fn test_noise_on_config_mismatch() {
         let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
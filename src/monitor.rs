@@ -0,0 +1,316 @@
+//! Monitoring and splitting the traffic of ports.
+//!
+//! A monitor is a third, read-only port attached to a pair, which receives a
+//! copy of the data sent in both directions, as a protocol analyzer hooked to
+//! the line would. A listener receives a copy of the data sent by a single
+//! port, as the extra receivers of a splitter cable do. Neither end of the
+//! pair notices them.
+
+use std::{
+    sync::mpsc::{self, Receiver},
+    time::Instant,
+};
+
+use serialport::{Error, ErrorKind, Result};
+
+use crate::{capture::CaptureTap, pipe::Pipe, VirtualPort};
+
+/// End of a monitored pair.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PairEnd {
+    /// The first port returned by [`VirtualPort::pair_with_monitor`].
+    First,
+
+    /// The second port returned by [`VirtualPort::pair_with_monitor`].
+    Second,
+}
+
+/// Chunk of data sent through a monitored pair, delivered to the receivers
+/// returned by [`VirtualPort::subscribe_traffic`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Traffic {
+    /// The end of the pair which sent the data.
+    pub from: PairEnd,
+
+    /// The data as it was sent, before the faults of the monitor.
+    pub data: Vec<u8>,
+
+    /// When the data was sent.
+    pub timestamp: Instant,
+}
+
+/// Direction of a [`TrafficEvent`], from the point of view of the observed
+/// port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrafficDirection {
+    /// The port wrote the data.
+    Sent,
+
+    /// The port received the data.
+    Received,
+}
+
+/// Chunk of data sent or received by a port, delivered to the receivers
+/// returned by [`VirtualPort::monitor`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrafficEvent {
+    /// Whether the port sent or received the data.
+    pub direction: TrafficDirection,
+
+    /// The data as it was sent, before the faults of the receiver.
+    pub data: Vec<u8>,
+
+    /// When the data was sent.
+    pub timestamp: Instant,
+}
+
+impl VirtualPort {
+    /// Opens a pair of connected virtual ports like
+    /// [`pair`](VirtualPort::pair), and a third port monitoring it.
+    ///
+    /// The monitor receives the data sent in both directions, interleaved as
+    /// it is written, while its writes fail with `PermissionDenied`. Like a
+    /// receiving port, it has its own buffer, timing and faults, and never
+    /// holds the writers back: the data which doesn't fit in its buffer is
+    /// lost. [`subscribe_traffic`](VirtualPort::subscribe_traffic) tells the
+    /// directions apart.
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// use virtual_serialport::{PairEnd, VirtualPort};
+    ///
+    /// let (mut port1, mut port2, mut monitor) = VirtualPort::pair_with_monitor(9600, 1024).unwrap();
+    /// let traffic = monitor.subscribe_traffic();
+    ///
+    /// port1.write_all(b"ping").unwrap();
+    /// port2.write_all(b"pong").unwrap();
+    ///
+    /// let mut read_data = [0u8; 8];
+    /// monitor.read_exact(&mut read_data).unwrap();
+    /// assert_eq!(&read_data, b"pingpong");
+    ///
+    /// let chunk = traffic.recv().unwrap();
+    /// assert_eq!((chunk.from, chunk.data), (PairEnd::First, b"ping".to_vec()));
+    /// ```
+    pub fn pair_with_monitor(baud_rate: u32, buffer_capacity: usize) -> Result<(Self, Self, Self)> {
+        let (port1, port2) = Self::pair(baud_rate, buffer_capacity)?;
+        let monitor = Self::with_own_lines(baud_rate, Pipe::monitor(buffer_capacity))?;
+        port1.pipe.add_tap(&monitor.pipe, Some(PairEnd::First));
+        port2.pipe.add_tap(&monitor.pipe, Some(PairEnd::Second));
+        Ok((port1, port2, monitor))
+    }
+
+    /// Opens a port whose transmit line is split to `listeners` ports, as
+    /// with a Y cable: the first listener is connected to it as with
+    /// [`pair`](VirtualPort::pair), and the others are read-only listeners
+    /// (see [`add_listener`](VirtualPort::add_listener)). Fails with
+    /// `ErrorKind::InvalidInput` if there are no listeners.
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// use virtual_serialport::VirtualPort;
+    ///
+    /// let (mut port, mut listeners) = VirtualPort::splitter(9600, 1024, 2).unwrap();
+    /// port.write_all(b"log").unwrap();
+    ///
+    /// for listener in &mut listeners {
+    ///     let mut read_data = [0u8; 3];
+    ///     listener.read_exact(&mut read_data).unwrap();
+    ///     assert_eq!(&read_data, b"log");
+    /// }
+    /// ```
+    pub fn splitter(
+        baud_rate: u32,
+        buffer_capacity: usize,
+        listeners: usize,
+    ) -> Result<(Self, Vec<Self>)> {
+        if listeners == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a splitter needs at least one listener",
+            ));
+        }
+
+        let (port, primary) = Self::pair(baud_rate, buffer_capacity)?;
+        let mut ports = vec![primary];
+        for _ in 1..listeners {
+            ports.push(port.add_listener()?);
+        }
+        Ok((port, ports))
+    }
+
+    /// Wires the transmit line of this port to a new read-only port as well,
+    /// which receives a copy of everything this port sends from now on, with
+    /// the baud rate of this port and the capacity of its receive buffer.
+    ///
+    /// Like a receiving port, the listener has its own timing and faults, but
+    /// it never holds this port back: the data which doesn't fit in its
+    /// buffer is lost. Its writes fail with `PermissionDenied`.
+    pub fn add_listener(&self) -> Result<Self> {
+        let baud_rate = self.config.lock().unwrap().baud_rate;
+        let listener = Self::with_own_lines(baud_rate, Pipe::monitor(self.pipe.capacity()))?;
+        self.pipe.add_tap(&listener.pipe, None);
+        Ok(listener)
+    }
+
+    /// Subscribes to the data arriving at a monitor port (see
+    /// [`pair_with_monitor`](VirtualPort::pair_with_monitor)), tagged with
+    /// the end of the pair which sent it. Other ports, including the
+    /// listeners, deliver nothing.
+    pub fn subscribe_traffic(&self) -> Receiver<Traffic> {
+        self.pipe.subscribe_traffic()
+    }
+
+    /// Observes the data written and received by this port from now on,
+    /// without wrapping it: each chunk is delivered to the returned receiver
+    /// as it is written, timestamped with the clock of the port, so another
+    /// thread can watch the traffic concurrently. The observation stops once
+    /// the receiver is dropped.
+    ///
+    /// ```
+    /// use std::io::Write;
+    ///
+    /// use virtual_serialport::{TrafficDirection, VirtualPort};
+    ///
+    /// let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+    /// let traffic = port1.monitor();
+    ///
+    /// port1.write_all(b"ping").unwrap();
+    /// port2.write_all(b"pong").unwrap();
+    ///
+    /// let events: Vec<_> = traffic
+    ///     .try_iter()
+    ///     .map(|event| (event.direction, event.data))
+    ///     .collect();
+    /// assert_eq!(
+    ///     events,
+    ///     [
+    ///         (TrafficDirection::Sent, b"ping".to_vec()),
+    ///         (TrafficDirection::Received, b"pong".to_vec()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn monitor(&self) -> Receiver<TrafficEvent> {
+        let (sender, receiver) = mpsc::channel();
+        let (outbound, inbound) = CaptureTap::channel(sender);
+        self.pipe.add_capture(outbound, inbound);
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        time::Duration,
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_monitor() {
+        let (mut port1, mut port2, mut monitor) = VirtualPort::pair_with_monitor(9600, 4).unwrap();
+        let traffic = monitor.subscribe_traffic();
+
+        // The monitor doesn't hold the writers back, and loses what doesn't
+        // fit in its buffer
+        port1.write_all(b"abc").unwrap();
+        port2.write_all(b"defg").unwrap();
+        let mut read_data = [0u8; 4];
+        port2.read_exact(&mut read_data[..3]).unwrap();
+        assert_eq!(&read_data[..3], b"abc");
+        assert_eq!(monitor.bytes_to_read().unwrap(), 4);
+        assert_eq!(monitor.receive_stats().dropped, 3);
+        monitor.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+
+        let from: Vec<_> = traffic.try_iter().map(|chunk| chunk.from).collect();
+        assert_eq!(from, [PairEnd::First, PairEnd::Second]);
+
+        // The monitor is read-only
+        assert_eq!(
+            monitor.write(b"x").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        // The pair keeps working without the monitor
+        drop(monitor);
+        port1.set_timeout(Duration::from_millis(10)).unwrap();
+        port1.read_exact(&mut read_data).unwrap();
+        port2.write_all(b"hi").unwrap();
+        port1.read_exact(&mut read_data[..2]).unwrap();
+        assert_eq!(&read_data[..2], b"hi");
+    }
+
+    #[test]
+    fn test_monitor_address_filter() {
+        let (mut master, mut slave, mut monitor) =
+            VirtualPort::pair_with_monitor(9600, 1024).unwrap();
+        let traffic = monitor.subscribe_traffic();
+        slave.set_address_filter(Some(0x42));
+
+        // The monitor sees the frames addressed to other slaves too
+        master.write_address(0x17).unwrap();
+        master.write_all(b"other").unwrap();
+        assert_eq!(slave.bytes_to_read().unwrap(), 0);
+        assert_eq!(monitor.bytes_to_read().unwrap(), 6);
+        let mut read_data = [0u8; 6];
+        monitor.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"\x17other");
+        let data: Vec<u8> = traffic.try_iter().flat_map(|chunk| chunk.data).collect();
+        assert_eq!(data, b"\x17other");
+    }
+
+    #[test]
+    fn test_splitter() {
+        let (mut port, mut listeners) = VirtualPort::splitter(9600, 1024, 3).unwrap();
+        assert_eq!(listeners.len(), 3);
+        port.write_all(b"abc").unwrap();
+        for listener in &mut listeners {
+            assert_eq!(listener.bytes_to_read().unwrap(), 3);
+        }
+
+        // Only the first listener is wired back, and a late listener only
+        // gets the data sent after it was added
+        listeners[0].write_all(b"ok").unwrap();
+        assert_eq!(port.bytes_to_read().unwrap(), 2);
+        assert_eq!(
+            listeners[1].write(b"no").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        let mut late = port.add_listener().unwrap();
+        port.write_all(b"d").unwrap();
+        let mut read_data = [0u8; 1];
+        late.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"d");
+    }
+
+    #[test]
+    fn test_monitor_port() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let traffic = port2.monitor();
+        let observer = std::thread::spawn(move || {
+            traffic
+                .iter()
+                .take(2)
+                .map(|event| (event.direction, event.data))
+                .collect::<Vec<_>>()
+        });
+
+        port1.write_all(b"ping").unwrap();
+        port2.write_all(b"pong").unwrap();
+        let mut read_data = [0u8; 4];
+        port1.read_exact(&mut read_data).unwrap();
+        assert_eq!(
+            observer.join().unwrap(),
+            [
+                (TrafficDirection::Received, b"ping".to_vec()),
+                (TrafficDirection::Sent, b"pong".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,2633 @@
+//! In-memory transport used by virtual ports.
+//!
+//! A [`Pipe`] is one endpoint of a link. It reads from its own receive
+//! channel and writes into the receive channel of the peer (for a loopback
+//! both channels are the same). Blocked readers and writers are woken up
+//! through a condition variable, and async tasks through registered wakers.
+
+use std::{
+    collections::VecDeque,
+    hint, io,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Weak,
+    },
+    task::Waker,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+use std::task::{Context, Poll};
+
+use serialport::FlowControl;
+
+use crate::{
+    bus::Attachment,
+    capture::CaptureTap,
+    clock::{Clock, SystemClock},
+    control::ControlLine,
+    event_log::FaultEvent,
+    fault::{FaultStats, Faults, Jitter, LineError},
+    monitor::{PairEnd, Traffic},
+    sync::{Condvar, Mutex, MutexGuard},
+};
+
+#[cfg(feature = "log")]
+use crate::hexdump;
+#[cfg(feature = "metrics")]
+use crate::telemetry;
+
+#[cfg(any(
+    all(any(feature = "mio", feature = "raw-fd"), unix),
+    all(feature = "raw-fd", windows)
+))]
+use crate::readiness::{Notifier, RawReadiness};
+
+/// Behavior of a receive buffer when the data written by the peer doesn't
+/// fit, set with
+/// [`VirtualPort::set_overrun_policy`](crate::VirtualPort::set_overrun_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverrunPolicy {
+    /// The writer is held back: a write accepts only the bytes that fit,
+    /// and fails with `WouldBlock` while the buffer is full (the default).
+    Block,
+
+    /// The bytes that don't fit are discarded, and the writer sees them as
+    /// written.
+    DropNewest,
+
+    /// The oldest buffered bytes are discarded to make room for the new ones.
+    DropOldest,
+
+    /// As with a hardware overrun, the bytes that don't fit are discarded and
+    /// an overrun error is recorded in the [`ReceiveStats`].
+    Error,
+}
+
+/// Behavior of the reads and the writes of a port while its link is down, set
+/// with
+/// [`VirtualPort::set_disconnect_behavior`](crate::VirtualPort::set_disconnect_behavior).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DisconnectBehavior {
+    /// Reads fail with `NotConnected` and writes with `BrokenPipe`, as with
+    /// an adapter which disappears from the system (the default).
+    Fail,
+
+    /// Reads wait for data until the timeout and written data vanishes, as
+    /// with a UART whose cable is unplugged.
+    Silent,
+}
+
+/// How a blocking operation of a port waits, set with
+/// [`VirtualPort::set_wait_strategy`](crate::VirtualPort::set_wait_strategy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WaitStrategy {
+    /// The thread sleeps until the port changes (the default), which costs
+    /// no CPU time but adds the latency of waking it up.
+    Park,
+
+    /// The thread yields to the other threads between the checks of the
+    /// port.
+    Yield,
+
+    /// The thread checks the port in a busy loop, for the lowest latency at
+    /// the cost of a CPU core.
+    Spin,
+}
+
+impl Default for WaitStrategy {
+    fn default() -> Self {
+        Self::Park
+    }
+}
+
+/// Counters of the data received by a port, returned by
+/// [`VirtualPort::receive_stats`](crate::VirtualPort::receive_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReceiveStats {
+    /// Number of bytes which arrived, including the dropped ones.
+    pub received: u64,
+
+    /// Number of bytes discarded because the receive buffer was full.
+    pub dropped: u64,
+
+    /// Number of overrun errors (writes which didn't fit) recorded with
+    /// [`OverrunPolicy::Error`].
+    pub overruns: u64,
+}
+
+/// Counters of the flow control applied to the data sent by a port, returned
+/// by [`VirtualPort::flow_control_stats`](crate::VirtualPort::flow_control_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlowControlStats {
+    /// Number of times the transmission was paused by XOFF from the peer.
+    pub xoff_pauses: u64,
+
+    /// Number of times the transmission was paused by a deasserted CTS.
+    pub cts_pauses: u64,
+
+    /// Total time the transmission was paused, including an ongoing pause.
+    pub paused_time: Duration,
+
+    /// Number of bytes written after being held back by flow control.
+    pub delayed_bytes: u64,
+}
+
+/// Counters of the activity of a port, returned by
+/// [`VirtualPort::stats`](crate::VirtualPort::stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortStats {
+    /// Number of bytes accepted by the writes.
+    pub bytes_transmitted: u64,
+
+    /// Number of bytes which arrived, including the dropped ones.
+    pub bytes_received: u64,
+
+    /// Number of successful reads, including the ones which returned no
+    /// data.
+    pub reads: u64,
+
+    /// Number of successful writes.
+    pub writes: u64,
+
+    /// Number of reads and writes which failed with `TimedOut`.
+    pub timeouts: u64,
+
+    /// Number of overrun errors recorded with [`OverrunPolicy::Error`].
+    pub overruns: u64,
+
+    /// Number of line errors (e.g., parity or framing errors, as injected by
+    /// the faults) reported to the reads.
+    pub line_errors: u64,
+
+    /// Number of times the transmission was paused by flow control.
+    pub flow_control_pauses: u64,
+
+    /// Counters of the faults injected into the data received by the port.
+    pub faults: FaultStats,
+}
+
+/// Half-duplex operation of a link, set with
+/// [`VirtualPort::set_half_duplex`](crate::VirtualPort::set_half_duplex).
+///
+/// Only one end transmits at a time, as on an RS-485 bus or an IR link: the
+/// data written while the other end transmits waits until it is done and
+/// the line has turned around, or collides with it.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use virtual_serialport::HalfDuplex;
+///
+/// let mode = HalfDuplex::new(Duration::from_millis(2)).with_collisions(true);
+/// assert!(mode.collisions);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HalfDuplex {
+    /// Time the line needs to change direction after the last byte sent the
+    /// other way.
+    pub turnaround: Duration,
+
+    /// Whether a transmission starting while the other end transmits
+    /// collides with it instead of waiting, garbling the data of both
+    /// directions from then on.
+    pub collisions: bool,
+}
+
+impl HalfDuplex {
+    /// Creates a mode with the given turnaround delay, where transmissions
+    /// wait for the line instead of colliding.
+    pub fn new(turnaround: Duration) -> Self {
+        Self {
+            turnaround,
+            collisions: false,
+        }
+    }
+
+    /// Sets whether overlapping transmissions collide.
+    pub fn with_collisions(mut self, collisions: bool) -> Self {
+        self.collisions = collisions;
+        self
+    }
+}
+
+// Line shared by both directions of a half-duplex link
+struct HalfDuplexLine {
+    mode: HalfDuplex,
+
+    // Side which transmitted last, and when its last byte was sent
+    sender: Option<usize>,
+    busy_until: Instant,
+}
+
+/// Time to transmit one byte, kept as an exact fraction of nanoseconds so
+/// that high baud rates don't round down to nothing and the rounding errors
+/// of successive bytes don't add up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ByteTime {
+    nanos: u64,
+    baud_rate: u64,
+}
+
+impl ByteTime {
+    /// Returns the time to transmit `bits_per_byte` bits at `baud_rate`.
+    pub(crate) fn new(bits_per_byte: u32, baud_rate: u32) -> Self {
+        Self {
+            nanos: u64::from(bits_per_byte) * 1_000_000_000,
+            baud_rate: u64::from(baud_rate.max(1)),
+        }
+    }
+
+    // Returns the time to transmit the next byte, carrying the fraction of a
+    // nanosecond left over by the previous ones in `remainder`
+    fn next(&self, remainder: &mut u64) -> Duration {
+        let nanos = self.nanos + *remainder;
+        *remainder = nanos % self.baud_rate;
+        Duration::from_nanos(nanos / self.baud_rate)
+    }
+
+    /// Returns the time to transmit `len` bytes.
+    pub(crate) fn duration(&self, len: usize) -> Duration {
+        let nanos = u128::from(self.nanos) * len as u128 / u128::from(self.baud_rate);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+}
+
+// Data travelling in one direction
+struct Buffer {
+    // Bytes written but not read yet
+    data: VecDeque<u8>,
+
+    // Maximum number of bytes the buffer can hold
+    capacity: usize,
+
+    // Time to transmit one byte if the transmission delay is simulated, and
+    // the idle time the writer leaves after each byte and after every given
+    // number of bytes (with the number of bytes sent since the last such gap)
+    byte_time: Option<ByteTime>,
+    byte_time_remainder: u64,
+    inter_byte_gap: Duration,
+    frame_gap: Option<(usize, Duration)>,
+    frame_position: usize,
+
+    // Time the bytes take to propagate once transmitted, and the random
+    // variation of the time to transmit and to propagate each byte
+    latency: Duration,
+    timing_jitter: Jitter,
+    latency_jitter: Jitter,
+
+    // Number of bytes at the front of the buffer which have arrived, the
+    // arrival times of the bytes in flight after them, and when the line is
+    // free to transmit the next byte
+    arrived: usize,
+    arrivals: VecDeque<Instant>,
+    line_free: Instant,
+
+    // Whether a background thread wakes up the waiters as the bytes in flight
+    // arrive
+    scheduled: bool,
+
+    // Source of the time of the arrivals, the timeouts and the pauses
+    clock: Arc<dyn Clock>,
+
+    // Line shared with the opposite direction if the link is half-duplex,
+    // and the side of it which writes into the buffer
+    half_duplex: Option<(Arc<Mutex<HalfDuplexLine>>, usize)>,
+
+    // Damage applied to the data written into the buffer
+    faults: Faults,
+
+    // Whether the link is down, and how the reader and the writer behave
+    // while it is
+    disconnected: bool,
+    reader_disconnect: DisconnectBehavior,
+    writer_disconnect: DisconnectBehavior,
+
+    // Whether every handle of the writer or of the reader has been dropped,
+    // and the error failing the reads once the remaining data is read,
+    // `None` meaning end of file
+    closed: bool,
+    hangup_error: Option<io::ErrorKind>,
+
+    // Whether the writer only listens, failing its writes
+    listen_only: bool,
+
+    // Whether the reader only transmits, failing its reads and receiving
+    // nothing
+    transmit_only: bool,
+
+    // Subscribers to the data arriving at a monitor, tagged with its sender
+    traffic: Vec<Sender<Traffic>>,
+
+    // Captures recording the data put in the buffer
+    captures: Vec<CaptureTap>,
+
+    // Subscribers to the faults met by the data of the buffer
+    fault_subscribers: Vec<Sender<(Instant, FaultEvent)>>,
+
+    // Name under which the traffic of the reader is logged, if any
+    #[cfg(feature = "log")]
+    log_name: Option<String>,
+
+    // Label of the metrics of the reader, if they are published
+    #[cfg(feature = "metrics")]
+    metrics_label: Option<String>,
+
+    // Flow control of the writer and of the reader
+    writer_flow_control: FlowControl,
+    reader_flow_control: FlowControl,
+
+    // RTS output of the reader (the CTS input of the writer), which pauses a
+    // writer using hardware flow control while deasserted, and whether it is
+    // held deasserted automatically as the buffer crossed the high watermark
+    // (until it drains to the low one)
+    request_to_send: bool,
+    rts_held: bool,
+
+    // DTR output of the reader, and the output of the reader wired to the CTS
+    // input of the writer, if any
+    data_terminal_ready: bool,
+    cts_wiring: Option<ControlLine>,
+
+    // Level of the CTS input forced on the writer regardless of the reader
+    forced_cts: Option<bool>,
+    rts_watermarks: Option<(usize, usize)>,
+
+    // Whether the reader has sent XOFF, which pauses a writer using software
+    // flow control until XON, and whether it was sent automatically on
+    // crossing the high watermark (and is withdrawn below the low one)
+    xoff: bool,
+    xoff_auto: bool,
+    xoff_watermarks: Option<(usize, usize)>,
+
+    // Whether the writer holds the line in a break condition, whether the
+    // reader has received a break not queried yet, and whether the reader
+    // receives a break as a `0x00` byte
+    break_sent: bool,
+    break_received: bool,
+    break_as_data: bool,
+
+    // Address which wakes up the reader on a multidrop link, and whether it
+    // is awake
+    address_filter: Option<u8>,
+    addressed: bool,
+
+    // Flow control counters of the writer, the start of the ongoing pause,
+    // and whether a write was held back by the pause
+    flow_control_stats: FlowControlStats,
+    paused_since: Option<Instant>,
+    write_held: bool,
+
+    // Handling of the data which doesn't fit, and the counters of the data
+    // written into the buffer
+    overrun_policy: OverrunPolicy,
+    stats: ReceiveStats,
+
+    // Counters of the reads and the writes of the endpoint reading the buffer
+    port_stats: PortStats,
+
+    // Tasks waiting for the buffer state to change
+    wakers: Vec<Waker>,
+
+    // Pending timers of the clock waking up the tasks waiting for the data in
+    // flight, one per task
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    timers: Vec<(Instant, Waker)>,
+
+    // Readiness mirrors of the reading and the writing endpoint, created on
+    // first use
+    #[cfg(any(
+        all(any(feature = "mio", feature = "raw-fd"), unix),
+        all(feature = "raw-fd", windows)
+    ))]
+    reader_notifier: Option<Arc<Mutex<Notifier>>>,
+    #[cfg(any(
+        all(any(feature = "mio", feature = "raw-fd"), unix),
+        all(feature = "raw-fd", windows)
+    ))]
+    writer_notifier: Option<Arc<Mutex<Notifier>>>,
+}
+
+impl Buffer {
+    fn free(&self) -> usize {
+        self.capacity.saturating_sub(self.data.len())
+    }
+
+    // Whether the writer is paused by flow control
+    fn paused(&self) -> bool {
+        match self.writer_flow_control {
+            FlowControl::None => false,
+            FlowControl::Software => self.xoff,
+            FlowControl::Hardware => !self.clear_to_send(),
+        }
+    }
+
+    // Accounts the start or the end of a pause
+    fn track_pause(&mut self) {
+        match (self.paused(), self.paused_since) {
+            (true, None) => {
+                self.paused_since = Some(self.clock.now());
+                match self.writer_flow_control {
+                    FlowControl::Software => self.flow_control_stats.xoff_pauses += 1,
+                    FlowControl::Hardware => self.flow_control_stats.cts_pauses += 1,
+                    FlowControl::None => {}
+                }
+            }
+            (false, Some(since)) => {
+                self.flow_control_stats.paused_time +=
+                    self.clock.now().saturating_duration_since(since);
+                self.paused_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    // The CTS input of the writer
+    fn clear_to_send(&self) -> bool {
+        self.forced_cts.unwrap_or(match self.cts_wiring {
+            Some(ControlLine::Rts) => self.request_to_send && !self.rts_held,
+            Some(ControlLine::Dtr) => self.data_terminal_ready,
+            _ => false,
+        })
+    }
+
+    // Sends XOFF (or XON) on behalf of the reader
+    fn set_xoff(&mut self, xoff: bool, auto: bool) {
+        self.xoff = xoff;
+        self.xoff_auto = xoff && auto;
+    }
+
+    // Sends XOFF or deasserts RTS automatically once the buffered data
+    // reaches the high watermark, and withdraws it once the data drains to the
+    // low one
+    fn check_watermarks(&mut self) {
+        let len = self.data.len();
+        match (
+            self.reader_flow_control,
+            self.xoff_watermarks,
+            self.rts_watermarks,
+        ) {
+            (FlowControl::Software, Some((high, low)), _) => {
+                if !self.xoff && len >= high {
+                    self.set_xoff(true, true);
+                } else if self.xoff_auto && len <= low {
+                    self.set_xoff(false, true);
+                }
+            }
+            (FlowControl::Hardware, _, Some((high, low))) => {
+                if len >= high {
+                    self.rts_held = true;
+                } else if len <= low {
+                    self.rts_held = false;
+                }
+            }
+            _ => {
+                if self.xoff_auto {
+                    self.set_xoff(false, true);
+                }
+                self.rts_held = false;
+            }
+        }
+    }
+
+    // Whether a write can proceed: the writer is not paused and the buffer
+    // has room or the excess data is discarded, or the write fails as the
+    // link is down
+    fn accepts_writes(&self) -> bool {
+        let room = self.free() > 0 || self.overrun_policy != OverrunPolicy::Block;
+        (room && !self.paused()) || self.disconnected || self.closed || self.listen_only
+    }
+
+    fn clear(&mut self) {
+        self.faults.marks.consume(self.data.len());
+        self.data.clear();
+        self.arrived = 0;
+        self.arrivals.clear();
+    }
+
+    // Removes `len` bytes from the front of the buffer
+    fn drain_front(&mut self, len: usize) {
+        self.data.drain(..len);
+        self.faults.marks.consume(len);
+        let arrived = len.min(self.arrived);
+        self.arrived -= arrived;
+        self.arrivals.drain(..len - arrived);
+    }
+
+    // Returns whether the reader takes the characters (address bytes if
+    // `address` is set), following the address it is woken up by
+    fn accepts_frames(&mut self, data: &[u8], address: bool) -> bool {
+        let filter = match self.address_filter {
+            Some(filter) => filter,
+            None => return true,
+        };
+        if address {
+            self.addressed = data.last() == Some(&filter);
+        }
+        self.addressed
+    }
+
+    // Marks the bytes stored from `position` on as address bytes
+    fn mark_addresses(&mut self, position: usize) {
+        for position in position..self.data.len() {
+            self.faults.marks.push_address(position);
+        }
+    }
+
+    // Discards the data exceeding the capacity according to the overrun
+    // policy, returning the number of discarded bytes. A buffer holding more
+    // than its capacity before the last `stored` bytes (as it was shrunk)
+    // keeps its data, but doesn't take more.
+    fn limit_to_capacity(&mut self, stored: usize) -> usize {
+        let limit = self.capacity.max(stored);
+        let excess = self.data.len().saturating_sub(limit);
+        if excess > 0 {
+            match self.overrun_policy {
+                OverrunPolicy::DropOldest => self.drain_front(excess),
+                _ => self.truncate(limit),
+            }
+            self.stats.dropped += excess as u64;
+            if self.overrun_policy == OverrunPolicy::Error {
+                self.stats.overruns += 1;
+            }
+            self.publish_fault(FaultEvent::Dropped(excess));
+            #[cfg(feature = "metrics")]
+            if let Some(label) = &self.metrics_label {
+                telemetry::error(label, "overrun");
+            }
+        }
+        excess
+    }
+
+    // Notifies the subscribers of a fault, forgetting the ones which are gone
+    fn publish_fault(&mut self, fault: FaultEvent) {
+        if self.fault_subscribers.is_empty() {
+            return;
+        }
+        let now = self.clock.now();
+        self.fault_subscribers
+            .retain(|sender| sender.send((now, fault)).is_ok());
+    }
+
+    // Shortens the buffer to `len` bytes
+    fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.faults.marks.truncate(len);
+        if len < self.arrived {
+            self.arrived = len;
+            self.arrivals.clear();
+        } else {
+            self.arrivals.truncate(len - self.arrived);
+        }
+    }
+
+    // Delivers the data in flight at once if the data no longer takes time to
+    // arrive (the bytes in flight otherwise keep their arrival times)
+    fn update_timing(&mut self) {
+        if !self.timed() {
+            self.arrived = self.data.len();
+            self.arrivals.clear();
+        }
+    }
+
+    // Whether the data takes time to arrive
+    fn timed(&self) -> bool {
+        self.byte_time.is_some()
+            || self.inter_byte_gap > Duration::ZERO
+            || self.frame_gap.is_some()
+            || self.latency > Duration::ZERO
+            || self.half_duplex.is_some()
+    }
+
+    // Schedules the arrival of the last `len` bytes of the buffer. On a
+    // half-duplex link, the transmission waits for the line to turn around,
+    // or collides with the other direction, garbling the bytes sent before
+    // the other end is done; returns when the collision started, if any.
+    fn send(&mut self, len: usize) -> Option<Instant> {
+        if !self.timed() {
+            self.arrived += len;
+            return None;
+        }
+
+        let now = self.clock.now();
+        let half_duplex = self.half_duplex.clone();
+        let mut line = half_duplex
+            .as_ref()
+            .map(|(line, side)| (line.lock().unwrap(), *side));
+
+        let mut start = self.line_free.max(now);
+        let mut collided_until = None;
+        if let Some((line, side)) = &mut line {
+            match line.sender {
+                Some(sender) if sender == *side => {}
+                Some(_) if line.mode.collisions && line.busy_until > start => {
+                    collided_until = Some(line.busy_until);
+                }
+                Some(_) => start = start.max(line.busy_until + line.mode.turnaround),
+                None => {}
+            }
+            line.sender = Some(*side);
+        }
+        self.line_free = start;
+
+        let first = self.data.len() - len;
+        let mut end = start;
+        for position in first..first + len {
+            let byte_time = match &self.byte_time {
+                Some(byte_time) => byte_time.next(&mut self.byte_time_remainder),
+                None => Duration::ZERO,
+            };
+            let rng = &mut *self.faults.rng;
+            let byte_time = self.timing_jitter.apply(byte_time, rng);
+            let begin = self.line_free.max(now);
+            let transmitted = begin + byte_time;
+            end = transmitted;
+
+            // The bytes arrive in order even if the latency varies
+            let arrival = transmitted + self.latency_jitter.apply(self.latency, rng);
+            if collided_until.map_or(false, |until| begin < until) {
+                self.garble(position);
+            }
+            let arrival = self
+                .arrivals
+                .back()
+                .map_or(arrival, |&last| last.max(arrival));
+            self.arrivals.push_back(arrival);
+
+            let mut gap = self.inter_byte_gap;
+            self.frame_position += 1;
+            if let Some((frame_len, frame_gap)) = self.frame_gap {
+                if self.frame_position >= frame_len {
+                    self.frame_position = 0;
+                    gap += frame_gap;
+                }
+            }
+            self.line_free = transmitted + gap;
+        }
+
+        if let Some((line, _)) = &mut line {
+            line.busy_until = line.busy_until.max(end);
+        }
+        collided_until.map(|_| start)
+    }
+
+    // Replaces the byte at `position` with noise, or marks it with a framing
+    // error if errors are reported
+    fn garble(&mut self, position: usize) {
+        if self.faults.report_errors() {
+            self.faults.marks.insert(position, LineError::Framing);
+            self.faults.stats.errors_raised += 1;
+        } else {
+            let mut noise = [0];
+            self.faults.fill_with_noise(&mut noise);
+            self.data[position] = noise[0];
+        }
+    }
+
+    // Garbles the bytes in flight which arrive after `since`
+    fn garble_in_flight(&mut self, since: Instant) {
+        let first = self.arrived + self.arrivals.partition_point(|&arrival| arrival <= since);
+        for position in first..self.data.len() {
+            self.garble(position);
+        }
+    }
+
+    // Number of bytes which have arrived by `now` and can be read
+    fn available_at(&self, now: Instant) -> usize {
+        self.arrived + self.arrivals.partition_point(|&arrival| arrival <= now)
+    }
+
+    fn available(&self) -> usize {
+        self.available_at(self.clock.now())
+    }
+
+    // Whether a byte with a line error has arrived
+    fn error_arrived(&self) -> bool {
+        matches!(self.faults.marks.first(), Some((position, _)) if position < self.available())
+    }
+
+    // Accounts for the bytes which have arrived so far
+    fn settle(&mut self) {
+        let arrived = self.available() - self.arrived;
+        self.arrivals.drain(..arrived);
+        self.arrived += arrived;
+    }
+
+    // Returns when the next byte in flight arrives
+    fn next_arrival(&self) -> Option<Instant> {
+        let now = self.clock.now();
+        self.arrivals.iter().copied().find(|&arrival| arrival > now)
+    }
+
+    // Whether the reads fail as the link is down or the reader only transmits
+    fn read_fails(&self) -> bool {
+        (self.disconnected && self.reader_disconnect == DisconnectBehavior::Fail)
+            || self.transmit_only
+    }
+
+    // Fails with `PermissionDenied` if the reader only transmits, or with
+    // `NotConnected` if the link is down, unless the reader doesn't notice
+    fn check_readable(&self) -> io::Result<()> {
+        if self.transmit_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "port is write-only",
+            ));
+        }
+        if self.read_fails() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "link is disconnected",
+            ));
+        }
+        Ok(())
+    }
+
+    // Fails with `BrokenPipe` if the link is down, unless the writer doesn't
+    // notice
+    fn check_writer_connected(&self) -> io::Result<()> {
+        if self.disconnected && self.writer_disconnect == DisconnectBehavior::Fail {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "link is disconnected",
+            ));
+        }
+        Ok(())
+    }
+
+    // Whether the writer is gone and all its data has arrived, so no more
+    // data will become available
+    fn hung_up(&self) -> bool {
+        self.closed && self.available() == self.data.len()
+    }
+
+    // Fails with the hangup error if the writer is gone and all its data has
+    // been read
+    fn check_hangup(&self) -> io::Result<()> {
+        match self.hangup_error {
+            Some(kind) if self.closed && self.data.is_empty() => {
+                Err(io::Error::new(kind, "peer port is closed"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Fails with `PermissionDenied` if the writer only listens
+    fn check_writable(&self) -> io::Result<()> {
+        if self.listen_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "port is read-only",
+            ));
+        }
+        Ok(())
+    }
+
+    // Fails with `BrokenPipe` if the reader is gone
+    fn check_reader_open(&self) -> io::Result<()> {
+        if self.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "peer port is closed",
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(any(
+        all(any(feature = "mio", feature = "raw-fd"), unix),
+        all(feature = "raw-fd", windows)
+    ))]
+    fn update_notifiers(&self) {
+        if let Some(notifier) = &self.reader_notifier {
+            notifier
+                .lock()
+                .unwrap()
+                .set_readable(self.available() > 0 || self.read_fails() || self.hung_up());
+        }
+        if let Some(notifier) = &self.writer_notifier {
+            notifier.lock().unwrap().set_writable(self.accepts_writes());
+        }
+    }
+
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    fn register(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    // Arranges for `waker` to be woken once the time reaches `deadline`. A
+    // task polled again keeps its pending timer, unless the deadline moves
+    // earlier.
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    fn wake_at(&mut self, deadline: Instant, waker: &Waker) {
+        let now = self.clock.now();
+        self.timers.retain(|(pending, _)| *pending > now);
+        match self.timers.iter_mut().find(|(_, w)| w.will_wake(waker)) {
+            Some((pending, _)) if *pending <= deadline => return,
+            Some(timer) => *timer = (deadline, waker.clone()),
+            None => self.timers.push((deadline, waker.clone())),
+        }
+        self.clock.wake_at(deadline, waker);
+    }
+}
+
+// Largest buffer allocated upfront, larger buffers grow with the data
+const MAX_PREALLOCATED: usize = 64 * 1024;
+
+// A buffer shared between a writer and a reader
+struct Channel {
+    buffer: Mutex<Buffer>,
+
+    // Signalled whenever the buffer content changes
+    changed: Condvar,
+}
+
+impl Channel {
+    fn new(capacity: usize) -> Self {
+        Self {
+            buffer: Mutex::new(Buffer {
+                data: VecDeque::with_capacity(capacity.min(MAX_PREALLOCATED)),
+                capacity,
+                byte_time: None,
+                byte_time_remainder: 0,
+                inter_byte_gap: Duration::ZERO,
+                frame_gap: None,
+                frame_position: 0,
+                latency: Duration::ZERO,
+                timing_jitter: Jitter::None,
+                latency_jitter: Jitter::None,
+                arrived: 0,
+                arrivals: VecDeque::new(),
+                line_free: Instant::now(),
+                scheduled: false,
+                clock: Arc::new(SystemClock),
+                half_duplex: None,
+                faults: Faults::new(),
+                disconnected: false,
+                reader_disconnect: DisconnectBehavior::Fail,
+                writer_disconnect: DisconnectBehavior::Fail,
+                closed: false,
+                hangup_error: None,
+                listen_only: false,
+                transmit_only: false,
+                traffic: Vec::new(),
+                fault_subscribers: Vec::new(),
+                captures: Vec::new(),
+                #[cfg(feature = "log")]
+                log_name: None,
+                #[cfg(feature = "metrics")]
+                metrics_label: None,
+                writer_flow_control: FlowControl::None,
+                reader_flow_control: FlowControl::None,
+                request_to_send: true,
+                rts_held: false,
+                data_terminal_ready: true,
+                cts_wiring: Some(ControlLine::Rts),
+                forced_cts: None,
+                rts_watermarks: None,
+                xoff: false,
+                xoff_auto: false,
+                xoff_watermarks: None,
+                break_sent: false,
+                break_received: false,
+                break_as_data: false,
+                address_filter: None,
+                addressed: false,
+                flow_control_stats: FlowControlStats::default(),
+                paused_since: None,
+                write_held: false,
+                overrun_policy: OverrunPolicy::Block,
+                stats: ReceiveStats::default(),
+                port_stats: PortStats::default(),
+                wakers: Vec::new(),
+                #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+                timers: Vec::new(),
+                #[cfg(any(
+                    all(any(feature = "mio", feature = "raw-fd"), unix),
+                    all(feature = "raw-fd", windows)
+                ))]
+                reader_notifier: None,
+                #[cfg(any(
+                    all(any(feature = "mio", feature = "raw-fd"), unix),
+                    all(feature = "raw-fd", windows)
+                ))]
+                writer_notifier: None,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap()
+    }
+
+    // Wakes up both blocked threads and async tasks waiting on the buffer
+    fn notify(&self, buffer: &mut Buffer) {
+        buffer.track_pause();
+        buffer.wakers.drain(..).for_each(Waker::wake);
+        #[cfg(any(
+            all(any(feature = "mio", feature = "raw-fd"), unix),
+            all(feature = "raw-fd", windows)
+        ))]
+        buffer.update_notifiers();
+        self.changed.notify_all();
+    }
+
+    // Blocks while `condition` holds, failing with `TimedOut` once `timeout`
+    // expires (`None` means waiting indefinitely). With a clock which doesn't
+    // run in real time, the waiters are woken up as it advances instead.
+    // Unless `strategy` parks the thread, it checks `condition` in a loop.
+    fn wait_while(
+        &self,
+        strategy: WaitStrategy,
+        timeout: Option<Duration>,
+        mut condition: impl FnMut(&Buffer) -> bool,
+    ) -> io::Result<MutexGuard<'_, Buffer>> {
+        let mut buffer = self.lock();
+        let deadline = timeout.and_then(|timeout| buffer.clock.now().checked_add(timeout));
+
+        while condition(&buffer) {
+            if !buffer.clock.can_wait() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "operation would wait in a simulation",
+                ));
+            }
+            let now = buffer.clock.now();
+            if deadline.map_or(false, |deadline| now >= deadline) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "operation timed out",
+                ));
+            }
+
+            // Wake up as the next byte in flight arrives as well, instead of
+            // waiting for the background thread delivering the bytes, which
+            // batches them
+            let wakeup = match (deadline, buffer.next_arrival()) {
+                (Some(deadline), Some(arrival)) => Some(deadline.min(arrival)),
+                (deadline, arrival) => deadline.or(arrival),
+            };
+            buffer = match (strategy, wakeup) {
+                (WaitStrategy::Park, Some(wakeup)) if buffer.clock.is_real_time() => {
+                    self.changed.wait_timeout(buffer, wakeup - now).unwrap().0
+                }
+                (WaitStrategy::Park, _) => self.changed.wait(buffer).unwrap(),
+                (WaitStrategy::Yield, _) => {
+                    drop(buffer);
+                    thread::yield_now();
+                    self.lock()
+                }
+                (WaitStrategy::Spin, _) => {
+                    drop(buffer);
+                    hint::spin_loop();
+                    self.lock()
+                }
+            };
+        }
+        Ok(buffer)
+    }
+
+    // Switches the channel to `clock`, keeping the arrival times of the bytes
+    // in flight
+    fn set_clock(self: &Arc<Self>, clock: Arc<dyn Clock>) {
+        let mut buffer = self.lock();
+        let now = clock.now();
+        buffer.line_free = now;
+        if buffer.paused_since.is_some() {
+            buffer.paused_since = Some(now);
+        }
+        buffer.clock = clock.clone();
+        self.notify(&mut buffer);
+        schedule_arrivals(self, &mut buffer);
+        drop(buffer);
+
+        // Wakes up the waiters whenever the time moves, until the channel is
+        // gone
+        let channel = Arc::downgrade(self);
+        clock.on_advance(Box::new(move || {
+            Weak::upgrade(&channel).map_or(false, |channel| {
+                let mut buffer = channel.lock();
+                channel.notify(&mut buffer);
+                true
+            })
+        }));
+    }
+}
+
+// Closes both directions of the link once the last handle of an endpoint is
+// dropped, so the other endpoint sees a hangup. The channel the endpoint
+// writes into is left open if other endpoints write into it too.
+struct Endpoint {
+    rx: Arc<Channel>,
+    tx: Option<Arc<Channel>>,
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        for channel in std::iter::once(&self.rx).chain(&self.tx) {
+            let mut buffer = channel.lock();
+            buffer.closed = true;
+            channel.notify(&mut buffer);
+        }
+    }
+}
+
+// Receive channel of a monitor or a listener, and the end of the monitored
+// pair the writing endpoint is, if any
+type Tap = (Weak<Channel>, Option<PairEnd>);
+
+/// Endpoint attached to a bus, as seen by the other members, which doesn't
+/// keep it alive.
+#[derive(Clone)]
+pub(crate) struct BusMember {
+    rx: Weak<Channel>,
+    taps: Arc<Mutex<Vec<Tap>>>,
+}
+
+impl BusMember {
+    /// Makes the members receive the data of each other.
+    pub(crate) fn link(&self, other: &BusMember) {
+        self.taps.lock().unwrap().push((other.rx.clone(), None));
+        other.taps.lock().unwrap().push((self.rx.clone(), None));
+    }
+
+    /// Stops the members receiving the data of each other.
+    pub(crate) fn unlink(&self, other: &BusMember) {
+        self.taps
+            .lock()
+            .unwrap()
+            .retain(|(tap, _)| !tap.ptr_eq(&other.rx));
+        other
+            .taps
+            .lock()
+            .unwrap()
+            .retain(|(tap, _)| !tap.ptr_eq(&self.rx));
+    }
+}
+
+/// One endpoint of an in-memory link.
+#[derive(Clone)]
+pub(crate) struct Pipe {
+    rx: Arc<Channel>,
+    tx: Arc<Channel>,
+
+    // Shared by the clones of the endpoint
+    _endpoint: Arc<Endpoint>,
+
+    // Receive channels of the monitors and the listeners which get a copy of
+    // the data written by the endpoint
+    taps: Arc<Mutex<Vec<Tap>>>,
+
+    // Attachment to a bus, detaching the endpoint once it is dropped
+    bus: Option<Arc<Attachment>>,
+
+    // Read and blocking write timeout, `None` means waiting indefinitely
+    timeout: Option<Duration>,
+
+    // Whether writes wait for the peer to accept data instead of failing
+    // with `WouldBlock`
+    blocking_writes: bool,
+
+    // Whether timed-out reads return the data which arrived instead of
+    // failing with `TimedOut`
+    partial_reads: bool,
+
+    // How the blocking operations wait
+    wait_strategy: WaitStrategy,
+}
+
+impl Pipe {
+    /// Creates an endpoint which reads back everything written to it.
+    pub(crate) fn loopback(capacity: usize) -> Self {
+        let channel = Arc::new(Channel::new(capacity));
+        Self {
+            rx: channel.clone(),
+            tx: channel.clone(),
+            _endpoint: Arc::new(Endpoint {
+                rx: channel.clone(),
+                tx: Some(channel),
+            }),
+            taps: Arc::default(),
+            bus: None,
+            timeout: None,
+            blocking_writes: false,
+            partial_reads: false,
+            wait_strategy: WaitStrategy::Park,
+        }
+    }
+
+    /// Creates an endpoint which only listens, receiving the data fed by
+    /// [`add_tap`](Pipe::add_tap) and failing its writes.
+    pub(crate) fn monitor(capacity: usize) -> Self {
+        let rx = Arc::new(Channel::new(capacity));
+        let tx = Arc::new(Channel::new(0));
+        tx.lock().listen_only = true;
+        Self {
+            rx: rx.clone(),
+            tx: tx.clone(),
+            _endpoint: Arc::new(Endpoint { rx, tx: Some(tx) }),
+            taps: Arc::default(),
+            bus: None,
+            timeout: None,
+            blocking_writes: false,
+            partial_reads: false,
+            wait_strategy: WaitStrategy::Park,
+        }
+    }
+
+    /// Copies the data written by the endpoint to `monitor`, tagged with the
+    /// end of a monitored pair the endpoint is, if any.
+    pub(crate) fn add_tap(&self, monitor: &Pipe, from: Option<PairEnd>) {
+        self.taps
+            .lock()
+            .unwrap()
+            .push((Arc::downgrade(&monitor.rx), from));
+    }
+
+    /// Creates an endpoint attached to a bus, which sends its data to the
+    /// members it is [`link`](BusMember::link)ed to.
+    pub(crate) fn bus(capacity: usize, attachment: Attachment) -> Self {
+        let rx = Arc::new(Channel::new(capacity));
+
+        // The data written is only delivered through the taps
+        let tx = Arc::new(Channel::new(0));
+        tx.lock().overrun_policy = OverrunPolicy::DropNewest;
+
+        Self {
+            rx: rx.clone(),
+            tx: tx.clone(),
+            _endpoint: Arc::new(Endpoint { rx, tx: Some(tx) }),
+            taps: Arc::default(),
+            bus: Some(Arc::new(attachment)),
+            timeout: None,
+            blocking_writes: false,
+            partial_reads: false,
+            wait_strategy: WaitStrategy::Park,
+        }
+    }
+
+    /// Returns a handle on the endpoint as a bus member, which doesn't keep
+    /// it alive.
+    pub(crate) fn bus_member(&self) -> BusMember {
+        BusMember {
+            rx: Arc::downgrade(&self.rx),
+            taps: self.taps.clone(),
+        }
+    }
+
+    /// Returns whether the endpoint may transmit and receive.
+    pub(crate) fn directions(&self) -> (bool, bool) {
+        (!self.tx.lock().listen_only, !self.rx.lock().transmit_only)
+    }
+
+    /// Sets whether the endpoint may transmit and receive, failing the
+    /// writes or the reads with `PermissionDenied` otherwise. An endpoint
+    /// which may not receive loses the data sent to it.
+    pub(crate) fn set_directions(&self, transmit: bool, receive: bool) {
+        self.tx.lock().listen_only = !transmit;
+        let mut buffer = self.rx.lock();
+        buffer.transmit_only = !receive;
+        self.rx.notify(&mut buffer);
+    }
+
+    #[cfg(feature = "log")]
+    pub(crate) fn log_name(&self) -> Option<String> {
+        self.rx.lock().log_name.clone()
+    }
+
+    /// Sets the name under which the traffic of the endpoint is logged,
+    /// `None` disabling the logging.
+    #[cfg(feature = "log")]
+    pub(crate) fn set_log_name(&self, name: Option<String>) {
+        self.rx.lock().log_name = name;
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics_label(&self) -> Option<String> {
+        self.rx.lock().metrics_label.clone()
+    }
+
+    /// Sets the label of the metrics of the endpoint, `None` disabling them.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn set_metrics_label(&self, label: Option<String>) {
+        self.rx.lock().metrics_label = label;
+    }
+
+    /// Records the data written by the endpoint to `outbound`, and the data
+    /// it receives to `inbound`.
+    pub(crate) fn add_capture(&self, outbound: CaptureTap, inbound: CaptureTap) {
+        self.tx.lock().captures.push(outbound);
+        self.rx.lock().captures.push(inbound);
+    }
+
+    /// Returns the attachment of the endpoint to a bus, if any.
+    pub(crate) fn bus_attachment(&self) -> Option<&Attachment> {
+        self.bus.as_deref()
+    }
+
+    /// Subscribes to the data arriving at a monitor endpoint.
+    pub(crate) fn subscribe_traffic(&self) -> Receiver<Traffic> {
+        let (sender, receiver) = mpsc::channel();
+        self.rx.lock().traffic.push(sender);
+        receiver
+    }
+
+    /// Subscribes to the faults met by the data received by the endpoint,
+    /// timestamped with its clock.
+    pub(crate) fn subscribe_faults(&self) -> Receiver<(Instant, FaultEvent)> {
+        let (sender, receiver) = mpsc::channel();
+        self.rx.lock().fault_subscribers.push(sender);
+        receiver
+    }
+
+    /// Creates two connected endpoints.
+    pub(crate) fn pair(capacity: usize) -> (Self, Self) {
+        let channel1 = Arc::new(Channel::new(capacity));
+        let channel2 = Arc::new(Channel::new(capacity));
+
+        let endpoint = |rx: &Arc<Channel>, tx: &Arc<Channel>| Self {
+            rx: rx.clone(),
+            tx: tx.clone(),
+            _endpoint: Arc::new(Endpoint {
+                rx: rx.clone(),
+                tx: Some(tx.clone()),
+            }),
+            taps: Arc::default(),
+            bus: None,
+            timeout: None,
+            blocking_writes: false,
+            partial_reads: false,
+            wait_strategy: WaitStrategy::Park,
+        };
+        (
+            endpoint(&channel1, &channel2),
+            endpoint(&channel2, &channel1),
+        )
+    }
+
+    /// Creates `nodes` endpoints wired by the directed `links` between their
+    /// indices. An endpoint writes into the receive channel of the target of
+    /// its first link, and copies the data to the targets of its other links
+    /// as taps. Without links, its writes fail.
+    pub(crate) fn network(nodes: usize, links: &[(usize, usize)], capacity: usize) -> Vec<Self> {
+        let channels: Vec<_> = (0..nodes)
+            .map(|_| Arc::new(Channel::new(capacity)))
+            .collect();
+        let mut targets = vec![Vec::new(); nodes];
+        for &(from, to) in links {
+            if !targets[from].contains(&to) {
+                targets[from].push(to);
+            }
+        }
+        let mut writers = vec![0; nodes];
+        for &to in targets.iter().filter_map(|targets| targets.first()) {
+            writers[to] += 1;
+        }
+
+        (0..nodes)
+            .map(|node| {
+                let rx = channels[node].clone();
+                let (tx, shared) = match targets[node].first() {
+                    Some(&to) => (channels[to].clone(), writers[to] > 1),
+                    None => {
+                        let tx = Arc::new(Channel::new(0));
+                        tx.lock().listen_only = true;
+                        (tx, false)
+                    }
+                };
+                let taps = targets[node]
+                    .iter()
+                    .skip(1)
+                    .map(|&to| (Arc::downgrade(&channels[to]), None))
+                    .collect();
+
+                Self {
+                    rx: rx.clone(),
+                    tx: tx.clone(),
+                    _endpoint: Arc::new(Endpoint {
+                        rx,
+                        tx: Some(tx).filter(|_| !shared),
+                    }),
+                    taps: Arc::new(Mutex::new(taps)),
+                    bus: None,
+                    timeout: None,
+                    blocking_writes: false,
+                    partial_reads: false,
+                    wait_strategy: WaitStrategy::Park,
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub(crate) fn blocking_writes(&self) -> bool {
+        self.blocking_writes
+    }
+
+    pub(crate) fn set_blocking_writes(&mut self, enabled: bool) {
+        self.blocking_writes = enabled;
+    }
+
+    pub(crate) fn partial_reads(&self) -> bool {
+        self.partial_reads
+    }
+
+    pub(crate) fn set_partial_reads(&mut self, enabled: bool) {
+        self.partial_reads = enabled;
+    }
+
+    pub(crate) fn wait_strategy(&self) -> WaitStrategy {
+        self.wait_strategy
+    }
+
+    pub(crate) fn set_wait_strategy(&mut self, strategy: WaitStrategy) {
+        self.wait_strategy = strategy;
+    }
+
+    /// Makes both directions of the link follow `clock`.
+    pub(crate) fn set_clock(&self, clock: Arc<dyn Clock>) {
+        self.rx.set_clock(clock.clone());
+        if !Arc::ptr_eq(&self.rx, &self.tx) {
+            self.tx.set_clock(clock);
+        }
+    }
+
+    /// Makes the link half-duplex, or full-duplex again if `mode` is `None`.
+    pub(crate) fn set_half_duplex(&self, mode: Option<HalfDuplex>) {
+        let busy_until = self.now();
+        let line = mode.map(|mode| {
+            Arc::new(Mutex::new(HalfDuplexLine {
+                mode,
+                sender: None,
+                busy_until,
+            }))
+        });
+        for (side, channel) in [&self.rx, &self.tx].into_iter().enumerate() {
+            let mut buffer = channel.lock();
+            buffer.half_duplex = line.clone().map(|line| (line, side));
+            buffer.update_timing();
+            channel.notify(&mut buffer);
+        }
+    }
+
+    pub(crate) fn half_duplex(&self) -> Option<HalfDuplex> {
+        let buffer = self.rx.lock();
+        let (line, _) = buffer.half_duplex.as_ref()?;
+        let mode = line.lock().unwrap().mode;
+        Some(mode)
+    }
+
+    /// Returns the current time of the link.
+    pub(crate) fn now(&self) -> Instant {
+        self.rx.lock().clock.now()
+    }
+
+    /// Returns the number of bytes available for reading.
+    pub(crate) fn read_buffer_len(&self) -> usize {
+        self.rx.lock().available()
+    }
+
+    /// Returns the number of bytes written but not yet read by the peer.
+    pub(crate) fn write_buffer_len(&self) -> usize {
+        self.tx.lock().data.len()
+    }
+
+    /// Returns the number of bytes written but still in flight to the peer.
+    pub(crate) fn write_in_flight_len(&self) -> usize {
+        let buffer = self.tx.lock();
+        buffer.data.len() - buffer.available()
+    }
+
+    /// Waits until the data written has arrived at the peer, failing with
+    /// `TimedOut` once the timeout expires.
+    pub(crate) fn wait_transmitted(&self) -> io::Result<()> {
+        self.tx
+            .wait_while(self.wait_strategy, self.timeout, |buffer| {
+                buffer.available() < buffer.data.len() && !buffer.disconnected
+            })
+            .map(drop)
+    }
+
+    /// Waits until the peer has read all the data written, failing with
+    /// `TimedOut` once `timeout` expires, or with `BrokenPipe` if the link is
+    /// down or the peer is closed before reading it.
+    pub(crate) fn wait_drained(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let buffer = self.tx.wait_while(self.wait_strategy, timeout, |buffer| {
+            !buffer.data.is_empty() && !buffer.disconnected && !buffer.closed
+        })?;
+        buffer.check_writer_connected()?;
+        if !buffer.data.is_empty() {
+            buffer.check_reader_open()?;
+        }
+        Ok(())
+    }
+
+    /// Waits until the data written has arrived at the peer and takes it
+    /// out of the peer buffer, as a read of the peer would, stopping before a
+    /// byte with a line error.
+    pub(crate) fn take_transmitted(&self) -> io::Result<Vec<u8>> {
+        self.wait_transmitted()?;
+        let mut buffer = self.tx.lock();
+        let mut data = vec![0; buffer.data.len()];
+        let len = Self::take(&self.tx, &mut buffer, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Returns whether a write can proceed without `WouldBlock`.
+    #[cfg(feature = "embedded-io")]
+    pub(crate) fn is_writable(&self) -> bool {
+        self.tx.lock().accepts_writes()
+    }
+
+    /// Sets the flow control of the endpoint. With hardware flow control its
+    /// writes are paused while the RTS output of the peer is deasserted, with
+    /// software flow control while the peer has sent XOFF, and XON/XOFF
+    /// characters are filtered out of the received data.
+    pub(crate) fn set_flow_control(&self, flow_control: FlowControl) {
+        let mut buffer = self.tx.lock();
+        if buffer.writer_flow_control != flow_control {
+            buffer.writer_flow_control = flow_control;
+            buffer.set_xoff(false, false);
+        }
+        self.tx.notify(&mut buffer);
+        drop(buffer);
+
+        let mut buffer = self.rx.lock();
+        buffer.reader_flow_control = flow_control;
+        buffer.check_watermarks();
+        self.rx.notify(&mut buffer);
+    }
+
+    /// Sets the receive buffer levels at which the endpoint sends XOFF and
+    /// then XON to the peer when using software flow control.
+    pub(crate) fn set_xoff_watermarks(&self, watermarks: Option<(usize, usize)>) {
+        let mut buffer = self.rx.lock();
+        buffer.xoff_watermarks = watermarks;
+        if buffer.xoff_auto {
+            buffer.set_xoff(false, true);
+        }
+        buffer.check_watermarks();
+        self.rx.notify(&mut buffer);
+    }
+
+    /// Sets the RTS output of the endpoint, which pauses the writes of a peer
+    /// using hardware flow control while deasserted.
+    pub(crate) fn set_request_to_send(&self, level: bool) {
+        let mut buffer = self.rx.lock();
+        buffer.request_to_send = level;
+        self.rx.notify(&mut buffer);
+    }
+
+    /// Sets the DTR output of the endpoint, which pauses the writes of a peer
+    /// using hardware flow control while deasserted if wired to its CTS.
+    pub(crate) fn set_data_terminal_ready(&self, level: bool) {
+        let mut buffer = self.rx.lock();
+        buffer.data_terminal_ready = level;
+        self.rx.notify(&mut buffer);
+    }
+
+    /// Sets the output of the peer wired to the CTS input of the endpoint,
+    /// leaving the input undriven (and a writer using hardware flow control
+    /// paused) if `None`.
+    pub(crate) fn set_cts_wiring(&self, output: Option<ControlLine>) {
+        let mut buffer = self.tx.lock();
+        buffer.cts_wiring = output;
+        self.tx.notify(&mut buffer);
+    }
+
+    pub(crate) fn xoff_watermarks(&self) -> Option<(usize, usize)> {
+        self.rx.lock().xoff_watermarks
+    }
+
+    /// Sets the receive buffer levels at which the RTS output of the endpoint
+    /// is deasserted and then asserted again when using hardware flow
+    /// control.
+    pub(crate) fn set_rts_watermarks(&self, watermarks: Option<(usize, usize)>) {
+        let mut buffer = self.rx.lock();
+        buffer.rts_watermarks = watermarks;
+        buffer.rts_held = false;
+        buffer.check_watermarks();
+        self.rx.notify(&mut buffer);
+    }
+
+    pub(crate) fn rts_watermarks(&self) -> Option<(usize, usize)> {
+        self.rx.lock().rts_watermarks
+    }
+
+    /// Forces the CTS input of the endpoint to `level` regardless of the RTS
+    /// output of the peer, or lets the peer drive it again if `None`.
+    pub(crate) fn force_clear_to_send(&self, level: Option<bool>) {
+        let mut buffer = self.tx.lock();
+        buffer.forced_cts = level;
+        self.tx.notify(&mut buffer);
+    }
+
+    /// Returns whether the endpoint holds its RTS output deasserted because
+    /// its receive buffer crossed the high watermark.
+    pub(crate) fn rts_held(&self) -> bool {
+        self.rx.lock().rts_held
+    }
+
+    /// Returns whether the peer holds its RTS output deasserted because its
+    /// receive buffer crossed the high watermark.
+    pub(crate) fn peer_rts_held(&self) -> bool {
+        self.tx.lock().rts_held
+    }
+
+    /// Starts a break condition on the line to the peer. The peer receives
+    /// it once, as a `0x00` byte (marked with a line error if errors are
+    /// reported) if it asked for that, until the break is cleared.
+    pub(crate) fn set_break(&self) {
+        let mut buffer = self.tx.lock();
+        if buffer.disconnected || buffer.listen_only || buffer.break_sent {
+            return;
+        }
+
+        buffer.break_sent = true;
+        buffer.break_received = true;
+        if buffer.break_as_data && buffer.free() > 0 {
+            let position = buffer.data.len();
+            buffer.data.push_back(0);
+            buffer.send(1);
+            if buffer.faults.report_errors() {
+                buffer.faults.marks.push(position, LineError::Break);
+            }
+            buffer.stats.received += 1;
+        }
+        self.tx.notify(&mut buffer);
+        schedule_arrivals(&self.tx, &mut buffer);
+    }
+
+    /// Ends the break condition on the line to the peer.
+    pub(crate) fn clear_break(&self) {
+        self.tx.lock().break_sent = false;
+    }
+
+    /// Returns whether a break was received since the last call.
+    pub(crate) fn take_break(&self) -> bool {
+        std::mem::replace(&mut self.rx.lock().break_received, false)
+    }
+
+    pub(crate) fn break_as_data(&self) -> bool {
+        self.rx.lock().break_as_data
+    }
+
+    pub(crate) fn set_break_as_data(&self, enabled: bool) {
+        self.rx.lock().break_as_data = enabled;
+    }
+
+    /// Sets the time to transmit one byte to the endpoint, `None` meaning
+    /// that the data arrives instantly.
+    pub(crate) fn set_byte_time(&self, byte_time: Option<ByteTime>) {
+        Self::update_byte_time(&self.rx, byte_time);
+    }
+
+    /// Sets the time to transmit one byte to the peer.
+    pub(crate) fn set_peer_byte_time(&self, byte_time: Option<ByteTime>) {
+        Self::update_byte_time(&self.tx, byte_time);
+    }
+
+    fn update_byte_time(channel: &Channel, byte_time: Option<ByteTime>) {
+        let mut buffer = channel.lock();
+        if buffer.byte_time != byte_time {
+            // Starting from half a nanosecond rounds the arrival times to the
+            // nearest nanosecond
+            buffer.byte_time_remainder = byte_time.map_or(0, |byte_time| byte_time.baud_rate / 2);
+        }
+        buffer.byte_time = byte_time;
+        buffer.update_timing();
+        channel.notify(&mut buffer);
+    }
+
+    /// Sets the idle time the endpoint leaves after each byte it sends, and
+    /// after every given number of bytes.
+    pub(crate) fn set_transmit_gaps(
+        &self,
+        inter_byte_gap: Duration,
+        frame_gap: Option<(usize, Duration)>,
+    ) {
+        let mut buffer = self.tx.lock();
+        buffer.inter_byte_gap = inter_byte_gap;
+        buffer.frame_gap = frame_gap;
+        buffer.frame_position = 0;
+        buffer.update_timing();
+        self.tx.notify(&mut buffer);
+    }
+
+    /// Sets the time the data sent to the endpoint takes to propagate once
+    /// transmitted.
+    pub(crate) fn set_latency(&self, latency: Duration) {
+        Self::update_latency(&self.rx, latency);
+    }
+
+    pub(crate) fn latency(&self) -> Duration {
+        self.rx.lock().latency
+    }
+
+    /// Sets the time the data sent to the peer takes to propagate once
+    /// transmitted.
+    pub(crate) fn set_peer_latency(&self, latency: Duration) {
+        Self::update_latency(&self.tx, latency);
+    }
+
+    pub(crate) fn peer_latency(&self) -> Duration {
+        self.tx.lock().latency
+    }
+
+    fn update_latency(channel: &Channel, latency: Duration) {
+        let mut buffer = channel.lock();
+        buffer.latency = latency;
+        buffer.update_timing();
+        channel.notify(&mut buffer);
+    }
+
+    /// Sets the random variation of the time to transmit and of the latency
+    /// of each byte sent to the endpoint.
+    pub(crate) fn set_jitter(&self, timing: Jitter, latency: Jitter) {
+        let mut buffer = self.rx.lock();
+        buffer.timing_jitter = timing;
+        buffer.latency_jitter = latency;
+    }
+
+    pub(crate) fn jitter(&self) -> (Jitter, Jitter) {
+        let buffer = self.rx.lock();
+        (buffer.timing_jitter, buffer.latency_jitter)
+    }
+
+    /// Sets the random variation of the time to transmit and of the latency
+    /// of each byte sent to the peer.
+    pub(crate) fn set_peer_jitter(&self, timing: Jitter, latency: Jitter) {
+        let mut buffer = self.tx.lock();
+        buffer.timing_jitter = timing;
+        buffer.latency_jitter = latency;
+    }
+
+    pub(crate) fn peer_jitter(&self) -> (Jitter, Jitter) {
+        let buffer = self.tx.lock();
+        (buffer.timing_jitter, buffer.latency_jitter)
+    }
+
+    pub(crate) fn transmit_gaps(&self) -> (Duration, Option<(usize, Duration)>) {
+        let buffer = self.tx.lock();
+        (buffer.inter_byte_gap, buffer.frame_gap)
+    }
+
+    /// Sets the capacity of the receive buffer of the endpoint. The data it
+    /// holds is kept even if it exceeds the new capacity.
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        Self::update_capacity(&self.rx, capacity);
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.rx.lock().capacity
+    }
+
+    /// Sets the capacity of the receive buffer of the peer.
+    pub(crate) fn set_peer_capacity(&self, capacity: usize) {
+        Self::update_capacity(&self.tx, capacity);
+    }
+
+    pub(crate) fn peer_capacity(&self) -> usize {
+        self.tx.lock().capacity
+    }
+
+    fn update_capacity(channel: &Channel, capacity: usize) {
+        let mut buffer = channel.lock();
+        buffer.capacity = capacity;
+        buffer.check_watermarks();
+        channel.notify(&mut buffer);
+    }
+
+    pub(crate) fn overrun_policy(&self) -> OverrunPolicy {
+        self.rx.lock().overrun_policy
+    }
+
+    pub(crate) fn set_overrun_policy(&self, policy: OverrunPolicy) {
+        let mut buffer = self.rx.lock();
+        buffer.overrun_policy = policy;
+        self.rx.notify(&mut buffer);
+    }
+
+    pub(crate) fn flow_control_stats(&self) -> FlowControlStats {
+        let buffer = self.tx.lock();
+        let mut stats = buffer.flow_control_stats;
+        if let Some(since) = buffer.paused_since {
+            stats.paused_time += buffer.clock.now().saturating_duration_since(since);
+        }
+        stats
+    }
+
+    pub(crate) fn reset_flow_control_stats(&self) {
+        let mut buffer = self.tx.lock();
+        buffer.flow_control_stats = FlowControlStats::default();
+        if buffer.paused_since.is_some() {
+            buffer.paused_since = Some(buffer.clock.now());
+        }
+    }
+
+    pub(crate) fn receive_stats(&self) -> ReceiveStats {
+        self.rx.lock().stats
+    }
+
+    pub(crate) fn reset_receive_stats(&self) {
+        self.rx.lock().stats = ReceiveStats::default();
+    }
+
+    pub(crate) fn port_stats(&self) -> PortStats {
+        let flow_control = self.flow_control_stats();
+        let buffer = self.rx.lock();
+        PortStats {
+            bytes_received: buffer.stats.received,
+            overruns: buffer.stats.overruns,
+            flow_control_pauses: flow_control.xoff_pauses + flow_control.cts_pauses,
+            faults: buffer.faults.stats,
+            ..buffer.port_stats
+        }
+    }
+
+    /// Resets the counters of the endpoint, including the receive and the
+    /// flow control ones.
+    pub(crate) fn reset_port_stats(&self) {
+        self.reset_flow_control_stats();
+        let mut buffer = self.rx.lock();
+        buffer.stats = ReceiveStats::default();
+        buffer.port_stats = PortStats::default();
+        buffer.faults.stats = FaultStats::default();
+    }
+
+    // Counts a read or a write which timed out
+    fn count_timeout<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(err) = &result {
+            if err.kind() == io::ErrorKind::TimedOut {
+                let mut buffer = self.rx.lock();
+                buffer.port_stats.timeouts += 1;
+                #[cfg(feature = "metrics")]
+                if let Some(label) = &buffer.metrics_label {
+                    telemetry::error(label, "timeout");
+                }
+            }
+        }
+        result
+    }
+
+    // Counts a successful write
+    fn count_write(&self, result: io::Result<usize>) -> io::Result<usize> {
+        if let Ok(len) = result {
+            let mut buffer = self.rx.lock();
+            buffer.port_stats.writes += 1;
+            buffer.port_stats.bytes_transmitted += len as u64;
+        }
+        result
+    }
+
+    pub(crate) fn clear_read(&self) {
+        let mut buffer = self.rx.lock();
+        buffer.clear();
+        self.rx.notify(&mut buffer);
+    }
+
+    pub(crate) fn clear_write(&self) {
+        let mut buffer = self.tx.lock();
+        buffer.clear();
+        self.tx.notify(&mut buffer);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.clear_read();
+        self.clear_write();
+    }
+
+    /// Returns whether the link is up.
+    pub(crate) fn is_connected(&self) -> bool {
+        !self.rx.lock().disconnected
+    }
+
+    /// Returns whether the peer is closed and all its data has been read.
+    pub(crate) fn at_eof(&self) -> bool {
+        let buffer = self.rx.lock();
+        buffer.closed && buffer.data.is_empty()
+    }
+
+    /// Returns whether every handle of the peer has been dropped.
+    pub(crate) fn is_peer_closed(&self) -> bool {
+        self.rx.lock().closed
+    }
+
+    pub(crate) fn hangup_error(&self) -> Option<io::ErrorKind> {
+        self.rx.lock().hangup_error
+    }
+
+    /// Sets the error failing the reads once the peer is closed and its data
+    /// has been read, `None` meaning that they return 0 (end of file).
+    pub(crate) fn set_hangup_error(&self, kind: Option<io::ErrorKind>) {
+        self.rx.lock().hangup_error = kind;
+    }
+
+    /// Takes the link down, losing the buffered data of both directions.
+    pub(crate) fn disconnect(&self) {
+        for channel in [&self.rx, &self.tx] {
+            let mut buffer = channel.lock();
+            if !buffer.disconnected {
+                buffer.publish_fault(FaultEvent::Disconnected);
+            }
+            buffer.disconnected = true;
+            buffer.clear();
+            channel.notify(&mut buffer);
+        }
+    }
+
+    pub(crate) fn disconnect_behavior(&self) -> DisconnectBehavior {
+        self.rx.lock().reader_disconnect
+    }
+
+    /// Sets how the reads and the writes of the endpoint behave while the
+    /// link is down.
+    pub(crate) fn set_disconnect_behavior(&self, behavior: DisconnectBehavior) {
+        self.rx.lock().reader_disconnect = behavior;
+        self.tx.lock().writer_disconnect = behavior;
+    }
+
+    /// Brings the link up again.
+    pub(crate) fn reconnect(&self) {
+        for channel in [&self.rx, &self.tx] {
+            let mut buffer = channel.lock();
+            if buffer.disconnected {
+                buffer.publish_fault(FaultEvent::Reconnected);
+            }
+            buffer.disconnected = false;
+            channel.notify(&mut buffer);
+        }
+    }
+
+    /// Gives access to the faults applied to the data received by the
+    /// endpoint.
+    pub(crate) fn with_faults<R>(&self, f: impl FnOnce(&mut Faults) -> R) -> R {
+        f(&mut self.rx.lock().faults)
+    }
+
+    /// Gives access to the faults applied to the data sent by the endpoint.
+    pub(crate) fn with_peer_faults<R>(&self, f: impl FnOnce(&mut Faults) -> R) -> R {
+        f(&mut self.tx.lock().faults)
+    }
+
+    /// Returns the readiness handle of the endpoint (see the `readiness`
+    /// module), creating it on first use.
+    #[cfg(any(
+        all(any(feature = "mio", feature = "raw-fd"), unix),
+        all(feature = "raw-fd", windows)
+    ))]
+    pub(crate) fn readiness_handle(&self) -> io::Result<RawReadiness> {
+        let mut rx_buffer = self.rx.lock();
+        if let Some(notifier) = &rx_buffer.reader_notifier {
+            return Ok(notifier.lock().unwrap().as_raw());
+        }
+
+        let notifier = Arc::new(Mutex::new(Notifier::new()?));
+        let handle = notifier.lock().unwrap().as_raw();
+        rx_buffer.reader_notifier = Some(notifier.clone());
+        rx_buffer.update_notifiers();
+        drop(rx_buffer);
+
+        let mut tx_buffer = self.tx.lock();
+        tx_buffer.writer_notifier = Some(notifier);
+        tx_buffer.update_notifiers();
+
+        Ok(handle)
+    }
+
+    /// Reads the available bytes without blocking, returning 0 if there are
+    /// none.
+    pub(crate) fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.rx.lock();
+        buffer.check_readable()?;
+        buffer.check_hangup()?;
+        Self::take(&self.rx, &mut buffer, buf)
+    }
+
+    /// Copies the available bytes without removing them, stopping before a
+    /// byte with a line error. If the first byte has one, the error is
+    /// returned instead, and the byte is left for the next read.
+    pub(crate) fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let buffer = self.rx.lock();
+        buffer.check_readable()?;
+        let mut len = buf.len().min(buffer.available());
+        if let Some((position, error)) = buffer.faults.marks.first() {
+            if position == 0 && len > 0 {
+                return Err(error.into());
+            }
+            len = len.min(position);
+        }
+
+        buffer
+            .data
+            .iter()
+            .zip(&mut buf[..len])
+            .for_each(|(&src, dst)| *dst = src);
+        Ok(len)
+    }
+
+    /// Returns a copy of the bytes which have arrived, including the ones
+    /// with a line error.
+    pub(crate) fn pending(&self) -> Vec<u8> {
+        let buffer = self.rx.lock();
+        buffer
+            .data
+            .iter()
+            .take(buffer.available())
+            .copied()
+            .collect()
+    }
+
+    /// Takes the bytes which have arrived out of the buffer, including the
+    /// ones with a line error.
+    pub(crate) fn drain_pending(&self) -> Vec<u8> {
+        let mut buffer = self.rx.lock();
+        let len = buffer.available();
+        let data = buffer.data.iter().take(len).copied().collect();
+        buffer.drain_front(len);
+        buffer.check_watermarks();
+        self.rx.notify(&mut buffer);
+        data
+    }
+
+    /// Returns the number of available bytes, registering the task for
+    /// wakeup if there are none. Ready with no bytes if the link is down or
+    /// the peer is closed.
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    pub(crate) fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<usize> {
+        let mut buffer = self.rx.lock();
+        match buffer.available() {
+            0 if !buffer.read_fails() && !buffer.hung_up() => {
+                buffer.register(cx.waker());
+                if let Some(arrival) = buffer.next_arrival() {
+                    buffer.wake_at(arrival, cx.waker());
+                }
+                Poll::Pending
+            }
+            len => Poll::Ready(len),
+        }
+    }
+
+    /// Checks whether the peer buffer has free space (or the link is down),
+    /// registering the task for wakeup if it is full.
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    pub(crate) fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut buffer = self.tx.lock();
+        if !buffer.accepts_writes() {
+            buffer.register(cx.waker());
+            return Poll::Pending;
+        }
+        Poll::Ready(())
+    }
+
+    /// Checks whether the data written has arrived at the peer (or the link
+    /// is down), registering the task for wakeup until the last byte in
+    /// flight arrives otherwise.
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    pub(crate) fn poll_transmitted(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut buffer = self.tx.lock();
+        if buffer.available() < buffer.data.len() && !buffer.disconnected {
+            buffer.register(cx.waker());
+            if let Some(&departure) = buffer.arrivals.back() {
+                buffer.wake_at(departure, cx.waker());
+            }
+            return Poll::Pending;
+        }
+        Poll::Ready(())
+    }
+
+    /// Writes as many bytes as fit, registering the task for wakeup if the
+    /// peer buffer is full.
+    #[cfg(any(feature = "async", feature = "futures", feature = "embedded-io-async"))]
+    pub(crate) fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut buffer = self.tx.lock();
+        if !buffer.accepts_writes() && !buf.is_empty() {
+            buffer.write_held |= buffer.paused();
+            buffer.register(cx.waker());
+            return Poll::Pending;
+        }
+        Poll::Ready(self.count_write(self.put(buffer, buf, false)))
+    }
+
+    /// Waits until at least `min_len` bytes are available (or fails with
+    /// `TimedOut` once the timeout expires) and reads up to `buf.len()` bytes.
+    /// A byte with a line error ends the wait early, and a disconnection
+    /// fails it with `NotConnected`. Once the peer is closed, the remaining
+    /// data is read without waiting, and then the reads return 0 (end of
+    /// file) or fail with the hangup error.
+    pub(crate) fn read_min(&self, buf: &mut [u8], min_len: usize) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut buffer = self.wait_min(min_len)?;
+        Self::take(&self.rx, &mut buffer, buf)
+    }
+
+    /// Reads like [`read_min`](Pipe::read_min) into the unfilled part of
+    /// `cursor`, without initializing it first.
+    #[cfg(nightly)]
+    pub(crate) fn read_min_buf(
+        &self,
+        cursor: &mut io::BorrowedCursor<'_>,
+        min_len: usize,
+    ) -> io::Result<usize> {
+        if cursor.capacity() == 0 {
+            return Ok(0);
+        }
+
+        let mut buffer = self.wait_min(min_len)?;
+        Self::take_with(&self.rx, &mut buffer, cursor.capacity(), |_, data| {
+            cursor.append(data)
+        })
+    }
+
+    /// Reads like [`read_min`](Pipe::read_min), also returning the positions
+    /// of the address bytes among the bytes read.
+    pub(crate) fn read_min_addressed(
+        &self,
+        buf: &mut [u8],
+        min_len: usize,
+    ) -> io::Result<(usize, Vec<usize>)> {
+        if buf.is_empty() {
+            return Ok((0, Vec::new()));
+        }
+
+        let mut buffer = self.wait_min(min_len)?;
+        let addresses: Vec<_> = buffer.faults.marks.addresses(buf.len()).collect();
+        let len = Self::take(&self.rx, &mut buffer, buf)?;
+        Ok((
+            len,
+            addresses
+                .into_iter()
+                .take_while(|&position| position < len)
+                .collect(),
+        ))
+    }
+
+    // Waits until at least `min_len` bytes are available for `read_min`
+    fn wait_min(&self, min_len: usize) -> io::Result<MutexGuard<'_, Buffer>> {
+        let result = self
+            .rx
+            .wait_while(self.wait_strategy, self.timeout, |buffer| {
+                buffer.available() < min_len
+                    && !buffer.error_arrived()
+                    && !buffer.read_fails()
+                    && !buffer.hung_up()
+            });
+        let buffer = match result {
+            // Like most drivers, return the data which arrived before the
+            // timeout, only failing if there is none
+            Err(err) if err.kind() == io::ErrorKind::TimedOut && self.partial_reads => {
+                let buffer = self.rx.lock();
+                if buffer.available() == 0 {
+                    drop(buffer);
+                    return self.count_timeout(Err(err));
+                }
+                buffer
+            }
+            result => self.count_timeout(result)?,
+        };
+        buffer.check_readable()?;
+        buffer.check_hangup()?;
+        Ok(buffer)
+    }
+
+    /// Waits until data is available for reading or the peer is closed,
+    /// failing with `TimedOut` once `timeout` expires, or with `NotConnected`
+    /// if the link is down.
+    pub(crate) fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.rx
+            .wait_while(self.wait_strategy, timeout, |buffer| {
+                buffer.available() == 0 && !buffer.read_fails() && !buffer.hung_up()
+            })?
+            .check_readable()
+    }
+
+    /// Waits until the peer buffer has free space, failing with `TimedOut`
+    /// once `timeout` expires, or with `BrokenPipe` if the link is down or the
+    /// peer is closed.
+    pub(crate) fn wait_writable(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let buffer = self.tx.wait_while(self.wait_strategy, timeout, |buffer| {
+            !buffer.accepts_writes()
+        })?;
+        buffer.check_writable()?;
+        buffer.check_writer_connected()?;
+        buffer.check_reader_open()
+    }
+
+    // Moves up to `buf.len()` bytes out of the buffer, stopping before a byte
+    // with a line error. If the first byte has one, it is discarded and the
+    // error is returned instead.
+    fn take(channel: &Channel, buffer: &mut Buffer, buf: &mut [u8]) -> io::Result<usize> {
+        Self::take_with(channel, buffer, buf.len(), |offset, data| {
+            buf[offset..offset + data.len()].copy_from_slice(data);
+        })
+    }
+
+    // Takes like `take` up to `max_len` bytes, passing them to `copy` with
+    // their offset in one or two slices
+    fn take_with(
+        channel: &Channel,
+        buffer: &mut Buffer,
+        max_len: usize,
+        mut copy: impl FnMut(usize, &[u8]),
+    ) -> io::Result<usize> {
+        buffer.settle();
+        let mut len = max_len.min(buffer.arrived);
+        if let Some((position, error)) = buffer.faults.marks.first() {
+            if position == 0 && len > 0 {
+                buffer.data.pop_front();
+                buffer.arrived -= 1;
+                buffer.faults.marks.consume(1);
+                buffer.port_stats.line_errors += 1;
+                buffer.publish_fault(FaultEvent::LineError(error));
+                #[cfg(feature = "metrics")]
+                if let Some(label) = &buffer.metrics_label {
+                    telemetry::error(label, "line");
+                }
+                buffer.check_watermarks();
+                channel.notify(buffer);
+                return Err(error.into());
+            }
+            len = len.min(position);
+        }
+
+        buffer.faults.marks.consume(len);
+        let (front, back) = buffer.data.as_slices();
+        let front_len = len.min(front.len());
+        copy(0, &front[..front_len]);
+        copy(front_len, &back[..len - front_len]);
+        #[cfg(feature = "log")]
+        if let Some(name) = &buffer.log_name {
+            let data: Vec<u8> = buffer.data.range(..len).copied().collect();
+            hexdump::log(name, "rx", &data);
+        }
+        buffer.data.drain(..len);
+        buffer.arrived -= len;
+        buffer.port_stats.reads += 1;
+        buffer.check_watermarks();
+        if len > 0 {
+            channel.notify(buffer);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(label) = &buffer.metrics_label {
+            telemetry::received(label, len);
+            telemetry::buffer_level(label, buffer.data.len());
+        }
+        Ok(len)
+    }
+
+    // Appends the bytes to the peer buffer, passing them through the faults
+    // of the buffer and handling the excess according to its overrun policy.
+    // If the peer uses software flow control, XON/XOFF characters are taken
+    // out of the data and pause or resume the writes of the peer instead.
+    // Returns the number of consumed bytes, which includes the lost ones, or
+    // fails with `BrokenPipe` if the link is (or goes) down. With `address`,
+    // the bytes are sent with the 9th bit set.
+    fn put(
+        &self,
+        mut buffer: MutexGuard<'_, Buffer>,
+        buf: &[u8],
+        address: bool,
+    ) -> io::Result<usize> {
+        buffer.check_writable()?;
+        buffer.check_writer_connected()?;
+        buffer.check_reader_open()?;
+        if buffer.disconnected {
+            // The data vanishes as the writer doesn't notice the link is down
+            return Ok(buf.len());
+        }
+        // A sleeping reader ignores the data, which still goes to the taps
+        let accepted = buffer.accepts_frames(buf, address);
+
+        let stored = buffer.data.len();
+        let capacity = match buffer.overrun_policy {
+            OverrunPolicy::Block => buffer.capacity,
+            _ => usize::MAX,
+        };
+        let software_flow_control = accepted && buffer.reader_flow_control == FlowControl::Software;
+        let now = buffer.clock.now();
+        let Buffer { data, faults, .. } = &mut *buffer;
+
+        let mut len = 0;
+        let mut xoff = None;
+        let mut link_dropped = false;
+        for chunk in buf.split_inclusive(|&byte| software_flow_control && is_xon_xoff(byte)) {
+            let (chunk, control) = match chunk.split_last() {
+                Some((&byte, rest)) if software_flow_control && is_xon_xoff(byte) => {
+                    (rest, Some(byte))
+                }
+                _ => (chunk, None),
+            };
+            let (consumed, dropped) = if accepted {
+                faults.transfer(chunk, data, capacity, now)
+            } else {
+                (chunk.len(), false)
+            };
+            len += consumed;
+            link_dropped = dropped;
+            if link_dropped || consumed < chunk.len() {
+                break;
+            }
+            if let Some(control) = control {
+                xoff = Some(control == XOFF);
+                len += 1;
+            }
+        }
+        if link_dropped {
+            drop(buffer);
+            self.disconnect();
+            return Ok(len);
+        }
+
+        if address {
+            buffer.mark_addresses(stored);
+        }
+        let received = buffer.data.len() - stored;
+        let collision = if accepted {
+            buffer.send(received)
+        } else {
+            None
+        };
+        buffer.stats.received += received as u64;
+        buffer
+            .captures
+            .retain(|capture| capture.record(&buf[..len], now));
+
+        let excess = buffer.limit_to_capacity(stored);
+
+        if buffer.write_held && len > 0 {
+            buffer.flow_control_stats.delayed_bytes += len as u64;
+            buffer.write_held = false;
+        }
+
+        buffer.check_watermarks();
+        if buffer.data.len() != stored || excess > 0 {
+            self.tx.notify(&mut buffer);
+        }
+        schedule_arrivals(&self.tx, &mut buffer);
+        #[cfg(feature = "metrics")]
+        if let Some(label) = &buffer.metrics_label {
+            let arrival = buffer
+                .arrivals
+                .back()
+                .map_or(now, |&arrival| arrival.max(now));
+            telemetry::latency(label, arrival - now);
+            telemetry::buffer_level(label, buffer.data.len());
+        }
+        drop(buffer);
+
+        #[cfg(feature = "log")]
+        if let Some(name) = &self.rx.lock().log_name {
+            hexdump::log(name, "tx", &buf[..len]);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(label) = &self.rx.lock().metrics_label {
+            telemetry::transmitted(label, len);
+        }
+
+        // On a bus, a transmission overlapping another one garbles the
+        // overlapping bytes of both, including the tail of the other one
+        // received by this endpoint
+        let collided = match &self.bus {
+            Some(attachment) => attachment.transmit(len, now),
+            None => 0,
+        };
+        if collided > 0 {
+            let mut buffer = self.rx.lock();
+            let end = buffer.data.len();
+            for position in end.saturating_sub(collided)..end {
+                buffer.garble(position);
+            }
+            self.rx.notify(&mut buffer);
+        }
+
+        let taps: Vec<_> = {
+            let mut taps = self.taps.lock().unwrap();
+            taps.retain(|(tap, _)| tap.strong_count() > 0);
+            taps.iter()
+                .filter_map(|(tap, from)| Some((tap.upgrade()?, *from)))
+                .collect()
+        };
+        for (tap, from) in taps {
+            feed_tap(&tap, &buf[..len], address, from, collided);
+        }
+
+        // The flow control characters pause the transmission in the opposite
+        // direction, and a collision garbles the data in flight in it
+        if xoff.is_some() || collision.is_some() {
+            let mut buffer = self.rx.lock();
+            if let Some(xoff) = xoff {
+                buffer.set_xoff(xoff, false);
+            }
+            if let Some(since) = collision {
+                buffer.garble_in_flight(since);
+            }
+            self.rx.notify(&mut buffer);
+        }
+        Ok(len)
+    }
+}
+
+// Copies the data sent by a port into the receive buffer of a monitor, a
+// listener or a bus participant, through its faults and at its pace, garbling
+// the first `collided` bytes and as many bytes received before. A tap never
+// holds the writer back: the data which doesn't fit is lost.
+fn feed_tap(
+    channel: &Arc<Channel>,
+    data: &[u8],
+    address: bool,
+    from: Option<PairEnd>,
+    collided: usize,
+) {
+    let mut buffer = channel.lock();
+    if buffer.disconnected
+        || buffer.transmit_only
+        || data.is_empty()
+        || !buffer.accepts_frames(data, address)
+    {
+        return;
+    }
+
+    let stored = buffer.data.len();
+    let now = buffer.clock.now();
+    let Buffer {
+        data: tap_data,
+        faults,
+        ..
+    } = &mut *buffer;
+    faults.transfer(data, tap_data, usize::MAX, now);
+    if address {
+        buffer.mark_addresses(stored);
+    }
+    let end = buffer.data.len().min(stored + collided);
+    for position in stored.saturating_sub(collided)..end {
+        buffer.garble(position);
+    }
+    let received = buffer.data.len() - stored;
+    buffer.send(received);
+    buffer.stats.received += received as u64;
+    buffer.captures.retain(|capture| capture.record(data, now));
+    buffer.limit_to_capacity(stored);
+
+    if let Some(from) = from {
+        buffer.traffic.retain(|sender| {
+            sender
+                .send(Traffic {
+                    from,
+                    data: data.to_vec(),
+                    timestamp: now,
+                })
+                .is_ok()
+        });
+    }
+    channel.notify(&mut buffer);
+    schedule_arrivals(channel, &mut buffer);
+}
+
+// Shortest interval between the wakeups of a background thread delivering
+// the bytes in flight, batching the bytes of fast links
+const MIN_ARRIVAL_INTERVAL: Duration = Duration::from_millis(1);
+
+// Starts a background thread which wakes up the waiters of the channel as
+// the bytes in flight arrive, until all of them have arrived (a clock which
+// doesn't run in real time wakes them up itself). The thread doesn't keep
+// the channel alive while it sleeps, so it ends soon after the ports are
+// dropped, even with a long transmission in flight.
+fn schedule_arrivals(channel: &Arc<Channel>, buffer: &mut Buffer) {
+    if buffer.scheduled || !buffer.clock.is_real_time() || buffer.next_arrival().is_none() {
+        return;
+    }
+
+    buffer.scheduled = true;
+    let channel = Arc::downgrade(channel);
+    thread::spawn(move || {
+        let mut slept = false;
+        while let Some(channel) = channel.upgrade() {
+            let mut buffer = channel.lock();
+            if slept {
+                channel.notify(&mut buffer);
+            }
+            let arrival = match buffer.next_arrival() {
+                Some(arrival) if buffer.clock.is_real_time() => arrival,
+                _ => {
+                    buffer.scheduled = false;
+                    return;
+                }
+            };
+            let delay = arrival.saturating_duration_since(buffer.clock.now());
+            drop(buffer);
+            drop(channel);
+            thread::sleep(delay.max(MIN_ARRIVAL_INTERVAL));
+            slept = true;
+        }
+    });
+}
+
+// Flow control characters of software flow control
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+fn is_xon_xoff(byte: u8) -> bool {
+    byte == XON || byte == XOFF
+}
+
+impl io::Read for Pipe {
+    /// Waits until the whole buffer can be filled, or fails with
+    /// `TimedOut` once the timeout expires.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_min(buf, buf.len())
+    }
+}
+
+impl io::Write for Pipe {
+    /// Writes as many bytes as fit into the peer buffer, failing with
+    /// `WouldBlock` if it is full or the writer is paused by flow control.
+    /// With blocking writes, waits for the peer to accept data instead, or
+    /// fails with `TimedOut` once the timeout expires.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transmit(buf, false, self.blocking_writes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Pipe {
+    /// Writes as many bytes as fit into the peer buffer, failing with
+    /// `WouldBlock` if it is full or the writer is paused by flow control,
+    /// even with blocking writes.
+    #[cfg(any(feature = "async", feature = "embedded-hal-nb"))]
+    pub(crate) fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.transmit(buf, false, false)
+    }
+
+    /// Sends a byte with the 9th bit set, which the receivers of a multidrop
+    /// link take as an address. Fails as a write does.
+    pub(crate) fn write_address(&self, address: u8) -> io::Result<()> {
+        match self.transmit(&[address], true, self.blocking_writes)? {
+            0 => Err(io::ErrorKind::WouldBlock.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the address which wakes up the endpoint, `None` meaning that
+    /// it receives all the data.
+    pub(crate) fn address_filter(&self) -> Option<u8> {
+        self.rx.lock().address_filter
+    }
+
+    /// Sets the address which wakes up the endpoint: until a matching address
+    /// byte arrives, and after a different one, the data is discarded.
+    pub(crate) fn set_address_filter(&self, address: Option<u8>) {
+        let mut buffer = self.rx.lock();
+        buffer.address_filter = address;
+        buffer.addressed = false;
+    }
+
+    // Writes the data as `write` does, as address bytes if `address` is set,
+    // waiting for the peer to accept data if `wait` is set
+    fn transmit(&self, buf: &[u8], address: bool, wait: bool) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut buffer = self.tx.lock();
+        if wait && !buffer.accepts_writes() {
+            buffer.write_held |= buffer.paused();
+            drop(buffer);
+            buffer = self.count_timeout(self.tx.wait_while(
+                self.wait_strategy,
+                self.timeout,
+                |buffer| !buffer.accepts_writes(),
+            ))?;
+        }
+        buffer.check_writable()?;
+        buffer.check_writer_connected()?;
+        buffer.check_reader_open()?;
+        if buffer.disconnected {
+            return Ok(buf.len());
+        }
+        if buffer.paused() {
+            buffer.write_held = true;
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "transmission is paused by flow control",
+            ));
+        }
+        if !buffer.accepts_writes() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "write buffer is full",
+            ));
+        }
+
+        self.count_write(self.put(buffer, buf, address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_blocking_read_wakes_on_write() {
+        let (mut pipe1, mut pipe2) = Pipe::pair(16);
+
+        let reader = thread::spawn(move || {
+            let mut buf = [0u8; 4];
+            pipe2.read_exact(&mut buf).unwrap();
+            buf
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        pipe1.write_all(b"ping").unwrap();
+
+        assert_eq!(&reader.join().unwrap(), b"ping");
+    }
+
+    #[test]
+    fn test_write_to_full_buffer() {
+        let mut pipe = Pipe::loopback(4);
+
+        assert_eq!(pipe.write(b"abcdef").unwrap(), 4);
+        assert_eq!(
+            pipe.write(b"g").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let mut buf = [0u8; 2];
+        pipe.read_exact(&mut buf).unwrap();
+        assert_eq!(pipe.write(b"gh").unwrap(), 2);
+        assert_eq!(pipe.read_buffer_len(), 4);
+    }
+
+    #[test]
+    fn test_overrun_policies() {
+        let mut pipe = Pipe::loopback(4);
+        let mut buf = [0u8; 4];
+
+        pipe.set_overrun_policy(OverrunPolicy::DropOldest);
+        assert_eq!(pipe.write(b"abcdef").unwrap(), 6);
+        pipe.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"cdef");
+
+        pipe.set_overrun_policy(OverrunPolicy::DropNewest);
+        assert_eq!(pipe.write(b"abcdef").unwrap(), 6);
+        assert_eq!(pipe.write(b"g").unwrap(), 1);
+        pipe.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"abcd");
+
+        pipe.set_overrun_policy(OverrunPolicy::Error);
+        pipe.write_all(b"abcdef").unwrap();
+        assert_eq!(
+            pipe.receive_stats(),
+            ReceiveStats {
+                received: 19,
+                dropped: 7,
+                overruns: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_byte_time_accumulates_fractions() {
+        // 10 bits take 3333.3 ns at 3 Mbaud, each byte rounding the total
+        let byte_time = ByteTime::new(10, 3_000_000);
+        let mut remainder = byte_time.baud_rate / 2;
+        let times: Vec<_> = (0..3).map(|_| byte_time.next(&mut remainder)).collect();
+        assert_eq!(times[0], Duration::from_nanos(3333));
+        assert_eq!(times.iter().sum::<Duration>(), Duration::from_micros(10));
+
+        // Low rates are not skewed by truncating the bit time first
+        let mut remainder = 0;
+        let byte_time = ByteTime::new(10, 300);
+        assert_eq!(
+            byte_time.next(&mut remainder),
+            Duration::from_nanos(33_333_333)
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Readiness notification through an operating system handle.
+//!
+//! Virtual ports have no operating system handle, so readiness is mirrored
+//! onto an internal one. On Unix it is a socket pair: the polled socket is
+//! readable exactly while the receive buffer of the port contains data, and
+//! writable exactly while the buffer of the peer has free space. This makes
+//! it possible to register a [`VirtualPort`] in a `mio` event loop (the `mio`
+//! feature) and to pass its descriptor to `select()`/`poll()` (the `raw-fd`
+//! feature). On Windows the `raw-fd` feature provides a manual-reset event
+//! instead, which is signalled while data is available and can be waited on
+//! with `WaitForSingleObject`/`WaitForMultipleObjects`.
+//!
+//! The handle only signals readiness and never carries the data: the data is
+//! still read and written through the port, and reading from the descriptor
+//! directly consumes the readiness signal. As with any edge-triggered
+//! source, the port must be read until [`VirtualPort::try_read`] returns
+//! `WouldBlock` before the next readable event is delivered through `mio`,
+//! and written until a write returns `WouldBlock` before the next writable
+//! event.
+
+use std::io;
+
+#[cfg(unix)]
+use std::{
+    io::{Read, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+};
+
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, RawHandle};
+
+#[cfg(all(feature = "mio", unix))]
+use mio::{event::Source, unix::SourceFd, Interest, Registry, Token};
+
+#[cfg(windows)]
+use windows_sys::Win32::{
+    Foundation::{CloseHandle, HANDLE},
+    System::Threading::{CreateEventW, ResetEvent, SetEvent},
+};
+
+use crate::VirtualPort;
+
+#[cfg(unix)]
+pub(crate) type RawReadiness = RawFd;
+
+#[cfg(windows)]
+pub(crate) type RawReadiness = RawHandle;
+
+/// Mirrors the state of the port buffers onto a socket.
+///
+/// The polled socket is readable while the signal byte is pending in it, and
+/// it stops being writable once its own send buffer is filled up with junk.
+#[cfg(unix)]
+pub(crate) struct Notifier {
+    // The end the signal byte is written into and the junk is drained from
+    signal: UnixStream,
+
+    // The end exposed for polling
+    poll: UnixStream,
+
+    // Whether the signal byte is currently pending in the socket
+    readable: bool,
+
+    // Whether the send buffer of the polled socket is empty
+    writable: bool,
+}
+
+#[cfg(unix)]
+impl Notifier {
+    pub(crate) fn new() -> io::Result<Self> {
+        let (signal, poll) = UnixStream::pair()?;
+        signal.set_nonblocking(true)?;
+        poll.set_nonblocking(true)?;
+
+        Ok(Self {
+            signal,
+            poll,
+            readable: false,
+            writable: true,
+        })
+    }
+
+    /// Makes the polled socket readable if and only if `readable` is set.
+    pub(crate) fn set_readable(&mut self, readable: bool) {
+        if readable == self.readable {
+            return;
+        }
+
+        // Errors are not expected here: the socket holds at most one byte
+        let _ = if readable {
+            (&self.signal).write(&[1])
+        } else {
+            (&self.poll).read(&mut [0u8; 1])
+        };
+        self.readable = readable;
+    }
+
+    /// Makes the polled socket writable if and only if `writable` is set.
+    pub(crate) fn set_writable(&mut self, writable: bool) {
+        if writable == self.writable {
+            return;
+        }
+
+        // Either direction stops with `WouldBlock` once the socket buffer is
+        // empty (or full)
+        let mut junk = [0u8; 4096];
+        if writable {
+            while matches!((&self.signal).read(&mut junk), Ok(len) if len > 0) {}
+        } else {
+            while matches!((&self.poll).write(&junk), Ok(len) if len > 0) {}
+        }
+        self.writable = writable;
+    }
+
+    pub(crate) fn as_raw(&self) -> RawReadiness {
+        self.poll.as_raw_fd()
+    }
+}
+
+/// Mirrors the receive buffer state onto a manual-reset event.
+#[cfg(windows)]
+pub(crate) struct Notifier {
+    event: HANDLE,
+
+    // Whether the event is currently signalled
+    readable: bool,
+}
+
+#[cfg(windows)]
+impl Notifier {
+    pub(crate) fn new() -> io::Result<Self> {
+        let event = unsafe { CreateEventW(std::ptr::null(), 1, 0, std::ptr::null()) };
+        if event == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            event,
+            readable: false,
+        })
+    }
+
+    /// Signals the event if and only if `readable` is set.
+    pub(crate) fn set_readable(&mut self, readable: bool) {
+        if readable == self.readable {
+            return;
+        }
+
+        unsafe {
+            if readable {
+                SetEvent(self.event);
+            } else {
+                ResetEvent(self.event);
+            }
+        }
+        self.readable = readable;
+    }
+
+    /// Write readiness cannot be expressed by a single event.
+    pub(crate) fn set_writable(&mut self, _writable: bool) {}
+
+    pub(crate) fn as_raw(&self) -> RawReadiness {
+        self.event as RawReadiness
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.event);
+        }
+    }
+}
+
+#[cfg(all(feature = "raw-fd", unix))]
+impl AsRawFd for VirtualPort {
+    fn as_raw_fd(&self) -> RawFd {
+        // The descriptor is created when the port is opened
+        self.pipe
+            .readiness_handle()
+            .expect("readiness descriptor is not available")
+    }
+}
+
+#[cfg(all(feature = "raw-fd", windows))]
+impl AsRawHandle for VirtualPort {
+    fn as_raw_handle(&self) -> RawHandle {
+        // The event is created when the port is opened
+        self.pipe
+            .readiness_handle()
+            .expect("readiness event is not available")
+    }
+}
+
+#[cfg(all(feature = "mio", unix))]
+impl Source for VirtualPort {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.pipe.readiness_handle()?).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.pipe.readiness_handle()?).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.pipe.readiness_handle()?).deregister(registry)
+    }
+}
+
+#[cfg(all(test, feature = "mio", unix))]
+mod tests {
+    use std::time::Duration;
+
+    use mio::{Events, Poll};
+
+    use super::*;
+
+    const PORT: Token = Token(0);
+
+    fn poll_readable(poll: &mut Poll, events: &mut Events) -> bool {
+        poll.poll(events, Some(Duration::from_millis(50))).unwrap();
+        events
+            .iter()
+            .any(|event| event.token() == PORT && event.is_readable())
+    }
+
+    fn poll_writable(poll: &mut Poll, events: &mut Events) -> bool {
+        poll.poll(events, Some(Duration::from_millis(50))).unwrap();
+        events
+            .iter()
+            .any(|event| event.token() == PORT && event.is_writable())
+    }
+
+    #[test]
+    fn test_mio_readable_events() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(8);
+        poll.registry()
+            .register(&mut port2, PORT, Interest::READABLE)
+            .unwrap();
+
+        assert!(!poll_readable(&mut poll, &mut events));
+
+        port1.write_all(b"hello").unwrap();
+        assert!(poll_readable(&mut poll, &mut events));
+
+        // Drain the port until it would block
+        let mut buf = [0u8; 16];
+        assert_eq!(port2.try_read(&mut buf).unwrap(), 5);
+        assert_eq!(
+            port2.try_read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert!(!poll_readable(&mut poll, &mut events));
+
+        port1.write_all(b"again").unwrap();
+        assert!(poll_readable(&mut poll, &mut events));
+
+        poll.registry().deregister(&mut port2).unwrap();
+    }
+
+    #[test]
+    fn test_mio_writable_events() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 4).unwrap();
+
+        let mut poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(8);
+        poll.registry()
+            .register(&mut port1, PORT, Interest::WRITABLE)
+            .unwrap();
+
+        assert!(poll_writable(&mut poll, &mut events));
+
+        // Fill the peer buffer until the write would block
+        assert_eq!(port1.write(b"abcdef").unwrap(), 4);
+        assert_eq!(
+            port1.write(b"e").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert!(!poll_writable(&mut poll, &mut events));
+
+        // Draining the peer buffer makes the port writable again
+        let mut buf = [0u8; 4];
+        assert_eq!(port2.try_read(&mut buf).unwrap(), 4);
+        assert!(poll_writable(&mut poll, &mut events));
+    }
+}
+
+#[cfg(all(test, feature = "raw-fd", unix))]
+mod raw_fd_tests {
+    use super::*;
+
+    // Checks readiness the way a `select()`-based application would
+    fn is_readable(fd: RawFd) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        assert!(ready >= 0);
+        pollfd.revents & libc::POLLIN != 0
+    }
+
+    #[test]
+    fn test_raw_fd_readiness() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let fd = port2.as_raw_fd();
+
+        assert!(!is_readable(fd));
+        port1.write_all(b"hello").unwrap();
+        assert!(is_readable(fd));
+
+        let mut buf = [0u8; 5];
+        port2.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert!(!is_readable(fd));
+    }
+}
+
+#[cfg(all(test, feature = "raw-fd", windows))]
+mod raw_handle_tests {
+    use std::io::{Read, Write};
+
+    use windows_sys::Win32::{Foundation::WAIT_OBJECT_0, System::Threading::WaitForSingleObject};
+
+    use super::*;
+
+    fn is_signalled(handle: RawHandle) -> bool {
+        unsafe { WaitForSingleObject(handle as HANDLE, 0) == WAIT_OBJECT_0 }
+    }
+
+    #[test]
+    fn test_raw_handle_readiness() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let handle = port2.as_raw_handle();
+
+        assert!(!is_signalled(handle));
+        port1.write_all(b"hello").unwrap();
+        assert!(is_signalled(handle));
+
+        let mut buf = [0u8; 5];
+        port2.read_exact(&mut buf).unwrap();
+        assert!(!is_signalled(handle));
+    }
+}
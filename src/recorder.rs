@@ -0,0 +1,516 @@
+//! Recording the sessions of a port and replaying them.
+//!
+//! A [`Recorder`] records the data, the control line changes and the line
+//! settings of a port with their timing into a [`Recording`], which can be
+//! saved to a file. A [`Replayer`] plays the peer of a recorded port back
+//! against another port with the original timing, so traces of sessions with
+//! real hardware become reproducible tests.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use crate::{
+    bridge::{is_retryable, send_all, Bridge, CHUNK_SIZE, POLL_INTERVAL},
+    capture::{CaptureSink, CaptureTap},
+    ControlEvent, ControlLine, Settings, VirtualPort,
+};
+
+// First line of a recording file
+const HEADER: &str = "virtual-serialport recording 1";
+
+/// Event of a recorded session, as seen by the recorded port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordedEvent {
+    /// The port wrote the data.
+    Sent(Vec<u8>),
+
+    /// The port received the data.
+    Received(Vec<u8>),
+
+    /// A control line of the port changed to the given level.
+    Line(ControlLine, bool),
+
+    /// The line settings of the port changed.
+    Settings(Settings),
+}
+
+/// Event of a [`Recording`], with its time since the recording started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the recording started.
+    pub offset: Duration,
+
+    /// What happened.
+    pub event: RecordedEvent,
+}
+
+/// Session of a port recorded by a [`Recorder`], starting with the line
+/// settings and the input levels of the port when the recording started.
+///
+/// A recording is saved as text, one event per line, so it can be reviewed
+/// and edited by hand:
+///
+/// ```text
+/// virtual-serialport recording 1
+/// 0 settings 9600 8 none 1 none -
+/// 0 line cts 1
+/// 1500000 rx 68656c6c6f
+/// 2000000 tx 6f6b
+/// ```
+///
+/// Each line starts with the offset in nanoseconds. The data is in hex, and
+/// the settings are the baud rate, the data bits, the parity, the stop bits,
+/// the flow control and the timeout in nanoseconds (`-` for none).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    records: Vec<Record>,
+}
+
+impl Recording {
+    /// Creates a recording of the given events, ordered by their offset.
+    pub fn new(records: Vec<Record>) -> Self {
+        Self { records }
+    }
+
+    /// Returns the recorded events, ordered by their offset.
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Loads a recording from the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Saves the recording to the file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Reads a recording, failing with `InvalidData` if it is malformed.
+    pub fn read_from(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) => {
+                if line? != HEADER {
+                    return Err(invalid_data(1, "not a recording"));
+                }
+            }
+            None => return Err(invalid_data(1, "not a recording")),
+        }
+
+        let mut records = Vec::new();
+        for (index, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = parse_record(&line).ok_or_else(|| invalid_data(index + 1, &line))?;
+            records.push(record);
+        }
+        Ok(Self { records })
+    }
+
+    /// Writes the recording.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        for record in &self.records {
+            writeln!(writer, "{}", format_record(record))?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("recording line {}: {}", line, message),
+    )
+}
+
+fn format_record(record: &Record) -> String {
+    let mut line = record.offset.as_nanos().to_string();
+    match &record.event {
+        RecordedEvent::Sent(data) => write!(line, " tx {}", to_hex(data)),
+        RecordedEvent::Received(data) => write!(line, " rx {}", to_hex(data)),
+        RecordedEvent::Line(control_line, level) => write!(
+            line,
+            " line {} {}",
+            line_name(*control_line),
+            u8::from(*level)
+        ),
+        RecordedEvent::Settings(settings) => write!(
+            line,
+            " settings {} {} {} {} {} {}",
+            settings.baud_rate,
+            u8::from(settings.data_bits),
+            match settings.parity {
+                Parity::None => "none",
+                Parity::Odd => "odd",
+                Parity::Even => "even",
+            },
+            u8::from(settings.stop_bits),
+            match settings.flow_control {
+                FlowControl::None => "none",
+                FlowControl::Software => "software",
+                FlowControl::Hardware => "hardware",
+            },
+            match settings.timeout {
+                Duration::MAX => "-".to_owned(),
+                timeout => timeout.as_nanos().to_string(),
+            }
+        ),
+    }
+    .unwrap();
+    line
+}
+
+// Parses a line of a recording, returning `None` if it is malformed
+fn parse_record(line: &str) -> Option<Record> {
+    let mut fields = line.split_whitespace();
+    let offset = Duration::from_nanos(fields.next()?.parse().ok()?);
+    let event = match fields.next()? {
+        "tx" => RecordedEvent::Sent(from_hex(fields.next()?)?),
+        "rx" => RecordedEvent::Received(from_hex(fields.next()?)?),
+        "line" => {
+            let name = fields.next()?;
+            let control_line = ALL_LINES
+                .iter()
+                .copied()
+                .find(|&control_line| line_name(control_line) == name)?;
+            let level = match fields.next()? {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            };
+            RecordedEvent::Line(control_line, level)
+        }
+        "settings" => RecordedEvent::Settings(Settings {
+            baud_rate: fields.next()?.parse().ok()?,
+            data_bits: DataBits::try_from(fields.next()?.parse::<u8>().ok()?).ok()?,
+            parity: match fields.next()? {
+                "none" => Parity::None,
+                "odd" => Parity::Odd,
+                "even" => Parity::Even,
+                _ => return None,
+            },
+            stop_bits: StopBits::try_from(fields.next()?.parse::<u8>().ok()?).ok()?,
+            flow_control: match fields.next()? {
+                "none" => FlowControl::None,
+                "software" => FlowControl::Software,
+                "hardware" => FlowControl::Hardware,
+                _ => return None,
+            },
+            timeout: match fields.next()? {
+                "-" => Duration::MAX,
+                nanos => Duration::from_nanos(nanos.parse().ok()?),
+            },
+        }),
+        _ => return None,
+    };
+    match fields.next() {
+        Some(_) => None,
+        None => Some(Record { offset, event }),
+    }
+}
+
+const ALL_LINES: [ControlLine; 6] = [
+    ControlLine::Rts,
+    ControlLine::Dtr,
+    ControlLine::Cts,
+    ControlLine::Dsr,
+    ControlLine::Cd,
+    ControlLine::Ri,
+];
+
+fn line_name(control_line: ControlLine) -> &'static str {
+    match control_line {
+        ControlLine::Rts => "rts",
+        ControlLine::Dtr => "dtr",
+        ControlLine::Cts => "cts",
+        ControlLine::Dsr => "dsr",
+        ControlLine::Cd => "cd",
+        ControlLine::Ri => "ri",
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+// Data recorded so far, timestamped with the clock of the port
+struct Log {
+    records: Vec<(Instant, RecordedEvent)>,
+}
+
+impl CaptureSink for Log {
+    fn record(&mut self, data: &[u8], outbound: bool, timestamp: Instant) {
+        let data = data.to_vec();
+        let event = if outbound {
+            RecordedEvent::Sent(data)
+        } else {
+            RecordedEvent::Received(data)
+        };
+        self.records.push((timestamp, event));
+    }
+}
+
+/// Running recording of the session of a port, started with
+/// [`VirtualPort::start_recording`].
+pub struct Recorder {
+    start: Instant,
+    log: Arc<Mutex<Log>>,
+    control_events: Receiver<ControlEvent>,
+    settings: Receiver<(Instant, Settings)>,
+}
+
+impl Recorder {
+    /// Stops recording and returns the recorded session.
+    pub fn finish(self) -> Recording {
+        let mut records = std::mem::take(&mut self.log.lock().unwrap().records);
+        records.extend(self.control_events.try_iter().map(|event| {
+            (
+                event.timestamp,
+                RecordedEvent::Line(event.line, event.level),
+            )
+        }));
+        records.extend(
+            self.settings
+                .try_iter()
+                .map(|(timestamp, settings)| (timestamp, RecordedEvent::Settings(settings))),
+        );
+
+        // The events are only ordered by source, and the initial state
+        // comes first
+        records.sort_by_key(|(timestamp, _)| *timestamp);
+        Recording::new(
+            records
+                .into_iter()
+                .map(|(timestamp, event)| Record {
+                    offset: timestamp.saturating_duration_since(self.start),
+                    event,
+                })
+                .collect(),
+        )
+    }
+}
+
+impl VirtualPort {
+    /// Starts recording the data written and received by the port, the
+    /// changes of its control lines and of its line settings, timestamped
+    /// with the clock of the port.
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// use virtual_serialport::{RecordedEvent, VirtualPort};
+    ///
+    /// let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+    /// let recorder = port1.start_recording();
+    /// port2.write_all(b"hello").unwrap();
+    /// let mut read_data = [0u8; 5];
+    /// port1.read_exact(&mut read_data).unwrap();
+    ///
+    /// let recording = recorder.finish();
+    /// let received = recording.records().last().unwrap();
+    /// assert_eq!(received.event, RecordedEvent::Received(b"hello".to_vec()));
+    /// ```
+    pub fn start_recording(&self) -> Recorder {
+        let start = self.pipe.now();
+        let (levels, _) = self.line_levels();
+        let mut records = vec![(start, RecordedEvent::Settings(self.settings()))];
+        records.extend(
+            [
+                (ControlLine::Cts, levels.cts),
+                (ControlLine::Dsr, levels.dsr),
+                (ControlLine::Cd, levels.cd),
+                (ControlLine::Ri, levels.ri),
+            ]
+            .into_iter()
+            .map(|(control_line, level)| (start, RecordedEvent::Line(control_line, level))),
+        );
+
+        let log = Arc::new(Mutex::new(Log { records }));
+        let (outbound, inbound) = CaptureTap::pair(log.clone());
+        self.pipe.add_capture(outbound, inbound);
+        Recorder {
+            start,
+            log,
+            control_events: self.subscribe_control_events(),
+            settings: self.subscribe_settings(),
+        }
+    }
+}
+
+/// Plays the peer of a recorded port back against a port on a background
+/// thread, with the original timing.
+///
+/// The data the recorded port received is written to the port, its input
+/// lines are reproduced with the outputs of the port (CTS with RTS, DSR with
+/// DTR, CD and RI with [`VirtualPort::set_carrier_detect`] and
+/// [`VirtualPort::set_ring_indicator`]), and its line settings are applied
+/// to the port, keeping its timeout. The data the port receives is
+/// discarded.
+pub struct Replayer {
+    bridge: Bridge,
+}
+
+impl Replayer {
+    /// Starts playing `recording` back against `port`.
+    ///
+    /// Typically `port` is the second port of a pair, while the code under
+    /// test uses the first one.
+    pub fn spawn(port: VirtualPort, recording: Recording) -> Self {
+        let mut bridge = Bridge::new();
+        let mut port = port;
+        let mut records = recording.records.into_iter().peekable();
+        let mut start = None;
+        bridge.spawn(move |running| {
+            let elapsed = start.get_or_insert_with(Instant::now).elapsed();
+            while let Some(record) = records.next_if(|record| record.offset <= elapsed) {
+                play(&mut port, record.event, running)?;
+            }
+
+            match records.peek() {
+                Some(record) => discard(&mut port, (record.offset - elapsed).min(POLL_INTERVAL)),
+                None => {
+                    running.store(false, Ordering::Relaxed);
+                    Ok(())
+                }
+            }
+        });
+        Self { bridge }
+    }
+
+    /// Returns whether the replay is still going on.
+    pub fn is_running(&self) -> bool {
+        self.bridge.is_running()
+    }
+
+    /// Waits until the whole recording is played back, returning the error
+    /// that terminated the replay, if any.
+    pub fn wait(self) -> io::Result<()> {
+        self.bridge.join()
+    }
+
+    /// Stops the replay, returning the error that terminated it, if any.
+    pub fn stop(self) -> io::Result<()> {
+        self.bridge.stop()
+    }
+}
+
+// Reproduces a recorded event with the port on the other side
+fn play(port: &mut VirtualPort, event: RecordedEvent, running: &AtomicBool) -> io::Result<()> {
+    match event {
+        RecordedEvent::Received(data) => return send_all(port, &data, running),
+        RecordedEvent::Line(ControlLine::Cts, level) => port.write_request_to_send(level)?,
+        RecordedEvent::Line(ControlLine::Dsr, level) => port.write_data_terminal_ready(level)?,
+        RecordedEvent::Line(ControlLine::Cd, level) => port.set_carrier_detect(Some(level)),
+        RecordedEvent::Line(ControlLine::Ri, level) => port.set_ring_indicator(level),
+        RecordedEvent::Settings(settings) => {
+            let timeout = port.settings().timeout;
+            port.apply_settings(&Settings {
+                timeout,
+                ..settings
+            });
+        }
+        // The outputs and the data of the recorded port come from the code
+        // under test
+        RecordedEvent::Line(..) | RecordedEvent::Sent(_) => {}
+    }
+    Ok(())
+}
+
+// Discards the data the port receives for up to `timeout`
+fn discard(port: &mut VirtualPort, timeout: Duration) -> io::Result<()> {
+    match port.wait_readable(Some(timeout)) {
+        Ok(()) => {}
+        Err(err) if is_retryable(&err) => return Ok(()),
+        Err(err) => return Err(err),
+    }
+
+    let mut buf = [0u8; CHUNK_SIZE];
+    match port.try_read(&mut buf) {
+        Err(err) if is_retryable(&err) => Ok(()),
+        // The peer is closed, so there is nothing to wait for
+        Ok(0) => {
+            thread::sleep(timeout);
+            Ok(())
+        }
+        result => result.map(drop),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_record_and_replay() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let recorder = port1.start_recording();
+        port1.set_baud_rate(19_200).unwrap();
+        port2.write_request_to_send(false).unwrap();
+        port2.write_all(b"hello").unwrap();
+        port1.write_all(b"ok").unwrap();
+        let recording = recorder.finish();
+
+        let events: Vec<_> = recording
+            .records()
+            .iter()
+            .map(|record| record.event.clone())
+            .skip(5)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                RecordedEvent::Settings(Settings::new(19_200)),
+                RecordedEvent::Line(ControlLine::Cts, false),
+                RecordedEvent::Received(b"hello".to_vec()),
+                RecordedEvent::Sent(b"ok".to_vec()),
+            ]
+        );
+
+        let mut file = Vec::new();
+        recording.write_to(&mut file).unwrap();
+        assert_eq!(Recording::read_from(&file[..]).unwrap(), recording);
+        assert!(Recording::read_from(&b"garbage\n"[..]).is_err());
+
+        // The replayed peer sends the same data to a new port
+        let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_baud_rate(19_200).unwrap();
+        port1.set_timeout(Duration::from_secs(2)).unwrap();
+        Replayer::spawn(port2, recording).wait().unwrap();
+        let mut read_data = [0u8; 5];
+        port1.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"hello");
+        assert!(!port1.read_clear_to_send().unwrap());
+    }
+}
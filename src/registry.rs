@@ -0,0 +1,182 @@
+//! Process-global registry of named virtual ports.
+//!
+//! Registering a port under a name (e.g., `"VCOM1"`) lets the code under test
+//! open it by name with [`VirtualPort::open`], so code which opens ports by
+//! path can be pointed at the simulator with only a configuration change.
+//! [`available_ports`] lists the registered ports the way
+//! `serialport::available_ports` lists the hardware ones.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use once_cell::sync::Lazy;
+use serialport::{Error, ErrorKind, Result, SerialPortInfo, SerialPortType, UsbPortInfo};
+
+use crate::VirtualPort;
+
+// A registered port, and the hardware it pretends to be
+struct Entry {
+    port: VirtualPort,
+    port_type: SerialPortType,
+}
+
+static REGISTRY: Lazy<Mutex<HashMap<String, Entry>>> = Lazy::new(Default::default);
+
+// USB vendor ID of pid.codes, and its product ID reserved for testing
+const VIRTUAL_VID: u16 = 0x1209;
+const VIRTUAL_PID: u16 = 0x0001;
+
+// USB metadata of a port registered without its own
+fn virtual_usb_port(name: &str) -> SerialPortType {
+    // The optional fields of the serialport crate (`interface` and
+    // `location`) are not set, as they are gated behind its features
+    SerialPortType::UsbPort(UsbPortInfo {
+        vid: VIRTUAL_VID,
+        pid: VIRTUAL_PID,
+        serial_number: Some(name.to_owned()),
+        manufacturer: Some("virtual-serialport".to_owned()),
+        product: Some("Virtual serial port".to_owned()),
+    })
+}
+
+/// Lists the registered virtual ports like `serialport::available_ports`
+/// lists the hardware ones, sorted by name, so device discovery code can be
+/// run against the simulator.
+///
+/// Ports registered with [`VirtualPort::register`] appear as USB adapters
+/// with the pid.codes test IDs (VID `0x1209`, PID `0x0001`) and their name as
+/// the serial number; [`VirtualPort::register_as`] sets other metadata.
+pub fn available_ports() -> Result<Vec<SerialPortInfo>> {
+    let mut ports: Vec<_> = REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, entry)| SerialPortInfo {
+            port_name: name.clone(),
+            port_type: entry.port_type.clone(),
+        })
+        .collect();
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+    Ok(ports)
+}
+
+impl VirtualPort {
+    /// Registers the port under `name`, so [`VirtualPort::open`] returns a
+    /// handle to it, failing if the name is taken.
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// use virtual_serialport::VirtualPort;
+    ///
+    /// let (mut device, port) = VirtualPort::pair(9600, 1024).unwrap();
+    /// port.register("VCOM-example").unwrap();
+    ///
+    /// // The code under test opens the port by name
+    /// let mut opened = VirtualPort::open("VCOM-example").unwrap();
+    /// opened.write_all(b"ping").unwrap();
+    ///
+    /// let mut read_data = [0u8; 4];
+    /// device.read_exact(&mut read_data).unwrap();
+    /// assert_eq!(&read_data, b"ping");
+    /// # VirtualPort::unregister("VCOM-example");
+    /// ```
+    pub fn register(&self, name: &str) -> Result<()> {
+        self.register_as(name, virtual_usb_port(name))
+    }
+
+    /// Registers the port under `name` as [`register`](VirtualPort::register)
+    /// does, listing it in [`available_ports`] with the given hardware
+    /// metadata, e.g., the USB IDs of the adapter the code under test looks
+    /// for.
+    pub fn register_as(&self, name: &str, port_type: SerialPortType) -> Result<()> {
+        let mut registry = REGISTRY.lock().unwrap();
+        if registry.contains_key(name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("port name {:?} is already registered", name),
+            ));
+        }
+        registry.insert(
+            name.to_owned(),
+            Entry {
+                port: self.clone(),
+                port_type,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the port registered under `name`, returning whether there
+    /// was one. Handles opened before keep working.
+    pub fn unregister(name: &str) -> bool {
+        REGISTRY.lock().unwrap().remove(name).is_some()
+    }
+
+    /// Opens the port registered under `name`, failing with
+    /// `ErrorKind::NoDevice` if there is none. The returned handle shares the
+    /// buffers and the settings of the registered port, as every open of the
+    /// same device does.
+    pub fn open(name: &str) -> Result<Self> {
+        REGISTRY
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|entry| entry.port.clone())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NoDevice,
+                    format!("no virtual port is registered as {:?}", name),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_register_and_open() {
+        let port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.register("VCOM-test").unwrap();
+        assert!(port.register("VCOM-test").is_err());
+
+        let opened = VirtualPort::open("VCOM-test").unwrap();
+        assert!(Arc::ptr_eq(&opened.config, &port.config));
+
+        assert!(VirtualPort::unregister("VCOM-test"));
+        assert!(!VirtualPort::unregister("VCOM-test"));
+        assert!(matches!(
+            VirtualPort::open("VCOM-test"),
+            Err(error) if error.kind() == ErrorKind::NoDevice
+        ));
+    }
+
+    #[test]
+    fn test_available_ports() {
+        let port = VirtualPort::loopback(9600, 1024).unwrap();
+        port.register("VCOM-list-b").unwrap();
+        port.register_as("VCOM-list-a", SerialPortType::PciPort)
+            .unwrap();
+
+        let ports: Vec<_> = available_ports()
+            .unwrap()
+            .into_iter()
+            .filter(|info| info.port_name.starts_with("VCOM-list-"))
+            .collect();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports[0].port_type, SerialPortType::PciPort);
+        match &ports[1].port_type {
+            SerialPortType::UsbPort(info) => {
+                assert_eq!((info.vid, info.pid), (VIRTUAL_VID, VIRTUAL_PID));
+                assert_eq!(info.serial_number.as_deref(), Some("VCOM-list-b"));
+            }
+            port_type => panic!("unexpected port type {:?}", port_type),
+        }
+
+        VirtualPort::unregister("VCOM-list-a");
+        VirtualPort::unregister("VCOM-list-b");
+    }
+}
@@ -0,0 +1,273 @@
+//! Bulk configuration of a port.
+
+use std::{
+    str::FromStr,
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use serialport::{DataBits, Error, ErrorKind, FlowControl, Parity, Result, StopBits};
+
+use crate::VirtualPort;
+
+/// Line settings of a port, read at once with [`VirtualPort::settings`] and
+/// applied at once with [`VirtualPort::apply_settings`], as drivers
+/// configure a port in bulk (e.g., `tcsetattr`).
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use serialport::{Parity, SerialPort};
+/// use virtual_serialport::{Settings, VirtualPort};
+///
+/// let mut port = VirtualPort::loopback(9600, 1024).unwrap();
+/// let settings = Settings {
+///     parity: Parity::Even,
+///     timeout: Duration::from_millis(100),
+///     ..Settings::new(115_200)
+/// };
+/// port.apply_settings(&settings);
+///
+/// assert_eq!(port.baud_rate().unwrap(), 115_200);
+/// assert_eq!(port.settings(), settings);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    /// Baud rate (of both the transmitter and the receiver).
+    pub baud_rate: u32,
+
+    /// Number of bits per character.
+    pub data_bits: DataBits,
+
+    /// Parity checking mode.
+    pub parity: Parity,
+
+    /// Number of stop bits.
+    pub stop_bits: StopBits,
+
+    /// Flow control mode.
+    pub flow_control: FlowControl,
+
+    /// Timeout of the blocking operations, `Duration::MAX` meaning waiting
+    /// indefinitely.
+    pub timeout: Duration,
+}
+
+impl Settings {
+    /// Returns the settings of a newly opened port with the given baud rate:
+    /// 8 data bits, no parity, 1 stop bit, no flow control and no timeout.
+    pub fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::MAX,
+        }
+    }
+}
+
+/// Parses the classic shorthand of the line settings: the baud rate,
+/// optionally followed (after a comma or whitespace) by the data bits (`5` to
+/// `8`), the parity (`N`, `O` or `E`) and the stop bits (`1` or `2`), e.g.,
+/// `"115200,8N1"` or `"9600 7E2"`. The other settings are the ones of
+/// [`Settings::new`]. Fails with `InvalidInput` if the text is malformed.
+///
+/// ```
+/// use serialport::{DataBits, Parity, StopBits};
+/// use virtual_serialport::Settings;
+///
+/// let settings: Settings = "9600 7E2".parse().unwrap();
+/// assert_eq!(settings.baud_rate, 9600);
+/// assert_eq!(settings.data_bits, DataBits::Seven);
+/// assert_eq!(settings.parity, Parity::Even);
+/// assert_eq!(settings.stop_bits, StopBits::Two);
+/// ```
+impl FromStr for Settings {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid port settings: {:?}", text),
+            )
+        };
+        let parts: Vec<&str> = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .collect();
+        let (baud_rate, frame) = match parts[..] {
+            [baud_rate] => (baud_rate, None),
+            [baud_rate, frame] => (baud_rate, Some(frame)),
+            _ => return Err(invalid()),
+        };
+
+        let baud_rate = baud_rate
+            .parse()
+            .ok()
+            .filter(|&baud_rate| baud_rate > 0)
+            .ok_or_else(invalid)?;
+        let mut settings = Settings::new(baud_rate);
+        if let Some(frame) = frame {
+            let (data_bits, parity, stop_bits) = match frame.as_bytes() {
+                &[data_bits, parity, stop_bits] => (data_bits, parity, stop_bits),
+                _ => return Err(invalid()),
+            };
+            settings.data_bits = match data_bits {
+                b'5' => DataBits::Five,
+                b'6' => DataBits::Six,
+                b'7' => DataBits::Seven,
+                b'8' => DataBits::Eight,
+                _ => return Err(invalid()),
+            };
+            settings.parity = match parity.to_ascii_uppercase() {
+                b'N' => Parity::None,
+                b'O' => Parity::Odd,
+                b'E' => Parity::Even,
+                _ => return Err(invalid()),
+            };
+            settings.stop_bits = match stop_bits {
+                b'1' => StopBits::One,
+                b'2' => StopBits::Two,
+                _ => return Err(invalid()),
+            };
+        }
+        Ok(settings)
+    }
+}
+
+impl VirtualPort {
+    /// Returns the current line settings of the port. With split baud rates,
+    /// the baud rate is the one of the transmitter.
+    pub fn settings(&self) -> Settings {
+        let config = self.config.lock().unwrap();
+        Settings {
+            baud_rate: config.baud_rate,
+            data_bits: config.data_bits,
+            parity: config.parity,
+            stop_bits: config.stop_bits,
+            flow_control: config.flow_control,
+            timeout: self.pipe.timeout().unwrap_or(Duration::MAX),
+        }
+    }
+
+    /// Applies all the line settings at once, as the individual
+    /// `SerialPort` setters would.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let mut config = self.config.lock().unwrap();
+        config.baud_rate = settings.baud_rate;
+        config.rx_baud_rate = None;
+        config.data_bits = settings.data_bits;
+        config.parity = settings.parity;
+        config.stop_bits = settings.stop_bits;
+        config.flow_control = settings.flow_control;
+        drop(config);
+
+        self.sync_byte_times();
+        self.pipe.set_flow_control(settings.flow_control);
+        self.pipe
+            .with_faults(|faults| faults.parity_check = settings.parity != Parity::None);
+        self.pipe
+            .set_timeout(Some(settings.timeout).filter(|&timeout| timeout != Duration::MAX));
+        self.publish_settings();
+    }
+
+    /// Subscribes to the changes of the line settings of the port, made
+    /// through any of its handles, timestamped with the clock of the port.
+    pub(crate) fn subscribe_settings(&self) -> Receiver<(Instant, Settings)> {
+        let (sender, receiver) = mpsc::channel();
+        self.config
+            .lock()
+            .unwrap()
+            .settings_subscribers
+            .push(sender);
+        receiver
+    }
+
+    // Notifies the subscribers of a change of the line settings
+    pub(crate) fn publish_settings(&self) {
+        let settings = self.settings();
+        let now = self.pipe.now();
+        self.config
+            .lock()
+            .unwrap()
+            .settings_subscribers
+            .retain(|sender| sender.send((now, settings)).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serialport::SerialPort;
+
+    use super::*;
+
+    #[test]
+    fn test_apply_settings() {
+        let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+        assert_eq!(port1.settings(), Settings::new(9600));
+
+        port1.set_split_baud_rates(1200, 75);
+        let settings = Settings {
+            data_bits: DataBits::Seven,
+            parity: Parity::Odd,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Software,
+            timeout: Duration::from_secs(1),
+            ..Settings::new(19_200)
+        };
+        port1.apply_settings(&settings);
+        assert_eq!(port1.settings(), settings);
+        assert_eq!(port1.rx_baud_rate(), 19_200);
+        assert_eq!(port1.parity().unwrap(), Parity::Odd);
+        assert_eq!(port1.timeout(), Duration::from_secs(1));
+
+        // The peer keeps its own settings
+        assert_eq!(port2.settings(), Settings::new(9600));
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let settings: Settings = "115200,8N1".parse().unwrap();
+        assert_eq!(settings, Settings::new(115_200));
+        assert_eq!(
+            " 300 , 5o2 ".parse::<Settings>().unwrap(),
+            Settings {
+                data_bits: DataBits::Five,
+                parity: Parity::Odd,
+                stop_bits: StopBits::Two,
+                ..Settings::new(300)
+            }
+        );
+        assert_eq!("9600".parse::<Settings>().unwrap(), Settings::new(9600));
+
+        for text in [
+            "",
+            "0",
+            "fast",
+            "9600,8N",
+            "9600,9N1",
+            "9600,8M1",
+            "9600,8N3",
+            "9600,8N1,x",
+        ] {
+            let err = text.parse::<Settings>().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", text);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_settings() {
+        let settings = Settings {
+            parity: Parity::Even,
+            timeout: Duration::MAX,
+            ..Settings::new(115_200)
+        };
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(serde_json::from_str::<Settings>(&json).unwrap(), settings);
+    }
+}
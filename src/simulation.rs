@@ -0,0 +1,176 @@
+//! Deterministic simulation of links on a single thread.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serialport::Result;
+
+use crate::{Clock, ManualClock, VirtualPort};
+
+// Clock of a simulation, which only moves on steps and under which nothing
+// waits
+#[derive(Default)]
+struct SimulationClock(ManualClock);
+
+impl Clock for SimulationClock {
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    fn is_real_time(&self) -> bool {
+        false
+    }
+
+    fn can_wait(&self) -> bool {
+        false
+    }
+
+    fn on_advance(&self, callback: Box<dyn Fn() -> bool + Send + Sync>) {
+        self.0.on_advance(callback);
+    }
+}
+
+/// Simulation of links driven step by step from a single thread, with no
+/// background threads and no real sleeps.
+///
+/// The ports of a simulation simulate the transmission delay, and share a
+/// clock which only moves with [`step`](Simulation::step), so the arrival
+/// of every byte, the latency and the scheduled faults are exact and the
+/// tests never flake. As nothing happens while the thread waits, the
+/// operations which would wait (a read of data not arrived yet, a blocking
+/// write into a full buffer, a drain) fail with `TimedOut` instead.
+///
+/// ```
+/// use std::{io::{self, Read, Write}, time::Duration};
+///
+/// use serialport::SerialPort;
+/// use virtual_serialport::Simulation;
+///
+/// let sim = Simulation::new();
+/// let (mut port1, mut port2) = sim.pair(9600, 1024).unwrap();
+///
+/// // At 9600 baud a byte takes about a millisecond
+/// port1.write_all(b"hello").unwrap();
+/// let mut read_data = [0u8; 5];
+/// let err = port2.read_exact(&mut read_data).unwrap_err();
+/// assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+///
+/// sim.step(Duration::from_millis(3));
+/// assert_eq!(port2.bytes_to_read().unwrap(), 2);
+/// sim.step(Duration::from_millis(3));
+/// port2.read_exact(&mut read_data).unwrap();
+/// assert_eq!(&read_data, b"hello");
+/// ```
+pub struct Simulation {
+    clock: Arc<SimulationClock>,
+}
+
+impl Simulation {
+    /// Creates a simulation starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            clock: Arc::default(),
+        }
+    }
+
+    /// Opens a loopback port driven by the simulation (see
+    /// [`VirtualPort::loopback`]).
+    pub fn loopback(&self, baud_rate: u32, buffer_capacity: usize) -> Result<VirtualPort> {
+        let mut port = VirtualPort::loopback(baud_rate, buffer_capacity)?;
+        self.add_port(&mut port);
+        Ok(port)
+    }
+
+    /// Opens a pair of ports driven by the simulation (see
+    /// [`VirtualPort::pair`]).
+    pub fn pair(
+        &self,
+        baud_rate: u32,
+        buffer_capacity: usize,
+    ) -> Result<(VirtualPort, VirtualPort)> {
+        let (mut port1, mut port2) = VirtualPort::pair(baud_rate, buffer_capacity)?;
+        self.add_port(&mut port1);
+        self.add_port(&mut port2);
+        Ok((port1, port2))
+    }
+
+    /// Brings the link of `port`, opened otherwise (e.g., by a
+    /// [`Topology`](crate::Topology)), into the simulation, simulating the
+    /// transmission delay of the data the port receives.
+    pub fn add_port(&self, port: &mut VirtualPort) {
+        port.set_clock(self.clock.clone());
+        port.set_simulate_delay(true);
+    }
+
+    /// Moves the time of the simulation forward: the data in flight
+    /// arrives, and the faults scheduled in the meantime take effect.
+    pub fn step(&self, duration: Duration) {
+        self.clock.0.advance(duration);
+    }
+
+    /// Returns the time simulated so far.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.0.elapsed()
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use serialport::SerialPort;
+
+    use super::*;
+    use crate::FaultSchedule;
+
+    #[test]
+    fn test_simulation() {
+        let sim = Simulation::new();
+        let (mut port1, mut port2) = sim.pair(300, 4).unwrap();
+        port1.set_blocking_writes(true);
+        port2.set_fault_schedule(FaultSchedule::new().disconnect_after(Duration::from_millis(200)));
+
+        // A byte takes about 33 ms at 300 baud, and nothing waits for it
+        port1.write_all(b"abcd").unwrap();
+        assert_eq!(
+            port1.write_all(b"e").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        let mut read_data = [0u8; 4];
+        assert_eq!(
+            port2.read_exact(&mut read_data).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        sim.step(Duration::from_millis(70));
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+        sim.step(Duration::from_millis(70));
+        port2.read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+        assert_eq!(sim.elapsed(), Duration::from_millis(140));
+
+        // The scheduled link drop hits the data sent after its simulated time
+        port1.write_all(b"e").unwrap();
+        assert!(port2.is_connected());
+        sim.step(Duration::from_millis(60));
+        // The link goes down before the byte is written
+        assert_eq!(port1.write(b"f").unwrap(), 0);
+        assert!(!port2.is_connected());
+    }
+}
@@ -0,0 +1,323 @@
+//! A drop-in counterpart of `tokio_serial::SerialStream` (requires the
+//! `async` feature).
+//!
+//! [`VirtualSerialStream`] mirrors the surface of `tokio_serial::SerialStream`
+//! (readiness futures, non-blocking `try_read`/`try_write`, tokio IO traits
+//! and the `SerialPort` trait), so code written against tokio-serial can be
+//! tested against the simulator by only changing how the stream is created.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, Result, SerialPort, StopBits};
+
+use crate::{AsyncVirtualPort, VirtualPort};
+
+/// `VirtualSerialStream` is an asynchronous virtual serial port with the API
+/// of `tokio_serial::SerialStream`.
+pub struct VirtualSerialStream {
+    inner: AsyncVirtualPort,
+
+    // Kept for API compatibility only, virtual ports are never locked
+    exclusive: bool,
+}
+
+impl VirtualSerialStream {
+    /// Opens a single loopback stream with the specified baud rate.
+    pub fn loopback(baud_rate: u32, buffer_capacity: usize) -> Result<Self> {
+        VirtualPort::loopback(baud_rate, buffer_capacity).map(Self::from)
+    }
+
+    /// Opens a pair of connected streams with the specified baud rate.
+    pub fn pair(baud_rate: u32, buffer_capacity: usize) -> Result<(Self, Self)> {
+        let (port1, port2) = VirtualPort::pair(baud_rate, buffer_capacity)?;
+        Ok((Self::from(port1), Self::from(port2)))
+    }
+
+    /// Sets the exclusivity of the port. Has no effect on virtual ports.
+    pub fn set_exclusive(&mut self, exclusive: bool) -> Result<()> {
+        self.exclusive = exclusive;
+        Ok(())
+    }
+
+    /// Returns the exclusivity of the port.
+    pub fn exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Reads the available bytes into `buf` without waiting, returning
+    /// `WouldBlock` if there is no data, or 0 once the peer is closed.
+    ///
+    /// With transmission delay simulation, only the bytes which have already
+    /// arrived are available.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.get_mut().try_read(buf)
+    }
+
+    /// Waits for the port to become readable.
+    pub async fn readable(&self) -> io::Result<()> {
+        self.inner.readable().await
+    }
+
+    /// Tries to write data from `buf` without waiting, returning
+    /// `WouldBlock` if the peer buffer is full.
+    pub fn try_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_ref().pipe.try_write(buf)
+    }
+
+    /// Waits for the port to become writable.
+    pub async fn writable(&self) -> io::Result<()> {
+        self.inner.writable().await
+    }
+
+    /// Returns a reference to the underlying virtual port.
+    pub fn get_ref(&self) -> &VirtualPort {
+        self.inner.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying virtual port.
+    pub fn get_mut(&mut self) -> &mut VirtualPort {
+        self.inner.get_mut()
+    }
+
+    /// Unwraps the underlying virtual port.
+    pub fn into_inner(self) -> VirtualPort {
+        self.inner.into_inner()
+    }
+}
+
+impl From<VirtualPort> for VirtualSerialStream {
+    fn from(port: VirtualPort) -> Self {
+        Self {
+            inner: AsyncVirtualPort::new(port),
+            exclusive: true,
+        }
+    }
+}
+
+impl AsyncRead for VirtualSerialStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for VirtualSerialStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl io::Read for VirtualSerialStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.get_mut().read(buf)
+    }
+}
+
+impl io::Write for VirtualSerialStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
+
+impl SerialPort for VirtualSerialStream {
+    fn name(&self) -> Option<String> {
+        self.get_ref().name()
+    }
+
+    fn baud_rate(&self) -> Result<u32> {
+        self.get_ref().baud_rate()
+    }
+
+    fn data_bits(&self) -> Result<DataBits> {
+        self.get_ref().data_bits()
+    }
+
+    fn flow_control(&self) -> Result<FlowControl> {
+        self.get_ref().flow_control()
+    }
+
+    fn parity(&self) -> Result<Parity> {
+        self.get_ref().parity()
+    }
+
+    fn stop_bits(&self) -> Result<StopBits> {
+        self.get_ref().stop_bits()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.get_ref().timeout()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.get_mut().set_baud_rate(baud_rate)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> Result<()> {
+        self.get_mut().set_flow_control(flow_control)
+    }
+
+    fn set_parity(&mut self, parity: Parity) -> Result<()> {
+        self.get_mut().set_parity(parity)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> Result<()> {
+        self.get_mut().set_data_bits(data_bits)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> Result<()> {
+        self.get_mut().set_stop_bits(stop_bits)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.get_mut().set_timeout(timeout)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> Result<()> {
+        self.get_mut().write_request_to_send(level)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> Result<()> {
+        self.get_mut().write_data_terminal_ready(level)
+    }
+
+    fn read_clear_to_send(&mut self) -> Result<bool> {
+        self.get_mut().read_clear_to_send()
+    }
+
+    fn read_data_set_ready(&mut self) -> Result<bool> {
+        self.get_mut().read_data_set_ready()
+    }
+
+    fn read_ring_indicator(&mut self) -> Result<bool> {
+        self.get_mut().read_ring_indicator()
+    }
+
+    fn read_carrier_detect(&mut self) -> Result<bool> {
+        self.get_mut().read_carrier_detect()
+    }
+
+    fn bytes_to_read(&self) -> Result<u32> {
+        self.get_ref().bytes_to_read()
+    }
+
+    fn bytes_to_write(&self) -> Result<u32> {
+        self.get_ref().bytes_to_write()
+    }
+
+    fn clear(&self, buffer_to_clear: ClearBuffer) -> Result<()> {
+        self.get_ref().clear(buffer_to_clear)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
+        self.get_ref().try_clone()
+    }
+
+    fn set_break(&self) -> Result<()> {
+        self.get_ref().set_break()
+    }
+
+    fn clear_break(&self) -> Result<()> {
+        self.get_ref().clear_break()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_try_read_and_readable() {
+        let (mut stream1, mut stream2) = VirtualSerialStream::pair(9600, 1024).unwrap();
+        let mut buf = [0u8; 16];
+
+        assert_eq!(
+            stream2.try_read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        stream1.writable().await.unwrap();
+        assert_eq!(stream1.try_write(b"hello").unwrap(), 5);
+
+        stream2.readable().await.unwrap();
+        assert_eq!(stream2.try_read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_writable_waits_for_space() {
+        let (mut stream1, mut stream2) = VirtualSerialStream::pair(9600, 2).unwrap();
+
+        assert_eq!(stream1.try_write(b"abc").unwrap(), 2);
+        assert_eq!(
+            stream1.try_write(b"c").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let reader = tokio::spawn(async move {
+            let mut buf = [0u8; 3];
+            stream2.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+
+        stream1.writable().await.unwrap();
+        stream1.write_all(b"c").await.unwrap();
+        assert_eq!(&reader.await.unwrap(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn test_try_write_with_blocking_writes() {
+        let (mut stream1, _stream2) = VirtualSerialStream::pair(9600, 2).unwrap();
+        stream1.get_mut().set_blocking_writes(true);
+        stream1.set_timeout(Duration::from_secs(10)).unwrap();
+
+        // A full buffer fails the write at once instead of waiting
+        assert_eq!(stream1.try_write(b"ab").unwrap(), 2);
+        let start = Instant::now();
+        assert_eq!(
+            stream1.try_write(b"c").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_serial_port_settings() {
+        let (mut stream1, stream2) = VirtualSerialStream::pair(9600, 1024).unwrap();
+
+        stream1.set_baud_rate(115_200).unwrap();
+        assert_eq!(stream1.baud_rate().unwrap(), 115_200);
+
+        stream1.write_request_to_send(false).unwrap();
+        assert!(!stream2.into_inner().read_clear_to_send().unwrap());
+    }
+}
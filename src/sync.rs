@@ -0,0 +1,44 @@
+//! Synchronization primitives shared by the ends of a link.
+//!
+//! Built with `--cfg loom`, the primitives are the ones of `loom`, so the
+//! concurrency of the ports (clones, concurrent reads and writes, control
+//! lines) can be model-checked.
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Condvar, Mutex, MutexGuard,
+};
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    atomic::{AtomicU64, Ordering},
+    Condvar, Mutex, MutexGuard,
+};
+
+/// Runs `f` under the `loom` model checker (requires building with
+/// `--cfg loom`), once for every interleaving of the threads it spawns, so
+/// tests can check how their own code uses the ports concurrently. The
+/// threads are spawned with `loom::thread` (`loom` 0.7).
+///
+/// ```ignore
+/// use std::io::{Read, Write};
+///
+/// use virtual_serialport::VirtualPort;
+///
+/// virtual_serialport::model(|| {
+///     let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+///     let writer = loom::thread::spawn(move || port1.write_all(b"hi").unwrap());
+///     let mut read_data = [0u8; 2];
+///     port2.read_exact(&mut read_data).unwrap();
+///     writer.join().unwrap();
+///     assert_eq!(&read_data, b"hi");
+/// });
+/// ```
+#[cfg(loom)]
+pub fn model<F>(f: F)
+where
+    F: Fn() + Sync + Send + 'static,
+{
+    loom::model(f);
+}
@@ -0,0 +1,123 @@
+//! Metrics of the traffic of the ports, published through the `metrics`
+//! facade.
+//!
+//! The metrics are labeled with the name given to a port with
+//! [`VirtualPort::set_metrics_label`], under the `port` label:
+//!
+//! - `virtual_serialport_tx_bytes_total` (counter): bytes written.
+//! - `virtual_serialport_rx_bytes_total` (counter): bytes read.
+//! - `virtual_serialport_rx_buffer_bytes` (gauge): bytes in the receive
+//!   buffer, including the ones in flight.
+//! - `virtual_serialport_errors_total` (counter): errors met, by `kind`
+//!   (`line`, `overrun` or `timeout`).
+//! - `virtual_serialport_latency_seconds` (histogram): time from a write to
+//!   the arrival of its last byte at the receiver.
+
+use std::time::Duration;
+
+use crate::VirtualPort;
+
+/// Counts the bytes written by the port.
+pub(crate) fn transmitted(port: &str, len: usize) {
+    metrics::counter!("virtual_serialport_tx_bytes_total", "port" => port.to_owned())
+        .increment(len as u64);
+}
+
+/// Counts the bytes read by the port.
+pub(crate) fn received(port: &str, len: usize) {
+    metrics::counter!("virtual_serialport_rx_bytes_total", "port" => port.to_owned())
+        .increment(len as u64);
+}
+
+/// Records the number of bytes in the receive buffer of the port.
+pub(crate) fn buffer_level(port: &str, len: usize) {
+    metrics::gauge!("virtual_serialport_rx_buffer_bytes", "port" => port.to_owned())
+        .set(len as f64);
+}
+
+/// Counts an error of the given kind met by the port.
+pub(crate) fn error(port: &str, kind: &'static str) {
+    metrics::counter!(
+        "virtual_serialport_errors_total",
+        "port" => port.to_owned(),
+        "kind" => kind
+    )
+    .increment(1);
+}
+
+/// Records the time the data written to the port takes to arrive.
+pub(crate) fn latency(port: &str, latency: Duration) {
+    metrics::histogram!("virtual_serialport_latency_seconds", "port" => port.to_owned())
+        .record(latency.as_secs_f64());
+}
+
+impl VirtualPort {
+    /// Returns the label of the metrics of the port, if they are published.
+    pub fn metrics_label(&self) -> Option<String> {
+        self.pipe.metrics_label()
+    }
+
+    /// Publishes the throughput, the receive buffer occupancy, the error
+    /// counts and the latency of the port through the `metrics` facade,
+    /// labeled with `port` set to `label`, or stops publishing them if
+    /// `label` is `None`. The setting is shared by all the handles of the
+    /// port.
+    pub fn set_metrics_label(&mut self, label: Option<&str>) {
+        self.pipe.set_metrics_label(label.map(str::to_owned));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        port1.set_metrics_label(Some("port1"));
+        port2.set_metrics_label(Some("port2"));
+        assert_eq!(port1.clone().metrics_label().as_deref(), Some("port1"));
+
+        metrics::with_local_recorder(&recorder, || {
+            port1.write_all(b"hello").unwrap();
+            let mut read_data = [0u8; 3];
+            port2.read_exact(&mut read_data).unwrap();
+        });
+
+        let mut values: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let (_, key) = key.into_parts();
+                let port = key.labels().next()?.value().to_owned();
+                let value = match value {
+                    DebugValue::Counter(value) => value as f64,
+                    DebugValue::Gauge(value) => value.into_inner(),
+                    DebugValue::Histogram(values) => values.len() as f64,
+                };
+                Some((key.name().to_owned(), port, value))
+            })
+            .collect();
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expected = [
+            ("virtual_serialport_latency_seconds", "port2", 1.0),
+            ("virtual_serialport_rx_buffer_bytes", "port2", 2.0),
+            ("virtual_serialport_rx_bytes_total", "port2", 3.0),
+            ("virtual_serialport_tx_bytes_total", "port1", 5.0),
+        ];
+        assert_eq!(
+            values,
+            expected.map(|(name, port, value)| (name.to_owned(), port.to_owned(), value))
+        );
+
+        port1.set_metrics_label(None);
+        assert_eq!(port1.metrics_label(), None);
+    }
+}
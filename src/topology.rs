@@ -0,0 +1,288 @@
+//! Declarative wiring of several virtual ports.
+
+use std::time::Duration;
+
+use serialport::{Error, ErrorKind, Result};
+
+use crate::{pipe::Pipe, Settings, VirtualPort};
+
+/// Node of a [`Topology`], returned by [`Topology::add_node`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Node(usize);
+
+impl Node {
+    /// Returns the position of the port of the node in the ports returned by
+    /// [`Topology::build`].
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Builder of a set of interconnected virtual ports, e.g., a gateway and the
+/// devices it talks to.
+///
+/// Each node is a port, and each directed link wires the transmit line of a
+/// port to the receive line of another. A port linked to several nodes sends
+/// its data to all of them: the first link behaves as with
+/// [`VirtualPort::pair`] (e.g., holding the writer back while the receiver
+/// is full), and the others as the listeners of
+/// [`VirtualPort::add_listener`], which lose the data that doesn't fit. A
+/// port linked from several nodes receives their data interleaved, and a port
+/// without outgoing links fails its writes with `PermissionDenied`.
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use virtual_serialport::Topology;
+///
+/// let mut topology = Topology::new(9600, 1024);
+/// let gateway = topology.add_node();
+/// let sensor1 = topology.add_node();
+/// let sensor2 = topology.add_node();
+/// topology.link_both(gateway, sensor1);
+/// topology.link_both(gateway, sensor2);
+///
+/// let mut ports = topology.build().unwrap();
+///
+/// // The gateway polls both sensors
+/// ports[gateway.index()].write_all(b"?").unwrap();
+/// let mut read_data = [0u8; 1];
+/// ports[sensor2.index()].read_exact(&mut read_data).unwrap();
+///
+/// // Both answer the gateway
+/// ports[sensor1.index()].write_all(b"1").unwrap();
+/// ports[sensor2.index()].write_all(b"2").unwrap();
+/// let mut read_data = [0u8; 2];
+/// ports[gateway.index()].read_exact(&mut read_data).unwrap();
+/// assert_eq!(&read_data, b"12");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Topology {
+    settings: Settings,
+    buffer_capacity: usize,
+    nodes: usize,
+    links: Vec<(usize, usize)>,
+
+    // Latency of the data received by each node
+    hop_delay: Duration,
+}
+
+impl Topology {
+    /// Creates an empty topology whose ports have the given baud rate and
+    /// receive buffer capacity.
+    pub fn new(baud_rate: u32, buffer_capacity: usize) -> Self {
+        Self {
+            settings: Settings::new(baud_rate),
+            buffer_capacity,
+            nodes: 0,
+            links: Vec::new(),
+            hop_delay: Duration::ZERO,
+        }
+    }
+
+    /// Creates a ring of `nodes` nodes, where the transmit line of each node
+    /// is wired to the receive line of the next one and the last node to the
+    /// first, as in daisy-chained instruments or LED strips. The nodes are
+    /// numbered from 0 in ring order.
+    ///
+    /// ```
+    /// use std::{
+    ///     io::{Read, Write},
+    ///     time::Duration,
+    /// };
+    ///
+    /// use virtual_serialport::Topology;
+    ///
+    /// let mut topology = Topology::ring(9600, 1024, 3);
+    /// topology.set_hop_delay(Duration::from_millis(1));
+    /// let mut ports = topology.build().unwrap();
+    ///
+    /// // Each node forwards the frame to the next one, back to the first
+    /// ports[0].write_all(b"token").unwrap();
+    /// let mut frame = [0u8; 5];
+    /// for node in (1..3).chain(0..1) {
+    ///     ports[node].read_exact(&mut frame).unwrap();
+    ///     if node != 0 {
+    ///         ports[node].write_all(&frame).unwrap();
+    ///     }
+    /// }
+    /// assert_eq!(&frame, b"token");
+    /// ```
+    pub fn ring(baud_rate: u32, buffer_capacity: usize, nodes: usize) -> Self {
+        let mut topology = Self::new(baud_rate, buffer_capacity);
+        topology.nodes = nodes;
+        topology.links = (0..nodes).map(|node| (node, (node + 1) % nodes)).collect();
+        topology
+    }
+
+    /// Adds a node, whose port is built at the position given by
+    /// [`Node::index`].
+    pub fn add_node(&mut self) -> Node {
+        self.nodes += 1;
+        Node(self.nodes - 1)
+    }
+
+    /// Wires the transmit line of `from` to the receive line of `to`.
+    /// Linking a node to itself loops its data back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a node was not added to this topology.
+    pub fn link(&mut self, from: Node, to: Node) -> &mut Self {
+        assert!(
+            from.0 < self.nodes && to.0 < self.nodes,
+            "node is not part of the topology"
+        );
+        self.links.push((from.0, to.0));
+        self
+    }
+
+    /// Wires `a` and `b` to each other, as a cable between two ports does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a node was not added to this topology.
+    pub fn link_both(&mut self, a: Node, b: Node) -> &mut Self {
+        self.link(a, b).link(b, a)
+    }
+
+    /// Returns the line settings of the ports.
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// Sets the line settings of the ports, e.g., parsed from a shorthand
+    /// like `"115200,8N1"` (see [`Settings`]).
+    pub fn set_settings(&mut self, settings: Settings) -> &mut Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Returns the delay added by each hop.
+    pub fn hop_delay(&self) -> Duration {
+        self.hop_delay
+    }
+
+    /// Sets the delay added by each hop, as the latency of the data received
+    /// by every port (see [`VirtualPort::set_link_latency`]), e.g., the time
+    /// a daisy-chained device takes to repeat a frame.
+    pub fn set_hop_delay(&mut self, delay: Duration) -> &mut Self {
+        self.hop_delay = delay;
+        self
+    }
+
+    /// Opens the ports of the nodes, in the order they were added, failing
+    /// with `InvalidInput` if a link refers to a missing node (as a topology
+    /// read from a file may).
+    pub fn build(&self) -> Result<Vec<VirtualPort>> {
+        if self
+            .links
+            .iter()
+            .any(|&(from, to)| from >= self.nodes || to >= self.nodes)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "link to a node which is not part of the topology",
+            ));
+        }
+
+        Pipe::network(self.nodes, &self.links, self.buffer_capacity)
+            .into_iter()
+            .map(|pipe| {
+                let mut port = VirtualPort::with_own_lines(self.settings.baud_rate, pipe)?;
+                port.apply_settings(&self.settings);
+                port.set_link_latency(self.hop_delay);
+                Ok(port)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        sync::Arc,
+    };
+
+    use serialport::SerialPort;
+
+    use super::*;
+    use crate::ManualClock;
+
+    #[test]
+    fn test_topology() {
+        let mut topology = Topology::new(9600, 1024);
+        let source = topology.add_node();
+        let relay = topology.add_node();
+        let sink = topology.add_node();
+        topology
+            .link(source, relay)
+            .link(relay, sink)
+            .link(source, sink);
+        let mut ports = topology.build().unwrap();
+
+        ports[source.index()].write_all(b"ab").unwrap();
+        assert_eq!(ports[relay.index()].bytes_to_read().unwrap(), 2);
+        ports[relay.index()].write_all(b"cd").unwrap();
+
+        let mut read_data = [0u8; 4];
+        ports[sink.index()].read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"abcd");
+        assert_eq!(ports[source.index()].bytes_to_read().unwrap(), 0);
+        assert_eq!(
+            ports[sink.index()].write(b"x").unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_ring() {
+        let clock = Arc::new(ManualClock::new());
+        let mut topology = Topology::ring(9600, 1024, 3);
+        topology.set_hop_delay(Duration::from_millis(5));
+        let mut ports = topology.build().unwrap();
+        for port in &mut ports {
+            port.set_clock(clock.clone());
+        }
+
+        ports[2].write_all(b"x").unwrap();
+        assert_eq!(ports[1].bytes_to_read().unwrap(), 0);
+        assert_eq!(ports[0].bytes_to_read().unwrap(), 0);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(ports[0].bytes_to_read().unwrap(), 1);
+        assert_eq!(ports[1].bytes_to_read().unwrap(), 0);
+
+        // A single node ring loops the data back
+        let mut ports = Topology::ring(9600, 1024, 1).build().unwrap();
+        ports[0].write_all(b"y").unwrap();
+        let mut read_data = [0u8; 1];
+        ports[0].read_exact(&mut read_data).unwrap();
+        assert_eq!(&read_data, b"y");
+    }
+
+    #[test]
+    fn test_topology_settings() {
+        let mut topology = Topology::ring(9600, 1024, 2);
+        topology.set_settings("19200,7E1".parse().unwrap());
+        for port in topology.build().unwrap() {
+            assert_eq!(port.settings(), topology.settings());
+            assert_eq!(port.baud_rate().unwrap(), 19_200);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_topology() {
+        let mut topology = Topology::ring(9600, 1024, 3);
+        topology.set_hop_delay(Duration::from_millis(5));
+        let json = serde_json::to_string(&topology).unwrap();
+        assert_eq!(serde_json::from_str::<Topology>(&json).unwrap(), topology);
+
+        // A topology read from a file may link missing nodes
+        let topology: Topology = serde_json::from_str(&json.replace("[2,0]", "[2,3]")).unwrap();
+        let err = topology.build().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,182 @@
+//! Wiring of the control lines between two ports.
+
+use crate::{ControlLine, VirtualPort};
+
+/// Wiring of the control line inputs of a port (CTS, DSR, CD and RI) to the
+/// outputs of the other side (RTS and DTR), as set with
+/// [`VirtualPort::set_wiring`], so tests can reproduce the cable a device
+/// ships with.
+///
+/// An input wired to no output is never asserted (unless forced, e.g., with
+/// [`VirtualPort::force_cts`]). An output can drive several inputs.
+///
+/// ```
+/// use serialport::SerialPort;
+/// use virtual_serialport::{ControlLine, VirtualPort, Wiring};
+///
+/// let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+///
+/// // A cable which only carries RTS, to CD
+/// port2.set_wiring(Wiring::straight().connect(ControlLine::Rts, ControlLine::Cd));
+///
+/// port1.write_request_to_send(false).unwrap();
+/// assert!(!port2.read_carrier_detect().unwrap());
+/// port1.write_request_to_send(true).unwrap();
+/// assert!(port2.read_carrier_detect().unwrap());
+/// assert!(!port2.read_data_set_ready().unwrap());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Wiring {
+    cts: Option<ControlLine>,
+    dsr: Option<ControlLine>,
+    cd: Option<ControlLine>,
+    ri: Option<ControlLine>,
+}
+
+impl Wiring {
+    /// Returns the wiring of a null-modem cable (the default): RTS drives
+    /// CTS, and DTR drives DSR and CD.
+    pub fn null_modem() -> Self {
+        Self {
+            cts: Some(ControlLine::Rts),
+            dsr: Some(ControlLine::Dtr),
+            cd: Some(ControlLine::Dtr),
+            ri: None,
+        }
+    }
+
+    /// Returns the wiring of a straight-through cable between two ports of
+    /// the same kind, which connects the outputs of each side to the outputs
+    /// of the other: no input is driven.
+    pub fn straight() -> Self {
+        Self {
+            cts: None,
+            dsr: None,
+            cd: None,
+            ri: None,
+        }
+    }
+
+    /// Wires `output` of the other side to `input`, replacing the previous
+    /// wiring of `input`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `output` is not an output line or `input` not an input line.
+    pub fn connect(mut self, output: ControlLine, input: ControlLine) -> Self {
+        assert!(
+            matches!(output, ControlLine::Rts | ControlLine::Dtr),
+            "{:?} is not an output line",
+            output
+        );
+        *self.slot(input) = Some(output);
+        self
+    }
+
+    /// Leaves `input` undriven.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `input` is not an input line.
+    pub fn disconnect(mut self, input: ControlLine) -> Self {
+        *self.slot(input) = None;
+        self
+    }
+
+    /// Returns the output of the other side driving `input`, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `input` is not an input line.
+    pub fn source(mut self, input: ControlLine) -> Option<ControlLine> {
+        *self.slot(input)
+    }
+
+    // Level of `input` given the levels of the outputs of the other side
+    pub(crate) fn level(&self, input: ControlLine, rts: bool, dtr: bool) -> bool {
+        match self.source(input) {
+            Some(ControlLine::Rts) => rts,
+            Some(ControlLine::Dtr) => dtr,
+            _ => false,
+        }
+    }
+
+    fn slot(&mut self, input: ControlLine) -> &mut Option<ControlLine> {
+        match input {
+            ControlLine::Cts => &mut self.cts,
+            ControlLine::Dsr => &mut self.dsr,
+            ControlLine::Cd => &mut self.cd,
+            ControlLine::Ri => &mut self.ri,
+            line => panic!("{:?} is not an input line", line),
+        }
+    }
+}
+
+impl Default for Wiring {
+    fn default() -> Self {
+        Self::null_modem()
+    }
+}
+
+impl VirtualPort {
+    /// Returns the wiring of the control line inputs of this port.
+    pub fn wiring(&self) -> Wiring {
+        *self.wiring.lock().unwrap()
+    }
+
+    /// Sets which outputs of the peer drive the control line inputs of this
+    /// port, instead of the null-modem wiring. With `FlowControl::Hardware`,
+    /// the writes of this port follow its CTS input as wired. The wiring of
+    /// the peer is left alone, so the cable can be asymmetric.
+    pub fn set_wiring(&mut self, wiring: Wiring) {
+        self.change_lines(|port| {
+            *port.wiring.lock().unwrap() = wiring;
+            port.pipe.set_cts_wiring(wiring.source(ControlLine::Cts));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use serialport::{FlowControl, SerialPort};
+
+    use super::*;
+
+    #[test]
+    fn test_wiring() {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        assert_eq!(port2.wiring(), Wiring::null_modem());
+
+        let wiring = Wiring::null_modem()
+            .connect(ControlLine::Dtr, ControlLine::Cts)
+            .connect(ControlLine::Rts, ControlLine::Ri)
+            .disconnect(ControlLine::Cd);
+        port2.set_wiring(wiring);
+        assert_eq!(
+            port2.wiring().source(ControlLine::Cts),
+            Some(ControlLine::Dtr)
+        );
+
+        port1.write_request_to_send(true).unwrap();
+        port1.write_data_terminal_ready(false).unwrap();
+        assert!(!port2.read_clear_to_send().unwrap());
+        assert!(!port2.read_data_set_ready().unwrap());
+        assert!(!port2.read_carrier_detect().unwrap());
+        assert!(port2.read_ring_indicator().unwrap());
+
+        // The peer keeps the null-modem wiring
+        assert!(port1.read_clear_to_send().unwrap());
+
+        // Hardware flow control follows the CTS input as wired
+        port2.set_flow_control(FlowControl::Hardware).unwrap();
+        assert_eq!(
+            port2.write(b"a").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        port1.write_data_terminal_ready(true).unwrap();
+        port2.write_all(b"a").unwrap();
+    }
+}
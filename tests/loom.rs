@@ -0,0 +1,45 @@
+//! Model checks of the concurrency of the ports, run with
+//! `RUSTFLAGS="--cfg loom" cargo test --test loom --release`.
+
+#![cfg(loom)]
+
+use std::io::{Read, Write};
+
+use loom::thread;
+use serialport::SerialPort;
+use virtual_serialport::{model, VirtualPort};
+
+#[test]
+fn loom_concurrent_read_write() {
+    model(|| {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let writer = thread::spawn(move || port1.write_all(b"hi").unwrap());
+        let mut read_data = [0u8; 2];
+        port2.read_exact(&mut read_data).unwrap();
+        writer.join().unwrap();
+        assert_eq!(&read_data, b"hi");
+    });
+}
+
+#[test]
+fn loom_clones() {
+    model(|| {
+        let (mut port1, port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let mut clone = port1.clone();
+        let writer = thread::spawn(move || clone.write_all(b"a").unwrap());
+        port1.write_all(b"b").unwrap();
+        writer.join().unwrap();
+        assert_eq!(port2.bytes_to_read().unwrap(), 2);
+    });
+}
+
+#[test]
+fn loom_control_lines() {
+    model(|| {
+        let (mut port1, mut port2) = VirtualPort::pair(9600, 1024).unwrap();
+        let setter = thread::spawn(move || port1.write_request_to_send(false).unwrap());
+        port2.read_clear_to_send().unwrap();
+        setter.join().unwrap();
+        assert!(!port2.read_clear_to_send().unwrap());
+    });
+}